@@ -1,7 +1,5 @@
 use proxy_server::server::ProxyServer;
 use std::error::Error;
-use tokio;
-use reqwest;
 use std::time::Duration;
 
 #[tokio::main]
@@ -10,7 +8,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
     
     // 创建并启动代理服务器
     let server = ProxyServer::new(8080, "./cache");
-    let server_handle = tokio::spawn(async move {
+    let _server_handle = tokio::spawn(async move {
         if let Err(e) = server.start().await {
             eprintln!("Server error: {}", e);
         }
@@ -1,7 +1,5 @@
 use proxy_server::server::ProxyServer;
 use std::error::Error;
-use tokio;
-use reqwest;
 use std::time::Duration;
 use proxy_server::log_info;
 
@@ -26,7 +24,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
     
     // 第一步：请求前 100KB 数据
     log_info!("Example", "第一步：请求前 100KB 数据");
-    let resp = client.get(&format!("http://127.0.0.1:8080/proxy/{}", url))
+    let resp = client.get(format!("http://127.0.0.1:8080/proxy/{}", url))
         .header("Range", "bytes=0-102399")
         .send()
         .await?;
@@ -41,7 +39,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
     
     // 第二步：请求 50KB-150KB 数据（混合源）
     log_info!("Example", "第二步：请求 50KB-150KB 数据（混合源）");
-    let resp = client.get(&format!("http://127.0.0.1:8080/proxy/{}", url))
+    let resp = client.get(format!("http://127.0.0.1:8080/proxy/{}", url))
         .header("Range", "bytes=51200-153599")
         .send()
         .await?;
@@ -53,7 +51,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
     
     // 第三步：再次请求相同范围（验证缓存）
     log_info!("Example", "第三步：再次请求相同范围（验证缓存）");
-    let resp = client.get(&format!("http://127.0.0.1:8080/proxy/{}", url))
+    let resp = client.get(format!("http://127.0.0.1:8080/proxy/{}", url))
         .header("Range", "bytes=51200-153599")
         .send()
         .await?;
@@ -17,7 +17,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     tokio::spawn(async {
         log_info!("Server", "启动代理服务器...");
         let server = server::ProxyServer::new(8080, "./cache");
-        server.start().await;
+        if let Err(e) = server.start().await {
+            eprintln!("Server error: {}", e);
+        }
     });
 
     // 等待服务器启动
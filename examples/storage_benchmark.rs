@@ -0,0 +1,103 @@
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use bytes::Bytes;
+use futures::StreamExt;
+use proxy_server::storage::{DiskStorage, ShardLayout, StorageConfig, StorageEngine};
+
+/// 每个文件的大小
+const FILE_SIZE: usize = 64 * 1024 * 1024;
+/// 写入时每个数据块的大小，与上游响应的数据块相近
+const WRITE_CHUNK: usize = 64 * 1024;
+/// 文件数量
+const FILE_COUNT: usize = 16;
+/// 并发读取的任务数
+const READERS: usize = 32;
+
+fn storage_config(root: PathBuf) -> StorageConfig {
+    StorageConfig {
+        root_path: root,
+        chunk_size: 8192,
+        fast_root_path: None,
+        layout: ShardLayout::default(),
+        mmap: false,
+        mmap_threshold: u64::MAX,
+    }
+}
+
+/// 写入全部文件，再由多个任务并发读取，返回写入和读取耗时
+async fn run<E: StorageEngine + 'static>(engine: std::sync::Arc<E>) -> Result<(Duration, Duration), Box<dyn std::error::Error>> {
+    let chunk = Bytes::from(vec![0x5a; WRITE_CHUNK]);
+
+    let started = Instant::now();
+    for i in 0..FILE_COUNT {
+        let chunks = vec![Ok(chunk.clone()); FILE_SIZE / WRITE_CHUNK];
+        let key = format!("http://bench.local/{}.mp4", i);
+        engine.write(&key, futures::stream::iter(chunks), (0, FILE_SIZE as u64 - 1)).await?;
+    }
+    let write_time = started.elapsed();
+
+    let started = Instant::now();
+    let mut tasks = Vec::new();
+    for reader in 0..READERS {
+        let engine = engine.clone();
+        tasks.push(tokio::spawn(async move {
+            let key = format!("http://bench.local/{}.mp4", reader % FILE_COUNT);
+            let mut stream = engine.read(&key, (0, u64::MAX)).await?;
+            let mut total = 0usize;
+            while let Some(chunk) = stream.next().await {
+                total += chunk?.len();
+            }
+            Ok::<usize, proxy_server::utils::error::ProxyError>(total)
+        }));
+    }
+    for task in tasks {
+        assert_eq!(task.await??, FILE_SIZE);
+    }
+    let read_time = started.elapsed();
+
+    Ok((write_time, read_time))
+}
+
+fn report(name: &str, (write_time, read_time): (Duration, Duration)) {
+    let mb = |bytes: usize| bytes as f64 / 1024.0 / 1024.0;
+    println!(
+        "{:<8} 写入 {:>8.1} MB/s  并发读取 {:>8.1} MB/s",
+        name,
+        mb(FILE_SIZE * FILE_COUNT) / write_time.as_secs_f64(),
+        mb(FILE_SIZE * READERS) / read_time.as_secs_f64()
+    );
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let root = std::env::args()
+        .nth(1)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| std::env::temp_dir().join("proxy-storage-bench"));
+    println!(
+        "基准测试目录: {:?}, {} 个 {}MB 文件, {} 个并发读取",
+        root,
+        FILE_COUNT,
+        FILE_SIZE / 1024 / 1024,
+        READERS
+    );
+
+    let disk_root = root.join("disk");
+    let _ = std::fs::remove_dir_all(&disk_root);
+    let disk = std::sync::Arc::new(DiskStorage::new(storage_config(disk_root.clone())));
+    report("disk", run(disk).await?);
+    std::fs::remove_dir_all(&disk_root)?;
+
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    {
+        let uring_root = root.join("uring");
+        let _ = std::fs::remove_dir_all(&uring_root);
+        let uring = std::sync::Arc::new(proxy_server::storage::UringStorage::new(storage_config(uring_root.clone())));
+        report("io_uring", run(uring).await?);
+        std::fs::remove_dir_all(&uring_root)?;
+    }
+    #[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+    println!("未启用 io-uring 特性，使用 --features io-uring 运行以对比 io_uring 引擎");
+
+    Ok(())
+}
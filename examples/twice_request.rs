@@ -1,5 +1,4 @@
 use hyper::{Client, Request, Body};
-use tokio;
 use proxy_server::{log_info, server};
 
 #[tokio::main]
@@ -8,7 +7,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     tokio::spawn(async {
         log_info!("Example", "启动代理服务器...");
         let server = server::ProxyServer::new(8080, "./cache");
-        server.start().await;
+        if let Err(e) = server.start().await {
+            eprintln!("Server error: {}", e);
+        }
     });
 
     // 等待服务器启动
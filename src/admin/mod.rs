@@ -0,0 +1,417 @@
+mod prefetch;
+
+pub use prefetch::{parse_url_list, Prefetcher};
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use hyper::header::{AUTHORIZATION, CONTENT_TYPE};
+use serde_json::json;
+use crate::cluster::{is_peer_authorized, AVAILABILITY_PATH, RECEIVE_ENTRY_PATH, RECEIVE_META_PATH};
+use crate::data_source_manager::DataSourceManager;
+use crate::handlers::session::SessionTracker;
+use crate::handlers::stats::StatsOrder;
+use crate::storage::EntryMeta;
+use crate::utils::error::Result;
+use crate::utils::range::parse_range;
+use crate::log_info;
+
+/// 管理接口路径前缀
+pub const ADMIN_PREFIX: &str = "/admin/";
+
+/// 就绪检查路径，索引加载期间或缓存目录不可用时返回 503 和加载进度
+pub const READY_PATH: &str = "/readyz";
+
+/// `/admin/stats/top` 默认返回的数量
+const DEFAULT_TOP_N: usize = 20;
+
+/// `/admin/sessions` 默认返回的数量
+const DEFAULT_SESSIONS: usize = 100;
+
+/// 管理接口的访问控制
+#[derive(Debug, Clone, Default)]
+pub struct AdminConfig {
+    /// 修改缓存或返回源站 URL 的管理接口要求携带 `Authorization: Bearer <token>`；未设置时只接受本机回环地址的请求
+    pub token: Option<String>,
+}
+
+impl AdminConfig {
+    /// 请求是否可以调用受保护的管理接口
+    pub fn authorizes(&self, req: &Request<Body>) -> bool {
+        match &self.token {
+            Some(token) => req
+                .headers()
+                .get(AUTHORIZATION)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.strip_prefix("Bearer "))
+                .is_some_and(|value| {
+                    value.len() == token.len()
+                        && value.bytes().zip(token.bytes()).fold(0u8, |diff, (a, b)| diff | (a ^ b)) == 0
+                }),
+            None => req.extensions().get::<SocketAddr>().is_some_and(|addr| addr.ip().is_loopback()),
+        }
+    }
+}
+
+/// 管理接口
+pub struct AdminHandler {
+    source_manager: Arc<DataSourceManager>,
+    prefetcher: Arc<Prefetcher>,
+    sessions: Option<Arc<SessionTracker>>,
+    config: AdminConfig,
+    /// 节点共享密钥，未设置时不开放节点间接口
+    peer_secret: Option<String>,
+}
+
+impl AdminHandler {
+    pub fn new(source_manager: Arc<DataSourceManager>, prefetcher: Arc<Prefetcher>) -> Self {
+        Self {
+            source_manager,
+            prefetcher,
+            sessions: None,
+            config: AdminConfig::default(),
+            peer_secret: None,
+        }
+    }
+
+    /// 通过 `/admin/sessions` 查看播放会话统计
+    pub fn with_sessions(mut self, sessions: Arc<SessionTracker>) -> Self {
+        self.sessions = Some(sessions);
+        self
+    }
+
+    pub fn with_config(mut self, config: AdminConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// 开放 `/admin/peer/` 节点间接口，请求必须携带相同的共享密钥
+    pub fn with_peer_secret(mut self, secret: Option<String>) -> Self {
+        self.peer_secret = secret;
+        self
+    }
+
+    pub fn prefetcher(&self) -> &Arc<Prefetcher> {
+        &self.prefetcher
+    }
+
+    /// 判断请求是否为管理接口
+    pub fn is_admin_request(req: &Request<Body>) -> bool {
+        let path = req.uri().path();
+        req.uri().host().is_none() && (path.starts_with(ADMIN_PREFIX) || path == READY_PATH)
+    }
+
+    pub async fn handle(&self, req: Request<Body>) -> Result<Response<Body>> {
+        let method = req.method().clone();
+        let path = req.uri().path().to_string();
+
+        if matches!(path.as_str(), AVAILABILITY_PATH | RECEIVE_ENTRY_PATH | RECEIVE_META_PATH) {
+            if let Some(response) = self.reject_peer(&req) {
+                return Ok(response);
+            }
+        }
+
+        if is_protected(&req) && !self.config.authorizes(&req) {
+            log_info!("Admin", "拒绝未授权的管理请求: {} {}", method, path);
+            return Ok(json_response(StatusCode::UNAUTHORIZED, json!({ "error": "需要管理令牌或本机访问" })));
+        }
+
+        match (method, path.as_str()) {
+            (Method::GET, READY_PATH) => {
+                let index = self.source_manager.index_status();
+                let online = self.source_manager.is_cache_online();
+                // 索引加载失败时回退到磁盘检查，仍可正常提供服务
+                let status = if !index.loading && online {
+                    StatusCode::OK
+                } else {
+                    StatusCode::SERVICE_UNAVAILABLE
+                };
+                Ok(json_response(status, json!({ "online": online, "index": index })))
+            }
+            (Method::POST, "/admin/prefetch") => {
+                let body = hyper::body::to_bytes(req.into_body()).await?;
+                let urls = parse_url_list(&String::from_utf8_lossy(&body));
+                if urls.is_empty() {
+                    return Ok(json_response(StatusCode::BAD_REQUEST, json!({ "error": "没有需要预取的 URL" })));
+                }
+                let queued = urls.len();
+                self.prefetcher.submit(urls);
+                Ok(json_response(StatusCode::ACCEPTED, json!({ "queued": queued })))
+            }
+            (Method::POST, "/admin/cleanup") => {
+                let (freed, removed) = self.source_manager.cleanup().await;
+                Ok(json_response(StatusCode::OK, json!({ "freed_bytes": freed, "removed_entries": removed })))
+            }
+            (Method::POST, "/admin/verify") => {
+                let repair = matches!(query_param(&req, "repair").as_deref(), Some("true") | Some("1"));
+                let report = self.source_manager.verify(repair).await?;
+                Ok(json_response(StatusCode::OK, json!(report)))
+            }
+            (Method::GET, "/admin/plan") => {
+                let url = match query_param(&req, "url") {
+                    Some(url) => url,
+                    None => return Ok(json_response(StatusCode::BAD_REQUEST, json!({ "error": "缺少 url 参数" }))),
+                };
+                let range = query_param(&req, "range").unwrap_or_else(|| "0-".to_string());
+                let range = format!("bytes={}", range.trim_start_matches("bytes="));
+                let (start, end) = match parse_range(&range) {
+                    Ok(range) => range,
+                    Err(e) => return Ok(json_response(StatusCode::BAD_REQUEST, json!({ "error": e.to_string() }))),
+                };
+                Ok(json_response(StatusCode::OK, json!(self.source_manager.plan(&url, start, end).await)))
+            }
+            (Method::GET, "/admin/explain") => {
+                let url = match query_param(&req, "url") {
+                    Some(url) => url,
+                    None => return Ok(json_response(StatusCode::BAD_REQUEST, json!({ "error": "缺少 url 参数" }))),
+                };
+                let range = match query_param(&req, "range") {
+                    Some(range) => match parse_range(&format!("bytes={}", range.trim_start_matches("bytes="))) {
+                        Ok(range) => Some(range),
+                        Err(e) => return Ok(json_response(StatusCode::BAD_REQUEST, json!({ "error": e.to_string() }))),
+                    },
+                    None => None,
+                };
+                let (start, end) = range.unwrap_or((0, u64::MAX));
+                Ok(json_response(StatusCode::OK, json!({
+                    "plan": self.source_manager.plan(&url, start, end).await,
+                    "decisions": self.source_manager.decisions(&url, range),
+                })))
+            }
+            (Method::GET, AVAILABILITY_PATH) => {
+                let url = match query_param(&req, "url") {
+                    Some(url) => url,
+                    None => return Ok(json_response(StatusCode::BAD_REQUEST, json!({ "error": "缺少 url 参数" }))),
+                };
+                let availability = self.source_manager.availability(&url).await?;
+                Ok(json_response(StatusCode::OK, json!(availability)))
+            }
+            (Method::POST, RECEIVE_ENTRY_PATH) => {
+                let (url, offset) = match (query_param(&req, "url"), query_param(&req, "offset").and_then(|o| o.parse().ok())) {
+                    (Some(url), Some(offset)) => (url, offset),
+                    _ => return Ok(json_response(StatusCode::BAD_REQUEST, json!({ "error": "缺少 url 或 offset 参数" }))),
+                };
+                let replace = query_param(&req, "replace").as_deref() == Some("true");
+                match self.source_manager.receive_entry(&url, offset, replace, req.into_body()).await {
+                    Ok(cached_bytes) => Ok(json_response(StatusCode::OK, json!({ "cached_bytes": cached_bytes }))),
+                    Err(e) => Ok(json_response(StatusCode::CONFLICT, json!({ "error": e.to_string() }))),
+                }
+            }
+            (Method::POST, RECEIVE_META_PATH) => {
+                let body = hyper::body::to_bytes(req.into_body()).await?;
+                let meta = EntryMeta::decode(&body)?;
+                self.source_manager.receive_meta(meta).await?;
+                Ok(json_response(StatusCode::OK, json!({ "ok": true })))
+            }
+            (Method::GET, path) if path.starts_with("/admin/namespaces/") => {
+                let namespace = &path["/admin/namespaces/".len()..];
+                let (bytes, entries) = self.source_manager.namespace_usage(namespace).await;
+                Ok(json_response(StatusCode::OK, json!({ "namespace": namespace, "bytes": bytes, "entries": entries })))
+            }
+            (Method::DELETE, path) if path.starts_with("/admin/namespaces/") => {
+                let namespace = &path["/admin/namespaces/".len()..];
+                let (freed, removed) = self.source_manager.purge_namespace(namespace).await;
+                Ok(json_response(StatusCode::OK, json!({ "namespace": namespace, "freed_bytes": freed, "removed_entries": removed })))
+            }
+            (method @ (Method::GET | Method::PUT | Method::DELETE), "/admin/meta") => {
+                let url = match query_param(&req, "url") {
+                    Some(url) => url,
+                    None => return Ok(json_response(StatusCode::BAD_REQUEST, json!({ "error": "缺少 url 参数" }))),
+                };
+                let not_found = || json_response(StatusCode::NOT_FOUND, json!({ "error": format!("没有缓存条目: {}", url) }));
+                match method {
+                    Method::GET => match self.source_manager.user_meta(&url).await {
+                        Some(user) => Ok(json_response(StatusCode::OK, json!({ "url": url, "meta": user }))),
+                        None => Ok(not_found()),
+                    },
+                    Method::PUT => {
+                        let body = hyper::body::to_bytes(req.into_body()).await?;
+                        let user: serde_json::Value = match serde_json::from_slice(&body) {
+                            Ok(user) => user,
+                            Err(e) => return Ok(json_response(StatusCode::BAD_REQUEST, json!({ "error": format!("无效的 JSON: {}", e) }))),
+                        };
+                        match self.source_manager.set_user_meta(&url, Some(user)).await {
+                            Ok(true) => Ok(json_response(StatusCode::OK, json!({ "ok": true }))),
+                            Ok(false) => Ok(not_found()),
+                            Err(e) => Ok(json_response(e.status_code(), json!({ "error": e.to_string() }))),
+                        }
+                    }
+                    _ => match self.source_manager.set_user_meta(&url, None).await? {
+                        true => Ok(json_response(StatusCode::OK, json!({ "ok": true }))),
+                        false => Ok(not_found()),
+                    },
+                }
+            }
+            (Method::GET, "/admin/stats/top") => {
+                let limit = query_param(&req, "n").and_then(|n| n.parse().ok()).unwrap_or(DEFAULT_TOP_N);
+                let order = match query_param(&req, "by") {
+                    Some(by) => match StatsOrder::parse(&by) {
+                        Some(order) => order,
+                        None => return Ok(json_response(StatusCode::BAD_REQUEST, json!({ "error": format!("未知的排序字段: {}", by) }))),
+                    },
+                    None => StatsOrder::Requests,
+                };
+                Ok(json_response(StatusCode::OK, json!({ "urls": self.source_manager.top_stats(limit, order) })))
+            }
+            (Method::GET, "/admin/usage") => {
+                let since = query_param(&req, "since");
+                let host = query_param(&req, "host");
+                let records = self.source_manager.usage(since.as_deref(), host.as_deref());
+                let upstream: u64 = records.iter().map(|record| record.upstream_bytes).sum();
+                let downstream: u64 = records.iter().map(|record| record.downstream_bytes).sum();
+                Ok(json_response(StatusCode::OK, json!({
+                    "upstream_bytes": upstream,
+                    "downstream_bytes": downstream,
+                    "saved_bytes": downstream.saturating_sub(upstream),
+                    "days": records,
+                })))
+            }
+            (Method::GET, "/admin/sessions") => {
+                let sessions = match &self.sessions {
+                    Some(sessions) => sessions,
+                    None => return Ok(json_response(StatusCode::NOT_FOUND, json!({ "error": "未开启会话统计" }))),
+                };
+                if let Some(id) = query_param(&req, "id") {
+                    return Ok(match sessions.session(&id) {
+                        Some(session) => json_response(StatusCode::OK, json!(session)),
+                        None => json_response(StatusCode::NOT_FOUND, json!({ "error": format!("没有会话: {}", id) })),
+                    });
+                }
+                let limit = query_param(&req, "n").and_then(|n| n.parse().ok()).unwrap_or(DEFAULT_SESSIONS);
+                Ok(json_response(StatusCode::OK, json!({ "sessions": sessions.sessions(limit) })))
+            }
+            (Method::GET, "/admin/stats/clients") => {
+                Ok(json_response(StatusCode::OK, json!(self.source_manager.slow_clients())))
+            }
+            (Method::GET, "/admin/stats/compression") => {
+                Ok(json_response(StatusCode::OK, json!({ "algorithms": self.source_manager.stats().compression_summary() })))
+            }
+            (Method::GET, "/admin/storage/permits") => {
+                Ok(json_response(StatusCode::OK, json!(self.source_manager.storage_permits())))
+            }
+            (Method::GET, "/admin/caching") => {
+                Ok(json_response(StatusCode::OK, json!({
+                    "paused": self.source_manager.is_caching_paused(),
+                    "online": self.source_manager.is_cache_online(),
+                })))
+            }
+            (Method::POST, "/admin/caching/pause") | (Method::POST, "/admin/caching/resume") => {
+                let paused = path.ends_with("/pause");
+                self.source_manager.set_caching_paused(paused);
+                Ok(json_response(StatusCode::OK, json!({ "paused": paused })))
+            }
+            (Method::GET, "/admin/active") => {
+                let downloads = self.source_manager.active_downloads();
+                Ok(json_response(StatusCode::OK, json!({ "downloads": downloads })))
+            }
+            (Method::DELETE, path) if path.starts_with("/admin/active/") => {
+                let id = match path["/admin/active/".len()..].parse::<u64>() {
+                    Ok(id) => id,
+                    Err(_) => return Ok(json_response(StatusCode::BAD_REQUEST, json!({ "error": "无效的下载 ID" }))),
+                };
+                if self.source_manager.cancel_download(id) {
+                    Ok(json_response(StatusCode::OK, json!({ "cancelled": id })))
+                } else {
+                    Ok(json_response(StatusCode::NOT_FOUND, json!({ "error": format!("下载不存在: {}", id) })))
+                }
+            }
+            _ => Ok(json_response(StatusCode::NOT_FOUND, json!({ "error": format!("未知的管理接口: {}", path) }))),
+        }
+    }
+
+    /// 没有配置共享密钥时节点间接口不存在；密钥不匹配的请求返回 403
+    fn reject_peer(&self, req: &Request<Body>) -> Option<Response<Body>> {
+        let path = req.uri().path();
+        match &self.peer_secret {
+            None => Some(json_response(StatusCode::NOT_FOUND, json!({ "error": format!("未知的管理接口: {}", path) }))),
+            Some(secret) if !is_peer_authorized(req.headers(), secret) => {
+                log_info!("Cluster", "拒绝未授权的节点请求: {}", path);
+                Some(json_response(StatusCode::FORBIDDEN, json!({ "error": "节点密钥无效" })))
+            }
+            Some(_) => None,
+        }
+    }
+}
+
+/// 需要管理令牌的接口：所有写操作，以及返回源站 URL（可能带 CDN 令牌）的查询。
+/// 节点间接口使用共享密钥单独校验
+fn is_protected(req: &Request<Body>) -> bool {
+    let path = req.uri().path();
+    if matches!(path, AVAILABILITY_PATH | RECEIVE_ENTRY_PATH | RECEIVE_META_PATH) {
+        return false;
+    }
+    match *req.method() {
+        Method::GET | Method::HEAD => {
+            matches!(path, "/admin/stats/top" | "/admin/usage" | "/admin/sessions" | "/admin/active" | "/admin/meta")
+        }
+        _ => true,
+    }
+}
+
+/// 读取查询参数
+fn query_param(req: &Request<Body>, name: &str) -> Option<String> {
+    let query = req.uri().query()?;
+    url::form_urlencoded::parse(query.as_bytes())
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.into_owned())
+}
+
+fn json_response(status: StatusCode, value: serde_json::Value) -> Response<Body> {
+    let mut response = Response::new(Body::from(value.to_string()));
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, "application/json".parse().unwrap());
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: Method, uri: &str, client: Option<&str>, auth: Option<&str>) -> Request<Body> {
+        let mut builder = Request::builder().method(method).uri(uri);
+        if let Some(auth) = auth {
+            builder = builder.header(AUTHORIZATION, auth);
+        }
+        let mut req = builder.body(Body::empty()).unwrap();
+        if let Some(client) = client {
+            req.extensions_mut().insert(client.parse::<SocketAddr>().unwrap());
+        }
+        req
+    }
+
+    #[test]
+    fn test_is_protected() {
+        assert!(is_protected(&request(Method::POST, "/admin/prefetch", None, None)));
+        assert!(is_protected(&request(Method::POST, "/admin/cleanup", None, None)));
+        assert!(is_protected(&request(Method::POST, "/admin/caching/pause", None, None)));
+        assert!(is_protected(&request(Method::POST, "/admin/verify?repair=true", None, None)));
+        assert!(is_protected(&request(Method::PUT, "/admin/meta?url=x", None, None)));
+        assert!(is_protected(&request(Method::GET, "/admin/meta?url=x", None, None)));
+        assert!(is_protected(&request(Method::DELETE, "/admin/namespaces/alice", None, None)));
+        assert!(!is_protected(&request(Method::GET, "/admin/namespaces/alice", None, None)));
+        assert!(is_protected(&request(Method::DELETE, "/admin/active/3", None, None)));
+        assert!(is_protected(&request(Method::GET, "/admin/active", None, None)));
+        assert!(is_protected(&request(Method::GET, "/admin/stats/top?n=5", None, None)));
+        assert!(is_protected(&request(Method::GET, "/admin/usage", None, None)));
+        assert!(is_protected(&request(Method::GET, "/admin/sessions", None, None)));
+        assert!(!is_protected(&request(Method::GET, "/admin/caching", None, None)));
+        assert!(!is_protected(&request(Method::GET, READY_PATH, None, None)));
+        assert!(!is_protected(&request(Method::POST, RECEIVE_META_PATH, None, None)));
+    }
+
+    #[test]
+    fn test_admin_authorizes() {
+        let local = AdminConfig::default();
+        assert!(local.authorizes(&request(Method::POST, "/admin/cleanup", Some("127.0.0.1:5000"), None)));
+        assert!(local.authorizes(&request(Method::POST, "/admin/cleanup", Some("[::1]:5000"), None)));
+        assert!(!local.authorizes(&request(Method::POST, "/admin/cleanup", Some("192.168.1.20:5000"), None)));
+        assert!(!local.authorizes(&request(Method::POST, "/admin/cleanup", None, None)));
+
+        let token = AdminConfig { token: Some("s3cret".to_string()) };
+        assert!(token.authorizes(&request(Method::POST, "/admin/cleanup", Some("192.168.1.20:5000"), Some("Bearer s3cret"))));
+        assert!(!token.authorizes(&request(Method::POST, "/admin/cleanup", Some("192.168.1.20:5000"), Some("Bearer s3cre"))));
+        assert!(!token.authorizes(&request(Method::POST, "/admin/cleanup", Some("127.0.0.1:5000"), None)));
+    }
+}
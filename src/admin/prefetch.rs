@@ -0,0 +1,119 @@
+use std::path::Path;
+use std::sync::Arc;
+use futures::StreamExt;
+use crate::data_source_manager::DataSourceManager;
+use crate::hls::DefaultHlsHandler;
+use crate::utils::error::Result;
+use crate::log_info;
+
+/// 同时预取的 URL 数量
+const PREFETCH_CONCURRENCY: usize = 2;
+
+/// 后台预取 URL 到缓存，m3u8 地址会展开为全部分片
+pub struct Prefetcher {
+    source_manager: Arc<DataSourceManager>,
+    hls_handler: Arc<DefaultHlsHandler>,
+}
+
+impl Prefetcher {
+    pub fn new(source_manager: Arc<DataSourceManager>, hls_handler: Arc<DefaultHlsHandler>) -> Self {
+        Self {
+            source_manager,
+            hls_handler,
+        }
+    }
+
+    /// 提交预取任务，立即返回
+    pub fn submit(self: &Arc<Self>, urls: Vec<String>) {
+        let prefetcher = self.clone();
+        tokio::spawn(async move {
+            let results: Vec<bool> = futures::stream::iter(urls)
+                .map(|url| {
+                    let prefetcher = prefetcher.clone();
+                    async move { prefetcher.prefetch_url(&url).await }
+                })
+                .buffer_unordered(PREFETCH_CONCURRENCY)
+                .collect()
+                .await;
+            let succeeded = results.iter().filter(|ok| **ok).count();
+            log_info!("Prefetch", "预取完成: 成功 {} 个, 失败 {} 个", succeeded, results.len() - succeeded);
+        });
+    }
+
+    /// 读取预热文件并提交预取，每行一个 URL，`#` 开头为注释
+    pub async fn warmup_from_file(self: &Arc<Self>, path: &Path) -> Result<usize> {
+        let content = tokio::fs::read_to_string(path).await?;
+        let urls = parse_url_list(&content);
+        log_info!("Prefetch", "读取预热文件: {:?} 共 {} 个 URL", path, urls.len());
+        let count = urls.len();
+        self.submit(urls);
+        Ok(count)
+    }
+
+    async fn prefetch_url(&self, url: &str) -> bool {
+        let urls = if is_playlist(url) {
+            match self.hls_handler.segment_urls(url).await {
+                Ok(urls) => urls,
+                Err(e) => {
+                    log_info!("Prefetch", "解析播放列表失败: {} - {}", url, e);
+                    return false;
+                }
+            }
+        } else {
+            vec![url.to_string()]
+        };
+
+        for url in &urls {
+            match self.source_manager.prefetch(url).await {
+                Ok(bytes) => log_info!("Prefetch", "已预取: {} {} 字节", url, bytes),
+                Err(e) => {
+                    log_info!("Prefetch", "预取失败: {} - {}", url, e);
+                    return false;
+                }
+            }
+        }
+        true
+    }
+}
+
+fn is_playlist(url: &str) -> bool {
+    url.split(['?', '#']).next().unwrap_or(url).ends_with(".m3u8")
+}
+
+/// 解析 URL 列表：JSON 数组、`{"urls": [...]}` 或每行一个 URL
+pub fn parse_url_list(content: &str) -> Vec<String> {
+    if let Ok(value) = serde_json::from_str::<serde_json::Value>(content) {
+        let items = match &value {
+            serde_json::Value::Array(items) => Some(items),
+            serde_json::Value::Object(map) => map.get("urls").and_then(|urls| urls.as_array()),
+            _ => None,
+        };
+        if let Some(items) = items {
+            return items.iter().filter_map(|item| item.as_str()).map(|url| url.to_string()).collect();
+        }
+    }
+
+    content
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_url_list() {
+        let text = "# 今晚的电影\nhttp://example.com/a.mp4\n\n  http://example.com/b.m3u8  \n";
+        assert_eq!(parse_url_list(text), vec!["http://example.com/a.mp4", "http://example.com/b.m3u8"]);
+
+        let json = r#"{"urls": ["http://example.com/a.mp4"]}"#;
+        assert_eq!(parse_url_list(json), vec!["http://example.com/a.mp4"]);
+
+        let json = r#"["http://example.com/a.mp4", "http://example.com/b.mp4"]"#;
+        assert_eq!(parse_url_list(json).len(), 2);
+    }
+}
@@ -0,0 +1,403 @@
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use bytes::Bytes;
+use hyper::body::HttpBody;
+use hyper::header::RANGE;
+use hyper::{Body, Client, Request};
+use crate::data_source_manager::CACHE_STATUS_HEADER;
+use crate::handlers::CacheHandler;
+use crate::storage::{CacheEngine, FsyncPolicy, ShardLayout, StorageConfig, StorageManager, StorageManagerConfig};
+use crate::utils::error::{ProxyError, Result};
+
+/// 压测配置
+#[derive(Clone)]
+pub struct BenchConfig {
+    /// 代理服务地址
+    pub target: String,
+    /// 通过代理请求的上游 URL，按顺序轮流使用
+    pub urls: Vec<String>,
+    /// 请求的字节范围，`None` 结束位置表示到文件末尾，为空时请求完整内容
+    pub ranges: Vec<(u64, Option<u64>)>,
+    pub concurrency: usize,
+    /// 总请求数
+    pub requests: usize,
+}
+
+impl Default for BenchConfig {
+    fn default() -> Self {
+        Self {
+            target: "http://127.0.0.1:8080".to_string(),
+            urls: Vec::new(),
+            ranges: Vec::new(),
+            concurrency: 8,
+            requests: 100,
+        }
+    }
+}
+
+impl BenchConfig {
+    /// 解析命令行参数：`--target`、`--url`（可重复）、`--ranges`、`--concurrency`、`--requests`
+    pub fn from_args(args: &[String]) -> Result<Self> {
+        let mut config = Self::default();
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            let mut value = || {
+                iter.next()
+                    .cloned()
+                    .ok_or_else(|| ProxyError::request(format!("参数缺少取值: {}", arg)))
+            };
+            match arg.as_str() {
+                "--target" => config.target = value()?.trim_end_matches('/').to_string(),
+                "--url" => config.urls.push(value()?),
+                "--ranges" => config.ranges = parse_ranges(&value()?)?,
+                "--concurrency" => config.concurrency = value()?.parse()?,
+                "--requests" => config.requests = value()?.parse()?,
+                other => return Err(ProxyError::request(format!("未知参数: {}", other))),
+            }
+        }
+        if config.urls.is_empty() {
+            return Err(ProxyError::request("至少需要一个 --url".to_string()));
+        }
+        config.concurrency = config.concurrency.max(1);
+        Ok(config)
+    }
+}
+
+/// 单个请求的结果
+struct Sample {
+    ttfb: Duration,
+    bytes: u64,
+    cache_status: Option<String>,
+}
+
+/// 压测结果
+#[derive(Debug, Clone, Default)]
+pub struct BenchReport {
+    pub requests: usize,
+    pub errors: usize,
+    pub bytes: u64,
+    pub elapsed: Duration,
+    pub ttfb_p50: Duration,
+    pub ttfb_p90: Duration,
+    pub ttfb_p99: Duration,
+    pub hits: usize,
+    pub partial_hits: usize,
+}
+
+impl BenchReport {
+    /// 吞吐量（字节/秒）
+    pub fn throughput(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 { self.bytes as f64 / secs } else { 0.0 }
+    }
+
+    /// 完全命中缓存的比例
+    pub fn hit_ratio(&self) -> f64 {
+        let succeeded = self.requests - self.errors;
+        if succeeded > 0 { self.hits as f64 / succeeded as f64 } else { 0.0 }
+    }
+}
+
+/// 按配置向代理发送范围请求并统计结果
+pub async fn run(config: BenchConfig) -> BenchReport {
+    let client = Client::new();
+    let config = Arc::new(config);
+    let next = Arc::new(AtomicUsize::new(0));
+    let started = Instant::now();
+
+    let workers: Vec<_> = (0..config.concurrency)
+        .map(|_| {
+            let client = client.clone();
+            let config = config.clone();
+            let next = next.clone();
+            tokio::spawn(async move {
+                let mut samples = Vec::new();
+                loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    if index >= config.requests {
+                        break;
+                    }
+                    let url = &config.urls[index % config.urls.len()];
+                    let range = if config.ranges.is_empty() {
+                        None
+                    } else {
+                        Some(config.ranges[index % config.ranges.len()])
+                    };
+                    samples.push(send(&client, &config.target, url, range).await);
+                }
+                samples
+            })
+        })
+        .collect();
+
+    let mut samples = Vec::new();
+    for worker in workers {
+        if let Ok(worker_samples) = worker.await {
+            samples.extend(worker_samples);
+        }
+    }
+
+    let mut report = BenchReport {
+        requests: samples.len(),
+        elapsed: started.elapsed(),
+        ..Default::default()
+    };
+    let mut ttfbs = Vec::new();
+    for sample in samples {
+        match sample {
+            Ok(sample) => {
+                report.bytes += sample.bytes;
+                ttfbs.push(sample.ttfb);
+                match sample.cache_status.as_deref() {
+                    Some("HIT") => report.hits += 1,
+                    Some("PARTIAL") => report.partial_hits += 1,
+                    _ => {}
+                }
+            }
+            Err(_) => report.errors += 1,
+        }
+    }
+    ttfbs.sort_unstable();
+    report.ttfb_p50 = percentile(&ttfbs, 50.0);
+    report.ttfb_p90 = percentile(&ttfbs, 90.0);
+    report.ttfb_p99 = percentile(&ttfbs, 99.0);
+    report
+}
+
+async fn send(
+    client: &Client<hyper::client::HttpConnector>,
+    target: &str,
+    url: &str,
+    range: Option<(u64, Option<u64>)>,
+) -> Result<Sample> {
+    let mut builder = Request::builder()
+        .method("GET")
+        .uri(format!("{}/proxy/{}", target, urlencoding::encode(url)));
+    if let Some((start, end)) = range {
+        let end = end.map(|end| end.to_string()).unwrap_or_default();
+        builder = builder.header(RANGE, format!("bytes={}-{}", start, end));
+    }
+
+    let started = Instant::now();
+    let response = client.request(builder.body(Body::empty())?).await?;
+    if !response.status().is_success() {
+        return Err(ProxyError::network(format!("响应状态码: {}", response.status())));
+    }
+    let cache_status = response
+        .headers()
+        .get(CACHE_STATUS_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string());
+
+    let mut body = response.into_body();
+    let mut ttfb = None;
+    let mut bytes = 0;
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        ttfb.get_or_insert_with(|| started.elapsed());
+        bytes += chunk.len() as u64;
+    }
+
+    Ok(Sample {
+        ttfb: ttfb.unwrap_or_else(|| started.elapsed()),
+        bytes,
+        cache_status,
+    })
+}
+
+/// 解析范围列表，如 `0-1048575,1048576-,5000000-5999999`
+pub fn parse_ranges(value: &str) -> Result<Vec<(u64, Option<u64>)>> {
+    value
+        .split(',')
+        .map(|item| item.trim())
+        .filter(|item| !item.is_empty())
+        .map(|item| {
+            let (start, end) = item
+                .split_once('-')
+                .ok_or_else(|| ProxyError::invalid_range(format!("无效的范围: {}", item)))?;
+            let start = start.parse::<u64>()?;
+            let end = if end.is_empty() { None } else { Some(end.parse::<u64>()?) };
+            if end.is_some_and(|end| end < start) {
+                return Err(ProxyError::invalid_range(format!("无效的范围: {}", item)));
+            }
+            Ok((start, end))
+        })
+        .collect()
+}
+
+/// 磁盘写入压测配置
+#[derive(Clone)]
+pub struct DiskBenchConfig {
+    /// 压测使用的缓存目录，每种方式使用一个子目录，结束后删除
+    pub dir: PathBuf,
+    /// 每种方式写入的字节数
+    pub size: u64,
+    /// 模拟上游响应的数据块大小
+    pub chunk_size: usize,
+}
+
+impl Default for DiskBenchConfig {
+    fn default() -> Self {
+        Self {
+            dir: std::env::temp_dir().join("proxy-server-disk-bench"),
+            size: 256 * 1024 * 1024, // 256MB
+            chunk_size: 16 * 1024,   // 16KB
+        }
+    }
+}
+
+impl DiskBenchConfig {
+    /// 解析命令行参数：`--dir`、`--size`（MB）、`--chunk`（KB）
+    pub fn from_args(args: &[String]) -> Result<Self> {
+        let mut config = Self::default();
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            let mut value = || {
+                iter.next()
+                    .cloned()
+                    .ok_or_else(|| ProxyError::request(format!("参数缺少取值: {}", arg)))
+            };
+            match arg.as_str() {
+                "--dir" => config.dir = PathBuf::from(value()?),
+                "--size" => config.size = value()?.parse::<u64>()? * 1024 * 1024,
+                "--chunk" => config.chunk_size = value()?.parse::<usize>()? * 1024,
+                other => return Err(ProxyError::request(format!("未知参数: {}", other))),
+            }
+        }
+        if config.size == 0 || config.chunk_size == 0 {
+            return Err(ProxyError::request("--size 和 --chunk 必须大于 0".to_string()));
+        }
+        Ok(config)
+    }
+}
+
+/// 一种写入方式的结果
+#[derive(Debug, Clone)]
+pub struct DiskBenchResult {
+    pub mode: &'static str,
+    pub bytes: u64,
+    pub elapsed: Duration,
+}
+
+impl DiskBenchResult {
+    /// 吞吐量（字节/秒）
+    pub fn throughput(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 { self.bytes as f64 / secs } else { 0.0 }
+    }
+}
+
+/// 按不同方式将同样的数据写入缓存并计时：逐批重新打开文件写入（不同步），以及连续写入下的各种 fsync 策略
+pub async fn run_disk(config: &DiskBenchConfig) -> Result<Vec<DiskBenchResult>> {
+    let modes = [
+        ("逐批重新打开", None),
+        ("每批同步", Some(FsyncPolicy::EveryChunk)),
+        ("每 8MB 同步", Some(FsyncPolicy::EveryBytes(8 * 1024 * 1024))),
+        ("结束时同步", Some(FsyncPolicy::OnCompletion)),
+    ];
+    let chunk = Bytes::from(vec![0x5a; config.chunk_size]);
+    let chunks = config.size.div_ceil(config.chunk_size as u64) as usize;
+    let key = "https://bench.example.com/video.mp4";
+
+    let mut results = Vec::new();
+    for (index, (mode, fsync)) in modes.into_iter().enumerate() {
+        let dir = config.dir.join(index.to_string());
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+        let storage_config = StorageConfig {
+            root_path: dir.clone(),
+            chunk_size: 8192,
+            fast_root_path: None,
+            layout: ShardLayout::default(),
+            mmap: false,
+            mmap_threshold: u64::MAX,
+        };
+        let manager_config = StorageManagerConfig {
+            max_cache_size: config.size.saturating_mul(2),
+            reserved_free_space: 0,
+            mount_check_interval: None,
+            fsync: fsync.unwrap_or_default(),
+            ..Default::default()
+        };
+        let manager = Arc::new(StorageManager::new(CacheEngine::new(storage_config), manager_config));
+        while manager.is_index_loading() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let bytes = chunks as u64 * config.chunk_size as u64;
+        let started = Instant::now();
+        match fsync {
+            // 原来的写入方式：每 64KB 调用一次 write_bytes，每次重新打开、定位并 flush
+            None => {
+                let mut buffer = Vec::new();
+                let mut written = 0u64;
+                for _ in 0..chunks {
+                    buffer.extend_from_slice(&chunk);
+                    if buffer.len() >= 64 * 1024 {
+                        let data = Bytes::from(std::mem::take(&mut buffer));
+                        written += manager.write_bytes(key, data, (written, bytes - 1)).await?;
+                    }
+                }
+                if !buffer.is_empty() {
+                    manager.write_bytes(key, Bytes::from(buffer), (written, bytes - 1)).await?;
+                }
+            }
+            Some(_) => {
+                let stream = futures::stream::iter((0..chunks).map({
+                    let chunk = chunk.clone();
+                    move |_| Ok(chunk.clone())
+                }));
+                CacheHandler::new(manager.clone()).write_stream(key, (0, bytes - 1), Box::pin(stream)).await?;
+            }
+        }
+        results.push(DiskBenchResult {
+            mode,
+            bytes,
+            elapsed: started.elapsed(),
+        });
+
+        drop(manager);
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+    }
+    Ok(results)
+}
+
+/// 已排序样本的百分位数
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ranges() {
+        let ranges = parse_ranges("0-1023, 1024-,2048-4095").unwrap();
+        assert_eq!(ranges, vec![(0, Some(1023)), (1024, None), (2048, Some(4095))]);
+        assert!(parse_ranges("100-10").is_err());
+        assert!(parse_ranges("abc").is_err());
+    }
+
+    #[test]
+    fn test_disk_bench_args() {
+        let args: Vec<String> = ["--size", "64", "--chunk", "32"].iter().map(|arg| arg.to_string()).collect();
+        let config = DiskBenchConfig::from_args(&args).unwrap();
+        assert_eq!(config.size, 64 * 1024 * 1024);
+        assert_eq!(config.chunk_size, 32 * 1024);
+        assert!(DiskBenchConfig::from_args(&["--size".to_string(), "0".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_percentile() {
+        let samples: Vec<Duration> = (1..=100).map(Duration::from_millis).collect();
+        assert_eq!(percentile(&samples, 50.0), Duration::from_millis(50));
+        assert_eq!(percentile(&samples, 99.0), Duration::from_millis(99));
+        assert_eq!(percentile(&[], 90.0), Duration::ZERO);
+    }
+}
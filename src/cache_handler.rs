@@ -85,7 +85,7 @@ impl CacheHandler {
                     }
                     Err(e) => {
                         log_info!("Cache", "写入缓存失败: {} - {}", key, e);
-                        return Err(ProxyError::Cache(format!("写入缓存失败: {}", e)));
+                        return Err(ProxyError::cache(format!("写入缓存失败: {}", e)));
                     }
                 }
             }
@@ -104,7 +104,7 @@ impl CacheHandler {
                 }
                 Err(e) => {
                     log_info!("Cache", "写入最后的数据块失败: {} - {}", key, e);
-                    return Err(ProxyError::Cache(format!("写入最后的数据块失败: {}", e)));
+                    return Err(ProxyError::cache(format!("写入最后的数据块失败: {}", e)));
                 }
             }
         }
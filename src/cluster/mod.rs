@@ -0,0 +1,159 @@
+mod replicate;
+mod ring;
+mod shard;
+mod shield;
+mod sibling;
+
+pub use replicate::{ConflictRule, ReplicationConfig, Replicator};
+pub use ring::HashRing;
+pub use shard::ShardRouter;
+pub use shield::ParentShield;
+pub use sibling::SiblingLookup;
+
+use std::time::Duration;
+use hyper::client::HttpConnector;
+use hyper::{Body, Client, HeaderMap, Request};
+use serde::{Deserialize, Serialize};
+use crate::utils::error::Result;
+
+/// 节点间请求携带的请求头，收到该请求头的节点不再向其他节点转发，避免循环
+pub const PEER_HEADER: &str = "x-proxy-peer";
+
+/// 节点间内部接口携带的共享密钥
+pub const PEER_SECRET_HEADER: &str = "x-proxy-peer-secret";
+
+/// 经过的缓存节点列表，用于发现上级缓存之间的环路
+pub const LOOP_DETECT_HEADER: &str = "x-loop-detect";
+
+/// 查询本节点缓存范围的内部接口
+pub const AVAILABILITY_PATH: &str = "/admin/peer/available";
+
+/// 接收其他节点推送数据的内部接口
+pub const RECEIVE_ENTRY_PATH: &str = "/admin/peer/entry";
+
+/// 接收其他节点推送元数据的内部接口
+pub const RECEIVE_META_PATH: &str = "/admin/peer/meta";
+
+/// 节点上某个缓存键的缓存情况
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EntryAvailability {
+    /// 已缓存的连续字节数（从 0 开始）
+    pub cached_bytes: u64,
+    pub total_size: Option<u64>,
+    #[serde(default)]
+    pub etag: Option<String>,
+}
+
+impl EntryAvailability {
+    /// 是否完整缓存了 `[start, end]`，`end` 为 `u64::MAX` 表示到文件末尾
+    pub fn covers(&self, start: u64, end: u64) -> bool {
+        let end = if end == u64::MAX {
+            match self.total_size {
+                Some(total) if total > 0 => total - 1,
+                _ => return false,
+            }
+        } else {
+            end
+        };
+        start <= end && end < self.cached_bytes
+    }
+}
+
+/// 集群配置
+#[derive(Clone)]
+pub struct ClusterConfig {
+    /// 同组节点地址，如 `http://192.168.1.11:8080`，本地未命中时先向它们查找；需要同时配置 `peer_secret`
+    pub siblings: Vec<String>,
+    /// 查询同组节点缓存范围的超时时间
+    pub lookup_timeout: Duration,
+    /// 分片节点地址（包括本节点），按一致性哈希分配缓存键
+    pub nodes: Vec<String>,
+    /// 本节点在 `nodes` 中的地址，未设置时不启用分片
+    pub self_node: Option<String>,
+    /// 每个节点在哈希环上的虚拟节点数
+    pub virtual_nodes: usize,
+    /// 向其他节点同步缓存
+    pub replication: ReplicationConfig,
+    /// 上级缓存代理地址，本地未命中时向它请求而不是源站
+    pub parent: Option<String>,
+    /// 本节点标识，用于环路检测，未设置时使用 `self_node` 或随机生成
+    pub node_id: Option<String>,
+    /// 节点间内部接口的共享密钥，未设置时不开放 `/admin/peer/` 接口，也不向其他节点推送缓存
+    pub peer_secret: Option<String>,
+}
+
+impl Default for ClusterConfig {
+    fn default() -> Self {
+        Self {
+            siblings: Vec::new(),
+            lookup_timeout: Duration::from_millis(500),
+            nodes: Vec::new(),
+            self_node: None,
+            virtual_nodes: 160,
+            replication: ReplicationConfig::default(),
+            parent: None,
+            node_id: None,
+            peer_secret: None,
+        }
+    }
+}
+
+/// 节点上通过 `/proxy/` 访问上游 URL 的地址
+pub fn peer_proxy_url(peer: &str, url: &str) -> String {
+    format!("{}/proxy/{}", peer.trim_end_matches('/'), urlencoding::encode(url))
+}
+
+/// 请求是否携带了正确的节点共享密钥，逐字节比较全部内容，耗时与不匹配的位置无关
+pub fn is_peer_authorized(headers: &HeaderMap, secret: &str) -> bool {
+    headers.get(PEER_SECRET_HEADER).is_some_and(|value| {
+        let value = value.as_bytes();
+        value.len() == secret.len()
+            && value.iter().zip(secret.as_bytes()).fold(0u8, |diff, (a, b)| diff | (a ^ b)) == 0
+    })
+}
+
+/// 查询节点上某个 URL 的缓存情况
+pub async fn fetch_availability(client: &Client<HttpConnector>, node: &str, secret: &str, url: &str) -> Result<EntryAvailability> {
+    let req = Request::builder()
+        .method("GET")
+        .uri(format!("{}{}?url={}", node.trim_end_matches('/'), AVAILABILITY_PATH, urlencoding::encode(url)))
+        .header(PEER_HEADER, "1")
+        .header(PEER_SECRET_HEADER, secret)
+        .body(Body::empty())?;
+    let resp = client.request(req).await?;
+    let body = hyper::body::to_bytes(resp.into_body()).await?;
+    Ok(serde_json::from_slice(&body)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_covers() {
+        let availability = EntryAvailability { cached_bytes: 1000, total_size: Some(1000), etag: None };
+        assert!(availability.covers(0, 999));
+        assert!(availability.covers(500, u64::MAX));
+        assert!(!availability.covers(0, 1000));
+
+        let partial = EntryAvailability { cached_bytes: 500, total_size: Some(1000), etag: None };
+        assert!(partial.covers(0, 499));
+        assert!(!partial.covers(0, u64::MAX));
+    }
+
+    #[test]
+    fn test_peer_authorized() {
+        let mut headers = HeaderMap::new();
+        assert!(!is_peer_authorized(&headers, "secret"));
+
+        headers.insert(PEER_HEADER, "1".parse().unwrap());
+        assert!(!is_peer_authorized(&headers, "secret"));
+
+        headers.insert(PEER_SECRET_HEADER, "secreT".parse().unwrap());
+        assert!(!is_peer_authorized(&headers, "secret"));
+        headers.insert(PEER_SECRET_HEADER, "secret2".parse().unwrap());
+        assert!(!is_peer_authorized(&headers, "secret"));
+        headers.insert(PEER_SECRET_HEADER, "secret".parse().unwrap());
+        assert!(is_peer_authorized(&headers, "secret"));
+    }
+}
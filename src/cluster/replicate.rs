@@ -0,0 +1,202 @@
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use futures::StreamExt;
+use hyper::client::HttpConnector;
+use hyper::header::{CONTENT_TYPE, ETAG};
+use hyper::{Body, Client, Request, StatusCode};
+use crate::admin::parse_url_list;
+use crate::handlers::CacheHandler;
+use crate::utils::error::{ProxyError, Result};
+use crate::log_info;
+use super::{fetch_availability, PEER_HEADER, PEER_SECRET_HEADER, RECEIVE_ENTRY_PATH, RECEIVE_META_PATH};
+
+/// 目标节点已有同一 URL 的不同内容时的处理方式
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConflictRule {
+    /// 保留目标节点的数据
+    Skip,
+    /// 用本节点的数据覆盖
+    Overwrite,
+}
+
+/// 缓存同步配置
+#[derive(Clone)]
+pub struct ReplicationConfig {
+    /// 目标节点地址，未设置时不同步
+    pub peer: Option<String>,
+    /// 同步间隔
+    pub interval: Duration,
+    pub conflict: ConflictRule,
+    /// 固定同步的 URL 列表文件，每行一个 URL
+    pub pin_file: Option<PathBuf>,
+}
+
+impl Default for ReplicationConfig {
+    fn default() -> Self {
+        Self {
+            peer: None,
+            interval: Duration::from_secs(30),
+            conflict: ConflictRule::Skip,
+            pin_file: None,
+        }
+    }
+}
+
+/// 将下载完成的缓存推送到目标节点，中断后从目标节点已有的位置继续
+pub struct Replicator {
+    config: ReplicationConfig,
+    /// 节点共享密钥，目标节点据此接受推送
+    secret: Option<String>,
+    cache_handler: Arc<CacheHandler>,
+    client: Client<HttpConnector>,
+    pending: Mutex<VecDeque<String>>,
+}
+
+impl Replicator {
+    /// 目标节点只接受携带共享密钥的推送，没有配置 `secret` 时不启用同步
+    pub fn new(config: ReplicationConfig, secret: Option<String>, cache_handler: Arc<CacheHandler>) -> Arc<Self> {
+        let replicator = Arc::new(Self {
+            config,
+            secret,
+            cache_handler,
+            client: Client::new(),
+            pending: Mutex::new(VecDeque::new()),
+        });
+        if let Some(peer) = &replicator.config.peer {
+            if replicator.secret.is_some() {
+                log_info!("Cluster", "启用缓存同步: {}", peer);
+                replicator.clone().start();
+            } else {
+                log_info!("Cluster", "没有配置节点共享密钥，不启用缓存同步: {}", peer);
+            }
+        }
+        replicator
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.config.peer.is_some() && self.secret.is_some()
+    }
+
+    /// 登记下载完成的缓存键，下一轮同步时推送
+    pub fn enqueue(&self, key: &str) {
+        if self.is_enabled() {
+            self.pending.lock().unwrap().push_back(key.to_string());
+        }
+    }
+
+    fn start(self: Arc<Self>) {
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(self.config.interval).await;
+                self.sync_round().await;
+            }
+        });
+    }
+
+    async fn sync_round(&self) {
+        let (peer, secret) = match (&self.config.peer, &self.secret) {
+            (Some(peer), Some(secret)) => (peer.trim_end_matches('/'), secret.as_str()),
+            _ => return,
+        };
+
+        let mut keys: Vec<String> = self.pending.lock().unwrap().drain(..).collect();
+        if let Some(path) = &self.config.pin_file {
+            match tokio::fs::read_to_string(path).await {
+                Ok(content) => keys.extend(parse_url_list(&content)),
+                Err(e) => log_info!("Cluster", "读取同步列表失败: {:?} - {}", path, e),
+            }
+        }
+        keys.sort();
+        keys.dedup();
+
+        for key in keys {
+            match self.sync_entry(peer, secret, &key).await {
+                Ok(0) => {}
+                Ok(bytes) => log_info!("Cluster", "已同步: {} {} 字节 -> {}", key, bytes, peer),
+                Err(e) => {
+                    // 留到下一轮，从目标节点已有的位置继续
+                    log_info!("Cluster", "同步失败，稍后重试: {} - {}", key, e);
+                    self.pending.lock().unwrap().push_back(key);
+                }
+            }
+        }
+    }
+
+    /// 同步单个缓存键，返回推送的字节数
+    async fn sync_entry(&self, peer: &str, secret: &str, key: &str) -> Result<u64> {
+        let meta = match self.cache_handler.get_meta(key).await {
+            Some(meta) => meta,
+            None => return Ok(0),
+        };
+        let total = match meta.content_length {
+            Some(total) if total > 0 => total,
+            _ => return Ok(0),
+        };
+        // 只同步完整下载的数据
+        if self.cache_handler.get_size(key).await? != Some(total) {
+            return Ok(0);
+        }
+
+        let remote = fetch_availability(&self.client, peer, secret, key).await?;
+        let local_etag = meta.header_map().get(ETAG).and_then(|v| v.to_str().ok()).map(|v| v.to_string());
+        let conflict = remote.cached_bytes > 0
+            && (remote.total_size.is_some_and(|size| size != total)
+                || (remote.etag.is_some() && local_etag.is_some() && remote.etag != local_etag));
+
+        let offset = if conflict {
+            match self.config.conflict {
+                ConflictRule::Skip => {
+                    log_info!("Cluster", "目标节点内容不同，跳过: {}", key);
+                    return Ok(0);
+                }
+                ConflictRule::Overwrite => {
+                    log_info!("Cluster", "目标节点内容不同，覆盖: {}", key);
+                    0
+                }
+            }
+        } else if remote.cached_bytes >= total {
+            return Ok(0);
+        } else {
+            remote.cached_bytes
+        };
+
+        let stream = self
+            .cache_handler
+            .read(key, (offset, total - 1))
+            .await?
+            .map(|chunk| chunk.map_err(|e| std::io::Error::other(e.to_string())));
+        let req = Request::builder()
+            .method("POST")
+            .uri(format!(
+                "{}{}?url={}&offset={}&replace={}",
+                peer,
+                RECEIVE_ENTRY_PATH,
+                urlencoding::encode(key),
+                offset,
+                conflict
+            ))
+            .header(PEER_HEADER, "1")
+            .header(PEER_SECRET_HEADER, secret)
+            .body(Body::wrap_stream(stream))?;
+        let resp = self.client.request(req).await?;
+        if resp.status() != StatusCode::OK {
+            return Err(ProxyError::network(format!("目标节点拒绝数据: {}", resp.status())));
+        }
+
+        let req = Request::builder()
+            .method("POST")
+            .uri(format!("{}{}", peer, RECEIVE_META_PATH))
+            .header(PEER_HEADER, "1")
+            .header(PEER_SECRET_HEADER, secret)
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(meta.encode()?))?;
+        let resp = self.client.request(req).await?;
+        if resp.status() != StatusCode::OK {
+            return Err(ProxyError::network(format!("目标节点拒绝元数据: {}", resp.status())));
+        }
+
+        Ok(total - offset)
+    }
+}
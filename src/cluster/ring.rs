@@ -0,0 +1,78 @@
+/// 缓存键的一致性哈希环，每个节点映射为多个虚拟节点以均衡分布
+#[derive(Debug, Clone, Default)]
+pub struct HashRing {
+    /// 按哈希值排序的虚拟节点及其所属节点下标
+    points: Vec<(u64, usize)>,
+    nodes: Vec<String>,
+}
+
+impl HashRing {
+    pub fn new(nodes: &[String], virtual_nodes: usize) -> Self {
+        let nodes: Vec<String> = nodes.iter().map(|node| normalize(node)).collect();
+        let mut points: Vec<(u64, usize)> = nodes
+            .iter()
+            .enumerate()
+            .flat_map(|(index, node)| {
+                (0..virtual_nodes.max(1)).map(move |i| (hash(&format!("{}#{}", node, i)), index))
+            })
+            .collect();
+        points.sort_unstable();
+        Self { points, nodes }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// 负责该缓存键的节点
+    pub fn owner(&self, key: &str) -> Option<&str> {
+        if self.points.is_empty() {
+            return None;
+        }
+        let h = hash(key);
+        let position = self.points.partition_point(|(point, _)| *point < h);
+        let (_, index) = self.points[position % self.points.len()];
+        Some(&self.nodes[index])
+    }
+}
+
+/// 统一节点地址格式，去掉末尾的 `/`
+pub fn normalize(node: &str) -> String {
+    node.trim_end_matches('/').to_string()
+}
+
+fn hash(value: &str) -> u64 {
+    let digest = md5::compute(value.as_bytes());
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&digest.0[..8]);
+    u64::from_be_bytes(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_owner_is_stable() {
+        let nodes: Vec<String> = ["http://10.0.0.1:8080", "http://10.0.0.2:8080", "http://10.0.0.3:8080/"]
+            .iter()
+            .map(|node| node.to_string())
+            .collect();
+        let ring = HashRing::new(&nodes, 100);
+        let smaller = HashRing::new(&nodes[..2], 100);
+
+        let mut counts = [0usize; 3];
+        for i in 0..3000 {
+            let key = format!("http://example.com/video/{}.ts", i);
+            let owner = ring.owner(&key).unwrap();
+            counts[ring.nodes.iter().position(|node| node == owner).unwrap()] += 1;
+
+            // 去掉一个节点时，其余节点负责的键不变
+            if owner != "http://10.0.0.3:8080" {
+                assert_eq!(smaller.owner(&key), Some(owner));
+            }
+        }
+        assert!(counts.iter().all(|count| *count > 500), "{:?}", counts);
+        assert_eq!(HashRing::default().owner("key"), None);
+    }
+}
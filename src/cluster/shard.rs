@@ -0,0 +1,68 @@
+use hyper::client::HttpConnector;
+use hyper::{Body, Client, HeaderMap, Request, Response};
+use crate::utils::error::Result;
+use crate::log_info;
+use super::ring::{normalize, HashRing};
+use super::{peer_proxy_url, ClusterConfig, PEER_HEADER};
+
+/// 不转发给其他节点的逐跳请求头
+const HOP_BY_HOP_HEADERS: &[&str] = &[
+    "connection",
+    "host",
+    "keep-alive",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// 按一致性哈希将请求转发给负责该缓存键的节点
+pub struct ShardRouter {
+    ring: HashRing,
+    self_node: Option<String>,
+    client: Client<HttpConnector>,
+}
+
+impl ShardRouter {
+    pub fn new(config: &ClusterConfig) -> Self {
+        let ring = match &config.self_node {
+            Some(_) => HashRing::new(&config.nodes, config.virtual_nodes),
+            None => HashRing::default(),
+        };
+        if !ring.is_empty() {
+            log_info!("Cluster", "启用分片: {} 个节点", config.nodes.len());
+        }
+        Self {
+            ring,
+            self_node: config.self_node.as_deref().map(normalize),
+            client: Client::new(),
+        }
+    }
+
+    /// 负责该键的其他节点，由本节点负责或未启用分片时返回 None
+    pub fn remote_owner(&self, key: &str) -> Option<&str> {
+        let owner = self.ring.owner(key)?;
+        if self.self_node.as_deref() == Some(owner) {
+            None
+        } else {
+            Some(owner)
+        }
+    }
+
+    /// 将请求转发到负责该键的节点，保留 Range 和条件请求头
+    pub async fn forward(&self, owner: &str, url: &str, headers: &HeaderMap) -> Result<Response<Body>> {
+        log_info!("Cluster", "转发到分片节点: {} -> {}", url, owner);
+        let mut req = Request::builder()
+            .method("GET")
+            .uri(peer_proxy_url(owner, url))
+            .body(Body::empty())?;
+        for (name, value) in headers {
+            if !HOP_BY_HOP_HEADERS.contains(&name.as_str()) {
+                req.headers_mut().append(name, value.clone());
+            }
+        }
+        req.headers_mut().insert(PEER_HEADER, "1".parse().unwrap());
+        Ok(self.client.request(req).await?)
+    }
+}
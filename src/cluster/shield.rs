@@ -0,0 +1,76 @@
+use hyper::HeaderMap;
+use crate::utils::request_id;
+use crate::log_info;
+use super::{peer_proxy_url, ClusterConfig, LOOP_DETECT_HEADER};
+
+/// 二级缓存：本地未命中时向上级缓存代理请求，而不是直接请求源站
+pub struct ParentShield {
+    parent: Option<String>,
+    node_id: String,
+}
+
+impl ParentShield {
+    pub fn new(config: &ClusterConfig) -> Self {
+        let node_id = config
+            .node_id
+            .clone()
+            .or_else(|| config.self_node.clone())
+            .unwrap_or_else(request_id::generate);
+        if let Some(parent) = &config.parent {
+            log_info!("Cluster", "启用上级缓存: {} (节点 {})", parent, node_id);
+        }
+        Self {
+            parent: config.parent.clone(),
+            node_id,
+        }
+    }
+
+    /// 返回上级缓存上的代理地址和需要携带的环路检测值，
+    /// 未配置上级或请求已经过本节点时返回 None，改为直接请求源站
+    pub fn parent_url(&self, url: &str, headers: &HeaderMap) -> Option<(String, String)> {
+        let parent = self.parent.as_ref()?;
+        let via = headers
+            .get(LOOP_DETECT_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or("");
+        if via.split(',').any(|node| node.trim() == self.node_id) {
+            log_info!("Cluster", "检测到缓存环路，直接请求源站: {} via {}", url, via);
+            return None;
+        }
+        Some((peer_proxy_url(parent, url), append_node(via, &self.node_id)))
+    }
+}
+
+fn append_node(via: &str, node_id: &str) -> String {
+    if via.trim().is_empty() {
+        node_id.to_string()
+    } else {
+        format!("{}, {}", via, node_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parent_url_detects_loop() {
+        let config = ClusterConfig {
+            parent: Some("http://parent:8080".to_string()),
+            node_id: Some("edge-1".to_string()),
+            ..Default::default()
+        };
+        let shield = ParentShield::new(&config);
+
+        let mut headers = HeaderMap::new();
+        let (parent_url, via) = shield.parent_url("http://example.com/a.mp4", &headers).unwrap();
+        assert!(parent_url.starts_with("http://parent:8080/proxy/"));
+        assert_eq!(via, "edge-1");
+
+        headers.insert(LOOP_DETECT_HEADER, "edge-2".parse().unwrap());
+        assert_eq!(shield.parent_url("http://example.com/a.mp4", &headers).unwrap().1, "edge-2, edge-1");
+
+        headers.insert(LOOP_DETECT_HEADER, "edge-2, edge-1".parse().unwrap());
+        assert!(shield.parent_url("http://example.com/a.mp4", &headers).is_none());
+    }
+}
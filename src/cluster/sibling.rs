@@ -0,0 +1,52 @@
+use hyper::client::HttpConnector;
+use hyper::Client;
+use tokio::time::timeout;
+use crate::log_info;
+use super::{fetch_availability, peer_proxy_url, ClusterConfig};
+
+/// 本地未命中时向同组节点查找缓存
+pub struct SiblingLookup {
+    config: ClusterConfig,
+    client: Client<HttpConnector>,
+}
+
+impl SiblingLookup {
+    pub fn new(config: ClusterConfig) -> Self {
+        Self {
+            config,
+            client: Client::new(),
+        }
+    }
+
+    /// 同组节点只回应携带共享密钥的查询，没有配置密钥时不查找
+    pub fn is_enabled(&self) -> bool {
+        !self.config.siblings.is_empty() && self.config.peer_secret.is_some()
+    }
+
+    /// 并发询问全部同组节点，返回第一个完整缓存了该范围的节点上的代理地址
+    pub async fn find(&self, url: &str, start: u64, end: u64) -> Option<String> {
+        let secret = match &self.config.peer_secret {
+            Some(secret) if self.is_enabled() => secret.as_str(),
+            _ => return None,
+        };
+
+        let lookups = self.config.siblings.iter().map(|sibling| async move {
+            match timeout(self.config.lookup_timeout, fetch_availability(&self.client, sibling, secret, url)).await {
+                Ok(Ok(availability)) if availability.covers(start, end) => Some(sibling.clone()),
+                Ok(Ok(_)) => None,
+                Ok(Err(e)) => {
+                    log_info!("Cluster", "查询同组节点失败: {} - {}", sibling, e);
+                    None
+                }
+                Err(_) => {
+                    log_info!("Cluster", "查询同组节点超时: {}", sibling);
+                    None
+                }
+            }
+        });
+
+        let sibling = futures::future::join_all(lookups).await.into_iter().flatten().next()?;
+        log_info!("Cluster", "同组节点已缓存: {} -> {}", url, sibling);
+        Some(peer_proxy_url(&sibling, url))
+    }
+}
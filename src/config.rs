@@ -1,5 +1,88 @@
 use std::path::{PathBuf, Path};
+use crate::utils::canonical::canonicalize;
 use crate::utils::error::Result;
+use crate::handlers::{CachePolicyConfig, CoalesceConfig, CorsConfig, FaultConfig, HeaderPolicy, MirrorConfig, NamespaceConfig, ParallelDownloadConfig, PopularityConfig, ReadAheadConfig, SchedulerConfig, UpstreamProfiles, WatchdogConfig};
+use crate::storage::StorageManagerConfig;
+use crate::media::{FastStartConfig, SniffConfig, SubtitleConfig};
+use crate::route::RouteTable;
+use crate::rules::RuleSet;
+use crate::cluster::ClusterConfig;
+use crate::server::{ConnectionConfig, LimitConfig, ListenConfig};
+use crate::data_source::UpstreamConnectConfig;
+use crate::data_request::RequestValidation;
+use crate::handlers::alert::AlertConfig;
+use crate::handlers::session::SessionConfig;
+use crate::handlers::tunnel::{PassthroughConfig, TunnelConfig};
+use crate::admin::AdminConfig;
+
+/// 代理服务器配置
+#[derive(Clone, Default)]
+pub struct ProxyConfig {
+    /// 存储管理配置
+    pub storage: StorageManagerConfig,
+    /// 缓存策略配置
+    pub cache_policy: CachePolicyConfig,
+    /// MP4 快速起播配置
+    pub fast_start: FastStartConfig,
+    /// 多连接分段下载配置
+    pub parallel_download: ParallelDownloadConfig,
+    /// 顺序读取时预先缓存后续数据
+    pub read_ahead: ReadAheadConfig,
+    /// 上游请求按前台、预读、后台分级调度
+    pub scheduler: SchedulerConfig,
+    /// 空闲时主动补全热门内容
+    pub popularity: PopularityConfig,
+    /// 相邻的小范围请求合并为一次上游请求
+    pub coalesce: CoalesceConfig,
+    /// 上游数据停滞时从当前位置重新请求
+    pub watchdog: WatchdogConfig,
+    /// 上游镜像配置
+    pub mirrors: MirrorConfig,
+    /// 按主机配置上游请求的 User-Agent 和默认请求头
+    pub upstream: UpstreamProfiles,
+    /// 上游连接的地址族偏好和 Happy Eyeballs 回退
+    pub upstream_connect: UpstreamConnectConfig,
+    /// 反向代理路由映射
+    pub routes: RouteTable,
+    /// 入口请求校验：URL 长度、允许的协议和嵌套代理
+    pub validation: RequestValidation,
+    /// 非 GET/HEAD 请求原样转发到解码后的目标地址，不缓存；关闭时 OPTIONS 在本地应答，其他方法返回 405
+    pub method_passthrough: bool,
+    /// WebSocket、事件流和 ICY 电台请求直接透传到上游，默认关闭
+    pub passthrough: PassthroughConfig,
+    /// CONNECT 隧道，默认关闭
+    pub tunnel: TunnelConfig,
+    /// 请求规则，分发前按顺序求值，可改写 URL、修改响应头、跳过缓存、指定缓存时间或拒绝请求
+    pub rules: RuleSet,
+    /// 监听地址
+    pub listen: ListenConfig,
+    /// 连接和请求并发限制
+    pub limits: LimitConfig,
+    /// 客户端连接的超时和保活
+    pub connection: ConnectionConfig,
+    /// 跨域配置
+    pub cors: CorsConfig,
+    /// 管理接口的访问控制
+    pub admin: AdminConfig,
+    /// 预热文件，启动时预取其中的 URL
+    pub warmup_file: Option<PathBuf>,
+    /// 故障注入，仅用于测试
+    pub faults: FaultConfig,
+    /// 集群节点配置
+    pub cluster: ClusterConfig,
+    /// 按用户划分缓存命名空间
+    pub namespaces: NamespaceConfig,
+    /// 返回给客户端的响应头策略
+    pub headers: HeaderPolicy,
+    /// 按文件头修正错误的 Content-Type
+    pub sniff: SniffConfig,
+    /// 请求视频时探测并缓存同名的外挂字幕
+    pub subtitles: SubtitleConfig,
+    /// 缓存占用、命中率、磁盘错误和源站失败的告警
+    pub alerts: AlertConfig,
+    /// 按播放会话统计起播时间、流量和卡顿
+    pub sessions: SessionConfig,
+}
 
 pub struct Config {
     pub cache_dir: String,
@@ -13,12 +96,12 @@ impl Config {
     }
     
     pub fn get_cache_state(&self, url: &str) -> Result<PathBuf> {
-        let url_hash = format!("{:x}", md5::compute(url));
+        let url_hash = format!("{:x}", md5::compute(canonicalize(url)));
         Ok(Path::new(&self.cache_dir).join(url_hash).join("state.json"))
     }
     
     pub fn get_cache_file(&self, url: &str) -> Result<PathBuf> {
-        let url_hash = format!("{:x}", md5::compute(url));
+        let url_hash = format!("{:x}", md5::compute(canonicalize(url)));
         Ok(Path::new(&self.cache_dir).join(url_hash).join("cache.data"))
     }
 }
@@ -26,3 +109,57 @@ impl Config {
 lazy_static::lazy_static! {
     pub static ref CONFIG: Config = Config::new("cache".to_string());
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::ShardLayout;
+
+    /// Windows 下文件名不能使用的字符
+    const WINDOWS_ILLEGAL: &[char] = &['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+    /// Windows 保留的设备名，带扩展名时同样不可用
+    const WINDOWS_RESERVED: &[&str] = &[
+        "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+        "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+    ];
+
+    /// 检查缓存根目录之后的路径在 Windows 下可用，并给根目录留出 MAX_PATH 的大部分长度
+    fn assert_windows_safe(root: &Path, path: &Path) {
+        let relative = path.strip_prefix(root).unwrap();
+        assert!(relative.as_os_str().len() <= 64, "路径过长: {:?}", relative);
+        for component in relative.components() {
+            let name = component.as_os_str().to_str().unwrap();
+            assert!(!name.is_empty() && name.len() <= 255, "文件名长度不合法: {}", name);
+            assert!(!name.chars().any(|c| WINDOWS_ILLEGAL.contains(&c) || c.is_control()), "非法字符: {}", name);
+            assert!(!name.ends_with('.') && !name.ends_with(' '), "以点或空格结尾: {}", name);
+            let stem = name.split('.').next().unwrap();
+            assert!(!WINDOWS_RESERVED.iter().any(|reserved| reserved.eq_ignore_ascii_case(stem)), "保留名称: {}", name);
+        }
+    }
+
+    #[test]
+    fn test_windows_safe_cache_paths() {
+        let long = format!("http://example.com/{}.mp4", "a".repeat(4096));
+        let urls = [
+            "http://example.com/CON",
+            "http://example.com/nul.txt",
+            "http://example.com/aux/com1/lpt9.mp4",
+            "http://example.com/C:/Windows/system32",
+            "http://example.com/a.mp4?token=a:b|c*d\"e<f>g",
+            "http://example.com/dir\\file .mp4.",
+            "http://example.com/%00%1f/%E4%B8%AD%E6%96%87.mp4",
+            long.as_str(),
+        ];
+
+        let root = Path::new("cache");
+        let config = Config::new("cache".to_string());
+        for url in urls {
+            assert_windows_safe(root, &config.get_cache_file(url).unwrap());
+            assert_windows_safe(root, &config.get_cache_state(url).unwrap());
+            for layout in [ShardLayout::default(), ShardLayout::new(0, 2), ShardLayout::new(4, 4)] {
+                assert_windows_safe(root, &layout.file_path(root, url));
+            }
+        }
+    }
+}
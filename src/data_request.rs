@@ -1,9 +1,18 @@
+use crate::handlers::DEFAULT_USER_AGENT;
+use crate::handlers::download::DOWNLOAD_PREFIX;
 use crate::log_info;
+use crate::route::RouteTable;
+use crate::utils::canonical::canonicalize;
 use crate::utils::error::{ProxyError, Result};
+use crate::utils::link_codec::{LinkCodec, PercentCodec};
 use hyper::{
-    header::{HeaderMap, HeaderValue, RANGE},
+    header::{HeaderMap, HeaderValue, HOST, RANGE},
+    http::uri::Authority,
     Request,
 };
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
 use url::Url;
 use urlencoding;
 
@@ -14,48 +23,258 @@ pub enum RequestType {
     Segment,
 }
 
+/// 代理入口的默认路径前缀
+pub const DEFAULT_PROXY_PREFIX: &str = "/proxy/";
+
+/// 规范化代理入口前缀，以 `/` 开头和结尾；空字符串表示直接使用 `/<编码后的 URL>`，适用于反向代理部署
+pub fn normalize_proxy_prefix(prefix: &str) -> String {
+    let trimmed = prefix.trim_matches('/');
+    if trimmed.is_empty() {
+        "/".to_string()
+    } else {
+        format!("/{}/", trimmed)
+    }
+}
+
+/// 入口请求的解析和校验，不通过的请求返回 400
+#[derive(Clone)]
+pub struct RequestValidation {
+    /// 代理入口路径前缀，已规范化，后接编码后的源站 URL
+    pub proxy_prefix: String,
+    /// 重写播放列表时源站 URL 的编码方式
+    pub link_codec: Arc<dyn LinkCodec>,
+    /// 使用签名或加密编码时是否仍接受百分号编码的明文地址，默认拒绝，否则客户端可以绕过签名
+    pub plain_links: bool,
+    /// 源站 URL 的最大长度
+    pub max_url_length: usize,
+    /// 允许代理的协议，不区分大小写
+    pub allowed_schemes: Vec<String>,
+}
+
+impl Default for RequestValidation {
+    fn default() -> Self {
+        Self {
+            proxy_prefix: DEFAULT_PROXY_PREFIX.to_string(),
+            link_codec: Arc::new(PercentCodec),
+            plain_links: false,
+            max_url_length: 8192,
+            allowed_schemes: vec!["http".to_string(), "https".to_string()],
+        }
+    }
+}
+
+impl RequestValidation {
+    /// 额外允许一种协议
+    pub fn with_scheme(mut self, scheme: impl Into<String>) -> Self {
+        self.allowed_schemes.push(scheme.into());
+        self
+    }
+
+    /// 播放列表中的链接使用签名或加密编码，客户端无法直接得到带令牌的源站 URL
+    pub fn with_link_codec(mut self, codec: Arc<dyn LinkCodec>) -> Self {
+        self.link_codec = codec;
+        self
+    }
+
+    /// 使用签名或加密编码时仍接受百分号编码的明文地址
+    pub fn with_plain_links(mut self, allow: bool) -> Self {
+        self.plain_links = allow;
+        self
+    }
+
+    /// 设置代理入口路径前缀，例如 `/p/`
+    pub fn with_proxy_prefix(mut self, prefix: &str) -> Self {
+        self.proxy_prefix = normalize_proxy_prefix(prefix);
+        self
+    }
+
+    /// 校验源站 URL：长度、协议、主机名，以及指回代理入口的嵌套地址
+    pub fn validate(&self, url: &str) -> Result<()> {
+        if url.len() > self.max_url_length {
+            return Err(ProxyError::request(format!("URL 长度 {} 超过上限 {}", url.len(), self.max_url_length)));
+        }
+        let parsed = Url::parse(url).map_err(|e| ProxyError::request(format!("无效的源站 URL: {}", e)))?;
+        if !self.allowed_schemes.iter().any(|scheme| scheme.eq_ignore_ascii_case(parsed.scheme())) {
+            return Err(ProxyError::request(format!("不支持的协议: {}", parsed.scheme())));
+        }
+        if parsed.host_str().is_none_or(str::is_empty) {
+            return Err(ProxyError::request("源站 URL 缺少主机名"));
+        }
+        if is_nested_proxy(parsed.path(), &self.proxy_prefix) {
+            return Err(ProxyError::request("源站 URL 指向代理入口，拒绝嵌套代理"));
+        }
+        Ok(())
+    }
+
+    /// 展开指回代理入口的嵌套地址后校验，返回最终的源站 URL
+    pub fn check(&self, req: &Request<hyper::Body>, url: String) -> Result<String> {
+        let url = unwrap_self_reference(req, url, &self.proxy_prefix)?;
+        self.validate(&url)?;
+        Ok(url)
+    }
+}
+
+impl fmt::Debug for RequestValidation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RequestValidation")
+            .field("proxy_prefix", &self.proxy_prefix)
+            .field("plain_links", &self.plain_links)
+            .field("max_url_length", &self.max_url_length)
+            .field("allowed_schemes", &self.allowed_schemes)
+            .finish_non_exhaustive()
+    }
+}
+
+/// 路径中是否包含代理入口或 `/download/` 后接另一个地址，这类请求会让代理请求自己
+fn is_nested_proxy(path: &str, proxy_prefix: &str) -> bool {
+    [proxy_prefix, DOWNLOAD_PREFIX].iter().any(|prefix| {
+        path.match_indices(prefix).any(|(idx, _)| {
+            let rest = &path[idx + prefix.len()..];
+            let rest = urlencoding::decode(rest).map(|rest| rest.to_ascii_lowercase()).unwrap_or_default();
+            rest.starts_with("http://") || rest.starts_with("https://")
+        })
+    })
+}
+
+/// 代理的监听地址，由服务器写入请求扩展，用于识别指回代理自身的 URL
+#[derive(Debug, Clone, Copy)]
+pub struct LocalAddr(pub SocketAddr);
+
+/// 指回代理自身的 URL 最多展开的层数
+const MAX_SELF_UNWRAP: usize = 4;
+
+/// 目标 URL 指回代理自身时，展开其中代理入口或 `/download/` 后的地址；不是代理入口的自身地址直接拒绝
+fn unwrap_self_reference(req: &Request<hyper::Body>, mut url: String, proxy_prefix: &str) -> Result<String> {
+    for _ in 0..MAX_SELF_UNWRAP {
+        // 无法解析的 URL 留给校验处理
+        let parsed = match Url::parse(&url) {
+            Ok(parsed) => parsed,
+            Err(_) => return Ok(url),
+        };
+        if !points_to_self(req, &parsed) {
+            return Ok(url);
+        }
+        let inner = [proxy_prefix, DOWNLOAD_PREFIX]
+            .iter()
+            .find_map(|prefix| parsed.path().strip_prefix(prefix))
+            .ok_or_else(|| ProxyError::request(format!("目标 URL 指向代理自身: {}", url)))?;
+        let mut inner = urlencoding::decode(inner)
+            .map_err(|e| ProxyError::request(format!("URL 解码失败: {}", e)))?
+            .into_owned();
+        if let Some(query) = parsed.query() {
+            inner = format!("{}?{}", inner, query);
+        }
+        log_info!("Request", "展开指向代理自身的 URL: {} -> {}", url, inner);
+        url = inner;
+    }
+    Err(ProxyError::request(format!("指向代理自身的嵌套层数超过 {}", MAX_SELF_UNWRAP)))
+}
+
+/// URL 的主机和端口是否为代理自身：与客户端请求的 Host 相同，或为监听端口上的本机地址
+fn points_to_self(req: &Request<hyper::Body>, url: &Url) -> bool {
+    let (host, port) = match (url.host_str(), url.port_or_known_default()) {
+        (Some(host), Some(port)) => (host.trim_matches(|c| c == '[' || c == ']').to_ascii_lowercase(), port),
+        _ => return false,
+    };
+
+    let authority = req
+        .headers()
+        .get(HOST)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<Authority>().ok());
+    if let Some(authority) = authority {
+        let same_host = authority.host().trim_matches(|c| c == '[' || c == ']').eq_ignore_ascii_case(&host);
+        if same_host && authority.port_u16().unwrap_or(80) == port {
+            return true;
+        }
+    }
+
+    is_listen_addr(req, &host, port)
+}
+
+/// 主机和端口是否为监听端口上的本机地址；CONNECT 请求的 Host 头就是目标地址，只能按监听地址判断
+pub fn is_listen_addr(req: &Request<hyper::Body>, host: &str, port: u16) -> bool {
+    let host = host.trim_matches(|c| c == '[' || c == ']').to_ascii_lowercase();
+    match req.extensions().get::<LocalAddr>() {
+        Some(LocalAddr(local)) if local.port() == port => {
+            host == "localhost"
+                || host.parse::<IpAddr>().is_ok_and(|ip| {
+                    ip == local.ip() || ip.is_loopback() || ip.is_unspecified()
+                })
+        }
+        _ => false,
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct DataRequest {
     pub url: String,
     pub range: String,
+    pub has_range: bool,        // 客户端是否携带了 Range 头
+    pub download: bool,         // 通过 /download/ 入口以附件形式下载
     pub headers: HeaderMap,
     pub request_type: RequestType,
 }
 
 impl DataRequest {
     pub fn new(req: &Request<hyper::Body>) -> Result<Self> {
+        Self::with_routes(req, &RouteTable::default())
+    }
+
+    /// 创建请求，本地路径匹配路由表时映射为对应的上游 URL
+    pub fn with_routes(req: &Request<hyper::Body>, routes: &RouteTable) -> Result<Self> {
+        Self::with_validation(req, routes, &RequestValidation::default())
+    }
+
+    /// 创建请求并按 `validation` 校验源站 URL
+    pub fn with_validation(req: &Request<hyper::Body>, routes: &RouteTable, validation: &RequestValidation) -> Result<Self> {
         log_info!("Request", "req: {}", req.uri());
         
         let url = if let Some(original_url) = req.headers().get("X-Original-Url") {
             original_url.to_str()?.to_string()
+        } else if let Some(upstream_url) = Self::route(req, routes) {
+            log_info!("Request", "路由映射: {} -> {}", req.uri(), upstream_url);
+            upstream_url
         } else {
             let path = req.uri().path();
             
-            // 检查是否是 /proxy/ 格式
-            if let Some(proxy_path) = path.strip_prefix("/proxy/") {
-                // 处理可能存在的多重 /proxy/ 前缀
-                let mut clean_url = proxy_path.to_string();
-                while let Some(idx) = clean_url.find("/proxy/") {
-                    clean_url = clean_url[idx + 7..].to_string();
+            // 检查是否是代理入口或 /download/ 格式
+            let proxy_path = path
+                .strip_prefix(DOWNLOAD_PREFIX)
+                .or_else(|| path.strip_prefix(validation.proxy_prefix.as_str()));
+            if let Some(proxy_path) = proxy_path {
+                // 编码后的长度不会小于解码结果，过长时不必解码
+                if proxy_path.len() > validation.max_url_length.saturating_mul(3) {
+                    return Err(ProxyError::request(format!("URL 长度 {} 超过上限 {}", proxy_path.len(), validation.max_url_length)));
                 }
                 
-                // 解码 URL
-                urlencoding::decode(&clean_url)
-                    .map_err(|e| ProxyError::Request(format!("URL 解码失败: {}", e)))?
-                    .into_owned()
+                // 解码 URL，嵌套的 /proxy/ 前缀在校验时拒绝；不是明文地址时按配置的编码方式还原
+                let decoded = PercentCodec.decode(proxy_path)?;
+                if !decoded.contains("://") {
+                    validation.link_codec.decode(proxy_path)?
+                } else if validation.link_codec.is_plain() || validation.plain_links {
+                    decoded
+                } else {
+                    return Err(ProxyError::request("代理链接必须使用配置的签名或加密编码"));
+                }
             } else {
-                // 如果不是 /proxy/ 格式，尝试查询参数
+                // 如果不是代理入口格式，尝试查询参数
                 let uri = req.uri().to_string();
                 let parsed_url = Url::parse(&uri)
-                    .map_err(|_| ProxyError::Request("无效的请求URL".to_string()))?;
+                    .map_err(|_| ProxyError::request("无效的请求URL".to_string()))?;
                 
                 parsed_url.to_string()
             }
         };
 
+        let url = validation.check(req, url)?;
+        
+        // 规范化后作为缓存键，等价的地址共用同一个缓存条目
+        let url = canonicalize(&url);
         log_info!("Request", "url: {}", url);
         
-        // 获取 Range 头
+        // 获取 Range 头，未携带时按完整内容处理
+        let has_range = req.headers().contains_key(RANGE);
         let range = if let Some(range_header) = req.headers().get(RANGE) {
             range_header.to_str()?.to_string()
         } else {
@@ -64,8 +283,30 @@ impl DataRequest {
         
         log_info!("Request", "key: range, value: {}", range);
         
-        // 确定请求类型
-        let request_type = if url.ends_with(".m3u8") {
+        let request_type = Self::request_type(&url);
+        let download = req.headers().get("X-Original-Url").is_none() && req.uri().path().starts_with(DOWNLOAD_PREFIX);
+        
+        Ok(Self {
+            url,
+            range,
+            has_range,
+            download,
+            headers: req.headers().clone(),
+            request_type,
+        })
+    }
+
+    /// 替换上游 URL，并按新的 URL 重新确定请求类型
+    pub fn with_url(mut self, url: String) -> Self {
+        log_info!("Request", "url 改写为: {}", url);
+        self.request_type = Self::request_type(&url);
+        self.url = url;
+        self
+    }
+
+    /// 确定请求类型
+    fn request_type(url: &str) -> RequestType {
+        if url.ends_with(".m3u8") {
             log_info!("Request", "type: M3u8");
             RequestType::M3u8
         } else if url.ends_with(".ts") {
@@ -74,14 +315,16 @@ impl DataRequest {
         } else {
             log_info!("Request", "type: Normal");
             RequestType::Normal
-        };
-        
-        Ok(Self {
-            url,
-            range,
-            headers: req.headers().clone(),
-            request_type,
-        })
+        }
+    }
+
+    /// 按路由表映射本地路径，绝对地址的请求不参与映射
+    fn route(req: &Request<hyper::Body>, routes: &RouteTable) -> Option<String> {
+        if routes.is_empty() || req.uri().host().is_some() {
+            return None;
+        }
+        let path_and_query = req.uri().path_and_query().map(|pq| pq.as_str()).unwrap_or("/");
+        routes.resolve(path_and_query)
     }
 
     pub fn new_request_with_range(url: &str, range: &str) -> Request<hyper::Body> {
@@ -94,8 +337,10 @@ impl DataRequest {
         }
 
         builder = builder
-            .header("User-Agent", "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/91.0.4472.124 Safari/537.36")
+            .header("User-Agent", DEFAULT_USER_AGENT)
             .header("Accept", "*/*")
+            // 只接受原始编码，保证范围计算和缓存内容基于未压缩的数据
+            .header("Accept-Encoding", "identity")
             .header("Connection", "keep-alive");
 
         builder
@@ -111,6 +356,14 @@ impl DataRequest {
         &self.range
     }
 
+    pub fn has_range(&self) -> bool {
+        self.has_range
+    }
+
+    pub fn is_download(&self) -> bool {
+        self.download
+    }
+
     pub fn get_headers(&self) -> &HeaderMap {
         &self.headers
     }
@@ -119,3 +372,98 @@ impl DataRequest {
         &self.request_type
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validation() {
+        let validation = RequestValidation::default();
+        assert!(validation.validate("https://example.com/a.mp4").is_ok());
+        assert!(validation.validate("https://example.com/proxy/a.mp4").is_ok());
+        assert!(validation.validate("ftp://example.com/a.mp4").is_err());
+        assert!(validation.validate("file:///etc/passwd").is_err());
+        assert!(validation.validate("not a url").is_err());
+        assert!(validation.validate("http://127.0.0.1:8080/proxy/http://example.com/a.mp4").is_err());
+        assert!(validation.validate("http://127.0.0.1:8080/download/https%3A%2F%2Fexample.com").is_err());
+        assert!(validation.validate(&format!("https://example.com/{}", "a".repeat(8192))).is_err());
+        assert!(validation.clone().with_scheme("ftp").validate("ftp://example.com/a.mp4").is_ok());
+
+        assert_eq!(normalize_proxy_prefix("p"), "/p/");
+        assert_eq!(normalize_proxy_prefix("/p/"), "/p/");
+        assert_eq!(normalize_proxy_prefix(""), "/");
+        let validation = validation.with_proxy_prefix("/p");
+        assert!(validation.validate("http://127.0.0.1:8080/p/http://example.com/a.mp4").is_err());
+    }
+
+    #[test]
+    fn test_self_reference() {
+        let mut req = Request::builder()
+            .uri("/proxy/x")
+            .header(HOST, "proxy.local:8080")
+            .body(hyper::Body::empty())
+            .unwrap();
+        req.extensions_mut().insert(LocalAddr("0.0.0.0:8080".parse().unwrap()));
+
+        let url = |url: &str| Url::parse(url).unwrap();
+        assert!(points_to_self(&req, &url("http://proxy.local:8080/a.mp4")));
+        assert!(points_to_self(&req, &url("http://127.0.0.1:8080/a.mp4")));
+        assert!(points_to_self(&req, &url("http://[::1]:8080/a.mp4")));
+        assert!(!points_to_self(&req, &url("http://proxy.local/a.mp4")));
+        assert!(!points_to_self(&req, &url("http://example.com:8080/a.mp4")));
+
+        assert_eq!(
+            unwrap_self_reference(&req, "http://127.0.0.1:8080/proxy/https%3A%2F%2Fexample.com%2Fa.mp4".to_string(), DEFAULT_PROXY_PREFIX).unwrap(),
+            "https://example.com/a.mp4"
+        );
+        assert!(unwrap_self_reference(&req, "http://localhost:8080/admin/stats".to_string(), DEFAULT_PROXY_PREFIX).is_err());
+
+        assert!(is_listen_addr(&req, "127.0.0.1", 8080));
+        assert!(is_listen_addr(&req, "[::1]", 8080));
+        assert!(!is_listen_addr(&req, "proxy.local", 8080));
+        assert!(!is_listen_addr(&req, "127.0.0.1", 443));
+    }
+
+    #[test]
+    fn test_link_codec() {
+        use crate::utils::link_codec::SignedCodec;
+
+        let codec = Arc::new(SignedCodec::new("secret"));
+        let validation = RequestValidation::default().with_link_codec(codec.clone());
+        let request = |path: &str| Request::builder().uri(path).body(hyper::Body::empty()).unwrap();
+        let parse = |path: &str| DataRequest::with_validation(&request(path), &RouteTable::default(), &validation);
+
+        let token = codec.encode("https://example.com/a.ts?token=1");
+        assert_eq!(parse(&format!("/proxy/{}", token)).unwrap().url, "https://example.com/a.ts?token=1");
+        assert!(parse("/proxy/https%3A%2F%2Fexample.com%2Fa.ts").is_err());
+        assert!(parse("/download/https%3A%2F%2Fexample.com%2Fa.ts").is_err());
+        assert!(parse(&format!("/proxy/{}x", token)).is_err());
+
+        let validation = validation.clone().with_plain_links(true);
+        let parsed = DataRequest::with_validation(&request("/proxy/https%3A%2F%2Fexample.com%2Fa.ts"), &RouteTable::default(), &validation);
+        assert_eq!(parsed.unwrap().url, "https://example.com/a.ts");
+    }
+
+    #[test]
+    fn test_has_range() {
+        let parse = |range: Option<&str>| {
+            let mut builder = Request::builder().uri("/proxy/https%3A%2F%2Fexample.com%2Fa.mp4");
+            if let Some(range) = range {
+                builder = builder.header(RANGE, range);
+            }
+            DataRequest::new(&builder.body(hyper::Body::empty()).unwrap()).unwrap()
+        };
+
+        let request = parse(None);
+        assert!(!request.has_range());
+        assert_eq!(request.get_range(), "bytes=0-");
+
+        // 从头开始的 Range 也是客户端显式请求的范围，应返回 206
+        let request = parse(Some("bytes=0-"));
+        assert!(request.has_range());
+        assert_eq!(request.get_range(), "bytes=0-");
+
+        assert!(parse(Some("bytes=100-199")).has_range());
+    }
+}
@@ -0,0 +1,116 @@
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use hyper::client::connect::dns::{GaiResolver, Name};
+use hyper::client::HttpConnector;
+use hyper::service::Service;
+use hyper_tls::HttpsConnector;
+
+/// 上游地址族偏好
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpPreference {
+    /// 按系统解析结果的顺序
+    System,
+    /// 优先 IPv6，超时后回退到 IPv4
+    PreferIpv6,
+    /// 优先 IPv4，超时后回退到 IPv6
+    PreferIpv4,
+    Ipv4Only,
+    Ipv6Only,
+}
+
+/// 上游连接配置
+#[derive(Debug, Clone)]
+pub struct UpstreamConnectConfig {
+    pub ip_preference: IpPreference,
+    /// Happy Eyeballs：首选地址族连接未在该时间内建立时，同时尝试另一地址族，None 表示按顺序逐个尝试
+    pub happy_eyeballs_timeout: Option<Duration>,
+}
+
+impl Default for UpstreamConnectConfig {
+    fn default() -> Self {
+        Self {
+            ip_preference: IpPreference::PreferIpv6,
+            happy_eyeballs_timeout: Some(Duration::from_millis(300)),
+        }
+    }
+}
+
+/// 上游连接器，支持 HTTP 和 HTTPS
+pub type UpstreamConnector = HttpsConnector<HttpConnector<OrderedResolver>>;
+
+/// 按配置创建上游连接器
+pub fn https_connector(config: &UpstreamConnectConfig) -> UpstreamConnector {
+    let mut http = HttpConnector::new_with_resolver(OrderedResolver::new(config.ip_preference));
+    http.enforce_http(false);
+    http.set_happy_eyeballs_timeout(config.happy_eyeballs_timeout);
+    HttpsConnector::new_with_connector(http)
+}
+
+/// 按地址族偏好排序解析结果。连接器把排在第一位的地址族作为首选，另一地址族作为回退
+#[derive(Clone)]
+pub struct OrderedResolver {
+    inner: GaiResolver,
+    preference: IpPreference,
+}
+
+impl OrderedResolver {
+    pub fn new(preference: IpPreference) -> Self {
+        Self {
+            inner: GaiResolver::new(),
+            preference,
+        }
+    }
+}
+
+impl Service<Name> for OrderedResolver {
+    type Response = std::vec::IntoIter<SocketAddr>;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = io::Result<Self::Response>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        let resolving = self.inner.call(name);
+        let preference = self.preference;
+        Box::pin(async move {
+            let addrs = order_addrs(resolving.await?.collect(), preference);
+            Ok(addrs.into_iter())
+        })
+    }
+}
+
+/// 按偏好过滤和排序地址，同一地址族内保持解析顺序
+pub fn order_addrs(mut addrs: Vec<SocketAddr>, preference: IpPreference) -> Vec<SocketAddr> {
+    match preference {
+        IpPreference::System => {}
+        IpPreference::PreferIpv6 => addrs.sort_by_key(|addr| !addr.is_ipv6()),
+        IpPreference::PreferIpv4 => addrs.sort_by_key(|addr| !addr.is_ipv4()),
+        IpPreference::Ipv4Only => addrs.retain(|addr| addr.is_ipv4()),
+        IpPreference::Ipv6Only => addrs.retain(|addr| addr.is_ipv6()),
+    }
+    addrs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_order_addrs() {
+        let v4a: SocketAddr = "192.0.2.1:80".parse().unwrap();
+        let v4b: SocketAddr = "192.0.2.2:80".parse().unwrap();
+        let v6: SocketAddr = "[2001:db8::1]:80".parse().unwrap();
+        let addrs = vec![v4a, v6, v4b];
+
+        assert_eq!(order_addrs(addrs.clone(), IpPreference::PreferIpv6), vec![v6, v4a, v4b]);
+        assert_eq!(order_addrs(addrs.clone(), IpPreference::PreferIpv4), vec![v4a, v4b, v6]);
+        assert_eq!(order_addrs(addrs.clone(), IpPreference::Ipv6Only), vec![v6]);
+        assert_eq!(order_addrs(addrs.clone(), IpPreference::System), addrs);
+    }
+}
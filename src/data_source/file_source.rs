@@ -52,7 +52,7 @@ impl FileSource {
         
         // 确保开始位置不超过文件大小
         if start >= file_size {
-            return Err(ProxyError::Cache("请求范围超出文件大小".to_string()));
+            return Err(ProxyError::cache("请求范围超出文件大小".to_string()));
         }
         
         // 设置实际的结束位置
@@ -113,7 +113,7 @@ impl Stream for FileStream {
             }
             Poll::Ready(Err(e)) => {
                 this.file.take();
-                Poll::Ready(Some(Err(ProxyError::IO(e.to_string()))))
+                Poll::Ready(Some(Err(ProxyError::io(e.to_string()))))
             }
             Poll::Pending => Poll::Pending,
         }
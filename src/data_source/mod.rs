@@ -1,6 +1,8 @@
+pub mod connector;
 pub mod file_source;
 pub mod net_source;
 
+pub use connector::{IpPreference, UpstreamConnectConfig};
 pub use file_source::FileSource;
 pub use net_source::NetSource;
 
@@ -3,14 +3,17 @@ use std::time::Duration;
 use crate::log_info;
 use crate::{data_request::DataRequest, utils::error::ProxyError};
 use crate::utils::error::Result;
-use hyper::client::HttpConnector;
 use hyper::{Body, Response};
-use hyper_tls::HttpsConnector;
+use super::connector::{https_connector, UpstreamConnectConfig, UpstreamConnector};
 
 #[derive(Debug, Clone)]
 pub struct NetSource {
     pub url: String,
     pub range: String,
+    /// 额外的请求头
+    pub headers: Vec<(String, String)>,
+    /// 上游连接的地址族偏好
+    pub connect: UpstreamConnectConfig,
 }
 
 impl NetSource {
@@ -18,11 +21,23 @@ impl NetSource {
         Self {
             url: url.to_string(),
             range: range.to_string(),
+            headers: Vec::new(),
+            connect: UpstreamConnectConfig::default(),
         }
     }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn with_headers(mut self, headers: Vec<(String, String)>) -> Self {
+        self.headers.extend(headers);
+        self
+    }
     
     pub async fn download_stream(&self) -> Result<(Response<Body>, u64)> {
-        let https = HttpsConnector::new();
+        let https = https_connector(&self.connect);
         let client = hyper::Client::builder()
         .pool_idle_timeout(Duration::from_secs(10))
         .pool_max_idle_per_host(0)
@@ -34,7 +49,8 @@ impl NetSource {
                 Ok(result) => return Ok(result),
                 Err(e) => {
                     retries -= 1;
-                    if retries == 0 {
+                    // 源站 404 等确定性错误重试也不会成功
+                    if retries == 0 || !e.is_retryable() {
                         return Err(e);
                     }
                     tokio::time::sleep(Duration::from_secs(1)).await;
@@ -42,31 +58,42 @@ impl NetSource {
             }
         }
         
-        Err(ProxyError::Request("Max retries reached".into()))
+        Err(ProxyError::request("Max retries reached"))
     }
 
-    async fn try_download(&self, client: &hyper::Client<HttpsConnector<HttpConnector>>) -> Result<(Response<Body>, u64)> {
-        let req = DataRequest::new_request_with_range(&self.url, &self.range);
+    async fn try_download(&self, client: &hyper::Client<UpstreamConnector>) -> Result<(Response<Body>, u64)> {
+        let mut req = DataRequest::new_request_with_range(&self.url, &self.range);
+        for (name, value) in &self.headers {
+            if let (Ok(name), Ok(value)) = (
+                hyper::header::HeaderName::from_bytes(name.as_bytes()),
+                hyper::header::HeaderValue::from_str(value),
+            ) {
+                req.headers_mut().insert(name, value);
+            }
+        }
         let resp = client.request(req).await?;
         
         // 验证响应状态码
         if !resp.status().is_success() {
-            return Err(ProxyError::Request(format!("Invalid response status: {}", resp.status())));
+            return Err(ProxyError::upstream(resp.status(), format!("Invalid response status: {}", resp.status())).with_url(&self.url));
         }
     
-        // 获取并验证 Content-Length
+        // 获取并验证 Content-Length，电台等无限流没有该头，按 0 处理
         let content_length = match resp.headers().get(hyper::header::CONTENT_LENGTH) {
             Some(len) => len.to_str()
-                .map_err(|_| ProxyError::Request("Invalid content length header".into()))?
+                .map_err(|_| ProxyError::request("Invalid content length header"))?
                 .parse::<u64>()
-                .map_err(|_| ProxyError::Request("Invalid content length value".into()))?,
-            None => return Err(ProxyError::Request("Missing content length header".into()))
+                .map_err(|_| ProxyError::request("Invalid content length value"))?,
+            None => {
+                log_info!("Request", "响应没有 Content-Length: {}", self.url);
+                0
+            }
         };
     
         // 验证 Content-Range
         if let Some(range) = resp.headers().get(hyper::header::CONTENT_RANGE) {
             let range_str = range.to_str()
-                .map_err(|_| ProxyError::Request("Invalid content range header".into()))?;
+                .map_err(|_| ProxyError::request("Invalid content range header"))?;
             // 可以添加进一步的范围验证
             log_info!("Request", "Content-Range: {}", range_str);
         }
@@ -1,79 +1,969 @@
-use std::sync::Arc;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::pin::Pin;
 use std::path::PathBuf;
 use bytes::Bytes;
-use futures::{Stream, StreamExt};
-use hyper::{Body, Response};
+use futures::{SinkExt, Stream, StreamExt};
+use hyper::{Body, HeaderMap, Response};
+use hyper::header::{HeaderValue, ACCEPT_RANGES, CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_TYPE, ETAG, IF_RANGE, RANGE};
+use crate::cluster::{EntryAvailability, ParentShield, Replicator, ShardRouter, SiblingLookup, PEER_HEADER};
 use crate::data_request::DataRequest;
-use crate::utils::error::{Result, ProxyError};
-use crate::storage::{StorageManager, StorageManagerConfig, DiskStorage, StorageConfig};
-use crate::handlers::{CacheHandler, NetworkHandler, MixedSourceHandler, ResponseBuilder};
+use crate::utils::error::{ErrorKind, Result, ProxyError};
+use crate::config::ProxyConfig;
+use crate::storage::{StorageManager, CacheEngine, StorageConfig, EntryMeta, VerifyReport, CacheInspection, RangePlan, IoPermitStats, IndexStatus};
+use crate::handlers::{CacheHandler, NetworkHandler, MixedSourceHandler, ResponseBuilder, CachePolicy, without_admission, ParallelDownloader, FaultInjector, NamespaceConfig, PopularFill, PlaybackHint, ReadAheadConfig, SequentialDetector, UpstreamProfiles, is_unbounded_stream, read_ahead};
+use crate::handlers::active::ActiveDownloadInfo;
+use crate::handlers::namespace::key_prefix;
+use crate::handlers::scheduler::{self, Priority};
+use crate::handlers::send_buffer::{send_buffer, SlowClientStats, SlowClients};
+use crate::handlers::stats::{CacheSource, StatsOrder, StatsRegistry, UrlStats, SERVED_BYTES_HEADER, UPSTREAM_BYTES_HEADER};
+use crate::handlers::usage::{UsageLedger, UsageRecord, USAGE_FILE};
+use crate::handlers::alert::{AlertSink, Alerts};
+use crate::handlers::conditional::{entity_tag, if_range_matches, is_not_modified};
+use crate::handlers::download::content_disposition;
+use crate::handlers::explain::{Decision, DecisionLog, DecisionRecord};
+use crate::handlers::hooks::{aux_key, url_of, CompletionHook};
+use crate::media::{self, mp4, FastStartConfig, SniffConfig, SubtitleConfig};
+use crate::media::subtitle::{self, sidecar_url};
+use crate::hls::normalize_playlist;
+use crate::media::sniff::{sniff, SNIFF_LEN};
+use crate::storage::inspect::missing_ranges;
+use crate::utils::canonical::{canonicalize, sort_query};
+use crate::utils::range::{cap_span, resolve_range, RangeError};
+use crate::utils::request_id;
 use crate::log_info;
 
+/// 缓存命中情况响应头：HIT 完全命中，PARTIAL 部分命中，MISS 未命中
+pub const CACHE_STATUS_HEADER: &str = "x-cache";
+const CACHE_HIT: &str = "HIT";
+const CACHE_PARTIAL: &str = "PARTIAL";
+const CACHE_MISS: &str = "MISS";
+
+/// 校验报告文件名，写在缓存根目录下
+const VERIFY_REPORT_FILE: &str = "verify-report.json";
+
+/// 记录已探测字幕的视频数量上限，超过时清空重新记录
+const MAX_SUBTITLE_PROBED: usize = 10000;
+
 pub struct DataSourceManager {
     cache_handler: Arc<CacheHandler>,
     network_handler: NetworkHandler,
     mixed_source_handler: MixedSourceHandler,
     response_builder: ResponseBuilder,
+    cache_policy: Arc<CachePolicy>,
+    /// 上游的 ICY 电台响应直接透传
+    icy_passthrough: bool,
+    /// 缓存键按参数名排序查询参数
+    sort_query: bool,
+    /// 单个范围请求最多返回的字节数
+    max_range_span: Option<u64>,
+    /// 边下载边转发时客户端发送缓冲的高、低水位
+    send_buffer_watermarks: (usize, usize),
+    slow_clients: Arc<SlowClients>,
+    fast_start: FastStartConfig,
+    parallel_downloader: ParallelDownloader,
+    mp4_prefetching: Arc<Mutex<HashSet<String>>>,
+    sequential: SequentialDetector,
+    reading_ahead: Arc<Mutex<HashSet<String>>>,
+    caching_paused: AtomicBool,
+    /// 校验报告输出路径
+    verify_report_path: PathBuf,
+    stats: Arc<StatsRegistry>,
+    alerts: Arc<Alerts>,
+    siblings: SiblingLookup,
+    shards: ShardRouter,
+    replicator: Arc<Replicator>,
+    popular: Arc<PopularFill>,
+    shield: ParentShield,
+    namespaces: NamespaceConfig,
+    sniff: SniffConfig,
+    subtitles: SubtitleConfig,
+    /// 最近的缓存命中决策
+    decisions: DecisionLog,
+    /// 已探测过字幕的视频 URL
+    subtitle_probed: Arc<Mutex<HashSet<String>>>,
 }
 
 impl DataSourceManager {
     pub fn new(cache_dir: PathBuf) -> Self {
+        Self::with_config(cache_dir, ProxyConfig::default())
+    }
+
+    pub fn with_config(cache_dir: PathBuf, config: ProxyConfig) -> Self {
         log_info!("Cache", "初始化数据源管理器，缓存目录: {:?}", cache_dir);
         
-        let storage_config = StorageConfig {
-            root_path: cache_dir.clone(),
-            chunk_size: 8192,
+        let manager_config = config.storage;
+
+        // 启用分层时，大容量层作为缓存根目录，未单独配置时就是缓存目录
+        let storage_config = match &manager_config.tiering {
+            Some(tiering) => StorageConfig {
+                root_path: tiering.slow_path.clone().unwrap_or_else(|| cache_dir.clone()),
+                chunk_size: 8192,
+                fast_root_path: Some(tiering.fast_path.clone()),
+                layout: manager_config.layout,
+                mmap: manager_config.mmap_reads,
+                mmap_threshold: manager_config.mmap_threshold,
+            },
+            None => StorageConfig {
+                root_path: cache_dir.clone(),
+                chunk_size: 8192,
+                fast_root_path: None,
+                layout: manager_config.layout,
+                mmap: manager_config.mmap_reads,
+                mmap_threshold: manager_config.mmap_threshold,
+            },
         };
+        let verify_report_path = storage_config.root_path.join(VERIFY_REPORT_FILE);
+        let usage = Arc::new(UsageLedger::load(storage_config.root_path.join(USAGE_FILE)));
         
-        let manager_config = StorageManagerConfig::default();
-        let storage_engine = DiskStorage::new(storage_config);
+        let storage_engine = CacheEngine::new(storage_config);
         let storage_manager = Arc::new(StorageManager::new(storage_engine, manager_config));
         
-        let cache_handler = Arc::new(CacheHandler::new(storage_manager));
-        let network_handler = NetworkHandler::new();
-        let mixed_source_handler = MixedSourceHandler::new(cache_handler.clone());
-        let response_builder = ResponseBuilder::new();
+        let faults = Arc::new(FaultInjector::new(config.faults));
+        let alerts = Arc::new(Alerts::new(config.alerts));
+        let cache_handler = Arc::new(
+            CacheHandler::new(storage_manager)
+                .with_namespace_quota(config.namespaces.quota)
+                .with_faults(faults.clone())
+                .with_alerts(alerts.clone()),
+        );
+        let network_handler = NetworkHandler::with_mirrors(config.mirrors)
+            .with_alerts(alerts.clone())
+            .with_profiles(config.upstream)
+            .with_scheduler(config.scheduler)
+            .with_coalesce(config.coalesce)
+            .with_watchdog(config.watchdog)
+            .with_connect(config.upstream_connect)
+            .with_faults(faults);
+        let sort_query = config.cache_policy.sort_query_params;
+        let mixed_revalidate_after = config.cache_policy.mixed_revalidate_after;
+        let cache_policy = Arc::new(CachePolicy::new(config.cache_policy));
+        let mixed_source_handler = MixedSourceHandler::new(cache_handler.clone(), network_handler.clone())
+            .with_revalidate_after(mixed_revalidate_after)
+            .with_policy(cache_policy.clone());
+        let parallel_downloader = ParallelDownloader::new(config.parallel_download, network_handler.clone());
+        let response_builder = ResponseBuilder::with_policy(config.headers);
+        let replicator = Replicator::new(config.cluster.replication.clone(), config.cluster.peer_secret.clone(), cache_handler.clone());
+        let popular = PopularFill::new(config.popularity, cache_handler.clone(), network_handler.clone());
         
         Self {
             cache_handler,
             network_handler,
             mixed_source_handler,
             response_builder,
+            cache_policy,
+            icy_passthrough: config.passthrough.icy,
+            sort_query,
+            max_range_span: config.limits.max_range_span,
+            send_buffer_watermarks: (config.connection.send_buffer_high, config.connection.send_buffer_low),
+            slow_clients: Arc::new(SlowClients::default()),
+            fast_start: config.fast_start,
+            parallel_downloader,
+            mp4_prefetching: Arc::new(Mutex::new(HashSet::new())),
+            sequential: SequentialDetector::new(config.read_ahead),
+            reading_ahead: Arc::new(Mutex::new(HashSet::new())),
+            caching_paused: AtomicBool::new(false),
+            verify_report_path,
+            stats: Arc::new(StatsRegistry::new().with_usage(usage)),
+            alerts,
+            replicator,
+            popular,
+            shards: ShardRouter::new(&config.cluster),
+            shield: ParentShield::new(&config.cluster),
+            namespaces: config.namespaces,
+            sniff: config.sniff,
+            subtitles: config.subtitles,
+            decisions: DecisionLog::default(),
+            subtitle_probed: Arc::new(Mutex::new(HashSet::new())),
+            siblings: SiblingLookup::new(config.cluster),
+        }
+    }
+    
+    /// 获取缓存命中时返回的响应头和文件总大小，优先使用保存的上游响应头
+    async fn cached_headers(&self, url: &str, key: &str) -> Result<(HeaderMap, u64)> {
+        if let Some(meta) = self.cache_handler.get_meta(key).await {
+            if let Some(total_size) = meta.content_length {
+                let mut headers = Self::entry_headers(&meta);
+                if self.sniff.should_sniff(headers.get(CONTENT_TYPE)) {
+                    self.correct_content_type(key, &meta, &mut headers).await;
+                }
+                self.response_builder.apply_age(&mut headers, meta.fetched_at);
+                return Ok((headers, total_size));
+            }
+        }
+
+        // 旧缓存没有保存响应头，向上游获取
+        log_info!("Cache", "缺少保存的响应头，向上游获取: {}", url);
+        let (resp, _, total_size) = self.network_handler.fetch(url, "bytes=0-0").await?;
+        let headers = self.network_handler.extract_headers(&resp);
+        if total_size > 0 {
+            self.cache_handler.save_headers(key, resp.headers(), Some(total_size)).await?;
+        }
+        Ok((headers, total_size))
+    }
+    
+    /// 按缓存数据的文件头修正 Content-Type，并保存修正结果，后续命中不再检测
+    async fn correct_content_type(&self, key: &str, meta: &EntryMeta, headers: &mut HeaderMap) {
+        let cached = match self.cache_handler.get_size(key).await {
+            Ok(Some(size)) if size > 0 => size,
+            _ => return,
+        };
+        let mut stream = match self.cache_handler.read(key, (0, cached.min(SNIFF_LEN) - 1)).await {
+            Ok(stream) => stream,
+            Err(_) => return,
+        };
+        let mut head = Vec::new();
+        while let Some(Ok(chunk)) = stream.next().await {
+            head.extend_from_slice(&chunk);
         }
+
+        let content_type = match sniff(&head) {
+            Some(content_type) => HeaderValue::from_static(content_type),
+            None => return,
+        };
+        log_info!("Cache", "修正内容类型: {} {:?} -> {:?}", key, headers.get(CONTENT_TYPE), content_type);
+        headers.insert(CONTENT_TYPE, content_type.clone());
+
+        let mut stored = meta.header_map();
+        stored.insert(CONTENT_TYPE, content_type);
+        if let Err(e) = self.cache_handler.save_headers(key, &stored, meta.content_length).await {
+            log_info!("Cache", "保存修正的内容类型失败: {} - {}", key, e);
+        }
+    }
+    
+    /// 从预取的 MP4 尾部缓存读取，请求范围必须完全落在尾部内
+    async fn serve_mp4_tail(&self, url: &str, key: &str, start: u64, end: u64) -> Result<Option<Response<Body>>> {
+        if !self.fast_start.enabled || !mp4::is_mp4(url) {
+            return Ok(None);
+        }
+
+        let tail_key = mp4::tail_key(key);
+        let tail_meta = match self.cache_handler.get_meta(&tail_key).await {
+            Some(meta) => meta,
+            None => return Ok(None),
+        };
+        let total_size = match tail_meta.content_length {
+            Some(total_size) if total_size > 0 => total_size,
+            _ => return Ok(None),
+        };
+        let tail_size = match self.cache_handler.get_size(&tail_key).await? {
+            Some(size) if size > 0 && size <= total_size => size,
+            _ => return Ok(None),
+        };
+
+        let tail_start = total_size - tail_size;
+        let end = end.min(total_size - 1);
+        if start < tail_start || start > end {
+            return Ok(None);
+        }
+
+        log_info!("Media", "从 MP4 尾部缓存读取: {} 范围: {}-{}", url, start, end);
+        let stream = self.cache_handler.read(&tail_key, (start - tail_start, end - tail_start)).await?;
+        let headers = match self.cache_handler.get_meta(key).await {
+            Some(meta) if meta.content_length.is_some() => Self::entry_headers(&meta),
+            _ => tail_meta.header_map(),
+        };
+
+        Ok(Some(self.response_builder.build_partial_content_response(
+            stream,
+            headers,
+            start,
+            end,
+            total_size,
+        )))
+    }
+    
+    /// 首次请求 MP4 文件时在后台检测 moov 位置并预取头尾数据
+    fn start_mp4_prefetch(&self, url: &str, key: &str, fetch_head: bool, total_size: u64) {
+        if !self.fast_start.enabled || !mp4::is_mp4(url) || total_size == 0 {
+            return;
+        }
+        if !self.mp4_prefetching.lock().unwrap().insert(key.to_string()) {
+            return;
+        }
+
+        let url = url.to_string();
+        let key = key.to_string();
+        let config = self.fast_start.clone();
+        let cache_handler = self.cache_handler.clone();
+        let network_handler = self.network_handler.clone();
+        let prefetching = self.mp4_prefetching.clone();
+        request_id::spawn(async move {
+            let prefetch = media::prefetch_mp4(&cache_handler, &network_handler, &url, &key, fetch_head, total_size, &config);
+            if let Err(e) = scheduler::with_priority(Priority::Background, prefetch).await {
+                log_info!("Media", "MP4 预取失败: {} - {}", url, e);
+            }
+            prefetching.lock().unwrap().remove(&key);
+        });
+    }
+    
+    /// 获取 m3u8 播放列表，与其他请求共用上游连接池、镜像和请求头配置。
+    /// 带 `#EXT-X-ENDLIST` 的点播列表内容不再变化，写入缓存；直播列表每次从上游获取
+    pub async fn fetch_playlist(&self, url: &str) -> Result<String> {
+        let url = &canonicalize(url);
+        let key = self.key_url(url);
+        if let Some(size) = self.cache_handler.get_meta(&key).await.and_then(|meta| meta.content_length) {
+            if size > 0 && self.cache_handler.get_size(&key).await? == Some(size) {
+                log_info!("HLS", "从缓存读取播放列表: {}", url);
+                let mut stream = self.cache_handler.read(&key, (0, size - 1)).await?;
+                let mut data = Vec::with_capacity(size as usize);
+                while let Some(chunk) = stream.next().await {
+                    data.extend_from_slice(&chunk?);
+                }
+                return Ok(normalize_playlist(&data));
+            }
+        }
+        
+        let (resp, _, _) = self.network_handler.fetch(url, "bytes=0-").await?;
+        if !resp.status().is_success() {
+            return Err(ProxyError::upstream(resp.status(), format!("请求失败: {}", resp.status())).with_url(url));
+        }
+        let upstream_headers = resp.headers().clone();
+        let headers = self.network_handler.extract_headers(&resp);
+        let body = hyper::body::to_bytes(resp.into_body()).await?;
+        let content = normalize_playlist(&body);
+        
+        let size = body.len() as u64;
+        let cacheable = size > 0
+            && content.contains("#EXT-X-ENDLIST")
+            && !self.is_caching_paused()
+            && self.cache_policy.should_cache(url, &upstream_headers, size);
+        if cacheable {
+            let stream = Box::pin(futures::stream::once(async move { Ok(body) }));
+            let stored = async {
+                self.cache_handler.write_stream(&key, (0, size - 1), stream).await?;
+                self.cache_handler.save_headers(&key, &headers, Some(size)).await?;
+                self.cache_handler.complete(&key, size).await
+            };
+            match stored.await {
+                Ok(()) => log_info!("HLS", "点播列表已缓存: {} {} 字节", url, size),
+                Err(e) => log_info!("HLS", "缓存播放列表失败: {} - {}", url, e),
+            }
+        }
+        Ok(content)
+    }
+    
+    /// 首次请求视频时在后台探测同名字幕文件，每个视频只探测一次
+    fn start_subtitle_probe(&self, url: &str) {
+        if !self.subtitles.enabled || !self.subtitles.is_video(url) {
+            return;
+        }
+        {
+            let mut probed = self.subtitle_probed.lock().unwrap();
+            if probed.len() >= MAX_SUBTITLE_PROBED {
+                probed.clear();
+            }
+            if !probed.insert(url.to_string()) {
+                return;
+            }
+        }
+
+        let candidates: Vec<(String, String)> = self
+            .subtitles
+            .extensions
+            .iter()
+            .filter_map(|ext| sidecar_url(url, ext))
+            .map(|subtitle_url| {
+                let key = self.key_url(&subtitle_url);
+                (subtitle_url, key)
+            })
+            .collect();
+        let config = self.subtitles.clone();
+        let cache_handler = self.cache_handler.clone();
+        let network_handler = self.network_handler.clone();
+        request_id::spawn(async move {
+            let probe = subtitle::probe_subtitles(&cache_handler, &network_handler, candidates, &config);
+            scheduler::with_priority(Priority::Background, probe).await;
+        });
+    }
+    
+    /// 视频的同名字幕，优先使用缓存，扩展名未配置或视频地址没有扩展名时返回 None
+    pub async fn serve_subtitle(&self, video_url: &str, ext: &str) -> Result<Option<Response<Body>>> {
+        if !self.subtitles.is_subtitle_extension(ext) {
+            return Ok(None);
+        }
+        let subtitle_url = match sidecar_url(&canonicalize(video_url), ext) {
+            Some(subtitle_url) => subtitle_url,
+            None => return Ok(None),
+        };
+        let req = hyper::Request::builder().uri(subtitle_url.as_str()).body(Body::empty())?;
+        let mut response = self.process_request(&DataRequest::new(&req)?).await?;
+        response
+            .headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_static(subtitle::content_type(ext)));
+        Ok(Some(response))
+    }
+    
+    /// 在后台预先缓存 `position` 之后 `window` 字节的数据，同一条目同时只有一个预读任务
+    fn start_read_ahead(&self, url: &str, key: &str, position: u64, total_size: u64, window: u64) {
+        if total_size == 0 || self.is_caching_paused() {
+            return;
+        }
+        if !self.reading_ahead.lock().unwrap().insert(key.to_string()) {
+            return;
+        }
+
+        let url = url.to_string();
+        let key = key.to_string();
+        let cache_handler = self.cache_handler.clone();
+        let network_handler = self.network_handler.clone();
+        let reading_ahead = self.reading_ahead.clone();
+        request_id::spawn(async move {
+            if let Err(e) = read_ahead(&cache_handler, &network_handler, &url, &key, position, total_size, window).await {
+                log_info!("Cache", "预读失败: {} - {}", url, e);
+            }
+            reading_ahead.lock().unwrap().remove(&key);
+        });
+    }
+    
+    /// 缓存条目的响应头，总是带上 ETag
+    fn entry_headers(meta: &EntryMeta) -> HeaderMap {
+        let mut headers = meta.header_map();
+        if !headers.contains_key(ETAG) {
+            if let Ok(etag) = entity_tag(meta).parse() {
+                headers.insert(ETAG, etag);
+            }
+        }
+        headers
+    }
+    
+    /// 暂停或恢复缓存写入，暂停期间未命中的请求直接转发，已缓存的数据照常使用
+    pub fn set_caching_paused(&self, paused: bool) {
+        self.caching_paused.store(paused, Ordering::Relaxed);
+        log_info!("Cache", "缓存写入已{}", if paused { "暂停" } else { "恢复" });
+    }
+    
+    /// 手动暂停、缓存目录不可用或索引正在加载时返回 true
+    pub fn is_caching_paused(&self) -> bool {
+        self.caching_paused.load(Ordering::Relaxed) || !self.is_cache_online() || self.cache_handler.is_index_loading()
+    }
+    
+    /// 索引加载进度，索引加载完成且缓存目录可用时就绪
+    pub fn index_status(&self) -> IndexStatus {
+        self.cache_handler.index_status()
+    }
+    
+    /// 缓存目录是否可用，不可用时所有请求直接转发到源站
+    pub fn is_cache_online(&self) -> bool {
+        self.cache_handler.is_online()
+    }
+    
+    /// 上游请求头配置
+    pub fn upstream_profiles(&self) -> &UpstreamProfiles {
+        self.network_handler.profiles()
+    }
+
+    /// 正在进行的上游下载
+    pub fn active_downloads(&self) -> Vec<ActiveDownloadInfo> {
+        self.network_handler.active_downloads()
+    }
+    
+    /// 取消正在进行的上游下载
+    pub fn cancel_download(&self, id: u64) -> bool {
+        self.network_handler.cancel_download(id)
+    }
+    
+    /// 立即执行一次缓存清理
+    pub async fn cleanup(&self) -> (u64, usize) {
+        self.cache_handler.cleanup().await
+    }
+    
+    /// 校验缓存一致性并将报告写入缓存目录，用于非正常退出后检查
+    pub async fn verify(&self, repair: bool) -> Result<VerifyReport> {
+        let report = self.cache_handler.verify(repair).await;
+        tokio::fs::write(&self.verify_report_path, serde_json::to_vec_pretty(&report)?).await?;
+        log_info!("Cache", "校验报告已写入: {:?}", self.verify_report_path);
+        Ok(report)
+    }
+    
+    /// 查看 URL 的缓存情况，没有任何缓存数据时返回 None
+    pub async fn inspect(&self, url: &str) -> Option<CacheInspection> {
+        let url = &canonicalize(url);
+        let key = self.key_url(url);
+        let entry = self.cache_handler.inspect(&key).await;
+        let tail = self.cache_handler.inspect(&mp4::tail_key(&key)).await;
+        if entry.is_none() && tail.is_none() {
+            return None;
+        }
+
+        let total_size = entry
+            .iter()
+            .chain(tail.iter())
+            .find_map(|info| info.meta.content_length);
+        let mut ranges = Vec::new();
+        if let Some(info) = entry.as_ref().filter(|info| info.cached_bytes > 0) {
+            ranges.push((0, info.cached_bytes - 1));
+        }
+        // 尾部缓存从文件末尾倒数
+        if let (Some(info), Some(total)) = (&tail, total_size) {
+            if info.cached_bytes > 0 && info.cached_bytes <= total {
+                ranges.push((total - info.cached_bytes, total - 1));
+            }
+        }
+        let gaps = total_size.map(|total| missing_ranges(&ranges, total)).unwrap_or_default();
+
+        Some(CacheInspection {
+            url: url.to_string(),
+            key,
+            total_size,
+            ranges,
+            gaps,
+            entry,
+            tail,
+        })
+    }
+    
+    /// URL 最近的缓存命中决策及原因，从新到旧
+    pub fn decisions(&self, url: &str, range: Option<(u64, u64)>) -> Vec<DecisionRecord> {
+        self.decisions.for_url(&canonicalize(url), range)
+    }
+    
+    /// URL 的内容是否已全部缓存
+    pub async fn is_complete(&self, url: &str) -> bool {
+        match self.inspect(url).await {
+            Some(inspection) => inspection.total_size.is_some() && !inspection.ranges.is_empty() && inspection.gaps.is_empty(),
+            None => false,
+        }
+    }
+    
+    /// 请求范围中哪些部分会从缓存读取、哪些需要从网络获取，范围超出文件大小时截断
+    pub async fn plan(&self, url: &str, start: u64, end: u64) -> RangePlan {
+        let (ranges, total_size) = match self.inspect(url).await {
+            Some(inspection) => (inspection.ranges, inspection.total_size),
+            None => (Vec::new(), None),
+        };
+        let end = match total_size {
+            Some(total) => end.min(total.saturating_sub(1)),
+            None => end,
+        };
+        RangePlan::new(url, start, end, total_size, &ranges)
+    }
+    
+    /// 完整下载一个 URL 写入缓存，返回读取的字节数
+    pub async fn prefetch(&self, url: &str) -> Result<u64> {
+        // 显式预取的 URL 不需要等第二次请求才写入缓存
+        without_admission(self.prefetch_with_priority(url, Priority::Background)).await
+    }
+    
+    /// 按指定优先级完整下载一个 URL 写入缓存，返回读取的字节数
+    pub async fn prefetch_with_priority(&self, url: &str, priority: Priority) -> Result<u64> {
+        let req = DataRequest::new(&DataRequest::new_request_with_range(url, "bytes=0-"))?;
+        let mut body = scheduler::with_priority(priority, self.process_request(&req)).await?.into_body();
+        let mut total = 0u64;
+        while let Some(chunk) = body.next().await {
+            total += chunk?.len() as u64;
+        }
+        Ok(total)
     }
     
     pub async fn process_request(&self, req: &DataRequest) -> Result<Response<Body>> {
         let url = req.get_url();
-        let range = req.get_range();
-        let key = url.to_string();
-        let (start, end) = crate::utils::range::parse_range(&range)?;
+        
+        // 携带凭据的请求使用独立的命名空间
+        let key = match self.namespaces.namespace_for(req.get_headers(), url) {
+            Some(namespace) => format!("{}{}", key_prefix(&namespace), self.key_url(url)),
+            None => self.key_url(url),
+        };
+        
+        self.popular.record(&key, url);
+        if !key.starts_with("ns:") {
+            self.start_subtitle_probe(url);
+        }
+        
+        // 来自其他节点的请求不再转发或询问同组节点，避免循环
+        let allow_peers = !req.get_headers().contains_key(PEER_HEADER);
+        
+        // 由其他分片节点负责的键转发过去，节点不可用时在本地处理
+        if allow_peers {
+            if let Some(owner) = self.shards.remote_owner(&key) {
+                match self.shards.forward(owner, url, req.get_headers()).await {
+                    Ok(response) => return Ok(response),
+                    Err(e) => log_info!("Cache", "分片节点不可用，本地处理: {} - {}", owner, e),
+                }
+            }
+        }
+        
+        // 客户端缓存仍然有效时返回 304
+        if let Some(meta) = self.cache_handler.get_meta(&key).await {
+            if is_not_modified(req.get_headers(), &meta) {
+                log_info!("Cache", "客户端缓存未修改，返回 304: {}", url);
+                return Ok(self.response_builder.build_not_modified_response(Self::entry_headers(&meta)));
+            }
+        }
+        
+        // If-Range 校验值不匹配时忽略 Range，返回完整内容
+        if let Some(if_range) = req.get_headers().get(IF_RANGE) {
+            if req.get_headers().contains_key(RANGE) {
+                let meta = self.cache_handler.get_meta(&key).await;
+                if !if_range_matches(if_range.to_str()?, meta.as_ref()) {
+                    log_info!("Cache", "If-Range 不匹配，返回完整内容: {}", url);
+                    let response = self.serve_range(url, &key, "bytes=0-", None, req.get_headers()).await?;
+                    return Ok(self.response_builder.into_full_response(response));
+                }
+            }
+        }
+        
+        // 只截断客户端显式请求的范围，没有 Range 头的请求需要返回完整内容；暂停缓存直接转发时同样截断
+        let max_span = self.max_range_span.filter(|_| req.has_range());
+        let response = self.serve_range(url, &key, req.get_range(), max_span, req.get_headers()).await?;
+        
+        // 客户端没有请求范围时返回 200 而不是 206
+        if !req.has_range() {
+            return Ok(self.response_builder.into_full_response(response));
+        }
+        
+        Ok(response)
+    }
+    
+    /// 以附件形式下载，与播放共用同一份缓存，支持断点续传。
+    /// 未命中时上游没有返回 ETag 的，使用缓存条目生成的 ETag，保证续传时的 If-Range 校验可用
+    pub async fn download(&self, req: &DataRequest) -> Result<Response<Body>> {
+        let url = req.get_url();
+        let mut response = self.process_request(req).await?;
+        
+        let headers = response.headers_mut();
+        if !headers.contains_key(ETAG) {
+            if let Some(meta) = self.cache_handler.get_meta(&self.cache_key(req.get_headers(), url)).await {
+                if let Ok(etag) = entity_tag(&meta).parse() {
+                    headers.insert(ETAG, etag);
+                }
+            }
+        }
+        headers.insert(ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+        headers.insert(CONTENT_DISPOSITION, content_disposition(url));
+        log_info!("Cache", "下载: {}", url);
+        Ok(response)
+    }
+    
+    /// HEAD 请求只返回响应头：缓存中有完整的响应头时直接使用，否则只向上游请求一个字节
+    pub async fn head(&self, req: &DataRequest) -> Result<Response<Body>> {
+        let url = req.get_url();
+        let key = self.cache_key(req.get_headers(), url);
+        if let Some(meta) = self.cache_handler.get_meta(&key).await {
+            if is_not_modified(req.get_headers(), &meta) {
+                return Ok(self.response_builder.build_not_modified_response(Self::entry_headers(&meta)));
+            }
+        }
+        
+        let (headers, total_size) = self.cached_headers(url, &key).await?;
+        let mut response = self.response_builder.build_head_response(headers, total_size);
+        if req.is_download() {
+            response.headers_mut().insert(CONTENT_DISPOSITION, content_disposition(url));
+        }
+        log_info!("Cache", "HEAD: {} 大小: {}", url, total_size);
+        Ok(response)
+    }
+    
+    /// 请求对应的缓存键，携带凭据的请求带命名空间前缀
+    fn cache_key(&self, headers: &HeaderMap, url: &str) -> String {
+        match self.namespaces.namespace_for(headers, url) {
+            Some(namespace) => format!("{}{}", key_prefix(&namespace), self.key_url(url)),
+            None => self.key_url(url),
+        }
+    }
+    
+    /// URL 部分的缓存键，`url` 已经规范化
+    fn key_url(&self, url: &str) -> String {
+        if self.sort_query {
+            sort_query(url)
+        } else {
+            url.to_string()
+        }
+    }
+    
+    /// 读取 URL 的用户元数据，条目不存在或没有设置时返回 None
+    pub async fn user_meta(&self, url: &str) -> Option<serde_json::Value> {
+        let key = self.key_url(&canonicalize(url));
+        self.cache_handler.get_meta(&key).await.and_then(|meta| meta.user)
+    }
+    
+    /// 设置或清除（`None`）URL 的用户元数据，与缓存条目一起持久化，条目被清理时一并删除。
+    /// URL 没有缓存条目时返回 false
+    pub async fn set_user_meta(&self, url: &str, user: Option<serde_json::Value>) -> Result<bool> {
+        let key = self.key_url(&canonicalize(url));
+        self.cache_handler.set_user_meta(&key, user).await
+    }
+    
+    /// 注册下载完成回调，回调返回的附属数据通过 `/meta/<名称>/<编码后的 URL>` 访问
+    pub fn register_hook(&self, hook: Arc<dyn CompletionHook>) {
+        self.cache_handler.register_hook(hook);
+    }
+    
+    /// 注册告警接收方，与配置的 webhook 一起通知
+    pub fn register_alert_sink(&self, sink: Arc<dyn AlertSink>) {
+        self.alerts.register(sink);
+    }
+    
+    /// 读取完成回调生成的附属条目，不存在时返回 None
+    pub async fn serve_aux(&self, name: &str, url: &str, headers: &HeaderMap) -> Result<Option<Response<Body>>> {
+        let key = aux_key(&self.cache_key(headers, &canonicalize(url)), name);
+        let meta = match self.cache_handler.get_meta(&key).await {
+            Some(meta) => meta,
+            None => return Ok(None),
+        };
+        let size = match (meta.content_length, self.cache_handler.get_size(&key).await?) {
+            (Some(total), Some(size)) if size == total && size > 0 => size,
+            _ => return Ok(None),
+        };
+        
+        let stream = self.cache_handler.read(&key, (0, size - 1)).await?;
+        let mut response = Response::new(Body::wrap_stream(stream));
+        let response_headers = response.headers_mut();
+        response_headers.extend(meta.header_map());
+        response_headers.insert(CONTENT_LENGTH, HeaderValue::from(size));
+        Ok(Some(response))
+    }
+    
+    /// 标记响应的缓存命中情况并统计流量。
+    /// 长度已知时附带 `X-Upstream-Bytes` 和 `X-Served-Bytes`，响应头在发送数据前确定，按缓存命中情况计算
+    fn finish_response(&self, url: &str, response: Response<Body>, source: CacheSource) -> Response<Body> {
+        let status = match source {
+            CacheSource::Hit => CACHE_HIT,
+            CacheSource::Partial(_) => CACHE_PARTIAL,
+            CacheSource::Miss => CACHE_MISS,
+        };
+        self.alerts.record_hit(source == CacheSource::Hit);
+        let (mut parts, body) = response.into_parts();
+        self.response_builder.apply_header_policy(&mut parts.headers);
+        parts.headers.insert(CACHE_STATUS_HEADER, HeaderValue::from_static(status));
+        let served = parts
+            .headers
+            .get(CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok());
+        if let Some(served) = served {
+            parts.headers.insert(UPSTREAM_BYTES_HEADER, HeaderValue::from(source.upstream_bytes(served)));
+            parts.headers.insert(SERVED_BYTES_HEADER, HeaderValue::from(served));
+        }
+        Response::from_parts(parts, Body::wrap_stream(self.stats.track(url, source, Box::pin(body))))
+    }
+
+    /// 命名空间的缓存大小和条目数
+    pub async fn namespace_usage(&self, namespace: &str) -> (u64, usize) {
+        self.cache_handler.prefix_usage(&key_prefix(namespace)).await
+    }
+
+    /// 清除命名空间下的全部缓存
+    pub async fn purge_namespace(&self, namespace: &str) -> (u64, usize) {
+        self.cache_handler.purge_prefix(&key_prefix(namespace)).await
+    }
+
+    /// 本节点的缓存情况，供其他节点查询
+    pub async fn availability(&self, key: &str) -> Result<EntryAvailability> {
+        let key = self.peer_key(key);
+        let cached_bytes = self.cache_handler.get_size(&key).await?.unwrap_or(0);
+        let meta = self.cache_handler.get_meta(&key).await;
+        Ok(EntryAvailability {
+            cached_bytes,
+            total_size: meta.as_ref().and_then(|meta| meta.content_length),
+            etag: meta
+                .as_ref()
+                .and_then(|meta| meta.header_map().get(ETAG).and_then(|v| v.to_str().ok()).map(|v| v.to_string())),
+        })
+    }
+
+    /// 其他节点传来的缓存键按 `process_request` 的方式规范化，命名空间前缀保持不变
+    fn peer_key(&self, key: &str) -> String {
+        let url = url_of(key);
+        let prefix = &key[..key.len() - url.len()];
+        format!("{}{}", prefix, self.key_url(&canonicalize(url)))
+    }
+
+    /// 接收其他节点推送的数据，`offset` 必须等于本节点已缓存的大小
+    pub async fn receive_entry(&self, key: &str, offset: u64, replace: bool, body: Body) -> Result<u64> {
+        let key = self.peer_key(key);
+        if replace && offset == 0 {
+            self.cache_handler.remove(&key).await;
+        }
+        let cached_bytes = self.cache_handler.get_size(&key).await?.unwrap_or(0);
+        if offset != cached_bytes {
+            return Err(ProxyError::cache(format!("写入位置 {} 与已缓存大小 {} 不一致", offset, cached_bytes)));
+        }
+
+        let stream = Box::pin(body.map(|chunk| chunk.map_err(|e| ProxyError::network(e.to_string()))));
+        self.cache_handler.write_stream(&key, (offset, u64::MAX), stream).await?;
+        Ok(self.cache_handler.get_size(&key).await?.unwrap_or(0))
+    }
+
+    /// 接收其他节点推送的元数据，数据完整时标记下载完成
+    pub async fn receive_meta(&self, meta: EntryMeta) -> Result<()> {
+        let key = self.peer_key(&meta.key);
+        self.cache_handler.save_headers(&key, &meta.header_map(), meta.content_length).await?;
+        if let Some(total) = meta.content_length {
+            self.cache_handler.complete(&key, total).await?;
+        }
+        Ok(())
+    }
+
+    /// 按请求数、命中数或流量排序的 URL 统计
+    pub fn top_stats(&self, limit: usize, order: StatsOrder) -> Vec<UrlStats> {
+        self.stats.top(limit, order)
+    }
+
+    /// 发送缓冲达到高水位的慢客户端统计
+    pub fn slow_clients(&self) -> SlowClientStats {
+        self.slow_clients.stats()
+    }
+    
+    pub fn read_ahead_config(&self) -> &ReadAheadConfig {
+        self.sequential.config()
+    }
+    
+    /// 按 URL 的请求和压缩统计
+    pub fn stats(&self) -> &StatsRegistry {
+        &self.stats
+    }
+
+    /// 按天和源站主机统计的上下行流量
+    pub fn usage(&self, since: Option<&str>, host: Option<&str>) -> Vec<UsageRecord> {
+        self.stats.usage().report(since, host)
+    }
+    
+    /// 将缓存用量、热门 URL、存储许可和正在进行的下载输出到日志，并保存流量统计
+    pub async fn log_snapshot(&self) {
+        let usage = self.stats.usage().clone();
+        if let Err(e) = tokio::task::spawn_blocking(move || usage.save()).await {
+            log_info!("Stats", "保存流量统计失败: {}", e);
+        }
+        
+        let (bytes, entries) = self.cache_handler.prefix_usage("").await;
+        log_info!("Stats", "缓存用量: {} 个条目, {} 字节, 缓存写入{}", entries, bytes, if self.is_caching_paused() { "已暂停" } else { "正常" });
+        
+        for url_stats in self.top_stats(10, StatsOrder::Requests) {
+            log_info!(
+                "Stats",
+                "{} - 请求 {} 次, 命中 {} 次, 部分命中 {} 次, 缓存 {} 字节, 网络 {} 字节",
+                url_stats.url,
+                url_stats.requests,
+                url_stats.hits,
+                url_stats.partial_hits,
+                url_stats.cache_bytes,
+                url_stats.network_bytes
+            );
+        }
+        
+        let permits = self.storage_permits();
+        for (name, stats) in [("读取", &permits.reads), ("写入", &permits.writes)] {
+            log_info!(
+                "Stats",
+                "存储{}许可: 使用 {}/{}, 等待 {}, 超时 {} 次",
+                name,
+                stats.in_use,
+                stats.limit,
+                stats.waiting,
+                stats.timeouts
+            );
+        }
+        
+        let slow = self.slow_clients();
+        log_info!("Stats", "慢客户端: 当前等待 {} 个, 累计 {} 个响应, 暂停转发 {} 次", slow.stalled, slow.slow_responses, slow.pauses);
+        
+        let downloads = self.active_downloads();
+        log_info!("Stats", "正在进行的下载: {} 个", downloads.len());
+        for download in downloads {
+            log_info!(
+                "Stats",
+                "#{} {} {} - {} 字节, {:.1} 秒, {} 字节/秒",
+                download.id,
+                download.url,
+                download.range,
+                download.bytes,
+                download.elapsed_secs,
+                download.speed
+            );
+        }
+    }
+    
+    /// 存储读写许可的饱和度
+    pub fn storage_permits(&self) -> IoPermitStats {
+        self.cache_handler.permit_stats()
+    }
+    
+    /// 处理范围请求，错误中附带 URL 和字节范围。
+    /// 按文件大小处理后缀范围并校验，未缓存大小时先向上游探测；上游使用规范化后的单个范围。
+    /// 不支持 multipart/byteranges，多个范围合并为覆盖全部范围的一个区间返回，
+    /// 例如 `bytes=0-9,1000-1009` 返回 0-1009 共 1010 字节。
+    /// `max_span` 限制单次返回的字节数，超出时截断结束位置
+    async fn serve_range(&self, url: &str, key: &str, range: &str, max_span: Option<u64>, req_headers: &HeaderMap) -> Result<Response<Body>> {
+        let total = self.cache_handler.get_meta(key).await.and_then(|meta| meta.content_length);
+        let resolved = match resolve_range(range, total) {
+            Err(RangeError::UnknownLength) => {
+                log_info!("Cache", "后缀范围需要文件大小，向上游探测: {}", url);
+                let (_, probed) = self.cached_headers(url, key).await.map_err(|e| e.with_url(url))?;
+                resolve_range(range, (probed > 0).then_some(probed))
+            }
+            resolved => resolved,
+        };
+        let (start, mut end) = resolved.map_err(|e| ProxyError::from(e).with_url(url))?;
+        if let Some(max_span) = max_span {
+            let capped = cap_span(start, end, max_span);
+            if capped < end {
+                log_info!("Cache", "范围过大，截断为 {} 字节: {} {}-{}", max_span, url, start, capped);
+                end = capped;
+            }
+        }
+        let range = if end == u64::MAX {
+            format!("bytes={}-", start)
+        } else {
+            format!("bytes={}-{}", start, end)
+        };
+        self.serve_parsed_range(url, key, &range, start, end, req_headers)
+            .await
+            .map_err(|e| e.with_url(url).with_range(start, end))
+    }
+
+    async fn serve_parsed_range(&self, url: &str, key: &str, range: &str, start: u64, end: u64, req_headers: &HeaderMap) -> Result<Response<Body>> {
+        let key = key.to_string();
         
         log_info!("Cache", "开始处理请求: {} 范围: {}-{}", url, start, end);
+        // 顺序读取或播放器提供了码率时预读，窗口按码率计算
+        let hint = PlaybackHint::from_headers(req_headers);
+        let read_ahead_window = self.sequential.window(&key, start, end, hint.as_ref());
+        
+        // 记录决策依据，通过 /admin/explain 查看
+        let mut reasons = Vec::new();
+        match self.cache_handler.get_meta(&key).await {
+            Some(meta) => reasons.push(match meta.content_length {
+                Some(total) => format!("元数据记录的文件大小为 {} 字节", total),
+                None => "元数据中没有文件大小".to_string(),
+            }),
+            None => reasons.push("没有缓存元数据".to_string()),
+        }
         
         // 检查缓存中是否有完整的数据
         if let Ok(has_range) = self.cache_handler.check_range(&key, (start, end)).await {
             if has_range {
                 log_info!("Cache", "从缓存读取数据: {} 范围: {}-{}", url, start, end);
-                if let Ok(stream) = self.cache_handler.read(&key, (start, end)).await {
-                    // 获取文件总大小
-                    let range_str = format!("bytes=0-0");
-                    let (resp, _, total_size) = self.network_handler.fetch(url, &range_str).await?;
-                    let headers = self.network_handler.extract_headers(&resp);
+                if let Some(stream) = busy_or_ok(self.cache_handler.read(&key, (start, end)).await)? {
+                    reasons.push(format!("缓存包含请求范围 {}-{}", start, end));
+                    self.decisions.record(url, &key, start, end, Decision::FullCache, reasons);
+                    let (headers, total_size) = self.cached_headers(url, &key).await?;
+                    if let Some(window) = read_ahead_window {
+                        self.start_read_ahead(url, &key, end, total_size, window);
+                    }
                     
-                    return Ok(self.response_builder.build_partial_content_response(
+                    return Ok(self.finish_response(url, self.response_builder.build_partial_content_response(
                         stream,
                         headers,
                         start,
                         end,
                         total_size,
-                    ));
+                    ), CacheSource::Hit));
                 }
             }
         }
         
+        // 检查预取的 MP4 尾部
+        if let Some(response) = self.serve_mp4_tail(url, &key, start, end).await? {
+            reasons.push("MP4 尾部缓存包含请求范围".to_string());
+            self.decisions.record(url, &key, start, end, Decision::Mp4Tail, reasons);
+            return Ok(self.finish_response(url, response, CacheSource::Hit));
+        }
+        
         // 获取缓存文件大小
         let cached_size = self.cache_handler.get_size(&key).await?.unwrap_or(0);
+        reasons.push(format!("缓存不包含完整的请求范围，已缓存连续数据 {} 字节", cached_size));
         
         // 如果请求的范围部分在缓存中，部分需要从网络获取
         if cached_size > start {
@@ -90,75 +980,188 @@ impl DataSourceManager {
                 if cached_end >= end {
                     // 如果不需要从网络获取，直接返回缓存数据
                     log_info!("Cache", "完全从缓存读取: {}-{}", start, end);
-                    if let Ok(stream) = self.cache_handler.read(&key, (start, end)).await {
-                        // 获取文件总大小
-                        let range_str = format!("bytes=0-0");
-                        let (resp, _, total_size) = self.network_handler.fetch(url, &range_str).await?;
-                        let headers = self.network_handler.extract_headers(&resp);
+                    if let Some(stream) = busy_or_ok(self.cache_handler.read(&key, (start, end)).await)? {
+                        reasons.push("已缓存的连续数据覆盖请求范围".to_string());
+                        self.decisions.record(url, &key, start, end, Decision::FullCache, reasons);
+                        let (headers, total_size) = self.cached_headers(url, &key).await?;
+                        if let Some(window) = read_ahead_window {
+                            self.start_read_ahead(url, &key, end, total_size, window);
+                        }
                         
-                        return Ok(self.response_builder.build_partial_content_response(
+                        return Ok(self.finish_response(url, self.response_builder.build_partial_content_response(
                             stream,
                             headers,
                             start,
                             end,
                             total_size,
-                        ));
+                        ), CacheSource::Hit));
                     }
                 }
                 
-                // 处理混合源请求
-                return self.mixed_source_handler.handle(url, &key, start, end, cached_end).await;
+                // 处理混合源请求，网络部分不写入缓存，顺序读取时由预读补齐
+                reasons.push(format!("缓存数据到 {} 为止，之后的部分从网络获取且不写入缓存", cached_end));
+                self.decisions.record(url, &key, start, end, Decision::Mixed, reasons);
+                let response = self.mixed_source_handler.handle(url, &key, start, end, cached_end).await?;
+                if let Some(window) = read_ahead_window {
+                    if let Some(total_size) = self.cache_handler.get_meta(&key).await.and_then(|meta| meta.content_length) {
+                        self.start_read_ahead(url, &key, end, total_size, window);
+                    }
+                }
+                return Ok(self.finish_response(url, response, CacheSource::Partial(cached_end - start)));
+            }
+        }
+        
+        if cached_size <= start {
+            reasons.push(format!("请求起始位置 {} 超出已缓存的连续数据", start));
+        }
+        
+        // 本地未命中时先向同组节点查找，再向上级缓存请求，最后才请求源站
+        let allow_peers = !req_headers.contains_key(PEER_HEADER);
+        let peer_url = if allow_peers { self.siblings.find(url, start, end).await } else { None };
+        let mut fetched = match &peer_url {
+            Some(peer_url) => match self.network_handler.fetch_from_peer(peer_url, range).await {
+                Ok(fetched) => Some(fetched),
+                Err(e) => {
+                    log_info!("Cache", "从同组节点获取失败，改为请求源站: {} - {}", peer_url, e);
+                    None
+                }
+            },
+            None => None,
+        };
+        if fetched.is_none() {
+            if let Some((parent_url, via)) = self.shield.parent_url(url, req_headers) {
+                match self.network_handler.fetch_from_parent(&parent_url, range, &via).await {
+                    Ok(result) => fetched = Some(result),
+                    Err(e) => log_info!("Cache", "上级缓存请求失败，改为请求源站: {} - {}", parent_url, e),
+                }
+            }
+        }
+        let from_peer = fetched.is_some();
+        let (resp, content_length, total_size) = match fetched {
+            Some(fetched) => fetched,
+            None => {
+                log_info!("Cache", "开始从网络获取: {} {}-{}", url, start, end);
+                self.network_handler.fetch(url, range).await?
             }
+        };
+        
+        let decision = if from_peer {
+            reasons.push("从同组节点或上级缓存获取".to_string());
+            Decision::Peer
+        } else {
+            reasons.push("从源站获取".to_string());
+            Decision::Network
+        };
+        
+        // ICY 电台等无限流保留上游状态和响应头直接透传
+        if is_unbounded_stream(resp.headers(), self.icy_passthrough) {
+            log_info!("Cache", "响应没有固定大小，直接透传: {}", url);
+            reasons.push("响应没有固定大小，直接透传".to_string());
+            self.decisions.record(url, &key, start, end, decision, reasons);
+            return Ok(self.finish_response(url, resp, CacheSource::Miss));
         }
         
-        // 完全从网络获取
-        log_info!("Cache", "开始从网络获取: {} {}-{}", url, start, end);
-        let (resp, _, total_size) = self.network_handler.fetch(url, &range).await?;
         let headers = self.network_handler.extract_headers(&resp);
-        let (_, body) = resp.into_parts();
         
-        // 将 body 转换为我们需要的格式
-        let stream = futures::StreamExt::map(Body::wrap_stream(body), |result| {
-            result.map_err(|e| ProxyError::Network(e.to_string()))
+        // 对象总大小，上游未返回 Content-Range 时只有从头请求的长度可信
+        let object_size = if total_size > 0 {
+            total_size
+        } else if start == 0 {
+            content_length
+        } else {
+            0
+        };
+        let cacheable = !self.is_caching_paused()
+            && self.cache_policy.should_cache(url, resp.headers(), object_size);
+        reasons.push(if self.is_caching_paused() {
+            "缓存写入已暂停，响应不写入缓存".to_string()
+        } else if cacheable {
+            "响应写入缓存".to_string()
+        } else {
+            format!("缓存策略不缓存该响应，对象大小 {} 字节", object_size)
         });
-        let stream = Box::pin(stream);
+        self.decisions.record(url, &key, start, end, decision, reasons);
+        let (_, body) = resp.into_parts();
+        
+        // 在启动头尾预取前登记本次写入的范围，预取不会与它重叠写入
+        let writing = cacheable.then(|| self.cache_handler.begin_write(&key, (start, end)));
+        if cacheable {
+            // 从头请求时头部随响应写入缓存
+            self.start_mp4_prefetch(url, &key, start > 0 && cached_size == 0, total_size);
+        }
+        
+        // 大范围请求改为多连接分段下载，丢弃已建立的单连接响应体
+        let stream: Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>> =
+            match self.parallel_downloader.split_end(start, end, total_size).filter(|_| !from_peer) {
+                Some(split_end) => {
+                    drop(body);
+                    self.parallel_downloader.download(url, start, split_end)
+                }
+                None => Box::pin(futures::StreamExt::map(Body::wrap_stream(body), |result| {
+                    result.map_err(|e| ProxyError::network(e.to_string()))
+                })),
+            };
+        let stream = self.network_handler.track(url, range, stream);
+        
+        // 不可缓存的响应直接转发给客户端
+        if !cacheable {
+            return Ok(self.finish_response(url, self.response_builder.build_partial_content_response(
+                Box::new(stream),
+                headers,
+                start,
+                end,
+                total_size,
+            ), CacheSource::Miss));
+        }
         
         // 创建两个独立的流
         let (mut tx1, rx1) = futures::channel::mpsc::channel::<Result<Bytes>>(32);
-        let (mut tx2, rx2) = futures::channel::mpsc::channel::<Result<Bytes>>(32);
+        // 发给客户端的数据按字节数限制缓冲，慢客户端不会无限占用内存
+        let (high_watermark, low_watermark) = self.send_buffer_watermarks;
+        let (mut tx2, rx2) = send_buffer(high_watermark, low_watermark, self.slow_clients.clone());
         
-        // 启动转发任务
-        let forward_handle = tokio::spawn(async move {
+        // 启动转发任务，缓存写入失败时继续向客户端转发
+        let forward_key = key.clone();
+        let forward_handle = request_id::spawn(async move {
             let mut stream = stream;
+            let mut cache_open = true;
             while let Some(result) = stream.next().await {
                 match result {
                     Ok(chunk) => {
-                        if tx1.try_send(Ok(chunk.clone())).is_err() || 
-                           tx2.try_send(Ok(chunk)).is_err() {
-                            break;
+                        if cache_open && tx1.send(Ok(chunk.clone())).await.is_err() {
+                            log_info!("Cache", "缓存写入已停止，继续向客户端转发: {}", forward_key);
+                            cache_open = false;
+                        }
+                        if !tx2.send(Ok(chunk)).await {
+                            return Err(ProxyError::client_aborted("客户端在响应完成前断开").with_url(&forward_key));
                         }
                     }
                     Err(e) => {
-                        let _ = tx1.try_send(Err(e.clone()));
-                        let _ = tx2.try_send(Err(e));
+                        if cache_open {
+                            let _ = tx1.send(Err(e.clone())).await;
+                        }
+                        tx2.send(Err(e)).await;
                         break;
                     }
                 }
             }
+            Ok(())
         });
         
         // 启动缓存写入
         let cache_stream = Box::pin(futures::StreamExt::map(rx1, |x| x)) as Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>>;
-        let response_stream = Box::new(futures::StreamExt::map(rx2, |x| x)) as Box<dyn Stream<Item = Result<Bytes>> + Send + Unpin>;
+        let response_stream = Box::new(rx2) as Box<dyn Stream<Item = Result<Bytes>> + Send + Unpin>;
         
         // 启动缓存写入任务
         let key_clone = key.clone();
         let cache_handler = self.cache_handler.clone();
-        let cache_handle = tokio::spawn(async move {
+        let cache_handle = request_id::spawn(async move {
+            let _writing = writing;
             cache_handler.write_stream(&key_clone, (start, end), cache_stream).await
         });
         
         // 构建响应
+        let upstream_headers = headers.clone();
         let response = self.response_builder.build_partial_content_response(
             response_stream,
             headers,
@@ -167,18 +1170,159 @@ impl DataSourceManager {
             total_size,
         );
 
-        // 等待转发任务完成
-        if let Err(e) = forward_handle.await {
-            log_info!("Cache", "转发任务失败: {}", e);
-        }
-
-        // 等待缓存写入任务完成
-        if let Err(e) = cache_handle.await {
-            log_info!("Cache", "缓存写入任务失败: {}", e);
-            // 处理缓存写入失败的情况，但仍然返回响应
-            log_info!("Cache", "继续返回响应，尽管缓存写入失败");
-        }
+        // 需要预读时当前范围写入完成后继续预读
+        let read_ahead_state = match read_ahead_window {
+            Some(window) if total_size > 0 && !from_peer => {
+                Some((url.to_string(), self.network_handler.clone(), self.reading_ahead.clone(), window))
+            }
+            _ => None,
+        };
         
-        Ok(response)
+        // 后台等待转发和缓存写入完成，缓存写入失败不影响响应
+        let cache_handler = self.cache_handler.clone();
+        let replicator = self.replicator.clone();
+        request_id::spawn(async move {
+            match forward_handle.await {
+                Ok(Err(e)) => log_info!("Cache", "转发中止: {}", e),
+                Err(e) => log_info!("Cache", "转发任务失败: {}", e),
+                Ok(Ok(())) => {}
+            }
+            let write_result = cache_handle.await;
+            let content_length = if object_size > 0 { Some(object_size) } else { None };
+            if let Err(e) = cache_handler.save_headers(&key, &upstream_headers, content_length).await {
+                log_info!("Cache", "保存响应头失败: {} - {}", key, e);
+            }
+            match write_result {
+                Ok(Err(e)) => log_info!("Cache", "缓存写入任务失败: {}", e),
+                Err(e) => log_info!("Cache", "缓存写入任务失败: {}", e),
+                Ok(Ok(())) => {
+                    if start == 0 && total_size > 0 {
+                        match cache_handler.complete(&key, total_size).await {
+                            Ok(()) => replicator.enqueue(&key),
+                            Err(e) => log_info!("Cache", "标记下载完成失败: {} - {}", key, e),
+                        }
+                    }
+                    if let Some((url, network_handler, reading_ahead, window)) = read_ahead_state {
+                        if reading_ahead.lock().unwrap().insert(key.clone()) {
+                            if let Err(e) = read_ahead(&cache_handler, &network_handler, &url, &key, end, total_size, window).await {
+                                log_info!("Cache", "预读失败: {} - {}", url, e);
+                            }
+                            reading_ahead.lock().unwrap().remove(&key);
+                        }
+                    }
+                }
+            }
+        });
+        
+        Ok(self.finish_response(url, response, CacheSource::Miss))
+    }
+}
+
+
+/// 读取缓存失败时回退到网络，只有存储繁忙时直接返回错误，避免把压力转移到源站
+fn busy_or_ok<T>(result: Result<T>) -> Result<Option<T>> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(e) if e.kind() == ErrorKind::Busy => Err(e),
+        Err(_) => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{proxy_url, MockOrigin, MockResource};
+
+    fn manager(name: &str) -> (DataSourceManager, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("data-source-manager-{}-{}", name, std::process::id()));
+        (DataSourceManager::new(dir.clone()), dir)
+    }
+
+    fn range_request(url: &str, range: &str) -> DataRequest {
+        let uri = proxy_url(([127, 0, 0, 1], 8080).into(), url);
+        let req = hyper::Request::builder().uri(uri).header(RANGE, range).body(Body::empty()).unwrap();
+        DataRequest::new(&req).unwrap()
+    }
+
+    async fn body_of(response: Response<Body>) -> Bytes {
+        hyper::body::to_bytes(response.into_body()).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_suffix_range_cold_cache() {
+        let origin = MockOrigin::start().await.unwrap();
+        let resource = MockResource::sized(4096);
+        let expected = resource.body.slice(4096 - 500..);
+        origin.add("/a.mp4", resource);
+        let (manager, dir) = manager("suffix");
+
+        let response = manager.process_request(&range_request(&origin.url("/a.mp4"), "bytes=-500")).await.unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.headers()[hyper::header::CONTENT_RANGE], "bytes 3596-4095/4096");
+        assert_eq!(body_of(response).await, expected);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_multiple_ranges_merged() {
+        let origin = MockOrigin::start().await.unwrap();
+        let resource = MockResource::sized(4096);
+        let expected = resource.body.slice(0..1010);
+        origin.add("/b.mp4", resource);
+        let (manager, dir) = manager("multi");
+
+        let response = manager.process_request(&range_request(&origin.url("/b.mp4"), "bytes=0-9,1000-1009")).await.unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.headers()[hyper::header::CONTENT_RANGE], "bytes 0-1009/4096");
+        assert_eq!(body_of(response).await, expected);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_range_span_capped_while_paused() {
+        let origin = MockOrigin::start().await.unwrap();
+        origin.add("/c.mp4", MockResource::sized(4096));
+        let dir = std::env::temp_dir().join(format!("data-source-manager-span-{}", std::process::id()));
+        let mut config = ProxyConfig::default();
+        config.limits.max_range_span = Some(1024);
+        let manager = DataSourceManager::with_config(dir.clone(), config);
+        manager.set_caching_paused(true);
+
+        let response = manager.process_request(&range_request(&origin.url("/c.mp4"), "bytes=0-")).await.unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::PARTIAL_CONTENT);
+        assert_eq!(body_of(response).await.len(), 1024);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_stale_prefix_revalidated() {
+        let origin = MockOrigin::start().await.unwrap();
+        let old = MockResource::sized(64 * 1024).with_etag("\"v1\"");
+        let new = MockResource::new(vec![9u8; 64 * 1024]).with_etag("\"v2\"");
+        let dir = std::env::temp_dir().join(format!("data-source-manager-revalidate-{}", std::process::id()));
+        let mut config = ProxyConfig::default();
+        config.cache_policy.mixed_revalidate_after = Some(std::time::Duration::ZERO);
+        let manager = DataSourceManager::with_config(dir.clone(), config);
+
+        // 缓存中只有旧版本的开头部分
+        let url = origin.url("/d.mp4");
+        let key = manager.key_url(&url);
+        let prefix = old.body.slice(..16 * 1024);
+        let stream: Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>> = Box::pin(futures::stream::once(async move { Ok(prefix) }));
+        manager.cache_handler.write_stream(&key, (0, 16 * 1024 - 1), stream).await.unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(ETAG, HeaderValue::from_static("\"v1\""));
+        manager.cache_handler.save_headers(&key, &headers, Some(64 * 1024)).await.unwrap();
+
+        // 源站已更新，If-Range 不匹配时不拼接旧数据
+        origin.add("/d.mp4", new.clone());
+        let response = manager.process_request(&range_request(&url, "bytes=0-65535")).await.unwrap();
+        assert_eq!(body_of(response).await, new.body);
+        assert!(origin.requests().iter().any(|(_, range)| range.as_deref() == Some("bytes=16384-65535")));
+
+        let _ = std::fs::remove_dir_all(&dir);
     }
 }
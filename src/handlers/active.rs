@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Instant;
+use bytes::Bytes;
+use futures::task::AtomicWaker;
+use futures::Stream;
+use serde::Serialize;
+use crate::utils::error::{ProxyError, Result};
+use crate::utils::request_id;
+use crate::log_info;
+
+/// 正在进行的上游下载信息
+#[derive(Debug, Clone, Serialize)]
+pub struct ActiveDownloadInfo {
+    pub id: u64,
+    pub url: String,
+    pub range: String,
+    pub client: Option<String>,
+    pub request_id: Option<String>,
+    pub bytes: u64,
+    pub elapsed_secs: f64,
+    /// 平均速度（字节/秒）
+    pub speed: u64,
+}
+
+struct ActiveEntry {
+    url: String,
+    range: String,
+    client: Option<String>,
+    request_id: Option<String>,
+    started: Instant,
+    control: Arc<DownloadControl>,
+}
+
+/// 下载进度和取消信号
+#[derive(Default)]
+struct DownloadControl {
+    bytes: AtomicU64,
+    cancelled: AtomicBool,
+    waker: AtomicWaker,
+}
+
+/// 上游下载登记表
+#[derive(Default)]
+pub struct ActiveDownloads {
+    next_id: AtomicU64,
+    entries: Mutex<HashMap<u64, ActiveEntry>>,
+}
+
+impl ActiveDownloads {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 登记一个上游数据流，流结束或被丢弃时自动移除
+    pub fn track(
+        self: &Arc<Self>,
+        url: &str,
+        range: &str,
+        stream: Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>>,
+    ) -> Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let control = Arc::new(DownloadControl::default());
+        self.entries.lock().unwrap().insert(id, ActiveEntry {
+            url: url.to_string(),
+            range: range.to_string(),
+            client: request_id::client(),
+            request_id: request_id::current(),
+            started: Instant::now(),
+            control: control.clone(),
+        });
+
+        Box::pin(TrackedStream {
+            id,
+            inner: stream,
+            control,
+            registry: self.clone(),
+            finished: false,
+        })
+    }
+
+    pub fn list(&self) -> Vec<ActiveDownloadInfo> {
+        let entries = self.entries.lock().unwrap();
+        let mut list: Vec<_> = entries
+            .iter()
+            .map(|(id, entry)| {
+                let bytes = entry.control.bytes.load(Ordering::Relaxed);
+                let elapsed = entry.started.elapsed().as_secs_f64();
+                ActiveDownloadInfo {
+                    id: *id,
+                    url: entry.url.clone(),
+                    range: entry.range.clone(),
+                    client: entry.client.clone(),
+                    request_id: entry.request_id.clone(),
+                    bytes,
+                    elapsed_secs: elapsed,
+                    speed: if elapsed > 0.0 { (bytes as f64 / elapsed) as u64 } else { 0 },
+                }
+            })
+            .collect();
+        list.sort_by_key(|info| info.id);
+        list
+    }
+
+    /// 取消下载，返回是否找到该下载
+    pub fn cancel(&self, id: u64) -> bool {
+        match self.entries.lock().unwrap().get(&id) {
+            Some(entry) => {
+                log_info!("Network", "取消下载: #{} {}", id, entry.url);
+                entry.control.cancelled.store(true, Ordering::Release);
+                entry.control.waker.wake();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn remove(&self, id: u64) {
+        self.entries.lock().unwrap().remove(&id);
+    }
+}
+
+struct TrackedStream {
+    id: u64,
+    inner: Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>>,
+    control: Arc<DownloadControl>,
+    registry: Arc<ActiveDownloads>,
+    finished: bool,
+}
+
+impl Stream for TrackedStream {
+    type Item = Result<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.finished {
+            return Poll::Ready(None);
+        }
+
+        self.control.waker.register(cx.waker());
+        if self.control.cancelled.load(Ordering::Acquire) {
+            self.finished = true;
+            return Poll::Ready(Some(Err(ProxyError::cancelled("下载已被取消"))));
+        }
+
+        let poll = self.inner.as_mut().poll_next(cx);
+        match &poll {
+            Poll::Ready(Some(Ok(chunk))) => {
+                self.control.bytes.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+            }
+            Poll::Ready(None) => self.finished = true,
+            _ => {}
+        }
+        poll
+    }
+}
+
+impl Drop for TrackedStream {
+    fn drop(&mut self) {
+        self.registry.remove(self.id);
+    }
+}
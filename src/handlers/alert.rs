@@ -0,0 +1,314 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use async_trait::async_trait;
+use hyper::header::CONTENT_TYPE;
+use hyper::{Body, Client, Method, Request};
+use hyper_tls::HttpsConnector;
+use serde::Serialize;
+use crate::utils::error::{ProxyError, Result};
+use crate::utils::request_id;
+use crate::log_info;
+
+/// 告警类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertKind {
+    /// 缓存占用超过上限
+    CacheUsage,
+    /// 命中率低于下限
+    HitRatio,
+    /// 缓存读写出错
+    DiskError,
+    /// 源站连续请求失败
+    OriginFailing,
+}
+
+/// 发送给告警接收方的事件
+#[derive(Debug, Clone, Serialize)]
+pub struct Alert {
+    pub kind: AlertKind,
+    /// 相关对象，例如源站主机或缓存键
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+    pub message: String,
+    pub value: f64,
+    pub threshold: f64,
+    pub time: String,
+}
+
+/// 告警接收方，在后台调用，失败只记录日志
+#[async_trait]
+pub trait AlertSink: Send + Sync {
+    async fn notify(&self, alert: &Alert) -> Result<()>;
+}
+
+/// 以 JSON 格式 POST 到指定地址
+pub struct WebhookSink {
+    url: String,
+}
+
+impl WebhookSink {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self { url: url.into() }
+    }
+}
+
+#[async_trait]
+impl AlertSink for WebhookSink {
+    async fn notify(&self, alert: &Alert) -> Result<()> {
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(&self.url)
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(serde_json::to_vec(alert)?))?;
+        let client = Client::builder().build::<_, Body>(HttpsConnector::new());
+        let response = client.request(request).await?;
+        if !response.status().is_success() {
+            return Err(ProxyError::upstream(response.status(), format!("告警推送失败: {}", self.url)));
+        }
+        Ok(())
+    }
+}
+
+/// 告警阈值配置
+#[derive(Clone)]
+pub struct AlertConfig {
+    /// 接收告警的 webhook 地址
+    pub webhooks: Vec<String>,
+    /// 缓存占用超过该字节数时告警
+    pub max_cache_bytes: Option<u64>,
+    /// 命中率低于该值时告警，取值 0 到 1
+    pub min_hit_ratio: Option<f64>,
+    /// 计算命中率的请求数窗口
+    pub hit_ratio_window: u64,
+    /// 同一源站主机连续失败达到该次数时告警
+    pub origin_failures: u32,
+    /// 同一类型、同一对象的告警在该时间内只发送一次
+    pub cooldown: Duration,
+}
+
+impl Default for AlertConfig {
+    fn default() -> Self {
+        Self {
+            webhooks: Vec::new(),
+            max_cache_bytes: None,
+            min_hit_ratio: None,
+            hit_ratio_window: 200,
+            origin_failures: 5,
+            cooldown: Duration::from_secs(3600),
+        }
+    }
+}
+
+/// 检查缓存占用的最短间隔，统计需要遍历所有条目
+const USAGE_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Default)]
+struct HitWindow {
+    requests: u64,
+    hits: u64,
+}
+
+/// 按阈值检查运行状态并通知已注册的接收方
+pub struct Alerts {
+    config: AlertConfig,
+    sinks: RwLock<Vec<Arc<dyn AlertSink>>>,
+    /// 每种告警和对象上次发送的时间
+    last_fired: Mutex<HashMap<(AlertKind, Option<String>), Instant>>,
+    /// 每个源站主机的连续失败次数
+    origin_failures: Mutex<HashMap<String, u32>>,
+    hit_window: Mutex<HitWindow>,
+    last_usage_check: Mutex<Option<Instant>>,
+}
+
+impl Default for Alerts {
+    fn default() -> Self {
+        Self::new(AlertConfig::default())
+    }
+}
+
+impl Alerts {
+    pub fn new(config: AlertConfig) -> Self {
+        let sinks: Vec<Arc<dyn AlertSink>> = config
+            .webhooks
+            .iter()
+            .map(|url| Arc::new(WebhookSink::new(url.clone())) as Arc<dyn AlertSink>)
+            .collect();
+        Self {
+            config,
+            sinks: RwLock::new(sinks),
+            last_fired: Mutex::new(HashMap::new()),
+            origin_failures: Mutex::new(HashMap::new()),
+            hit_window: Mutex::new(HitWindow::default()),
+            last_usage_check: Mutex::new(None),
+        }
+    }
+
+    /// 注册告警接收方
+    pub fn register(&self, sink: Arc<dyn AlertSink>) {
+        self.sinks.write().unwrap().push(sink);
+    }
+
+    fn has_sinks(&self) -> bool {
+        !self.sinks.read().unwrap().is_empty()
+    }
+
+    /// 记录一次源站请求的结果，连续失败达到阈值时告警，成功后重新计数
+    pub fn record_origin(&self, url: &str, ok: bool) {
+        let host = match url::Url::parse(url).ok().and_then(|url| url.host_str().map(str::to_ascii_lowercase)) {
+            Some(host) => host,
+            None => return,
+        };
+        let failures = {
+            let mut origins = self.origin_failures.lock().unwrap();
+            if ok {
+                origins.remove(&host);
+                return;
+            }
+            let failures = origins.entry(host.clone()).or_insert(0);
+            *failures += 1;
+            *failures
+        };
+        let threshold = self.config.origin_failures.max(1);
+        if failures >= threshold {
+            self.fire(
+                AlertKind::OriginFailing,
+                Some(host.clone()),
+                format!("源站 {} 连续失败 {} 次", host, failures),
+                failures as f64,
+                threshold as f64,
+            );
+        }
+    }
+
+    /// 记录一次缓存读写错误
+    pub fn record_disk_error(&self, key: &str, error: &ProxyError) {
+        self.fire(AlertKind::DiskError, None, format!("缓存读写失败: {} - {}", key, error), 1.0, 0.0);
+    }
+
+    /// 记录一次请求是否完全命中缓存，每满一个窗口计算一次命中率
+    pub fn record_hit(&self, hit: bool) {
+        let floor = match self.config.min_hit_ratio {
+            Some(floor) => floor,
+            None => return,
+        };
+        let ratio = {
+            let mut window = self.hit_window.lock().unwrap();
+            window.requests += 1;
+            window.hits += hit as u64;
+            if window.requests < self.config.hit_ratio_window.max(1) {
+                return;
+            }
+            let ratio = window.hits as f64 / window.requests as f64;
+            *window = HitWindow::default();
+            ratio
+        };
+        if ratio < floor {
+            self.fire(
+                AlertKind::HitRatio,
+                None,
+                format!("最近 {} 个请求的命中率 {:.1}% 低于 {:.1}%", self.config.hit_ratio_window, ratio * 100.0, floor * 100.0),
+                ratio,
+                floor,
+            );
+        }
+    }
+
+    /// 是否需要检查缓存占用，配置了上限且距上次检查超过间隔时返回 true
+    pub fn usage_check_due(&self) -> bool {
+        if self.config.max_cache_bytes.is_none() {
+            return false;
+        }
+        let mut last = self.last_usage_check.lock().unwrap();
+        if last.is_some_and(|last| last.elapsed() < USAGE_CHECK_INTERVAL) {
+            return false;
+        }
+        *last = Some(Instant::now());
+        true
+    }
+
+    /// 检查缓存占用是否超过上限
+    pub fn check_cache_usage(&self, bytes: u64) {
+        if let Some(limit) = self.config.max_cache_bytes {
+            if bytes > limit {
+                self.fire(
+                    AlertKind::CacheUsage,
+                    None,
+                    format!("缓存占用 {} 字节，超过上限 {} 字节", bytes, limit),
+                    bytes as f64,
+                    limit as f64,
+                );
+            }
+        }
+    }
+
+    /// 冷却时间内同一告警不重复发送
+    fn should_fire(&self, kind: AlertKind, subject: &Option<String>) -> bool {
+        let mut last_fired = self.last_fired.lock().unwrap();
+        let key = (kind, subject.clone());
+        if last_fired.get(&key).is_some_and(|time| time.elapsed() < self.config.cooldown) {
+            return false;
+        }
+        last_fired.insert(key, Instant::now());
+        true
+    }
+
+    fn fire(&self, kind: AlertKind, subject: Option<String>, message: String, value: f64, threshold: f64) {
+        if !self.has_sinks() || !self.should_fire(kind, &subject) {
+            return;
+        }
+        log_info!("Alert", "{}", message);
+        let alert = Alert {
+            kind,
+            subject,
+            message,
+            value,
+            threshold,
+            time: chrono::Local::now().to_rfc3339(),
+        };
+        let sinks = self.sinks.read().unwrap().clone();
+        for sink in sinks {
+            let alert = alert.clone();
+            request_id::spawn(async move {
+                if let Err(e) = sink.notify(&alert).await {
+                    log_info!("Alert", "发送告警失败: {:?} - {}", alert.kind, e);
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_thresholds_and_cooldown() {
+        let alerts = Alerts::new(AlertConfig {
+            min_hit_ratio: Some(0.5),
+            hit_ratio_window: 4,
+            origin_failures: 2,
+            ..Default::default()
+        });
+
+        alerts.record_origin("https://cdn.example.com/a.ts", false);
+        alerts.record_origin("https://cdn.example.com/b.ts", true);
+        alerts.record_origin("https://cdn.example.com/c.ts", false);
+        assert_eq!(alerts.origin_failures.lock().unwrap().get("cdn.example.com"), Some(&1));
+
+        let subject = Some("cdn.example.com".to_string());
+        assert!(alerts.should_fire(AlertKind::OriginFailing, &subject));
+        assert!(!alerts.should_fire(AlertKind::OriginFailing, &subject));
+        assert!(alerts.should_fire(AlertKind::OriginFailing, &None));
+
+        for hit in [true, false, false] {
+            alerts.record_hit(hit);
+        }
+        assert_eq!(alerts.hit_window.lock().unwrap().requests, 3);
+        alerts.record_hit(false);
+        assert_eq!(alerts.hit_window.lock().unwrap().requests, 0);
+
+        assert!(!alerts.usage_check_due());
+    }
+}
@@ -1,21 +1,100 @@
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use std::pin::Pin;
 use bytes::Bytes;
 use futures::{Stream, StreamExt};
-use hyper::Response;
+use hyper::HeaderMap;
+use hyper::header::{HeaderValue, CONTENT_TYPE};
 use tokio::sync::mpsc;
-use crate::storage::{StorageManager, DiskStorage};
+use crate::storage::{StorageManager, ManagedWriter, CacheEngine, EntryInfo, EntryMeta, IndexStatus, IoPermitStats, VerifyReport};
 use crate::utils::error::{Result, ProxyError};
+use crate::utils::request_id;
 use crate::log_info;
-use crate::handlers::ResponseBuilder;
+use crate::handlers::FaultInjector;
+use crate::handlers::alert::Alerts;
+use crate::media::segment::{self, SegmentError};
+use crate::handlers::namespace::namespace_prefix;
+use crate::handlers::hooks::{aux_key, is_sidecar_key, is_valid_name, url_of, CompletedEntry, CompletionHook, CompletionHooks};
+
+/// 缓存键 -> 正在写入的范围
+type WritingRanges = Arc<Mutex<HashMap<String, Vec<(u64, u64)>>>>;
 
 pub struct CacheHandler {
-    storage_manager: Arc<StorageManager<DiskStorage>>,
+    storage_manager: Arc<StorageManager<CacheEngine>>,
+    faults: Arc<FaultInjector>,
+    hooks: Arc<CompletionHooks>,
+    alerts: Arc<Alerts>,
+    writing: WritingRanges,
+    /// 每个命名空间的缓存配额（字节），0 表示不限制
+    namespace_quota: u64,
+}
+
+/// 正在写入的范围，释放时从登记中移除
+pub struct WriteReservation {
+    writing: WritingRanges,
+    key: String,
+    range: (u64, u64),
+}
+
+impl Drop for WriteReservation {
+    fn drop(&mut self) {
+        let mut writing = self.writing.lock().unwrap();
+        if let Some(ranges) = writing.get_mut(&self.key) {
+            if let Some(index) = ranges.iter().position(|range| *range == self.range) {
+                ranges.swap_remove(index);
+            }
+            if ranges.is_empty() {
+                writing.remove(&self.key);
+            }
+        }
+    }
 }
 
 impl CacheHandler {
-    pub fn new(storage_manager: Arc<StorageManager<DiskStorage>>) -> Self {
-        Self { storage_manager }
+    pub fn new(storage_manager: Arc<StorageManager<CacheEngine>>) -> Self {
+        Self {
+            storage_manager,
+            faults: Arc::new(FaultInjector::default()),
+            hooks: Arc::new(CompletionHooks::default()),
+            alerts: Arc::new(Alerts::default()),
+            writing: Arc::new(Mutex::new(HashMap::new())),
+            namespace_quota: 0,
+        }
+    }
+
+    /// 登记即将写入的范围，写入结束时释放返回值
+    pub fn begin_write(&self, key: &str, range: (u64, u64)) -> WriteReservation {
+        self.writing.lock().unwrap().entry(key.to_string()).or_default().push(range);
+        WriteReservation { writing: self.writing.clone(), key: key.to_string(), range }
+    }
+
+    /// 没有其他写入与 `range` 重叠时登记并返回预留，检查和登记在同一个锁内完成
+    pub fn try_begin_write(&self, key: &str, range: (u64, u64)) -> Option<WriteReservation> {
+        let mut writing = self.writing.lock().unwrap();
+        let ranges = writing.entry(key.to_string()).or_default();
+        if ranges.iter().any(|other| other.0 <= range.1 && range.0 <= other.1) {
+            return None;
+        }
+        ranges.push(range);
+        Some(WriteReservation { writing: self.writing.clone(), key: key.to_string(), range })
+    }
+
+    /// 设置告警，写入失败和缓存占用超限时通知
+    pub fn with_alerts(mut self, alerts: Arc<Alerts>) -> Self {
+        self.alerts = alerts;
+        self
+    }
+
+    /// 命名空间的缓存配额，写入命名空间下的条目后检查，超出时淘汰该命名空间最久未访问的条目
+    pub fn with_namespace_quota(mut self, quota: u64) -> Self {
+        self.namespace_quota = quota;
+        self
+    }
+
+    /// 设置故障注入，仅用于测试
+    pub fn with_faults(mut self, faults: Arc<FaultInjector>) -> Self {
+        self.faults = faults;
+        self
     }
 
     pub async fn check_range(&self, key: &str, range: (u64, u64)) -> Result<bool> {
@@ -26,23 +105,162 @@ impl CacheHandler {
         self.storage_manager.get_size(key).await
     }
 
+    /// 保存上游响应头，`content_length` 为完整对象大小
+    pub async fn save_headers(&self, key: &str, headers: &HeaderMap, content_length: Option<u64>) -> Result<()> {
+        self.storage_manager.save_headers(key, headers, content_length).await
+    }
+
+    /// 获取缓存条目元数据
+    pub async fn get_meta(&self, key: &str) -> Option<EntryMeta> {
+        self.storage_manager.get_meta(key).await
+    }
+
+    /// 设置或清除条目的用户元数据，条目不存在时返回 false
+    pub async fn set_user_meta(&self, key: &str, user: Option<serde_json::Value>) -> Result<bool> {
+        self.storage_manager.set_user_meta(key, user).await
+    }
+
+    /// 缓存大小达到文件总大小时标记为下载完成，并在后台执行完成回调。
+    /// HLS 分片先检查格式，损坏的分片直接删除，下次请求时重新从上游获取
+    pub async fn complete(&self, key: &str, total_size: u64) -> Result<()> {
+        if self.storage_manager.get_size(key).await? == Some(total_size) {
+            if !is_sidecar_key(key) && segment::is_segment(url_of(key)) {
+                if let Err(e) = self.check_segment(key, total_size).await {
+                    log_info!("Cache", "分片已损坏，删除缓存: {} - {}", key, e);
+                    self.storage_manager.remove(key).await;
+                    return Ok(());
+                }
+            }
+            self.storage_manager.mark_complete(key).await?;
+            if !self.hooks.is_empty() && !is_sidecar_key(key) {
+                let storage_manager = self.storage_manager.clone();
+                let hooks = self.hooks.snapshot();
+                let key = key.to_string();
+                request_id::spawn(async move {
+                    run_hooks(&storage_manager, hooks, &key, total_size).await;
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// 读取分片头部检查格式
+    async fn check_segment(&self, key: &str, total_size: u64) -> std::result::Result<(), SegmentError> {
+        if total_size == 0 {
+            return Err(SegmentError::Empty);
+        }
+        let end = total_size.min(segment::CHECK_LEN) - 1;
+        let mut head = Vec::with_capacity(end as usize + 1);
+        // 读取失败不能说明分片损坏，按正常处理
+        let mut stream = match self.storage_manager.read(key, (0, end)).await {
+            Ok(stream) => stream,
+            Err(_) => return Ok(()),
+        };
+        while let Some(chunk) = stream.next().await {
+            match chunk {
+                Ok(chunk) => head.extend_from_slice(&chunk),
+                Err(_) => return Ok(()),
+            }
+        }
+        segment::check_segment(&head, total_size)
+    }
+
+    /// 注册下载完成回调
+    pub fn register_hook(&self, hook: Arc<dyn CompletionHook>) {
+        self.hooks.register(hook);
+    }
+
+    pub async fn prefix_usage(&self, prefix: &str) -> (u64, usize) {
+        self.storage_manager.prefix_usage(prefix).await
+    }
+
+    pub async fn enforce_prefix_quota(&self, prefix: &str, quota: u64) -> u64 {
+        self.storage_manager.enforce_prefix_quota(prefix, quota).await
+    }
+
+    pub async fn purge_prefix(&self, prefix: &str) -> (u64, usize) {
+        self.storage_manager.purge_prefix(prefix).await
+    }
+
+    pub async fn remove(&self, key: &str) {
+        self.storage_manager.remove(key).await
+    }
+
+    /// 立即执行一次缓存清理，返回释放的字节数和删除的条目数
+    pub async fn cleanup(&self) -> (u64, usize) {
+        self.storage_manager.cleanup().await
+    }
+
+    pub async fn inspect(&self, key: &str) -> Option<EntryInfo> {
+        self.storage_manager.inspect(key).await
+    }
+
+    /// 校验缓存条目与数据文件是否一致
+    pub async fn verify(&self, repair: bool) -> VerifyReport {
+        self.storage_manager.verify(repair).await
+    }
+
+    /// 缓存目录是否可用
+    pub fn is_online(&self) -> bool {
+        self.storage_manager.is_online()
+    }
+
+    /// 索引加载进度
+    pub fn index_status(&self) -> IndexStatus {
+        self.storage_manager.index_status()
+    }
+
+    /// 索引是否正在加载
+    pub fn is_index_loading(&self) -> bool {
+        self.storage_manager.is_index_loading()
+    }
+
+    /// 存储读写许可的饱和度
+    pub fn permit_stats(&self) -> IoPermitStats {
+        self.storage_manager.permit_stats()
+    }
+
     pub async fn read(&self, key: &str, range: (u64, u64)) -> Result<Box<dyn Stream<Item = Result<Bytes>> + Send + Unpin>> {
         self.storage_manager.read(key, range).await
     }
 
+    /// 写入一批缓冲数据，第一次写入时打开条目，引擎不支持连续写入时每批单独写入
+    async fn write_buffer(
+        &self,
+        writer: &mut Option<ManagedWriter>,
+        key: &str,
+        range: (u64, u64),
+        data: Bytes,
+    ) -> Result<u64> {
+        if writer.is_none() {
+            *writer = self.storage_manager.open_writer(key, range).await?;
+        }
+        match writer {
+            Some(writer) => self.storage_manager.write_to(writer, data).await,
+            None => self.storage_manager.write_bytes(key, data, range).await,
+        }
+    }
+
     pub async fn write_stream(
         &self,
         key: &str,
         range: (u64, u64),
-        mut stream: Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>>,
+        stream: Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>>,
     ) -> Result<()> {
+        let _writing = self.begin_write(key, range);
+        let mut stream = self.faults.wrap_write(stream);
         let (tx_storage, mut rx_storage) = mpsc::channel::<Bytes>(32);
         let storage_manager = self.storage_manager.clone();
         let key = key.to_string();
         let key_for_process = key.clone();
 
+        // 大块写入前检查磁盘保留空间
+        if range.1 != u64::MAX && range.1 >= range.0 {
+            storage_manager.ensure_free_space(&key, range.1 - range.0 + 1).await;
+        }
+
         // 启动数据处理任务
-        let process_handle = tokio::spawn(async move {
+        let process_handle = request_id::spawn(async move {
             let mut total_bytes = 0u64;
             let mut chunk_count = 0;
 
@@ -74,9 +292,10 @@ impl CacheHandler {
             Ok(())
         });
 
-        // 启动存储写入任务
+        // 启动存储写入任务，第一次写入时打开文件，之后顺序追加
         let mut buffer = Vec::new();
         let mut total_written = 0u64;
+        let mut writer = None;
 
         while let Some(chunk) = rx_storage.recv().await {
             buffer.extend_from_slice(&chunk);
@@ -85,53 +304,203 @@ impl CacheHandler {
                 let buffer_size = buffer.len();
                 log_info!("Cache", "缓冲区达到写入阈值: {} 字节, 开始写入存储", buffer_size);
 
-                let data = std::mem::take(&mut buffer);
-                let stream = Box::pin(futures::stream::once(async move { Ok(Bytes::from(data)) }));
-                match storage_manager.write(&key, stream, (range.0 + total_written, range.1)).await {
+                let data = Bytes::from(std::mem::take(&mut buffer));
+                match self.write_buffer(&mut writer, &key, (range.0 + total_written, range.1), data).await {
                     Ok(written) => {
                         total_written += written;
                         log_info!("Cache", "成功写入存储: {} 字节, 总计: {} 字节", written, total_written);
                     }
                     Err(e) => {
                         log_info!("Cache", "写入缓存失败: {} - {}", key, e);
-                        return Err(ProxyError::Cache(format!("写入缓存失败: {}", e)));
+                        self.alerts.record_disk_error(&key, &e);
+                        return Err(ProxyError::cache(format!("写入缓存失败: {}", e)));
                     }
                 }
             }
         }
 
-        // 写入剩余的数据
+        // 数据流结束后 rx_storage 才会关闭，此时处理任务已经结束
+        let processed = process_handle.await;
+
+        // 写入剩余的数据，从头开始且不足一个缓冲区就正常结束的是完整的小对象，直接保存在索引中
         if !buffer.is_empty() {
             let buffer_size = buffer.len();
             log_info!("Cache", "写入剩余数据: {} 字节", buffer_size);
 
-            let stream = Box::pin(futures::stream::once(async move { Ok(Bytes::from(buffer)) }));
-            match storage_manager.write(&key, stream, (range.0 + total_written, range.1)).await {
+            let whole_object = total_written == 0
+                && range.0 == 0
+                && (buffer_size as u64) < range.1.saturating_add(1)
+                && matches!(processed, Ok(Ok(())))
+                && storage_manager.fits_inline(buffer_size as u64);
+            let written = if whole_object {
+                storage_manager.write_inline(&key, Bytes::from(buffer)).await
+            } else {
+                self.write_buffer(&mut writer, &key, (range.0 + total_written, range.1), Bytes::from(buffer)).await
+            };
+            match written {
                 Ok(written) => {
                     total_written += written;
                     log_info!("Cache", "成功写入最后的数据块: {} 字节, 总计: {} 字节", written, total_written);
                 }
                 Err(e) => {
                     log_info!("Cache", "写入最后的数据块失败: {} - {}", key, e);
-                    return Err(ProxyError::Cache(format!("写入最后的数据块失败: {}", e)));
+                    self.alerts.record_disk_error(&key, &e);
+                    return Err(ProxyError::cache(format!("写入最后的数据块失败: {}", e)));
                 }
             }
         }
 
-        // 等待处理任务完成
-        match process_handle.await {
+        if let Some(writer) = writer {
+            if let Err(e) = storage_manager.finish_writer(writer).await {
+                log_info!("Cache", "同步缓存数据失败: {} - {}", key, e);
+                self.alerts.record_disk_error(&key, &e);
+                return Err(ProxyError::cache(format!("同步缓存数据失败: {}", e)));
+            }
+        }
+
+        if self.alerts.usage_check_due() {
+            let (bytes, _) = self.prefix_usage("").await;
+            self.alerts.check_cache_usage(bytes);
+        }
+
+        // 只有写入会增加命名空间的用量，在这里检查配额
+        if self.namespace_quota > 0 && total_written > 0 {
+            if let Some(prefix) = namespace_prefix(&key) {
+                self.enforce_prefix_quota(prefix, self.namespace_quota).await;
+            }
+        }
+
+        match processed {
             Ok(Ok(())) => {
                 log_info!("Cache", "存储写入任务完成: {} - 总计写入: {} 字节", key, total_written);
                 Ok(())
             }
             Ok(Err(e)) => {
                 log_info!("Cache", "数据处理任务失败: {} - {}", key, e);
-                Err(ProxyError::Cache(format!("数据处理任务失败: {}", e)))
+                Err(ProxyError::cache(format!("数据处理任务失败: {}", e)))
             }
             Err(e) => {
                 log_info!("Cache", "数据处理任务异常终止: {} - {}", key, e);
-                Err(ProxyError::Cache(format!("数据处理任务异常终止: {}", e)))
+                Err(ProxyError::cache(format!("数据处理任务异常终止: {}", e)))
             }
         }
     }
-} 
\ No newline at end of file
+} 
+
+/// 依次执行完成回调，返回的附属数据保存为 `aux_key` 条目，同名条目被替换
+async fn run_hooks(
+    storage_manager: &StorageManager<CacheEngine>,
+    hooks: Vec<Arc<dyn CompletionHook>>,
+    key: &str,
+    size: u64,
+) {
+    let path = match storage_manager.inspect(key).await.and_then(|info| info.path) {
+        Some(path) => path,
+        None => return,
+    };
+    let entry = CompletedEntry {
+        key: key.to_string(),
+        url: url_of(key).to_string(),
+        path,
+        size,
+    };
+
+    for hook in hooks {
+        let aux_entries = match hook.on_complete(&entry).await {
+            Ok(aux_entries) => aux_entries,
+            Err(e) => {
+                log_info!("Cache", "完成回调失败: {} - {}", key, e);
+                continue;
+            }
+        };
+        for aux in aux_entries {
+            if !is_valid_name(&aux.name) || aux.data.is_empty() {
+                log_info!("Cache", "忽略无效的附属条目: {} - {:?}", key, aux.name);
+                continue;
+            }
+            let aux_key = aux_key(key, &aux.name);
+            let len = aux.data.len() as u64;
+            storage_manager.remove(&aux_key).await;
+            if let Err(e) = storage_manager.write_bytes(&aux_key, aux.data, (0, len - 1)).await {
+                log_info!("Cache", "保存附属条目失败: {} - {}", aux_key, e);
+                continue;
+            }
+            let mut headers = HeaderMap::new();
+            if let Ok(content_type) = HeaderValue::from_str(&aux.content_type) {
+                headers.insert(CONTENT_TYPE, content_type);
+            }
+            match storage_manager.save_headers(&aux_key, &headers, Some(len)).await {
+                Ok(()) => log_info!("Cache", "附属条目已保存: {} ({} 字节)", aux_key, len),
+                Err(e) => log_info!("Cache", "保存附属条目响应头失败: {} - {}", aux_key, e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{ShardLayout, StorageConfig, StorageManagerConfig};
+
+    #[tokio::test]
+    async fn test_write_reservation() {
+        let root = std::env::temp_dir().join(format!("cache-writing-{}", std::process::id()));
+        let engine = CacheEngine::new(StorageConfig {
+            root_path: root.clone(),
+            chunk_size: 8192,
+            fast_root_path: None,
+            layout: ShardLayout::default(),
+            mmap: false,
+            mmap_threshold: 0,
+        });
+        let config = StorageManagerConfig { mount_check_interval: None, ..StorageManagerConfig::default() };
+        let handler = CacheHandler::new(Arc::new(StorageManager::new(engine, config)));
+
+        // 正在写入的范围与头部重叠时预留失败，不重叠时成功
+        let main = handler.begin_write("a", (512, 4095));
+        assert!(handler.try_begin_write("a", (0, 1023)).is_none());
+        let head = handler.try_begin_write("a", (0, 511));
+        assert!(head.is_some());
+        assert!(handler.try_begin_write("a", (0, 99)).is_none());
+        assert!(handler.try_begin_write("b", (0, 1023)).is_some());
+
+        drop(main);
+        drop(head);
+        assert!(handler.writing.lock().unwrap().is_empty());
+        assert!(handler.try_begin_write("a", (0, 4095)).is_some());
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn test_namespace_quota_after_write() {
+        let root = std::env::temp_dir().join(format!("cache-quota-{}", std::process::id()));
+        let engine = CacheEngine::new(StorageConfig {
+            root_path: root.clone(),
+            chunk_size: 8192,
+            fast_root_path: None,
+            layout: ShardLayout::default(),
+            mmap: false,
+            mmap_threshold: 0,
+        });
+        let config = StorageManagerConfig { mount_check_interval: None, ..StorageManagerConfig::default() };
+        let handler = CacheHandler::new(Arc::new(StorageManager::new(engine, config))).with_namespace_quota(150 * 1024);
+
+        let body = || -> Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>> {
+            Box::pin(futures::stream::once(async { Ok(Bytes::from(vec![7u8; 100 * 1024])) }))
+        };
+        handler.write_stream("ns:alice:http://example.com/a", (0, 100 * 1024 - 1), body()).await.unwrap();
+        handler.write_stream("http://example.com/shared", (0, 100 * 1024 - 1), body()).await.unwrap();
+        assert_eq!(handler.prefix_usage("ns:alice:").await.1, 1);
+
+        // 第二个条目超出配额，淘汰最久未访问的条目，共享缓存不受影响
+        handler.write_stream("ns:alice:http://example.com/b", (0, 100 * 1024 - 1), body()).await.unwrap();
+        let (bytes, entries) = handler.prefix_usage("ns:alice:").await;
+        assert_eq!(entries, 1);
+        assert!(bytes <= 150 * 1024);
+        assert!(handler.get_size("ns:alice:http://example.com/b").await.unwrap().is_some());
+        assert_eq!(handler.prefix_usage("http://").await.1, 1);
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
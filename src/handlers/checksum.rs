@@ -0,0 +1,113 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use flate2::Crc;
+use hyper::body::HttpBody;
+use hyper::header::{HeaderValue, TE, TRAILER};
+use hyper::{Body, HeaderMap, Response, StatusCode};
+use sha2::{Digest, Sha256};
+use crate::utils::request_id;
+use crate::log_info;
+
+/// 响应体 CRC32 的尾部字段，8 位小写十六进制
+pub const CRC32_TRAILER: &str = "x-content-crc32";
+
+/// RFC 3230 的 `Digest` 尾部字段，值为 `sha-256=<Base64>`
+pub const DIGEST_TRAILER: &str = "digest";
+
+/// 客户端是否通过 `TE: trailers` 声明接受尾部字段
+pub fn accepts_trailers(request_headers: &HeaderMap) -> bool {
+    request_headers
+        .get_all(TE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|item| item.split(';').next().unwrap_or("").trim().eq_ignore_ascii_case("trailers"))
+}
+
+/// 发送的数据的校验和
+struct Checksum {
+    crc: Crc,
+    sha256: Sha256,
+}
+
+impl Checksum {
+    fn new() -> Self {
+        Self {
+            crc: Crc::new(),
+            sha256: Sha256::new(),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        self.crc.update(data);
+        self.sha256.update(data);
+    }
+
+    fn trailers(self) -> HeaderMap {
+        let mut trailers = HeaderMap::new();
+        let crc = format!("{:08x}", self.crc.sum());
+        let digest = format!("sha-256={}", STANDARD.encode(self.sha256.finalize()));
+        for (name, value) in [(CRC32_TRAILER, crc), (DIGEST_TRAILER, digest)] {
+            if let Ok(value) = HeaderValue::from_str(&value) {
+                trailers.insert(name, value);
+            }
+        }
+        trailers
+    }
+}
+
+/// 边发送边计算响应体的 CRC32 和 SHA-256，发送完后以尾部字段返回，覆盖的是本次响应实际发送的字节范围。
+/// 只处理 200 和 206 响应；hyper 只在 HTTP/2 连接上发送尾部字段，HTTP/1.1 客户端收到的响应不变
+pub fn with_checksum_trailers(response: Response<Body>) -> Response<Body> {
+    if !matches!(response.status(), StatusCode::OK | StatusCode::PARTIAL_CONTENT) {
+        return response;
+    }
+
+    let (mut parts, mut body) = response.into_parts();
+    parts.headers.insert(TRAILER, HeaderValue::from_static("x-content-crc32, digest"));
+    let (mut sender, checked) = Body::channel();
+
+    request_id::spawn(async move {
+        let mut checksum = Checksum::new();
+        while let Some(chunk) = body.data().await {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    // 数据不完整时不发送校验和，中断响应让客户端发现错误
+                    log_info!("Checksum", "读取响应体失败，中断响应: {}", e);
+                    sender.abort();
+                    return;
+                }
+            };
+            checksum.update(&chunk);
+            if sender.send_data(chunk).await.is_err() {
+                return;
+            }
+        }
+        let _ = sender.send_trailers(checksum.trailers()).await;
+    });
+
+    Response::from_parts(parts, checked)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checksum_trailers() {
+        let mut headers = HeaderMap::new();
+        assert!(!accepts_trailers(&headers));
+        headers.insert(TE, "gzip, Trailers;q=1".parse().unwrap());
+        assert!(accepts_trailers(&headers));
+
+        let mut checksum = Checksum::new();
+        checksum.update(b"123456789");
+        let trailers = checksum.trailers();
+        assert_eq!(trailers[CRC32_TRAILER], "cbf43926");
+        assert_eq!(
+            trailers[DIGEST_TRAILER],
+            "sha-256=FeKw08M4keuw8e9gnsQZQgwg4yDOlMZfvIwzEkSOsiU="
+        );
+    }
+}
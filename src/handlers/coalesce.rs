@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use bytes::Bytes;
+use hyper::header::{HeaderValue, CONTENT_LENGTH, CONTENT_RANGE};
+use hyper::{Body, HeaderMap, Response, StatusCode};
+
+/// 小范围请求合并配置
+#[derive(Clone)]
+pub struct CoalesceConfig {
+    pub enabled: bool,
+    /// 请求范围不超过该大小时合并
+    pub max_request: u64,
+    /// 合并后向上游请求的大小
+    pub window: u64,
+    /// 最多保留的合并数据块数
+    pub max_buffers: usize,
+    /// 数据块保留时间
+    pub ttl: Duration,
+}
+
+impl Default for CoalesceConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_request: 64 * 1024,   // 64KB
+            window: 1024 * 1024,      // 1MB
+            max_buffers: 64,
+            ttl: Duration::from_secs(30),
+        }
+    }
+}
+
+/// 一次合并请求取回的数据
+struct Segment {
+    start: u64,
+    data: Bytes,
+    total_size: u64,
+    headers: HeaderMap,
+    fetched_at: Instant,
+}
+
+/// 保存合并请求多取回的数据，后续相邻的小范围请求直接从中切片
+pub struct CoalesceBuffer {
+    config: CoalesceConfig,
+    segments: Mutex<HashMap<String, Segment>>,
+}
+
+impl CoalesceBuffer {
+    pub fn new(config: CoalesceConfig) -> Self {
+        Self {
+            config,
+            segments: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// 需要合并时返回向上游请求的范围
+    pub fn window_for(&self, start: u64, end: u64) -> Option<(u64, u64)> {
+        if !self.config.enabled || end == u64::MAX || end - start + 1 > self.config.max_request {
+            return None;
+        }
+        let window_end = start.saturating_add(self.config.window.max(end - start + 1) - 1);
+        Some((start, window_end))
+    }
+
+    /// 从已取回的数据中切出 [start, end]，返回数据、文件总大小和上游响应头
+    pub fn get(&self, url: &str, start: u64, end: u64) -> Option<(Bytes, u64, HeaderMap)> {
+        let segments = self.segments.lock().unwrap();
+        let segment = segments.get(url)?;
+        if segment.fetched_at.elapsed() > self.config.ttl {
+            return None;
+        }
+        let segment_end = segment.start + segment.data.len() as u64;
+        // 超出文件末尾的部分按实际数据截断
+        let end = if segment.total_size > 0 { end.min(segment.total_size - 1) } else { end };
+        if start < segment.start || start > end || end >= segment_end {
+            return None;
+        }
+        let offset = (start - segment.start) as usize;
+        let data = segment.data.slice(offset..offset + (end - start + 1) as usize);
+        Some((data, segment.total_size, segment.headers.clone()))
+    }
+
+    /// 保存合并请求取回的数据，超出数量上限时丢弃最早的
+    pub fn insert(&self, url: &str, start: u64, data: Bytes, total_size: u64, headers: HeaderMap) {
+        let mut segments = self.segments.lock().unwrap();
+        segments.retain(|_, segment| segment.fetched_at.elapsed() <= self.config.ttl);
+        if segments.len() >= self.config.max_buffers && !segments.contains_key(url) {
+            if let Some(oldest) = segments
+                .iter()
+                .min_by_key(|(_, segment)| segment.fetched_at)
+                .map(|(url, _)| url.clone())
+            {
+                segments.remove(&oldest);
+            }
+        }
+        segments.insert(url.to_string(), Segment {
+            start,
+            data,
+            total_size,
+            headers,
+            fetched_at: Instant::now(),
+        });
+    }
+}
+
+/// 用切出的数据构造 206 响应
+pub fn slice_response(data: Bytes, start: u64, total_size: u64, headers: &HeaderMap) -> Response<Body> {
+    let length = data.len() as u64;
+    let mut response = Response::new(Body::from(data));
+    *response.status_mut() = StatusCode::PARTIAL_CONTENT;
+    let response_headers = response.headers_mut();
+    for (name, value) in headers {
+        if name != CONTENT_RANGE && name != CONTENT_LENGTH {
+            response_headers.append(name, value.clone());
+        }
+    }
+    let end = start + length - 1;
+    let total = if total_size > 0 { total_size.to_string() } else { "*".to_string() };
+    if let Ok(value) = HeaderValue::from_str(&format!("bytes {}-{}/{}", start, end, total)) {
+        response_headers.insert(CONTENT_RANGE, value);
+    }
+    response_headers.insert(CONTENT_LENGTH, HeaderValue::from(length));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get() {
+        let buffer = CoalesceBuffer::new(CoalesceConfig::default());
+        assert_eq!(buffer.window_for(100, 199), Some((100, 100 + 1024 * 1024 - 1)));
+        assert_eq!(buffer.window_for(0, u64::MAX), None);
+
+        buffer.insert("a", 100, Bytes::from_static(b"0123456789"), 110, HeaderMap::new());
+        assert_eq!(buffer.get("a", 102, 104).unwrap().0, Bytes::from_static(b"234"));
+        // 文件末尾之后的请求按实际大小截断
+        assert_eq!(buffer.get("a", 108, 200).unwrap().0, Bytes::from_static(b"89"));
+        assert!(buffer.get("a", 95, 104).is_none());
+        assert!(buffer.get("b", 100, 101).is_none());
+    }
+}
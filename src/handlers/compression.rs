@@ -0,0 +1,181 @@
+use std::io::Write;
+use std::time::Instant;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use hyper::{Body, HeaderMap, Response, StatusCode};
+use hyper::body::HttpBody;
+use hyper::header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, ETAG, RANGE, VARY};
+use crate::utils::error::Result;
+use crate::log_info;
+use super::stats::{StatsRegistry, SERVED_BYTES_HEADER};
+
+/// 超过该大小的响应不压缩，避免把误判的大文件读入内存
+const MAX_COMPRESS_SIZE: u64 = 1024 * 1024;
+
+/// 可压缩的文本格式扩展名（播放列表、字幕）
+const TEXT_EXTENSIONS: &[&str] = &[".m3u8", ".m3u", ".mpd", ".vtt", ".srt"];
+
+/// 可压缩的文本内容类型
+const TEXT_CONTENT_TYPES: &[&str] = &[
+    "application/vnd.apple.mpegurl",
+    "application/x-mpegurl",
+    "audio/mpegurl",
+    "audio/x-mpegurl",
+    "application/dash+xml",
+    "text/",
+];
+
+/// 判断客户端是否接受 gzip 编码
+pub fn accepts_gzip(request_headers: &HeaderMap) -> bool {
+    request_headers
+        .get_all(ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|item| {
+            let mut parts = item.split(';');
+            let coding = parts.next().unwrap_or("").trim();
+            // q=0 表示明确拒绝
+            let rejected = parts.any(|param| {
+                matches!(param.trim().strip_prefix("q="), Some(q) if q.trim().parse::<f32>() == Ok(0.0))
+            });
+            (coding.eq_ignore_ascii_case("gzip") || coding == "*") && !rejected
+        })
+}
+
+/// 判断响应是否为播放列表或字幕等文本内容，媒体分片永远不压缩
+pub fn is_compressible(url: &str, content_type: Option<&str>) -> bool {
+    if let Some(content_type) = content_type {
+        let content_type = content_type.trim().to_ascii_lowercase();
+        if TEXT_CONTENT_TYPES.iter().any(|t| content_type.starts_with(t)) {
+            return true;
+        }
+    }
+
+    let path = url.split(['?', '#']).next().unwrap_or(url).to_ascii_lowercase();
+    TEXT_EXTENSIONS.iter().any(|ext| path.ends_with(ext))
+}
+
+/// gzip 压缩数据
+pub fn gzip(data: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::with_capacity(data.len() / 2), Compression::default());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+/// 按客户端 Accept-Encoding 对文本响应进行 gzip 压缩，压缩前后的大小和耗时记录到 `stats`
+///
+/// 只处理完整内容的 200 响应，部分内容的字节范围基于原始数据，不能压缩。
+/// 被客户端按范围读取过的 URL 之后也不再压缩，缓存中只有原始数据，任意位置的范围请求都不需要从头解压
+pub async fn compress_response(
+    response: Response<Body>,
+    request_headers: &HeaderMap,
+    url: &str,
+    stats: &StatsRegistry,
+) -> Result<Response<Body>> {
+    if request_headers.contains_key(RANGE) {
+        stats.record_range_access(url);
+        return Ok(response);
+    }
+    if stats.is_range_accessed(url) {
+        return Ok(response);
+    }
+
+    if response.status() != StatusCode::OK
+        || response.headers().contains_key(CONTENT_ENCODING)
+        || !accepts_gzip(request_headers)
+    {
+        return Ok(response);
+    }
+
+    let content_type = response.headers().get(CONTENT_TYPE).and_then(|v| v.to_str().ok());
+    if !is_compressible(url, content_type) {
+        return Ok(response);
+    }
+
+    let size = response
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok())
+        .or_else(|| response.body().size_hint().exact());
+    if size.is_none_or(|size| size > MAX_COMPRESS_SIZE) {
+        return Ok(response);
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let data = hyper::body::to_bytes(body).await?;
+    let started = Instant::now();
+    let compressed = gzip(&data)?;
+    stats.record_compression(url, "gzip", data.len() as u64, compressed.len() as u64, started.elapsed());
+    log_info!("Compress", "gzip 压缩 {} -> {} 字节: {}", data.len(), compressed.len(), url);
+
+    parts.headers.insert(CONTENT_ENCODING, "gzip".parse().unwrap());
+    parts.headers.insert(CONTENT_LENGTH, compressed.len().into());
+    if parts.headers.contains_key(SERVED_BYTES_HEADER) {
+        parts.headers.insert(SERVED_BYTES_HEADER, compressed.len().into());
+    }
+    parts.headers.append(VARY, "Accept-Encoding".parse().unwrap());
+
+    // 编码后的内容与原始内容字节不同，强 ETag 降级为弱 ETag
+    if let Some(etag) = parts.headers.get(ETAG).and_then(|v| v.to_str().ok()) {
+        if !etag.starts_with("W/") {
+            if let Ok(weak) = format!("W/{}", etag).parse() {
+                parts.headers.insert(ETAG, weak);
+            }
+        }
+    }
+
+    Ok(Response::from_parts(parts, Body::from(compressed)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accepts_gzip() {
+        let mut headers = HeaderMap::new();
+        assert!(!accepts_gzip(&headers));
+
+        headers.insert(ACCEPT_ENCODING, "deflate, gzip;q=0.8, br".parse().unwrap());
+        assert!(accepts_gzip(&headers));
+
+        headers.insert(ACCEPT_ENCODING, "gzip;q=0, identity".parse().unwrap());
+        assert!(!accepts_gzip(&headers));
+    }
+
+    #[test]
+    fn test_is_compressible() {
+        assert!(is_compressible("http://example.com/live/index.m3u8?token=1", None));
+        assert!(is_compressible("http://example.com/manifest", Some("application/dash+xml")));
+        assert!(is_compressible("http://example.com/sub.vtt", None));
+        assert!(!is_compressible("http://example.com/seg-1.ts", Some("video/mp2t")));
+        assert!(!is_compressible("http://example.com/video.mp4", None));
+    }
+
+    #[test]
+    fn test_skip_range_accessed() {
+        let stats = StatsRegistry::new();
+        let url = "http://example.com/sub.vtt";
+        let text = "WEBVTT\n\n".repeat(100);
+        let response = || {
+            let mut response = Response::new(Body::from(text.clone()));
+            response.headers_mut().insert(CONTENT_TYPE, "text/vtt".parse().unwrap());
+            response.headers_mut().insert(CONTENT_LENGTH, text.len().into());
+            response
+        };
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT_ENCODING, "gzip".parse().unwrap());
+        let compress = |headers: &HeaderMap| {
+            futures::executor::block_on(compress_response(response(), headers, url, &stats)).unwrap()
+        };
+
+        assert!(compress(&headers).headers().contains_key(CONTENT_ENCODING));
+
+        let mut ranged = headers.clone();
+        ranged.insert(RANGE, "bytes=0-".parse().unwrap());
+        assert!(!compress(&ranged).headers().contains_key(CONTENT_ENCODING));
+        assert!(!compress(&headers).headers().contains_key(CONTENT_ENCODING));
+    }
+}
@@ -0,0 +1,139 @@
+use hyper::HeaderMap;
+use hyper::header::{IF_MODIFIED_SINCE, IF_NONE_MATCH};
+use crate::storage::EntryMeta;
+
+/// 判断客户端 If-Range 中的校验值是否与保存的校验值一致
+///
+/// ETag 使用强比较，弱 ETag 永远不匹配；日期必须与 Last-Modified 完全一致。
+/// 没有保存校验值时视为不匹配，返回完整内容。
+pub fn if_range_matches(if_range: &str, meta: Option<&EntryMeta>) -> bool {
+    let meta = match meta {
+        Some(meta) => meta,
+        None => return false,
+    };
+
+    let if_range = if_range.trim();
+    if if_range.starts_with("W/") {
+        return false;
+    }
+    if if_range.starts_with('"') {
+        return matches!(meta.header("etag"), Some(etag) if !etag.starts_with("W/") && etag == if_range);
+    }
+    meta.header("last-modified") == Some(if_range)
+}
+
+/// 生成缓存条目的 ETag
+///
+/// 优先透传上游 ETag，没有时根据键、大小和修改时间生成弱 ETag。
+/// 只使用获取响应头时就确定的字段，下载完成前后返回同一个值，断点续传的 If-Range 不会失效
+pub fn entity_tag(meta: &EntryMeta) -> String {
+    if let Some(etag) = meta.header("etag") {
+        return etag.to_string();
+    }
+
+    let seed = format!(
+        "{}:{}:{}",
+        meta.key,
+        meta.content_length.unwrap_or(0),
+        meta.header("last-modified").unwrap_or("")
+    );
+    format!("W/\"{:x}\"", md5::compute(seed.as_bytes()))
+}
+
+/// 判断客户端的条件请求是否可以用 304 响应
+///
+/// 有 If-None-Match 时只比较 ETag（弱比较），否则比较 If-Modified-Since 与 Last-Modified
+pub fn is_not_modified(request_headers: &HeaderMap, meta: &EntryMeta) -> bool {
+    if let Some(if_none_match) = request_headers.get(IF_NONE_MATCH) {
+        let if_none_match = match if_none_match.to_str() {
+            Ok(value) => value,
+            Err(_) => return false,
+        };
+        let etag = entity_tag(meta);
+        return if_none_match
+            .split(',')
+            .map(str::trim)
+            .any(|candidate| candidate == "*" || opaque_tag(candidate) == opaque_tag(&etag));
+    }
+
+    if let Some(if_modified_since) = request_headers.get(IF_MODIFIED_SINCE) {
+        let since = if_modified_since
+            .to_str()
+            .ok()
+            .and_then(|value| chrono::DateTime::parse_from_rfc2822(value).ok());
+        let last_modified = meta
+            .header("last-modified")
+            .and_then(|value| chrono::DateTime::parse_from_rfc2822(value).ok());
+        if let (Some(since), Some(last_modified)) = (since, last_modified) {
+            return last_modified <= since;
+        }
+    }
+
+    false
+}
+
+/// 去掉弱标记，用于弱比较
+fn opaque_tag(tag: &str) -> &str {
+    tag.strip_prefix("W/").unwrap_or(tag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ETAG: &str = "\"v1\"";
+    const LAST_MODIFIED: &str = "Wed, 21 Oct 2015 07:28:00 GMT";
+
+    fn meta(headers: &[(&str, &str)]) -> EntryMeta {
+        let mut meta = EntryMeta::new("http://example.com/a.mp4");
+        meta.headers = headers.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+        meta
+    }
+
+    #[test]
+    fn test_if_range_etag() {
+        let meta = meta(&[("etag", ETAG), ("last-modified", LAST_MODIFIED)]);
+        assert!(if_range_matches(ETAG, Some(&meta)));
+        assert!(if_range_matches(" \"v1\" ", Some(&meta)));
+        assert!(!if_range_matches("\"v2\"", Some(&meta)));
+        // 弱 ETag 不能用于 If-Range
+        assert!(!if_range_matches("W/\"v1\"", Some(&meta)));
+        let weak = self::meta(&[("etag", "W/\"v1\"")]);
+        assert!(!if_range_matches("\"v1\"", Some(&weak)));
+        assert!(!if_range_matches("W/\"v1\"", Some(&weak)));
+    }
+
+    #[test]
+    fn test_if_range_date() {
+        let meta = meta(&[("etag", ETAG), ("last-modified", LAST_MODIFIED)]);
+        assert!(if_range_matches(LAST_MODIFIED, Some(&meta)));
+        assert!(!if_range_matches("Thu, 22 Oct 2015 07:28:00 GMT", Some(&meta)));
+        // 没有 Last-Modified 时日期形式不匹配
+        assert!(!if_range_matches(LAST_MODIFIED, Some(&self::meta(&[("etag", ETAG)]))));
+    }
+
+    #[test]
+    fn test_if_range_without_validators() {
+        assert!(!if_range_matches(ETAG, None));
+        assert!(!if_range_matches(ETAG, Some(&meta(&[]))));
+        assert!(!if_range_matches(LAST_MODIFIED, Some(&meta(&[]))));
+    }
+
+    #[test]
+    fn test_entity_tag_stable_across_completion() {
+        let mut meta = meta(&[("last-modified", LAST_MODIFIED)]);
+        meta.content_length = Some(4096);
+        let partial = entity_tag(&meta);
+        assert!(partial.starts_with("W/\""));
+
+        // 下载完成后记录内容哈希，ETag 保持不变
+        meta.content_hash = Some("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad".to_string());
+        assert_eq!(entity_tag(&meta), partial);
+
+        // 上游提供 ETag 时始终透传
+        meta.headers.push(("etag".to_string(), ETAG.to_string()));
+        assert_eq!(entity_tag(&meta), ETAG);
+        meta.content_hash = None;
+        assert_eq!(entity_tag(&meta), ETAG);
+    }
+}
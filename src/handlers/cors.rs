@@ -0,0 +1,112 @@
+use std::time::Duration;
+use hyper::{HeaderMap, Method};
+use hyper::header::{
+    HeaderValue, ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_HEADERS,
+    ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_EXPOSE_HEADERS,
+    ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_METHOD, ORIGIN, VARY,
+};
+
+/// 跨域配置，供浏览器中的 hls.js / dash.js 等播放器使用
+#[derive(Clone)]
+pub struct CorsConfig {
+    pub enabled: bool,
+    /// 允许的来源，`*` 表示任意来源
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    pub allowed_headers: Vec<String>,
+    /// 允许脚本读取的响应头
+    pub exposed_headers: Vec<String>,
+    pub allow_credentials: bool,
+    /// 预检结果缓存时间
+    pub max_age: Duration,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        let to_strings = |items: &[&str]| items.iter().map(|item| item.to_string()).collect();
+        Self {
+            enabled: false,
+            allowed_origins: vec!["*".to_string()],
+            allowed_methods: to_strings(&["GET", "HEAD", "OPTIONS"]),
+            allowed_headers: to_strings(&["Range", "If-Range", "If-None-Match", "If-Modified-Since", "Content-Type", "X-Playback-Position", "X-Session-Id"]),
+            exposed_headers: to_strings(&["Content-Length", "Content-Range", "Accept-Ranges", "ETag", "X-Upstream-Bytes", "X-Served-Bytes", "X-Session-Id"]),
+            allow_credentials: false,
+            max_age: Duration::from_secs(24 * 60 * 60),
+        }
+    }
+}
+
+impl CorsConfig {
+    /// 判断是否为跨域预检请求
+    pub fn is_preflight(&self, method: &Method, headers: &HeaderMap) -> bool {
+        self.enabled
+            && method == Method::OPTIONS
+            && headers.contains_key(ORIGIN)
+            && headers.contains_key(ACCESS_CONTROL_REQUEST_METHOD)
+    }
+
+    /// 根据请求的 Origin 生成跨域响应头，来源不被允许时返回 None
+    pub fn response_headers(&self, request_headers: &HeaderMap, preflight: bool) -> Option<HeaderMap> {
+        if !self.enabled {
+            return None;
+        }
+        let origin = request_headers.get(ORIGIN)?.to_str().ok()?;
+        let any_origin = self.allowed_origins.iter().any(|allowed| allowed == "*");
+        if !any_origin && !self.allowed_origins.iter().any(|allowed| allowed.eq_ignore_ascii_case(origin)) {
+            return None;
+        }
+
+        let mut headers = HeaderMap::new();
+        // 携带凭据时不能使用通配符
+        if any_origin && !self.allow_credentials {
+            headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, HeaderValue::from_static("*"));
+        } else {
+            headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, HeaderValue::from_str(origin).ok()?);
+            headers.insert(VARY, HeaderValue::from_static("Origin"));
+        }
+        if self.allow_credentials {
+            headers.insert(ACCESS_CONTROL_ALLOW_CREDENTIALS, HeaderValue::from_static("true"));
+        }
+
+        let join = |items: &[String]| HeaderValue::from_str(&items.join(", ")).ok();
+        if preflight {
+            if let Some(methods) = join(&self.allowed_methods) {
+                headers.insert(ACCESS_CONTROL_ALLOW_METHODS, methods);
+            }
+            if let Some(allowed) = join(&self.allowed_headers) {
+                headers.insert(ACCESS_CONTROL_ALLOW_HEADERS, allowed);
+            }
+            headers.insert(ACCESS_CONTROL_MAX_AGE, self.max_age.as_secs().into());
+        } else if let Some(exposed) = join(&self.exposed_headers) {
+            headers.insert(ACCESS_CONTROL_EXPOSE_HEADERS, exposed);
+        }
+
+        Some(headers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_response_headers() {
+        let mut request_headers = HeaderMap::new();
+        request_headers.insert(ORIGIN, "https://player.example.com".parse().unwrap());
+
+        let cors = CorsConfig::default();
+        assert!(cors.response_headers(&request_headers, false).is_none());
+
+        let cors = CorsConfig { enabled: true, ..Default::default() };
+        let headers = cors.response_headers(&request_headers, false).unwrap();
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+        assert!(headers.contains_key(ACCESS_CONTROL_EXPOSE_HEADERS));
+
+        let cors = CorsConfig {
+            enabled: true,
+            allowed_origins: vec!["https://other.example.com".to_string()],
+            ..Default::default()
+        };
+        assert!(cors.response_headers(&request_headers, true).is_none());
+    }
+}
@@ -0,0 +1,45 @@
+use hyper::header::HeaderValue;
+
+/// 下载入口路径前缀，后接编码后的源站 URL，例如 `/download/https%3A%2F%2Fexample.com%2Fa.mp4`
+pub const DOWNLOAD_PREFIX: &str = "/download/";
+
+/// 下载保存的文件名，取 URL 路径的最后一段，没有时使用 `download`
+pub fn file_name(url: &str) -> String {
+    let path = url.split(['?', '#']).next().unwrap_or("");
+    let path = path.split_once("://").map(|(_, rest)| rest).unwrap_or(path);
+    path.split_once('/')
+        .and_then(|(_, path)| path.rsplit('/').next())
+        .filter(|name| !name.is_empty())
+        .map(|name| urlencoding::decode(name).map(|name| name.into_owned()).unwrap_or_else(|_| name.to_string()))
+        .unwrap_or_else(|| "download".to_string())
+}
+
+/// `Content-Disposition: attachment` 响应头，`filename` 只保留 ASCII 字符，完整文件名放在 `filename*` 中
+pub fn content_disposition(url: &str) -> HeaderValue {
+    let name = file_name(url);
+    let ascii: String = name
+        .chars()
+        .map(|c| if (c.is_ascii_graphic() && c != '"' && c != '\\') || c == ' ' { c } else { '_' })
+        .collect();
+    let value = format!("attachment; filename=\"{}\"; filename*=UTF-8''{}", ascii, urlencoding::encode(&name));
+    HeaderValue::from_str(&value).unwrap_or_else(|_| HeaderValue::from_static("attachment"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_name() {
+        assert_eq!(file_name("https://example.com/videos/a.mp4?token=1"), "a.mp4");
+        assert_eq!(file_name("https://example.com/%E8%A7%86%E9%A2%91.mp4"), "视频.mp4");
+        assert_eq!(file_name("https://example.com/"), "download");
+        assert_eq!(file_name("https://example.com"), "download");
+
+        let value = content_disposition("https://example.com/%E8%A7%86%E9%A2%91.mp4");
+        assert_eq!(
+            value.to_str().unwrap(),
+            "attachment; filename=\"__.mp4\"; filename*=UTF-8''%E8%A7%86%E9%A2%91.mp4"
+        );
+    }
+}
@@ -0,0 +1,113 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use serde::Serialize;
+use crate::utils::request_id;
+
+/// 保留的最近决策记录数量
+const DEFAULT_CAPACITY: usize = 512;
+
+/// 请求最终的数据来源
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Decision {
+    /// 完全从缓存读取
+    FullCache,
+    /// 从预取的 MP4 尾部读取
+    Mp4Tail,
+    /// 前半部分来自缓存，其余来自网络
+    Mixed,
+    /// 从同组节点或上级缓存获取
+    Peer,
+    /// 从源站获取
+    Network,
+}
+
+/// 一次缓存命中决策及原因
+#[derive(Debug, Clone, Serialize)]
+pub struct DecisionRecord {
+    pub time: String,
+    pub request_id: Option<String>,
+    pub url: String,
+    pub key: String,
+    pub start: u64,
+    /// 结束位置未知时为 None
+    pub end: Option<u64>,
+    pub decision: Decision,
+    /// 决策依据：命中的缓存区间、元数据中的文件大小、是否写入缓存等
+    pub reasons: Vec<String>,
+}
+
+/// 最近的缓存命中决策，用于排查请求为什么没有命中缓存
+pub struct DecisionLog {
+    entries: Mutex<VecDeque<DecisionRecord>>,
+    capacity: usize,
+}
+
+impl Default for DecisionLog {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl DecisionLog {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity: capacity.max(1),
+        }
+    }
+
+    pub fn record(&self, url: &str, key: &str, start: u64, end: u64, decision: Decision, reasons: Vec<String>) {
+        let record = DecisionRecord {
+            time: chrono::Local::now().format("%Y-%m-%d %H:%M:%S%.3f").to_string(),
+            request_id: request_id::current(),
+            url: url.to_string(),
+            key: key.to_string(),
+            start,
+            end: if end == u64::MAX { None } else { Some(end) },
+            decision,
+            reasons,
+        };
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(record);
+    }
+
+    /// URL 的决策记录，从新到旧；指定范围时只返回与之重叠的记录
+    pub fn for_url(&self, url: &str, range: Option<(u64, u64)>) -> Vec<DecisionRecord> {
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .filter(|record| record.url == url)
+            .filter(|record| match range {
+                Some((start, end)) => record.start <= end && record.end.is_none_or(|record_end| record_end >= start),
+                None => true,
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decision_log() {
+        let log = DecisionLog::new(2);
+        log.record("http://example.com/a.mp4", "a", 0, 99, Decision::Network, vec![]);
+        log.record("http://example.com/a.mp4", "a", 100, u64::MAX, Decision::Mixed, vec![]);
+        log.record("http://example.com/b.mp4", "b", 0, 99, Decision::FullCache, vec![]);
+
+        let records = log.for_url("http://example.com/a.mp4", None);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].decision, Decision::Mixed);
+        assert_eq!(records[0].end, None);
+        assert_eq!(log.for_url("http://example.com/a.mp4", Some((0, 50))).len(), 0);
+        assert_eq!(log.for_url("http://example.com/b.mp4", Some((50, 60))).len(), 1);
+    }
+}
@@ -0,0 +1,197 @@
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use bytes::Bytes;
+use futures::Stream;
+use crate::utils::error::{ProxyError, Result};
+use crate::log_info;
+
+/// 故障注入配置，仅用于测试混合源和重试路径，默认全部关闭
+///
+/// 使用固定种子的伪随机数，相同的请求顺序得到相同的故障序列
+#[derive(Clone)]
+pub struct FaultConfig {
+    pub seed: u64,
+    /// 上游请求前附加的延迟
+    pub upstream_latency: Duration,
+    pub latency_probability: f64,
+    /// 上游数据流中途报错断开的概率
+    pub disconnect_probability: f64,
+    /// 上游数据流提前正常结束（响应体被截断）的概率
+    pub truncate_probability: f64,
+    /// 缓存写入失败的概率
+    pub write_failure_probability: f64,
+    /// 故障发生前最多放行的数据块数
+    pub max_chunks_before_fault: u64,
+}
+
+impl Default for FaultConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0x5eed,
+            upstream_latency: Duration::ZERO,
+            latency_probability: 0.0,
+            disconnect_probability: 0.0,
+            truncate_probability: 0.0,
+            write_failure_probability: 0.0,
+            max_chunks_before_fault: 8,
+        }
+    }
+}
+
+impl FaultConfig {
+    pub fn is_enabled(&self) -> bool {
+        (self.latency_probability > 0.0 && !self.upstream_latency.is_zero())
+            || self.disconnect_probability > 0.0
+            || self.truncate_probability > 0.0
+            || self.write_failure_probability > 0.0
+    }
+}
+
+/// 数据流故障类型
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum StreamFault {
+    Disconnect,
+    Truncate,
+    WriteFailure,
+}
+
+/// 按配置的概率注入故障
+pub struct FaultInjector {
+    config: FaultConfig,
+    state: AtomicU64,
+}
+
+impl Default for FaultInjector {
+    fn default() -> Self {
+        Self::new(FaultConfig::default())
+    }
+}
+
+impl FaultInjector {
+    pub fn new(config: FaultConfig) -> Self {
+        let state = AtomicU64::new(config.seed);
+        if config.is_enabled() {
+            log_info!("Fault", "已启用故障注入, 种子: {}", config.seed);
+        }
+        Self { config, state }
+    }
+
+    /// splitmix64 伪随机数
+    fn next(&self) -> u64 {
+        let mut z = self
+            .state
+            .fetch_add(0x9e37_79b9_7f4a_7c15, Ordering::Relaxed)
+            .wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn roll(&self, probability: f64) -> bool {
+        probability > 0.0 && ((self.next() >> 11) as f64 / (1u64 << 53) as f64) < probability
+    }
+
+    /// 上游请求前按概率等待
+    pub async fn upstream_delay(&self) {
+        if self.roll(self.config.latency_probability) {
+            log_info!("Fault", "注入上游延迟: {:?}", self.config.upstream_latency);
+            tokio::time::sleep(self.config.upstream_latency).await;
+        }
+    }
+
+    /// 按概率让上游数据流中途断开或被截断
+    pub fn wrap_upstream(
+        &self,
+        stream: Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>>,
+    ) -> Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>> {
+        let fault = if self.roll(self.config.disconnect_probability) {
+            StreamFault::Disconnect
+        } else if self.roll(self.config.truncate_probability) {
+            StreamFault::Truncate
+        } else {
+            return stream;
+        };
+        self.wrap(stream, fault)
+    }
+
+    /// 按概率让缓存写入中途失败
+    pub fn wrap_write(
+        &self,
+        stream: Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>>,
+    ) -> Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>> {
+        if !self.roll(self.config.write_failure_probability) {
+            return stream;
+        }
+        self.wrap(stream, StreamFault::WriteFailure)
+    }
+
+    fn wrap(
+        &self,
+        stream: Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>>,
+        fault: StreamFault,
+    ) -> Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>> {
+        let remaining = self.next() % self.config.max_chunks_before_fault.max(1);
+        log_info!("Fault", "注入数据流故障: {:?}, {} 个数据块后触发", fault, remaining);
+        Box::pin(FaultStream {
+            inner: stream,
+            remaining,
+            fault,
+            done: false,
+        })
+    }
+}
+
+struct FaultStream {
+    inner: Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>>,
+    remaining: u64,
+    fault: StreamFault,
+    done: bool,
+}
+
+impl Stream for FaultStream {
+    type Item = Result<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.done {
+            return Poll::Ready(None);
+        }
+        if self.remaining == 0 {
+            self.done = true;
+            return Poll::Ready(match self.fault {
+                StreamFault::Disconnect => Some(Err(ProxyError::network("注入的上游断开".to_string()))),
+                StreamFault::Truncate => None,
+                StreamFault::WriteFailure => Some(Err(ProxyError::storage("注入的磁盘写入失败".to_string()))),
+            });
+        }
+
+        let poll = self.inner.as_mut().poll_next(cx);
+        if let Poll::Ready(Some(Ok(_))) = &poll {
+            self.remaining -= 1;
+        }
+        poll
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roll_is_deterministic() {
+        let config = FaultConfig { disconnect_probability: 0.5, ..Default::default() };
+        let first: Vec<bool> = {
+            let injector = FaultInjector::new(config.clone());
+            (0..32).map(|_| injector.roll(0.5)).collect()
+        };
+        let injector = FaultInjector::new(config);
+        let second: Vec<bool> = (0..32).map(|_| injector.roll(0.5)).collect();
+        assert_eq!(first, second);
+        assert!(first.iter().any(|hit| *hit) && first.iter().any(|hit| !*hit));
+
+        let injector = FaultInjector::default();
+        assert!(!injector.roll(0.0));
+        assert!(injector.roll(1.0));
+    }
+}
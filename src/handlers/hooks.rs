@@ -0,0 +1,115 @@
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use async_trait::async_trait;
+use bytes::Bytes;
+use crate::utils::error::Result;
+
+/// 附属条目的访问路径前缀：`/meta/<名称>/<编码后的源站 URL>`
+pub const META_PREFIX: &str = "/meta/";
+
+/// 下载完成的缓存条目
+#[derive(Debug, Clone)]
+pub struct CompletedEntry {
+    /// 缓存键，带命名空间前缀时与 `url` 不同
+    pub key: String,
+    pub url: String,
+    /// 数据文件路径，只读；钩子返回后文件仍可能被清理或移动到其他存储层
+    pub path: PathBuf,
+    pub size: u64,
+}
+
+/// 钩子生成的附属数据，例如缩略图或时长信息，作为辅助缓存条目保存
+#[derive(Debug, Clone)]
+pub struct AuxEntry {
+    /// 名称，只允许字母、数字、`-` 和 `_`
+    pub name: String,
+    pub content_type: String,
+    pub data: Bytes,
+}
+
+impl AuxEntry {
+    pub fn new(name: impl Into<String>, content_type: impl Into<String>, data: impl Into<Bytes>) -> Self {
+        Self {
+            name: name.into(),
+            content_type: content_type.into(),
+            data: data.into(),
+        }
+    }
+}
+
+/// 条目下载完成后的回调，在后台执行，失败只记录日志
+#[async_trait]
+pub trait CompletionHook: Send + Sync {
+    async fn on_complete(&self, entry: &CompletedEntry) -> Result<Vec<AuxEntry>>;
+}
+
+/// 已注册的完成回调
+#[derive(Default)]
+pub struct CompletionHooks {
+    hooks: RwLock<Vec<Arc<dyn CompletionHook>>>,
+}
+
+impl CompletionHooks {
+    pub fn register(&self, hook: Arc<dyn CompletionHook>) {
+        self.hooks.write().unwrap().push(hook);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hooks.read().unwrap().is_empty()
+    }
+
+    pub fn snapshot(&self) -> Vec<Arc<dyn CompletionHook>> {
+        self.hooks.read().unwrap().clone()
+    }
+}
+
+/// 附属条目的缓存键
+pub fn aux_key(key: &str, name: &str) -> String {
+    format!("{}#meta-{}", key, name)
+}
+
+/// 附属条目和 MP4 尾部缓存等旁路条目完成时不触发回调
+pub fn is_sidecar_key(key: &str) -> bool {
+    key.contains("#meta-") || key.ends_with("#mp4-tail")
+}
+
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.len() <= 64 && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// 去掉命名空间前缀，得到源站 URL
+pub fn url_of(key: &str) -> &str {
+    key.strip_prefix("ns:")
+        .and_then(|rest| rest.split_once(':'))
+        .map(|(_, url)| url)
+        .unwrap_or(key)
+}
+
+/// 解析 `/meta/<名称>/<编码后的源站 URL>`，返回名称和 URL
+pub fn parse_meta_path(path: &str) -> Option<(String, String)> {
+    let (name, encoded) = path.strip_prefix(META_PREFIX)?.split_once('/')?;
+    if !is_valid_name(name) {
+        return None;
+    }
+    let url = urlencoding::decode(encoded).ok()?.into_owned();
+    Some((name.to_string(), url))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keys_and_paths() {
+        assert_eq!(url_of("ns:abc:https://example.com/a.mp4"), "https://example.com/a.mp4");
+        assert_eq!(url_of("https://example.com/a.mp4"), "https://example.com/a.mp4");
+        assert!(is_sidecar_key(&aux_key("https://example.com/a.mp4", "thumb")));
+        assert!(!is_sidecar_key("https://example.com/a.mp4"));
+
+        assert_eq!(
+            parse_meta_path("/meta/thumb/https%3A%2F%2Fexample.com%2Fa.mp4"),
+            Some(("thumb".to_string(), "https://example.com/a.mp4".to_string()))
+        );
+        assert_eq!(parse_meta_path("/meta/../https%3A%2F%2Fexample.com"), None);
+    }
+}
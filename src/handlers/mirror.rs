@@ -0,0 +1,81 @@
+use std::time::Duration;
+
+/// 镜像规则：以 `prefix` 开头的 URL 失败时，依次替换为各镜像前缀重试
+#[derive(Debug, Clone)]
+pub struct MirrorRule {
+    pub prefix: String,
+    pub mirrors: Vec<String>,
+}
+
+impl MirrorRule {
+    pub fn new(prefix: impl Into<String>, mirrors: Vec<String>) -> Self {
+        Self {
+            prefix: prefix.into(),
+            mirrors,
+        }
+    }
+}
+
+/// 上游镜像配置
+#[derive(Clone)]
+pub struct MirrorConfig {
+    pub rules: Vec<MirrorRule>,
+    /// 配置了镜像时，单个源超过该时间未响应即切换到下一个
+    pub timeout: Duration,
+}
+
+impl Default for MirrorConfig {
+    fn default() -> Self {
+        Self {
+            rules: Vec::new(),
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+impl MirrorConfig {
+    /// 按尝试顺序返回源站和镜像的 URL，第一个总是原始 URL
+    pub fn candidates(&self, url: &str) -> Vec<String> {
+        let mut candidates = vec![url.to_string()];
+        let rule = self
+            .rules
+            .iter()
+            .filter(|rule| url.starts_with(&rule.prefix))
+            .max_by_key(|rule| rule.prefix.len());
+
+        if let Some(rule) = rule {
+            let path = &url[rule.prefix.len()..];
+            candidates.extend(rule.mirrors.iter().map(|mirror| format!("{}{}", mirror, path)));
+        }
+        candidates
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_candidates() {
+        let config = MirrorConfig {
+            rules: vec![
+                MirrorRule::new("http://origin.example.com/", vec!["http://backup.example.com/".to_string()]),
+                MirrorRule::new(
+                    "http://origin.example.com/videos/",
+                    vec!["https://cdn.example.com/v/".to_string(), "https://cdn2.example.com/v/".to_string()],
+                ),
+            ],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            config.candidates("http://origin.example.com/videos/a.mp4"),
+            vec![
+                "http://origin.example.com/videos/a.mp4",
+                "https://cdn.example.com/v/a.mp4",
+                "https://cdn2.example.com/v/a.mp4",
+            ]
+        );
+        assert_eq!(config.candidates("http://other.com/a.mp4"), vec!["http://other.com/a.mp4"]);
+    }
+}
@@ -3,9 +3,9 @@ use bytes::Bytes;
 use futures::{Stream, StreamExt};
 use hyper::{Body, Response};
 use tokio::time::timeout;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use crate::utils::error::{Result, ProxyError};
-use crate::handlers::{CacheHandler, NetworkHandler, ResponseBuilder};
+use crate::handlers::{CacheHandler, CachePolicy, CachePolicyConfig, NetworkHandler, ResponseBuilder};
 use std::sync::Arc;
 use crate::log_info;
 
@@ -16,24 +16,40 @@ pub struct MixedSourceHandler {
     cache_handler: Arc<CacheHandler>,
     network_handler: NetworkHandler,
     response_builder: ResponseBuilder,
+    /// 缓存部分获取超过该时间时，拼接前先向上游校验
+    revalidate_after: Option<Duration>,
+    /// 网络部分的响应不可缓存时不更新缓存条目
+    cache_policy: Arc<CachePolicy>,
 }
 
 impl MixedSourceHandler {
-    pub fn new(cache_handler: Arc<CacheHandler>) -> Self {
+    pub fn new(cache_handler: Arc<CacheHandler>, network_handler: NetworkHandler) -> Self {
         Self {
             cache_handler,
-            network_handler: NetworkHandler::new(),
+            network_handler,
             response_builder: ResponseBuilder::new(),
+            revalidate_after: None,
+            cache_policy: Arc::new(CachePolicy::new(CachePolicyConfig::default())),
         }
     }
 
+    pub fn with_policy(mut self, cache_policy: Arc<CachePolicy>) -> Self {
+        self.cache_policy = cache_policy;
+        self
+    }
+
+    pub fn with_revalidate_after(mut self, revalidate_after: Option<Duration>) -> Self {
+        self.revalidate_after = revalidate_after;
+        self
+    }
+
     pub async fn handle(&self, url: &str, key: &str, start: u64, end: u64, cached_end: u64) -> Result<Response<Body>> {
         log_info!("Cache", "混合源请求开始 - 缓存范围: {}-{}, 网络范围: {}-{}", start, cached_end - 1, cached_end, end);
 
         // 验证请求范围
         if start > end || cached_end < start || cached_end > end {
             log_info!("Cache", "请求范围无效: start={}, end={}, cached_end={}", start, end, cached_end);
-            return Err(ProxyError::InvalidRange("无效的请求范围".to_string()));
+            return Err(ProxyError::invalid_range("无效的请求范围".to_string()));
         }
 
         // 计算数据大小
@@ -43,43 +59,19 @@ impl MixedSourceHandler {
         if cache_size < MIN_CACHE_SIZE {
             log_info!("Cache", "缓存范围过小 ({} 字节), 直接从网络获取整个范围: {}-{}", 
                 cache_size, start, end);
-            
-            let range = format!("bytes={}-{}", start, end);
-            let network_future = self.network_handler.fetch(url, &range);
-            let network_result = timeout(NETWORK_TIMEOUT, network_future).await
-                .map_err(|_| {
-                    log_info!("Cache", "网络请求超时: {} ({}秒)", url, NETWORK_TIMEOUT.as_secs());
-                    ProxyError::Network("网络请求超时".to_string())
-                })?;
-                
-            let (resp, content_length, total_file_size) = match network_result {
-                Ok(result) => result,
-                Err(e) => {
-                    log_info!("Cache", "网络请求失败: {} - {}", url, e);
-                    return Err(ProxyError::Network(format!("网络请求失败: {}", e)));
-                }
-            };
-
-            let headers = self.network_handler.extract_headers(&resp);
-            let (_, body) = resp.into_parts();
-            
-            let network_stream = futures::StreamExt::map(Body::wrap_stream(body), |result| {
-                result.map_err(|e| {
-                    log_info!("Cache", "网络数据流错误: {}", e);
-                    ProxyError::Network(e.to_string())
-                })
-            });
-
-            log_info!("Cache", "创建响应 - 范围: {}-{}, 总大小: {}", start, end, total_file_size);
-            return Ok(self.response_builder.build_partial_content_response(
-                Box::new(network_stream),
-                headers,
-                start,
-                end,
-                total_file_size,
-            ));
+            return self.fetch_network_range(url, start, end).await;
         }
 
+        // 缓存部分获取时间过久时先向上游确认对象没有变化，避免拼接出两个版本的内容
+        let validator = match self.stale_validator(key).await {
+            Some(Some(validator)) => Some(validator),
+            Some(None) => {
+                log_info!("Cache", "缓存部分已过期且没有校验值，从网络获取整个范围: {} {}-{}", url, start, end);
+                return self.fetch_network_range(url, start, end).await;
+            }
+            None => None,
+        };
+
         let network_size = (end - cached_end + 1) as usize;
         let total_size = cache_size + network_size;
 
@@ -90,34 +82,60 @@ impl MixedSourceHandler {
         let range = format!("bytes={}-{}", cached_end, end);
         log_info!("Cache", "发起网络请求 - URL: {}, Range: {}", url, range);
         
-        let network_future = self.network_handler.fetch(url, &range);
+        let network_future = async {
+            match &validator {
+                Some(validator) => self.network_handler.fetch_if_range(url, &range, validator).await,
+                None => self.network_handler.fetch(url, &range).await,
+            }
+        };
         let network_result = timeout(NETWORK_TIMEOUT, network_future).await
             .map_err(|_| {
                 log_info!("Cache", "网络请求超时: {} ({}秒)", url, NETWORK_TIMEOUT.as_secs());
-                ProxyError::Network("网络请求超时".to_string())
+                ProxyError::network("网络请求超时".to_string())
             })?;
             
         let (resp, content_length, total_file_size) = match network_result {
             Ok(result) => result,
             Err(e) => {
                 log_info!("Cache", "网络请求失败: {} - {}", url, e);
-                return Err(ProxyError::Network(format!("网络请求失败: {}", e)));
+                return Err(ProxyError::network(format!("网络请求失败: {}", e)));
             }
         };
 
+        // 校验值不匹配时上游返回完整内容，缓存部分已是旧版本
+        if validator.is_some() && resp.status() != hyper::StatusCode::PARTIAL_CONTENT {
+            log_info!("Cache", "源站内容已变化，删除旧缓存并从网络获取整个范围: {} {}-{}", url, start, end);
+            drop(resp);
+            self.cache_handler.remove(key).await;
+            return self.fetch_network_range(url, start, end).await;
+        }
+
         // 验证网络响应大小
         if content_length != network_size as u64 {
             log_info!("Cache", "警告：网络响应大小不匹配 - 期望: {} 字节, 实际: {} 字节", 
                 network_size, content_length);
         }
 
+        // 源站已不允许缓存时删除已缓存的部分，避免保存响应头和后续预读继续补齐
+        if !self.cache_policy.is_storable(url, resp.headers()) {
+            log_info!("Cache", "网络部分的响应不可缓存，删除旧缓存并从网络获取整个范围: {} {}-{}", url, start, end);
+            drop(resp);
+            self.cache_handler.remove(key).await;
+            return self.fetch_network_range(url, start, end).await;
+        }
+
         let headers = self.network_handler.extract_headers(&resp);
+        if total_file_size > 0 {
+            if let Err(e) = self.cache_handler.save_headers(key, resp.headers(), Some(total_file_size)).await {
+                log_info!("Cache", "保存响应头失败: {} - {}", key, e);
+            }
+        }
         let (_, body) = resp.into_parts();
         
         let network_stream = futures::StreamExt::map(Body::wrap_stream(body), |result| {
             result.map_err(|e| {
                 log_info!("Cache", "网络数据流错误: {}", e);
-                ProxyError::Network(e.to_string())
+                ProxyError::network(e.to_string())
             })
         });
 
@@ -134,7 +152,7 @@ impl MixedSourceHandler {
         // 创建合并的流
         let combined_stream = self.create_mixed_stream(
             cache_stream,
-            Box::pin(network_stream),
+            self.network_handler.track(url, &range, Box::pin(network_stream)),
             cache_size,
             network_size,
         );
@@ -149,6 +167,61 @@ impl MixedSourceHandler {
         ))
     }
 
+    /// 从网络获取整个范围，不使用缓存数据
+    async fn fetch_network_range(&self, url: &str, start: u64, end: u64) -> Result<Response<Body>> {
+        let range = format!("bytes={}-{}", start, end);
+        let network_future = self.network_handler.fetch(url, &range);
+        let network_result = timeout(NETWORK_TIMEOUT, network_future).await
+            .map_err(|_| {
+                log_info!("Cache", "网络请求超时: {} ({}秒)", url, NETWORK_TIMEOUT.as_secs());
+                ProxyError::network("网络请求超时".to_string())
+            })?;
+            
+        let (resp, _, total_file_size) = match network_result {
+            Ok(result) => result,
+            Err(e) => {
+                log_info!("Cache", "网络请求失败: {} - {}", url, e);
+                return Err(ProxyError::network(format!("网络请求失败: {}", e)));
+            }
+        };
+
+        let headers = self.network_handler.extract_headers(&resp);
+        let (_, body) = resp.into_parts();
+        
+        let network_stream = futures::StreamExt::map(Body::wrap_stream(body), |result| {
+            result.map_err(|e| {
+                log_info!("Cache", "网络数据流错误: {}", e);
+                ProxyError::network(e.to_string())
+            })
+        });
+
+        let network_stream = self.network_handler.track(url, &range, Box::pin(network_stream));
+
+        log_info!("Cache", "创建响应 - 范围: {}-{}, 总大小: {}", start, end, total_file_size);
+        Ok(self.response_builder.build_partial_content_response(
+            Box::new(network_stream),
+            headers,
+            start,
+            end,
+            total_file_size,
+        ))
+    }
+
+    /// 缓存部分超过 `revalidate_after` 时返回用于 `If-Range` 的校验值，优先使用 ETag；
+    /// 没有校验值时返回 `Some(None)`，不需要校验时返回 None
+    async fn stale_validator(&self, key: &str) -> Option<Option<String>> {
+        let threshold = self.revalidate_after?;
+        let meta = self.cache_handler.get_meta(key).await?;
+        let fetched_at = meta.fetched_at?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |now| now.as_secs());
+        if now.saturating_sub(fetched_at) < threshold.as_secs() {
+            return None;
+        }
+        // 弱 ETag 不能用于 If-Range
+        let etag = meta.header("etag").filter(|etag| !etag.starts_with("W/"));
+        Some(etag.or_else(|| meta.header("last-modified")).map(str::to_string))
+    }
+
     fn create_mixed_stream(
         &self,
         cached_stream: Box<dyn Stream<Item = Result<Bytes>> + Send + Unpin>,
@@ -226,7 +299,7 @@ impl MixedSourceHandler {
                                 log_info!("Cache", "警告：缓存数据不足 - 已接收: {} 字节, 期望: {} 字节", 
                                     state.cache_received, state.cache_size);
                                 state.error_occurred = true;
-                                return Some((Err(ProxyError::Network("缓存数据不足".to_string())), state));
+                                return Some((Err(ProxyError::network("缓存数据不足".to_string())), state));
                             }
 
                             state.using_cache = false;
@@ -276,7 +349,7 @@ impl MixedSourceHandler {
                                 log_info!("Cache", "警告：网络数据不足 - 已接收: {} 字节, 期望: {} 字节", 
                                     state.network_received, state.network_size);
                                 state.error_occurred = true;
-                                return Some((Err(ProxyError::Network("网络数据不足".to_string())), state));
+                                return Some((Err(ProxyError::network("网络数据不足".to_string())), state));
                             }
 
                             state.network_stream = None;
@@ -294,9 +367,6 @@ impl MixedSourceHandler {
             }
 
             if state.using_cache {
-                state.using_cache = false;
-                state.cached_stream = None;
-                state.chunk_count = 0;
                 log_info!("Cache", "缓存数据发送完毕，切换到网络数据");
             }
 
@@ -2,8 +2,45 @@ mod cache;
 mod network;
 mod mixed_source;
 mod response;
+mod policy;
+mod parallel;
+mod mirror;
+mod cors;
+mod fault;
+mod profile;
+mod readahead;
+mod popularity;
+mod coalesce;
+mod watchdog;
+pub mod active;
+pub mod alert;
+pub mod checksum;
+pub mod conditional;
+pub mod compression;
+pub mod download;
+pub mod explain;
+pub mod hooks;
+pub mod tunnel;
+pub mod stats;
+pub mod namespace;
+pub mod scheduler;
+pub mod send_buffer;
+pub mod session;
+pub mod usage;
 
 pub use cache::CacheHandler;
 pub use network::NetworkHandler;
 pub use mixed_source::MixedSourceHandler;
-pub use response::ResponseBuilder; 
\ No newline at end of file
+pub use response::{HeaderPolicy, ResponseBuilder};
+pub use policy::{CachePolicy, CachePolicyConfig, CacheRule, is_unbounded_stream, without_admission};
+pub use parallel::{ParallelDownloader, ParallelDownloadConfig};
+pub use mirror::{MirrorConfig, MirrorRule};
+pub use cors::CorsConfig;
+pub use fault::{FaultConfig, FaultInjector};
+pub use namespace::NamespaceConfig;
+pub use scheduler::{Priority, Scheduler, SchedulerConfig};
+pub use watchdog::WatchdogConfig;
+pub use coalesce::{CoalesceBuffer, CoalesceConfig};
+pub use popularity::{PopularFill, PopularityConfig};
+pub use readahead::{PlaybackHint, ReadAheadConfig, SequentialDetector, PLAYBACK_POSITION_HEADER, fill, read_ahead};
+pub use profile::{HostProfile, UpstreamProfiles, DEFAULT_USER_AGENT};
@@ -0,0 +1,98 @@
+use hyper::header::AUTHORIZATION;
+use hyper::HeaderMap;
+use url::Url;
+
+/// 按用户划分缓存的配置
+///
+/// 启用后，携带凭据的请求使用独立的缓存命名空间，命名空间标识为凭据 MD5 的前 16 位，
+/// 避免共享代理把一个用户的私有签名内容返回给另一个用户
+#[derive(Clone)]
+pub struct NamespaceConfig {
+    pub enabled: bool,
+    /// 读取凭据的请求头
+    pub header: String,
+    /// 读取凭据的查询参数，请求头不存在时使用
+    pub query_param: Option<String>,
+    /// 每个命名空间的缓存配额（字节），0 表示不限制
+    pub quota: u64,
+}
+
+impl Default for NamespaceConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            header: AUTHORIZATION.as_str().to_string(),
+            query_param: Some("token".to_string()),
+            quota: 0,
+        }
+    }
+}
+
+impl NamespaceConfig {
+    /// 请求所属的命名空间，未启用或请求没有凭据时返回 None（使用共享缓存）
+    pub fn namespace_for(&self, headers: &HeaderMap, url: &str) -> Option<String> {
+        if !self.enabled {
+            return None;
+        }
+        let from_header = headers
+            .get(self.header.as_str())
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.to_string());
+        let credential = from_header.or_else(|| {
+            let param = self.query_param.as_ref()?;
+            Url::parse(url)
+                .ok()?
+                .query_pairs()
+                .find(|(key, _)| key == param.as_str())
+                .map(|(_, value)| value.into_owned())
+        })?;
+        if credential.is_empty() {
+            return None;
+        }
+        Some(format!("{:x}", md5::compute(credential.as_bytes()))[..16].to_string())
+    }
+}
+
+/// 命名空间下缓存键的前缀
+pub fn key_prefix(namespace: &str) -> String {
+    format!("ns:{}:", namespace)
+}
+
+/// 缓存键所属命名空间的前缀，共享缓存的键返回 None
+pub fn namespace_prefix(key: &str) -> Option<&str> {
+    let rest = key.strip_prefix("ns:")?;
+    let end = rest.find(':')?;
+    Some(&key[.."ns:".len() + end + 1])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_namespace_for() {
+        let config = NamespaceConfig { enabled: true, ..Default::default() };
+        let url = "http://example.com/a.mp4";
+        assert_eq!(config.namespace_for(&HeaderMap::new(), url), None);
+
+        let mut alice = HeaderMap::new();
+        alice.insert(AUTHORIZATION, "Bearer alice".parse().unwrap());
+        let mut bob = HeaderMap::new();
+        bob.insert(AUTHORIZATION, "Bearer bob".parse().unwrap());
+        let alice_ns = config.namespace_for(&alice, url).unwrap();
+        assert_ne!(Some(alice_ns.clone()), config.namespace_for(&bob, url));
+        assert_eq!(Some(alice_ns), config.namespace_for(&alice, url));
+
+        let token_ns = config.namespace_for(&HeaderMap::new(), "http://example.com/a.mp4?token=abc");
+        assert!(token_ns.is_some());
+        assert!(NamespaceConfig::default().namespace_for(&alice, url).is_none());
+    }
+
+    #[test]
+    fn test_namespace_prefix() {
+        let key = format!("{}http://example.com/a.mp4", key_prefix("0123456789abcdef"));
+        assert_eq!(namespace_prefix(&key), Some("ns:0123456789abcdef:"));
+        assert_eq!(namespace_prefix("http://example.com/a.mp4"), None);
+        assert_eq!(namespace_prefix("ns:broken"), None);
+    }
+}
@@ -1,24 +1,232 @@
+use std::pin::Pin;
+use std::sync::Arc;
+use bytes::Bytes;
+use futures::Stream;
 use hyper::{Body, Response, HeaderMap};
-use crate::data_source::NetSource;
-use crate::utils::error::Result;
+use tokio::time::timeout;
+use crate::handlers::{CoalesceBuffer, CoalesceConfig, FaultInjector, MirrorConfig, Scheduler, SchedulerConfig, UpstreamProfiles, WatchdogConfig};
+use crate::handlers::watchdog;
+use crate::handlers::coalesce::slice_response;
+use crate::handlers::scheduler;
+use crate::handlers::active::{ActiveDownloadInfo, ActiveDownloads};
+use crate::handlers::alert::Alerts;
+use crate::cluster::{LOOP_DETECT_HEADER, PEER_HEADER};
+use crate::data_source::{NetSource, UpstreamConnectConfig};
+use crate::utils::error::{ErrorKind, ProxyError, Result};
 use crate::log_info;
 
-pub struct NetworkHandler;
+#[derive(Clone)]
+pub struct NetworkHandler {
+    mirrors: Arc<MirrorConfig>,
+    active: Arc<ActiveDownloads>,
+    faults: Arc<FaultInjector>,
+    profiles: Arc<UpstreamProfiles>,
+    scheduler: Arc<Scheduler>,
+    coalesce: Arc<CoalesceBuffer>,
+    watchdog: WatchdogConfig,
+    connect: UpstreamConnectConfig,
+    alerts: Arc<Alerts>,
+}
+
+impl Default for NetworkHandler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl NetworkHandler {
     pub fn new() -> Self {
-        Self
+        Self::with_mirrors(MirrorConfig::default())
+    }
+
+    pub fn with_mirrors(mirrors: MirrorConfig) -> Self {
+        Self {
+            mirrors: Arc::new(mirrors),
+            active: Arc::new(ActiveDownloads::new()),
+            faults: Arc::new(FaultInjector::default()),
+            profiles: Arc::new(UpstreamProfiles::default()),
+            scheduler: Scheduler::new(SchedulerConfig::default()),
+            coalesce: Arc::new(CoalesceBuffer::new(CoalesceConfig::default())),
+            watchdog: WatchdogConfig::default(),
+            connect: UpstreamConnectConfig::default(),
+            alerts: Arc::new(Alerts::default()),
+        }
+    }
+
+    /// 设置告警，源站连续失败时通知
+    pub fn with_alerts(mut self, alerts: Arc<Alerts>) -> Self {
+        self.alerts = alerts;
+        self
+    }
+
+    /// 设置上游数据停滞检测
+    pub fn with_watchdog(mut self, config: WatchdogConfig) -> Self {
+        self.watchdog = config;
+        self
+    }
+
+    /// 设置上游连接的地址族偏好
+    pub fn with_connect(mut self, config: UpstreamConnectConfig) -> Self {
+        self.connect = config;
+        self
+    }
+
+    /// 设置小范围请求合并
+    pub fn with_coalesce(mut self, config: CoalesceConfig) -> Self {
+        self.coalesce = Arc::new(CoalesceBuffer::new(config));
+        self
+    }
+
+    /// 设置上游请求的优先级调度
+    pub fn with_scheduler(mut self, config: SchedulerConfig) -> Self {
+        self.scheduler = Scheduler::new(config);
+        self
+    }
+
+    /// 设置按主机区分的上游请求头
+    pub fn with_profiles(mut self, profiles: UpstreamProfiles) -> Self {
+        self.profiles = Arc::new(profiles);
+        self
+    }
+
+    /// 上游请求头配置
+    pub fn profiles(&self) -> &UpstreamProfiles {
+        &self.profiles
+    }
+
+    /// 设置故障注入，仅用于测试
+    pub fn with_faults(mut self, faults: Arc<FaultInjector>) -> Self {
+        self.faults = faults;
+        self
+    }
+
+    /// 登记上游数据流，用于查看进度和取消
+    pub fn track(
+        &self,
+        url: &str,
+        range: &str,
+        stream: Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>>,
+    ) -> Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>> {
+        self.active.track(url, range, self.faults.wrap_upstream(stream))
+    }
+
+    /// 正在进行的上游下载
+    pub fn active_downloads(&self) -> Vec<ActiveDownloadInfo> {
+        self.active.list()
+    }
+
+    /// 取消正在进行的上游下载
+    pub fn cancel_download(&self, id: u64) -> bool {
+        self.active.cancel(id)
     }
 
+    /// 请求上游，小范围请求合并为更大的请求并在本地切片，返回响应、内容长度和文件总大小
     pub async fn fetch(&self, url: &str, range: &str) -> Result<(Response<Body>, u64, u64)> {
-        let net_source = NetSource::new(url, range);
-        let (resp, content_length) = net_source.download_stream().await?;
+        if let Ok((start, end)) = crate::utils::range::parse_range(range) {
+            if let Some(window) = self.coalesce.window_for(start, end) {
+                if let Some(result) = self.fetch_coalesced(url, start, end, window).await {
+                    return Ok(result);
+                }
+            }
+        }
+        self.fetch_with_mirrors(url, range).await
+    }
+
+    /// 从合并的数据中切出请求范围，合并请求失败或上游不支持范围请求时返回 None，改为直接请求
+    async fn fetch_coalesced(&self, url: &str, start: u64, end: u64, window: (u64, u64)) -> Option<(Response<Body>, u64, u64)> {
+        if self.coalesce.get(url, start, end).is_none() {
+            let range = format!("bytes={}-{}", window.0, window.1);
+            let (resp, _, total_size) = self.fetch_with_mirrors(url, &range).await.ok()?;
+            if resp.status() != hyper::StatusCode::PARTIAL_CONTENT {
+                return None;
+            }
+            let headers = resp.headers().clone();
+            let data = hyper::body::to_bytes(resp.into_body()).await.ok()?;
+            log_info!("Network", "合并小范围请求: {} {}-{} -> {}", url, start, end, range);
+            self.coalesce.insert(url, window.0, data, total_size, headers);
+        }
+
+        let (data, total_size, headers) = self.coalesce.get(url, start, end)?;
+        let content_length = data.len() as u64;
+        Some((slice_response(data, start, total_size, &headers), content_length, total_size))
+    }
+
+    /// 请求上游并记录源站是否可用，客户端错误（4xx）不计入
+    async fn fetch_with_mirrors(&self, url: &str, range: &str) -> Result<(Response<Body>, u64, u64)> {
+        let result = self.try_mirrors(url, range).await;
+        match &result {
+            Ok(_) => self.alerts.record_origin(url, true),
+            Err(e) if e.kind() == ErrorKind::Network || e.upstream_status().is_some_and(|status| status.is_server_error()) => {
+                self.alerts.record_origin(url, false)
+            }
+            Err(_) => {}
+        }
+        result
+    }
+
+    /// 请求上游，源站失败或超时时依次尝试镜像
+    async fn try_mirrors(&self, url: &str, range: &str) -> Result<(Response<Body>, u64, u64)> {
+        let candidates = self.mirrors.candidates(url);
+        if candidates.len() == 1 {
+            return self.fetch_from(url, range).await;
+        }
+
+        let mut last_error = None;
+        for candidate in &candidates {
+            match timeout(self.mirrors.timeout, self.fetch_from(candidate, range)).await {
+                Ok(Ok(result)) => {
+                    if candidate != url {
+                        log_info!("Network", "使用镜像: {} -> {}", url, candidate);
+                    }
+                    return Ok(result);
+                }
+                Ok(Err(e)) => {
+                    log_info!("Network", "上游请求失败，尝试下一个源: {} - {}", candidate, e);
+                    last_error = Some(e);
+                }
+                Err(_) => {
+                    log_info!("Network", "上游请求超时，尝试下一个源: {}", candidate);
+                    last_error = Some(ProxyError::network(format!("请求超时: {}", candidate)));
+                }
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| ProxyError::network(format!("没有可用的上游: {}", url))))
+    }
+
+    /// 从同组节点读取其缓存的数据
+    pub async fn fetch_from_peer(&self, peer_url: &str, range: &str) -> Result<(Response<Body>, u64, u64)> {
+        self.fetch_source(NetSource::new(peer_url, range).with_header(PEER_HEADER, "1")).await
+    }
+
+    /// 从上级缓存代理获取，携带经过的节点列表
+    pub async fn fetch_from_parent(&self, parent_url: &str, range: &str, via: &str) -> Result<(Response<Body>, u64, u64)> {
+        self.fetch_source(NetSource::new(parent_url, range).with_header(LOOP_DETECT_HEADER, via)).await
+    }
+
+    /// 携带 `If-Range` 请求源站，校验值不匹配时上游返回完整内容（200）
+    pub async fn fetch_if_range(&self, url: &str, range: &str, validator: &str) -> Result<(Response<Body>, u64, u64)> {
+        self.fetch_source(NetSource::new(url, range).with_header(hyper::header::IF_RANGE.as_str(), validator)).await
+    }
+
+    async fn fetch_from(&self, url: &str, range: &str) -> Result<(Response<Body>, u64, u64)> {
+        self.fetch_source(NetSource::new(url, range)).await
+    }
+
+    async fn fetch_source(&self, net_source: NetSource) -> Result<(Response<Body>, u64, u64)> {
+        self.faults.upstream_delay().await;
+        // 先放入主机配置的请求头，调用方指定的请求头写在后面，同名时以调用方为准
+        let mut headers = self.profiles.headers_for(&net_source.url);
+        headers.extend(net_source.headers);
+        let net_source = NetSource { headers, connect: self.connect.clone(), ..net_source };
+        let (resp, content_length) = self.download(&net_source).await?;
+        let resp = self.watch(resp, net_source);
         log_info!("Cache", "网络响应成功，内容长度: {}", content_length);
 
         // 获取文件总大小
         let total_size = if let Some(range) = resp.headers().get(hyper::header::CONTENT_RANGE) {
             if let Ok(range_str) = range.to_str() {
-                if let Some(total) = range_str.split('/').last() {
+                if let Some(total) = range_str.split('/').next_back() {
                     total.parse::<u64>().unwrap_or(0)
                 } else {
                     0
@@ -33,6 +241,44 @@ impl NetworkHandler {
         Ok((resp, content_length, total_size))
     }
 
+    /// 按当前任务的优先级排队后请求，名额随响应体释放
+    async fn download(&self, net_source: &NetSource) -> Result<(Response<Body>, u64)> {
+        let permit = self.scheduler.acquire(scheduler::current()).await;
+        let (resp, content_length) = net_source.download_stream().await?;
+        Ok((permit.attach(resp), content_length))
+    }
+
+    /// 范围响应的数据停滞时从已收到的位置重新请求
+    fn watch(&self, resp: Response<Body>, net_source: NetSource) -> Response<Body> {
+        if !self.watchdog.enabled || resp.status() != hyper::StatusCode::PARTIAL_CONTENT {
+            return resp;
+        }
+        let (start, end) = match crate::utils::range::parse_range(&net_source.range) {
+            Ok(range) => range,
+            Err(_) => return resp,
+        };
+        let end = if end == u64::MAX { None } else { Some(end) };
+
+        let handler = self.clone();
+        let url = net_source.url.clone();
+        let reissue = move |range: String| {
+            let handler = handler.clone();
+            let net_source = NetSource { range, ..net_source.clone() };
+            async move { handler.download(&net_source).await.map(|(resp, _)| resp) }
+        };
+        let (parts, body) = resp.into_parts();
+        Response::from_parts(parts, watchdog::watch(&url, body, start, end, self.watchdog.clone(), reissue))
+    }
+
+    /// 读取上游指定范围的完整数据，上游不支持范围请求时返回错误，避免下载整个文件
+    pub async fn fetch_bytes(&self, url: &str, start: u64, end: u64) -> Result<Bytes> {
+        let (resp, _, _) = self.fetch(url, &format!("bytes={}-{}", start, end)).await?;
+        if resp.status() != hyper::StatusCode::PARTIAL_CONTENT {
+            return Err(ProxyError::network(format!("上游不支持范围请求: {}", resp.status())));
+        }
+        Ok(hyper::body::to_bytes(resp.into_body()).await?)
+    }
+
     pub fn extract_headers(&self, resp: &Response<Body>) -> HeaderMap {
         let mut headers = HeaderMap::new();
         for (key, value) in resp.headers().iter() {
@@ -0,0 +1,116 @@
+use std::pin::Pin;
+use std::sync::Arc;
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use tokio::sync::Semaphore;
+use crate::handlers::NetworkHandler;
+use crate::utils::error::{ProxyError, Result};
+use crate::log_info;
+
+/// 多连接分段下载配置
+#[derive(Clone)]
+pub struct ParallelDownloadConfig {
+    /// 单个请求同时使用的上游连接数，1 表示不分段
+    pub connections: usize,
+    /// 请求范围小于该大小时不分段
+    pub min_size: u64,
+    /// 每段大小，同时在内存中缓冲的数据不超过 connections * part_size
+    pub part_size: u64,
+    /// 所有分段下载共享的上游连接数上限
+    pub max_connections: usize,
+}
+
+impl Default for ParallelDownloadConfig {
+    fn default() -> Self {
+        Self {
+            connections: 1,
+            min_size: 8 * 1024 * 1024,  // 8MB
+            part_size: 2 * 1024 * 1024, // 2MB
+            max_connections: 16,
+        }
+    }
+}
+
+/// 将大范围请求拆分为多个上游连接并行下载，按顺序重新组装
+pub struct ParallelDownloader {
+    config: ParallelDownloadConfig,
+    network_handler: NetworkHandler,
+    permits: Arc<Semaphore>,
+}
+
+impl ParallelDownloader {
+    pub fn new(config: ParallelDownloadConfig, network_handler: NetworkHandler) -> Self {
+        let permits = Arc::new(Semaphore::new(config.max_connections.max(1)));
+        Self { config, network_handler, permits }
+    }
+
+    /// 判断是否分段下载，返回实际结束位置
+    pub fn split_end(&self, start: u64, end: u64, total_size: u64) -> Option<u64> {
+        if self.config.connections <= 1 || total_size == 0 || start >= total_size {
+            return None;
+        }
+        let end = end.min(total_size - 1);
+        if end - start + 1 < self.config.min_size {
+            return None;
+        }
+        Some(end)
+    }
+
+    /// 按顺序输出 [start, end] 的数据，最多同时下载 connections 段
+    pub fn download(&self, url: &str, start: u64, end: u64) -> Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>> {
+        let parts = split_range(start, end, self.config.part_size);
+        log_info!("Network", "分段下载: {} {}-{} 共 {} 段, {} 个连接",
+            url, start, end, parts.len(), self.config.connections);
+
+        let url = url.to_string();
+        let permits = self.permits.clone();
+        let network_handler = self.network_handler.clone();
+        let stream = futures::stream::iter(parts)
+            .map(move |(part_start, part_end)| {
+                let url = url.clone();
+                let permits = permits.clone();
+                let network_handler = network_handler.clone();
+                async move {
+                    let _permit = permits.acquire_owned().await?;
+                    let data = network_handler.fetch_bytes(&url, part_start, part_end).await?;
+                    if data.len() as u64 != part_end - part_start + 1 {
+                        return Err(ProxyError::network(format!(
+                            "分段数据长度不符: {}-{} 收到 {} 字节", part_start, part_end, data.len()
+                        )));
+                    }
+                    Ok(data)
+                }
+            })
+            .buffered(self.config.connections.max(1));
+
+        Box::pin(stream)
+    }
+}
+
+/// 将闭区间 [start, end] 按 `part_size` 拆分
+fn split_range(start: u64, end: u64, part_size: u64) -> Vec<(u64, u64)> {
+    let part_size = part_size.max(1);
+    let mut parts = Vec::new();
+    let mut offset = start;
+    while offset <= end {
+        let part_end = offset.saturating_add(part_size - 1).min(end);
+        parts.push((offset, part_end));
+        if part_end == u64::MAX {
+            break;
+        }
+        offset = part_end + 1;
+    }
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_range() {
+        assert_eq!(split_range(0, 9, 4), vec![(0, 3), (4, 7), (8, 9)]);
+        assert_eq!(split_range(10, 13, 4), vec![(10, 13)]);
+        assert_eq!(split_range(5, 5, 4), vec![(5, 5)]);
+    }
+}
@@ -0,0 +1,370 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use hyper::HeaderMap;
+use hyper::header::{CACHE_CONTROL, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, SET_COOKIE};
+use url::Url;
+use crate::utils::bloom::BloomFilter;
+use crate::log_info;
+
+/// 不缓存的流式内容类型
+const STREAMING_CONTENT_TYPES: &[&str] = &["text/event-stream", "multipart/x-mixed-replace"];
+
+/// 站点缓存规则
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CacheRule {
+    Auto,   // 根据上游响应头判断
+    Always, // 总是缓存，忽略 Cache-Control 和 Set-Cookie
+    Never,  // 从不缓存
+}
+
+/// 缓存策略配置
+#[derive(Clone)]
+pub struct CachePolicyConfig {
+    /// 未单独配置的站点使用的规则
+    pub default_rule: CacheRule,
+    /// 按主机名覆盖的规则
+    pub host_rules: HashMap<String, CacheRule>,
+    /// 小于该大小的对象不缓存，直接转发（0 表示不限制）
+    pub min_object_size: u64,
+    /// 大于该大小的对象不缓存，直接转发
+    pub max_object_size: u64,
+    /// 二次命中准入窗口，设置后 URL 在窗口内第二次请求才写入缓存
+    pub admission_window: Option<Duration>,
+    /// 准入过滤器在一个窗口内预期记录的 URL 数量
+    pub admission_capacity: usize,
+    /// 缓存键按参数名排序查询参数，参数顺序不同的请求共用缓存，上游请求仍使用原始顺序
+    pub sort_query_params: bool,
+    /// 部分命中时缓存部分获取超过该时间，拼接网络数据前先用 If-Range 向源站校验，为 None 时不校验
+    pub mixed_revalidate_after: Option<Duration>,
+}
+
+impl Default for CachePolicyConfig {
+    fn default() -> Self {
+        Self {
+            default_rule: CacheRule::Auto,
+            host_rules: HashMap::new(),
+            min_object_size: 0,
+            max_object_size: u64::MAX,
+            admission_window: None,
+            admission_capacity: 100_000,
+            sort_query_params: false,
+            mixed_revalidate_after: Some(Duration::from_secs(600)),
+        }
+    }
+}
+
+tokio::task_local! {
+    static BYPASS_ADMISSION: bool;
+}
+
+/// 执行期间写入缓存不经过二次命中准入，用于管理接口显式要求的预取
+pub async fn without_admission<F: Future>(future: F) -> F::Output {
+    BYPASS_ADMISSION.scope(true, future).await
+}
+
+/// 二次命中准入过滤器
+///
+/// 使用新旧两代布隆过滤器轮换，近似记录一个窗口内出现过的 URL
+struct AdmissionFilter {
+    current: BloomFilter,
+    previous: BloomFilter,
+    window: Duration,
+    rotated_at: Instant,
+}
+
+impl AdmissionFilter {
+    fn new(window: Duration, capacity: usize) -> Self {
+        Self {
+            current: BloomFilter::new(capacity, 0.01),
+            previous: BloomFilter::new(capacity, 0.01),
+            window,
+            rotated_at: Instant::now(),
+        }
+    }
+
+    /// 记录一次请求，返回窗口内是否已经见过该 URL
+    fn admit(&mut self, key: &str) -> bool {
+        self.rotate();
+        let seen = self.current.contains(key) || self.previous.contains(key);
+        self.current.insert(key);
+        seen
+    }
+
+    fn rotate(&mut self) {
+        let elapsed = self.rotated_at.elapsed();
+        if elapsed >= self.window {
+            self.current.clear();
+            self.previous.clear();
+            self.rotated_at = Instant::now();
+        } else if elapsed >= self.window / 2 {
+            std::mem::swap(&mut self.current, &mut self.previous);
+            self.current.clear();
+            self.rotated_at = Instant::now();
+        }
+    }
+}
+
+/// 决定上游响应是否写入缓存
+pub struct CachePolicy {
+    config: CachePolicyConfig,
+    admission: Option<Mutex<AdmissionFilter>>,
+}
+
+impl CachePolicy {
+    pub fn new(config: CachePolicyConfig) -> Self {
+        let admission = config
+            .admission_window
+            .map(|window| Mutex::new(AdmissionFilter::new(window, config.admission_capacity)));
+        Self { config, admission }
+    }
+
+    /// 获取 URL 所属站点的缓存规则
+    pub fn rule_for(&self, url: &str) -> CacheRule {
+        Url::parse(url)
+            .ok()
+            .and_then(|parsed| parsed.host_str().map(|host| host.to_ascii_lowercase()))
+            .and_then(|host| self.config.host_rules.get(&host).copied())
+            .unwrap_or(self.config.default_rule)
+    }
+
+    /// 判断上游响应是否可以缓存，`object_size` 为对象总大小，未知时为 0
+    pub fn should_cache(&self, url: &str, headers: &HeaderMap, object_size: u64) -> bool {
+        if object_size > 0 && !self.size_allowed(object_size) {
+            log_info!("Policy", "对象大小 {} 字节超出缓存范围 [{}, {}]，直接转发: {}",
+                object_size, self.config.min_object_size, self.config.max_object_size, url);
+            return false;
+        }
+        if !self.is_storable(url, headers) {
+            return false;
+        }
+
+        let bypass = BYPASS_ADMISSION.try_with(|bypass| *bypass).unwrap_or(false);
+        if self.rule_for(url) == CacheRule::Auto && !bypass {
+            if let Some(admission) = &self.admission {
+                if !admission.lock().unwrap().admit(url) {
+                    log_info!("Policy", "首次请求，暂不缓存: {}", url);
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    /// 只按响应头和站点规则判断响应能否写入缓存，不经过大小限制和准入过滤，用于向已有条目追加数据
+    pub fn is_storable(&self, url: &str, headers: &HeaderMap) -> bool {
+        // 上游忽略 Accept-Encoding: identity 时，压缩数据的字节范围与原始内容不一致
+        if let Some(encoding) = content_encoding(headers) {
+            log_info!("Policy", "响应经过 {} 编码，不缓存: {}", encoding, url);
+            return false;
+        }
+
+        match self.rule_for(url) {
+            CacheRule::Always => true,
+            CacheRule::Never => {
+                log_info!("Policy", "站点配置为不缓存: {}", url);
+                false
+            }
+            CacheRule::Auto => match private_reason(headers) {
+                Some(reason) => {
+                    log_info!("Policy", "响应不可缓存 ({}): {}", reason, url);
+                    false
+                }
+                None => true,
+            },
+        }
+    }
+
+    fn size_allowed(&self, object_size: u64) -> bool {
+        object_size >= self.config.min_object_size && object_size <= self.config.max_object_size
+    }
+}
+
+/// 检查响应是否为私有或动态内容，返回原因
+fn private_reason(headers: &HeaderMap) -> Option<&'static str> {
+    if headers.contains_key(SET_COOKIE) {
+        return Some("Set-Cookie");
+    }
+
+    // 持续推送的流式内容没有固定大小
+    if let Some(content_type) = headers.get(CONTENT_TYPE).and_then(|value| value.to_str().ok()) {
+        let content_type = content_type.trim().to_ascii_lowercase();
+        if STREAMING_CONTENT_TYPES.iter().any(|t| content_type.starts_with(t)) {
+            return Some("流式内容");
+        }
+    }
+
+    for value in headers.get_all(CACHE_CONTROL).iter() {
+        let value = match value.to_str() {
+            Ok(value) => value,
+            Err(_) => continue,
+        };
+        for directive in value.split(',') {
+            // private 可能带字段列表，如 private="set-cookie"
+            let name = directive.split('=').next().unwrap_or("").trim();
+            if name.eq_ignore_ascii_case("no-store") {
+                return Some("Cache-Control: no-store");
+            }
+            if name.eq_ignore_ascii_case("private") {
+                return Some("Cache-Control: private");
+            }
+        }
+    }
+
+    None
+}
+
+/// 获取非 identity 的内容编码
+fn content_encoding(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(CONTENT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.trim())
+        .filter(|value| !value.is_empty() && !value.eq_ignore_ascii_case("identity"))
+}
+
+/// 判断上游响应是否为没有固定大小的流，这类响应只能直接透传；未给出长度的响应总是透传，
+/// ICY 电台响应在 `icy` 开启时透传
+pub fn is_unbounded_stream(headers: &HeaderMap, icy: bool) -> bool {
+    if icy && headers.keys().any(|name| name.as_str().starts_with("icy-")) {
+        return true;
+    }
+    !headers.contains_key(CONTENT_LENGTH) && !headers.contains_key(CONTENT_RANGE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::{HeaderName, VARY};
+
+    type Headers<'a> = &'a [(HeaderName, &'a str)];
+
+    fn headers(pairs: &[(HeaderName, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(name.clone(), value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn test_unbounded_stream() {
+        let radio = headers(&[(HeaderName::from_static("icy-name"), "radio"), (CONTENT_LENGTH, "1000")]);
+        assert!(is_unbounded_stream(&radio, true));
+        assert!(!is_unbounded_stream(&radio, false));
+
+        let unsized_headers = headers(&[(CONTENT_TYPE, "audio/mpeg")]);
+        assert!(is_unbounded_stream(&unsized_headers, false));
+        assert!(!is_unbounded_stream(&headers(&[(CONTENT_LENGTH, "1000")]), true));
+        assert!(!is_unbounded_stream(&headers(&[(CONTENT_RANGE, "bytes 0-9/100")]), true));
+    }
+
+    #[test]
+    fn test_private_reason() {
+        let cases: &[(Headers, Option<&str>)] = &[
+            (&[], None),
+            (&[(CACHE_CONTROL, "public, max-age=3600")], None),
+            (&[(CACHE_CONTROL, "no-cache")], None),
+            (&[(CACHE_CONTROL, "private")], Some("Cache-Control: private")),
+            (&[(CACHE_CONTROL, "max-age=60, Private=\"set-cookie\"")], Some("Cache-Control: private")),
+            (&[(CACHE_CONTROL, "NO-STORE")], Some("Cache-Control: no-store")),
+            (&[(CACHE_CONTROL, "public"), (CACHE_CONTROL, "no-store")], Some("Cache-Control: no-store")),
+            (&[(SET_COOKIE, "session=1")], Some("Set-Cookie")),
+            (&[(CONTENT_TYPE, "text/event-stream; charset=utf-8")], Some("流式内容")),
+            (&[(CONTENT_TYPE, "video/mp4")], None),
+            // Vary 只影响缓存键，不阻止缓存
+            (&[(VARY, "Accept-Encoding")], None),
+            (&[(VARY, "*")], None),
+        ];
+        for (pairs, expected) in cases {
+            assert_eq!(private_reason(&headers(pairs)), *expected, "{:?}", pairs);
+        }
+    }
+
+    #[test]
+    fn test_content_encoding() {
+        let cases: &[(Headers, Option<&str>)] = &[
+            (&[], None),
+            (&[(CONTENT_ENCODING, "identity")], None),
+            (&[(CONTENT_ENCODING, " IDENTITY ")], None),
+            (&[(CONTENT_ENCODING, "")], None),
+            (&[(CONTENT_ENCODING, "gzip")], Some("gzip")),
+            (&[(CONTENT_ENCODING, " br ")], Some("br")),
+            (&[(CONTENT_ENCODING, "gzip"), (VARY, "Accept-Encoding")], Some("gzip")),
+        ];
+        for (pairs, expected) in cases {
+            assert_eq!(content_encoding(&headers(pairs)), *expected, "{:?}", pairs);
+        }
+    }
+
+    #[test]
+    fn test_host_rule_overrides_headers() {
+        let config = CachePolicyConfig {
+            host_rules: [
+                ("always.example.com".to_string(), CacheRule::Always),
+                ("never.example.com".to_string(), CacheRule::Never),
+            ]
+            .into_iter()
+            .collect(),
+            ..CachePolicyConfig::default()
+        };
+        let policy = CachePolicy::new(config);
+        let private = headers(&[(CACHE_CONTROL, "private")]);
+
+        assert!(policy.is_storable("http://always.example.com/a.mp4", &private));
+        assert!(!policy.is_storable("http://example.com/a.mp4", &private));
+        assert!(!policy.is_storable("http://never.example.com/a.mp4", &HeaderMap::new()));
+        // 编码过的响应即使配置为总是缓存也不写入
+        assert!(!policy.is_storable("http://always.example.com/a.mp4", &headers(&[(CONTENT_ENCODING, "gzip")])));
+    }
+
+    #[test]
+    fn test_admission_second_hit() {
+        let mut filter = AdmissionFilter::new(Duration::from_secs(60), 1000);
+        assert!(!filter.admit("http://example.com/a.mp4"));
+        assert!(filter.admit("http://example.com/a.mp4"));
+        assert!(!filter.admit("http://example.com/b.mp4"));
+    }
+
+    #[test]
+    fn test_admission_rotation() {
+        let window = Duration::from_secs(60);
+        let mut filter = AdmissionFilter::new(window, 1000);
+        assert!(!filter.admit("a"));
+
+        // 过半个窗口后轮换，上一代仍然记得 a
+        filter.rotated_at = Instant::now() - window / 2;
+        assert!(!filter.admit("b"));
+        assert!(filter.admit("a"));
+
+        // 再次轮换后，上一代保留了上半个窗口内的 a 和 b
+        filter.rotated_at = Instant::now() - window / 2;
+        assert!(filter.admit("b"));
+
+        // 连续两次轮换后没有再次出现的 URL 被遗忘
+        filter.rotated_at = Instant::now() - window / 2;
+        filter.rotate();
+        filter.rotated_at = Instant::now() - window / 2;
+        filter.rotate();
+        assert!(!filter.admit("a"));
+
+        // 超过整个窗口没有请求时两代都清空
+        filter.rotated_at = Instant::now() - window;
+        assert!(!filter.admit("a"));
+    }
+
+    #[test]
+    fn test_prefetch_bypasses_admission() {
+        let policy = CachePolicy::new(CachePolicyConfig {
+            admission_window: Some(Duration::from_secs(60)),
+            ..CachePolicyConfig::default()
+        });
+        let url = "http://example.com/a.mp4";
+        let headers = HeaderMap::new();
+        let prefetched = futures::executor::block_on(without_admission(async { policy.should_cache(url, &headers, 100) }));
+        assert!(prefetched);
+        assert!(!policy.should_cache("http://example.com/b.mp4", &headers, 100));
+        assert!(policy.should_cache("http://example.com/b.mp4", &headers, 100));
+    }
+}
@@ -0,0 +1,181 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use crate::handlers::{fill, CacheHandler, NetworkHandler};
+use crate::handlers::scheduler::{self, Priority};
+use crate::log_info;
+
+/// 最多统计的 URL 数，超出时丢弃窗口内没有请求的记录
+const MAX_TRACKED: usize = 10_000;
+
+/// 按热度主动补全缓存的配置
+#[derive(Clone)]
+pub struct PopularityConfig {
+    pub enabled: bool,
+    /// 统计请求次数的滑动窗口
+    pub window: Duration,
+    /// 检查间隔，只在没有上游下载时补全
+    pub interval: Duration,
+    /// 每轮最多补全的条目数
+    pub top_n: usize,
+    /// 窗口内请求次数达到该值才补全
+    pub min_requests: usize,
+    /// 每个窗口内补全下载的字节数上限，同时限制占用的磁盘和带宽
+    pub fill_budget: u64,
+}
+
+impl Default for PopularityConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            window: Duration::from_secs(3600),
+            interval: Duration::from_secs(60),
+            top_n: 5,
+            min_requests: 3,
+            fill_budget: 2 * 1024 * 1024 * 1024, // 2GB
+        }
+    }
+}
+
+struct Popularity {
+    url: String,
+    requests: VecDeque<Instant>,
+}
+
+#[derive(Default)]
+struct Budget {
+    window_start: Option<Instant>,
+    used: u64,
+}
+
+/// 统计请求热度，空闲时为最热门的未完整缓存条目补全数据
+pub struct PopularFill {
+    config: PopularityConfig,
+    cache_handler: Arc<CacheHandler>,
+    network_handler: NetworkHandler,
+    entries: Mutex<HashMap<String, Popularity>>,
+    budget: Mutex<Budget>,
+}
+
+impl PopularFill {
+    pub fn new(config: PopularityConfig, cache_handler: Arc<CacheHandler>, network_handler: NetworkHandler) -> Arc<Self> {
+        let popular = Arc::new(Self {
+            config,
+            cache_handler,
+            network_handler,
+            entries: Mutex::new(HashMap::new()),
+            budget: Mutex::new(Budget::default()),
+        });
+        if popular.config.enabled {
+            log_info!("Cache", "启用热门内容主动缓存");
+            popular.clone().start();
+        }
+        popular
+    }
+
+    /// 记录一次请求
+    pub fn record(&self, key: &str, url: &str) {
+        if !self.config.enabled {
+            return;
+        }
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= MAX_TRACKED && !entries.contains_key(key) {
+            self.expire(&mut entries, now);
+            if entries.len() >= MAX_TRACKED {
+                return;
+            }
+        }
+        entries
+            .entry(key.to_string())
+            .or_insert_with(|| Popularity {
+                url: url.to_string(),
+                requests: VecDeque::new(),
+            })
+            .requests
+            .push_back(now);
+    }
+
+    /// 窗口内请求次数最多的条目，返回缓存键、URL 和请求次数
+    pub fn top(&self, limit: usize) -> Vec<(String, String, usize)> {
+        let mut entries = self.entries.lock().unwrap();
+        self.expire(&mut entries, Instant::now());
+        let mut top: Vec<_> = entries
+            .iter()
+            .filter(|(_, entry)| entry.requests.len() >= self.config.min_requests)
+            .map(|(key, entry)| (key.clone(), entry.url.clone(), entry.requests.len()))
+            .collect();
+        top.sort_by_key(|b| std::cmp::Reverse(b.2));
+        top.truncate(limit);
+        top
+    }
+
+    fn expire(&self, entries: &mut HashMap<String, Popularity>, now: Instant) {
+        for entry in entries.values_mut() {
+            while entry.requests.front().is_some_and(|at| now.duration_since(*at) > self.config.window) {
+                entry.requests.pop_front();
+            }
+        }
+        entries.retain(|_, entry| !entry.requests.is_empty());
+    }
+
+    /// 本窗口剩余的补全字节数
+    fn remaining_budget(&self) -> u64 {
+        let mut budget = self.budget.lock().unwrap();
+        let now = Instant::now();
+        let expired = match budget.window_start {
+            Some(start) => now.duration_since(start) >= self.config.window,
+            None => true,
+        };
+        if expired {
+            budget.window_start = Some(now);
+            budget.used = 0;
+        }
+        self.config.fill_budget.saturating_sub(budget.used)
+    }
+
+    fn start(self: Arc<Self>) {
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(self.config.interval).await;
+                if self.network_handler.active_downloads().is_empty() {
+                    self.fill_round().await;
+                }
+            }
+        });
+    }
+
+    async fn fill_round(&self) {
+        for (key, url, requests) in self.top(self.config.top_n) {
+            let remaining = self.remaining_budget();
+            if remaining == 0 {
+                log_info!("Cache", "本窗口主动缓存额度已用完");
+                return;
+            }
+            // 有客户端开始下载时停止，下一轮再继续
+            if !self.network_handler.active_downloads().is_empty() {
+                return;
+            }
+
+            let total = match self.cache_handler.get_meta(&key).await.and_then(|meta| meta.content_length) {
+                Some(total) if total > 0 => total,
+                _ => continue,
+            };
+            let cached = match self.cache_handler.get_size(&key).await {
+                Ok(size) => size.unwrap_or(0),
+                Err(_) => continue,
+            };
+            if cached >= total {
+                continue;
+            }
+
+            let target = cached.saturating_add(remaining - 1).min(total - 1);
+            log_info!("Cache", "主动缓存热门内容: {} ({} 次请求) {}-{}", url, requests, cached, target);
+            let fill = fill(&self.cache_handler, &self.network_handler, &url, &key, cached, target, total);
+            match scheduler::with_priority(Priority::Background, fill).await {
+                Ok(written) => self.budget.lock().unwrap().used += written,
+                Err(e) => log_info!("Cache", "主动缓存失败: {} - {}", url, e),
+            }
+        }
+    }
+}
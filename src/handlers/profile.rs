@@ -0,0 +1,124 @@
+use hyper::header::{HeaderMap, HeaderName, HeaderValue, USER_AGENT};
+use url::Url;
+
+/// 未配置时使用的 User-Agent
+pub const DEFAULT_USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/91.0.4472.124 Safari/537.36";
+
+/// 单个上游主机的请求特征，`host` 以 `*.` 开头时匹配所有子域名
+#[derive(Debug, Clone)]
+pub struct HostProfile {
+    pub host: String,
+    pub user_agent: Option<String>,
+    /// 附加的请求头，同名时覆盖默认值
+    pub headers: Vec<(String, String)>,
+}
+
+impl HostProfile {
+    pub fn new(host: impl Into<String>) -> Self {
+        Self {
+            host: host.into(),
+            user_agent: None,
+            headers: Vec::new(),
+        }
+    }
+
+    pub fn with_user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    fn matches(&self, host: &str) -> bool {
+        match self.host.strip_prefix("*.") {
+            Some(domain) => {
+                host.eq_ignore_ascii_case(domain)
+                    || (host.len() > domain.len()
+                        && host[host.len() - domain.len()..].eq_ignore_ascii_case(domain)
+                        && host.as_bytes()[host.len() - domain.len() - 1] == b'.')
+            }
+            None => host.eq_ignore_ascii_case(&self.host),
+        }
+    }
+}
+
+/// 上游请求头配置，按主机选择 User-Agent 和默认请求头
+#[derive(Clone)]
+pub struct UpstreamProfiles {
+    pub user_agent: String,
+    /// 所有上游请求都携带的请求头
+    pub headers: Vec<(String, String)>,
+    pub hosts: Vec<HostProfile>,
+}
+
+impl Default for UpstreamProfiles {
+    fn default() -> Self {
+        Self {
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            headers: Vec::new(),
+            hosts: Vec::new(),
+        }
+    }
+}
+
+impl UpstreamProfiles {
+    /// 返回请求该 URL 需要携带的请求头，精确主机优先于通配符，通配符中域名越长越优先
+    pub fn headers_for(&self, url: &str) -> Vec<(String, String)> {
+        let host = Url::parse(url).ok().and_then(|url| url.host_str().map(|host| host.to_string()));
+        let profile = host.as_deref().and_then(|host| {
+            self.hosts
+                .iter()
+                .filter(|profile| profile.matches(host))
+                .max_by_key(|profile| (!profile.host.starts_with("*."), profile.host.len()))
+        });
+
+        let user_agent = profile
+            .and_then(|profile| profile.user_agent.clone())
+            .unwrap_or_else(|| self.user_agent.clone());
+        let mut headers = vec![(USER_AGENT.as_str().to_string(), user_agent)];
+        headers.extend(self.headers.iter().cloned());
+        if let Some(profile) = profile {
+            headers.extend(profile.headers.iter().cloned());
+        }
+        headers
+    }
+
+    /// 将请求头写入请求，后写入的同名头覆盖先前的值
+    pub fn apply(&self, url: &str, headers: &mut HeaderMap) {
+        for (name, value) in self.headers_for(url) {
+            if let (Ok(name), Ok(value)) = (HeaderName::from_bytes(name.as_bytes()), HeaderValue::from_str(&value)) {
+                headers.insert(name, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_headers_for() {
+        let profiles = UpstreamProfiles {
+            hosts: vec![
+                HostProfile::new("*.example.com").with_user_agent("Player/1.0"),
+                HostProfile::new("nas.example.com")
+                    .with_user_agent("Kodi/20.0")
+                    .with_header("Referer", "http://nas.example.com/"),
+            ],
+            ..Default::default()
+        };
+
+        let headers = profiles.headers_for("http://cdn.example.com/a.mp4");
+        assert_eq!(headers, vec![("user-agent".to_string(), "Player/1.0".to_string())]);
+
+        let headers = profiles.headers_for("http://nas.example.com/a.mp4");
+        assert_eq!(headers[0].1, "Kodi/20.0");
+        assert_eq!(headers[1], ("Referer".to_string(), "http://nas.example.com/".to_string()));
+
+        assert_eq!(profiles.headers_for("http://badexample.com/a.mp4")[0].1, DEFAULT_USER_AGENT);
+    }
+}
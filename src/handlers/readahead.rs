@@ -0,0 +1,242 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use futures::StreamExt;
+use hyper::{Body, HeaderMap, StatusCode};
+use crate::handlers::{CacheHandler, NetworkHandler};
+use crate::handlers::scheduler::{self, Priority};
+use crate::utils::error::{ProxyError, Result};
+use crate::log_info;
+
+/// 最多同时跟踪的 URL 数，超出时丢弃长时间没有请求的记录
+const MAX_TRACKED: usize = 1024;
+/// 超过该时间没有后续请求时重新计算连续次数
+const IDLE_RESET: Duration = Duration::from_secs(60);
+
+/// 配合的播放器上报当前播放位置（秒）和码率（bit/s）的请求头，如 `position=12.5; bitrate=2500000`
+pub const PLAYBACK_POSITION_HEADER: &str = "x-playback-position";
+
+/// 播放器通过 `X-Playback-Position` 提供的播放状态
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlaybackHint {
+    /// 当前播放位置（秒）
+    pub position: f64,
+    /// 当前码率（bit/s）
+    pub bitrate: Option<u64>,
+}
+
+impl PlaybackHint {
+    /// 解析 `position=12.5; bitrate=2500000`，参数以 `;` 或 `,` 分隔，只有数字时视为播放位置
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut position = None;
+        let mut bitrate = None;
+        for param in value.split([';', ',']).map(str::trim).filter(|param| !param.is_empty()) {
+            match param.split_once('=') {
+                Some((name, value)) if name.trim().eq_ignore_ascii_case("position") => {
+                    position = value.trim().parse::<f64>().ok();
+                }
+                Some((name, value)) if name.trim().eq_ignore_ascii_case("bitrate") => {
+                    bitrate = value.trim().parse::<u64>().ok().filter(|bitrate| *bitrate > 0);
+                }
+                Some(_) => {}
+                None => position = param.parse::<f64>().ok(),
+            }
+        }
+        let position = position.filter(|position| position.is_finite() && *position >= 0.0)?;
+        Some(Self { position, bitrate })
+    }
+
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        headers
+            .get(PLAYBACK_POSITION_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(Self::parse)
+    }
+}
+
+/// 顺序读取预读配置
+#[derive(Clone)]
+pub struct ReadAheadConfig {
+    pub enabled: bool,
+    /// 在播放器请求位置之后预先缓存的数据量
+    pub window: u64,
+    /// 连续请求达到该次数后开始预读
+    pub min_sequential: u32,
+    /// 请求起点与上次结束位置相差不超过该值时仍视为连续
+    pub tolerance: u64,
+    /// 请求带 `X-Playback-Position` 时预读该播放时长的数据，HLS 预取覆盖该时长的后续分片
+    pub hint_lookahead: Duration,
+    /// 按码率计算的预读窗口上限
+    pub hint_max_window: u64,
+    /// HLS 按播放位置最多预取的分片数
+    pub hint_segments: usize,
+}
+
+impl ReadAheadConfig {
+    /// 按码率预读 `hint_lookahead` 时长的数据量
+    pub fn hint_window(&self, bitrate: u64) -> u64 {
+        (bitrate / 8)
+            .saturating_mul(self.hint_lookahead.as_secs().max(1))
+            .min(self.hint_max_window)
+    }
+}
+
+impl Default for ReadAheadConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            window: 8 * 1024 * 1024,  // 8MB
+            min_sequential: 2,
+            tolerance: 256 * 1024,    // 256KB
+            hint_lookahead: Duration::from_secs(30),
+            hint_max_window: 64 * 1024 * 1024,  // 64MB
+            hint_segments: 3,
+        }
+    }
+}
+
+struct ReadState {
+    next: u64,
+    streak: u32,
+    seen: Instant,
+}
+
+/// 识别同一 URL 的连续范围请求
+pub struct SequentialDetector {
+    config: ReadAheadConfig,
+    states: Mutex<HashMap<String, ReadState>>,
+}
+
+impl SequentialDetector {
+    pub fn new(config: ReadAheadConfig) -> Self {
+        Self {
+            config,
+            states: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn config(&self) -> &ReadAheadConfig {
+        &self.config
+    }
+
+    /// 记录一次请求，返回是否处于顺序读取状态，不限结束位置的请求不参与判断
+    pub fn observe(&self, key: &str, start: u64, end: u64) -> bool {
+        if !self.config.enabled || end == u64::MAX {
+            return false;
+        }
+
+        let now = Instant::now();
+        let mut states = self.states.lock().unwrap();
+        if states.len() >= MAX_TRACKED && !states.contains_key(key) {
+            states.retain(|_, state| now.duration_since(state.seen) < IDLE_RESET);
+            if states.len() >= MAX_TRACKED {
+                return false;
+            }
+        }
+
+        let state = states.entry(key.to_string()).or_insert(ReadState {
+            next: start,
+            streak: 0,
+            seen: now,
+        });
+        let continues = now.duration_since(state.seen) < IDLE_RESET
+            && start >= state.next.saturating_sub(self.config.tolerance)
+            && start <= state.next + self.config.tolerance;
+        state.streak = if continues { state.streak + 1 } else { 1 };
+        state.next = end + 1;
+        state.seen = now;
+        state.streak >= self.config.min_sequential
+    }
+
+    /// 记录一次请求并返回预读窗口：播放器提供码率时按码率计算，否则顺序读取时使用固定窗口，不需要预读时返回 None
+    pub fn window(&self, key: &str, start: u64, end: u64, hint: Option<&PlaybackHint>) -> Option<u64> {
+        let sequential = self.observe(key, start, end);
+        if !self.config.enabled || end == u64::MAX {
+            return None;
+        }
+        match hint.and_then(|hint| hint.bitrate) {
+            Some(bitrate) => Some(self.config.hint_window(bitrate)),
+            None if sequential => Some(self.config.window),
+            None => None,
+        }
+    }
+}
+
+/// 将缓存从当前已缓存位置向后补齐到 `position + window`，已缓存的数据超过半个窗口时不预读
+pub async fn read_ahead(
+    cache_handler: &CacheHandler,
+    network_handler: &NetworkHandler,
+    url: &str,
+    key: &str,
+    position: u64,
+    total_size: u64,
+    window: u64,
+) -> Result<()> {
+    if total_size == 0 {
+        return Ok(());
+    }
+    let cached = cache_handler.get_size(key).await?.unwrap_or(0);
+    let target = position.saturating_add(window).min(total_size - 1);
+    if cached > target || cached >= position.saturating_add(window / 2) {
+        return Ok(());
+    }
+
+    log_info!("Cache", "顺序读取预读: {} {}-{}", url, cached, target);
+    scheduler::with_priority(Priority::ReadAhead, fill(cache_handler, network_handler, url, key, cached, target, total_size)).await?;
+    Ok(())
+}
+
+/// 从上游下载 [cached, target] 追加到缓存末尾，`cached` 必须等于已缓存的大小，返回写入的字节数
+pub async fn fill(
+    cache_handler: &CacheHandler,
+    network_handler: &NetworkHandler,
+    url: &str,
+    key: &str,
+    cached: u64,
+    target: u64,
+    total_size: u64,
+) -> Result<u64> {
+    let range = format!("bytes={}-{}", cached, target);
+    let (resp, _, _) = network_handler.fetch(url, &range).await?;
+    if resp.status() != StatusCode::PARTIAL_CONTENT {
+        return Err(ProxyError::network(format!("上游不支持范围请求: {}", resp.status())));
+    }
+
+    let stream = Box::pin(Body::wrap_stream(resp.into_body()).map(|chunk| chunk.map_err(|e| ProxyError::network(e.to_string()))));
+    cache_handler.write_stream(key, (cached, target), network_handler.track(url, &range, stream)).await?;
+    if target == total_size - 1 {
+        cache_handler.complete(key, total_size).await?;
+    }
+    let written = cache_handler.get_size(key).await?.unwrap_or(0).saturating_sub(cached);
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_observe() {
+        let detector = SequentialDetector::new(ReadAheadConfig::default());
+        assert!(!detector.observe("a", 0, 1023));
+        assert!(detector.observe("a", 1024, 2047));
+        assert!(detector.observe("a", 2048, 4095));
+        // 跳转后重新计数
+        assert!(!detector.observe("a", 100 * 1024 * 1024, 100 * 1024 * 1024 + 1023));
+        assert!(!detector.observe("b", 0, u64::MAX));
+    }
+
+    #[test]
+    fn test_playback_hint() {
+        let hint = PlaybackHint::parse("position=12.5; bitrate=2500000").unwrap();
+        assert_eq!(hint, PlaybackHint { position: 12.5, bitrate: Some(2_500_000) });
+        assert_eq!(PlaybackHint::parse("30").unwrap().bitrate, None);
+        assert!(PlaybackHint::parse("bitrate=800000").is_none());
+        assert!(PlaybackHint::parse("position=-1").is_none());
+
+        // 有码率时不需要先识别出顺序读取
+        let detector = SequentialDetector::new(ReadAheadConfig::default());
+        assert_eq!(detector.window("c", 0, 1023, Some(&hint)), Some(312_500 * 30));
+        assert_eq!(detector.window("d", 0, 1023, None), None);
+    }
+}
@@ -1,13 +1,102 @@
 use hyper::{Body, Response, HeaderMap};
 use bytes::Bytes;
 use futures::Stream;
+use hyper::header::{HeaderValue, ACCEPT_RANGES, AGE, ALLOW, CACHE_CONTROL, CONTENT_LENGTH, VIA};
+use crate::storage::meta::header_matches;
 use crate::utils::error::Result;
 
-pub struct ResponseBuilder;
+/// 代理入口支持的请求方法，OPTIONS 和 405 响应的 `Allow` 头
+pub const ALLOWED_METHODS: &str = "GET, HEAD, OPTIONS";
+
+/// 默认的 `Via` 标识
+pub const DEFAULT_VIA: &str = "1.1 proxy-server";
+
+/// 返回给客户端的响应头策略
+#[derive(Clone)]
+pub struct HeaderPolicy {
+    /// 总是添加 `Accept-Ranges: bytes`
+    pub accept_ranges: bool,
+    /// 给下游缓存的 Cache-Control，设置后替换上游的值
+    pub cache_control: Option<String>,
+    /// 不转发给客户端的上游响应头，支持以 `*` 结尾的前缀匹配
+    pub strip_headers: Vec<String>,
+    /// 客户端发送 `TE: trailers` 时，在响应末尾附加 `X-Content-CRC32` 和 `Digest` 尾部字段
+    pub checksum_trailers: bool,
+    /// 追加到 `Via` 头的标识，为 None 时不声明代理身份
+    pub via: Option<String>,
+    /// 命中缓存时返回 `Age`：距离从上游获取的秒数
+    pub age: bool,
+}
+
+impl Default for HeaderPolicy {
+    fn default() -> Self {
+        Self {
+            accept_ranges: true,
+            cache_control: None,
+            strip_headers: ["set-cookie", "set-cookie2", "p3p", "x-tracking-*", "x-amz-request-id", "x-amz-id-2"]
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+            checksum_trailers: false,
+            via: Some(DEFAULT_VIA.to_string()),
+            age: true,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ResponseBuilder {
+    policy: HeaderPolicy,
+}
+
+impl Default for ResponseBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl ResponseBuilder {
     pub fn new() -> Self {
-        Self
+        Self::with_policy(HeaderPolicy::default())
+    }
+
+    pub fn with_policy(policy: HeaderPolicy) -> Self {
+        Self { policy }
+    }
+
+    /// 按策略整理返回给客户端的响应头
+    pub fn apply_header_policy(&self, headers: &mut HeaderMap) {
+        let stripped: Vec<_> = headers
+            .keys()
+            .filter(|name| header_matches(name.as_str(), &self.policy.strip_headers))
+            .cloned()
+            .collect();
+        for name in stripped {
+            headers.remove(name);
+        }
+
+        if self.policy.accept_ranges {
+            headers.insert(ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+        }
+        if let Some(value) = self.policy.cache_control.as_deref().and_then(|v| HeaderValue::from_str(v).ok()) {
+            headers.insert(CACHE_CONTROL, value);
+        }
+        if let Some(via) = &self.policy.via {
+            append_via(headers, via);
+        }
+    }
+
+    /// 按缓存条目的获取时间设置 `Age`
+    pub fn apply_age(&self, headers: &mut HeaderMap, fetched_at: Option<u64>) {
+        if !self.policy.age {
+            return;
+        }
+        if let Some(fetched_at) = fetched_at {
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |now| now.as_secs());
+            headers.insert(AGE, HeaderValue::from(now.saturating_sub(fetched_at)));
+        }
     }
 
     pub fn build_partial_content_response(
@@ -18,6 +107,8 @@ impl ResponseBuilder {
         end: u64,
         total_size: u64,
     ) -> Response<Body> {
+        // 开放区间（bytes=start-）按文件总大小确定结束位置
+        let end = if total_size > 0 { end.min(total_size - 1) } else { end };
         let mut response = Response::new(Body::wrap_stream(stream));
         
         *response.status_mut() = hyper::StatusCode::PARTIAL_CONTENT;
@@ -27,14 +118,191 @@ impl ResponseBuilder {
         );
         response.headers_mut().insert(
             hyper::header::CONTENT_LENGTH,
-            format!("{}", end - start + 1).parse().unwrap()
+            format!("{}", end.saturating_sub(start).saturating_add(1)).parse().unwrap()
         );
         
         // 复制其他响应头
         for (key, value) in headers.iter() {
             response.headers_mut().insert(key, value.clone());
         }
+        self.apply_header_policy(response.headers_mut());
         
         response
     }
-} 
\ No newline at end of file
+
+    /// 构建 304 响应，只携带校验和缓存相关的响应头
+    pub fn build_not_modified_response(&self, headers: HeaderMap) -> Response<Body> {
+        let mut response = Response::new(Body::empty());
+        *response.status_mut() = hyper::StatusCode::NOT_MODIFIED;
+
+        for name in [
+            hyper::header::ETAG,
+            hyper::header::LAST_MODIFIED,
+            hyper::header::CACHE_CONTROL,
+            hyper::header::EXPIRES,
+            hyper::header::VARY,
+        ] {
+            if let Some(value) = headers.get(&name) {
+                response.headers_mut().insert(name, value.clone());
+            }
+        }
+        if let Some(value) = self.policy.cache_control.as_deref().and_then(|v| HeaderValue::from_str(v).ok()) {
+            response.headers_mut().insert(CACHE_CONTROL, value);
+        }
+
+        response
+    }
+
+    /// 构建 HEAD 响应：与完整内容的 200 响应相同的响应头，不带响应体
+    pub fn build_head_response(&self, headers: HeaderMap, total_size: u64) -> Response<Body> {
+        let mut response = Response::new(Body::empty());
+        response.headers_mut().extend(headers);
+        if total_size > 0 {
+            response.headers_mut().insert(CONTENT_LENGTH, HeaderValue::from(total_size));
+        } else {
+            response.headers_mut().remove(CONTENT_LENGTH);
+        }
+        self.apply_header_policy(response.headers_mut());
+        response
+    }
+
+    /// 构建 OPTIONS 响应，列出支持的方法
+    pub fn build_options_response(&self) -> Response<Body> {
+        let mut response = Response::new(Body::empty());
+        *response.status_mut() = hyper::StatusCode::NO_CONTENT;
+        response.headers_mut().insert(ALLOW, HeaderValue::from_static(ALLOWED_METHODS));
+        response
+    }
+
+    /// 构建 405 响应，用于不支持的请求方法
+    pub fn build_method_not_allowed_response(&self) -> Response<Body> {
+        let mut response = Response::new(Body::from("Method Not Allowed"));
+        *response.status_mut() = hyper::StatusCode::METHOD_NOT_ALLOWED;
+        response.headers_mut().insert(ALLOW, HeaderValue::from_static(ALLOWED_METHODS));
+        response
+    }
+
+    /// 构建跨域预检响应
+    pub fn build_preflight_response(&self, headers: HeaderMap) -> Response<Body> {
+        let mut response = Response::new(Body::empty());
+        *response.status_mut() = hyper::StatusCode::NO_CONTENT;
+        response.headers_mut().extend(headers);
+        response
+    }
+
+    /// 将从头开始的 206 响应转换为完整内容的 200 响应
+    pub fn into_full_response(&self, mut response: Response<Body>) -> Response<Body> {
+        if response.status() != hyper::StatusCode::PARTIAL_CONTENT {
+            return response;
+        }
+
+        let total_size = response.headers()
+            .get(hyper::header::CONTENT_RANGE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.rsplit('/').next())
+            .and_then(|total| total.parse::<u64>().ok())
+            .filter(|total| *total > 0);
+
+        *response.status_mut() = hyper::StatusCode::OK;
+        response.headers_mut().remove(hyper::header::CONTENT_RANGE);
+        match total_size {
+            Some(total_size) => {
+                response.headers_mut().insert(
+                    hyper::header::CONTENT_LENGTH,
+                    format!("{}", total_size).parse().unwrap()
+                );
+            }
+            None => {
+                response.headers_mut().remove(hyper::header::CONTENT_LENGTH);
+            }
+        }
+
+        response
+    }
+}
+
+/// 在上游的 `Via` 之后追加本代理，同一响应多次整理时只追加一次
+fn append_via(headers: &mut HeaderMap, via: &str) {
+    let existing: Vec<&str> = headers.get_all(VIA).iter().filter_map(|value| value.to_str().ok()).collect();
+    if existing.iter().flat_map(|value| value.split(',')).any(|item| item.trim() == via) {
+        return;
+    }
+    let value = existing.into_iter().chain(std::iter::once(via)).collect::<Vec<_>>().join(", ");
+    if let Ok(value) = HeaderValue::from_str(&value) {
+        headers.insert(VIA, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::SET_COOKIE;
+
+    #[test]
+    fn test_apply_header_policy() {
+        let mut headers = HeaderMap::new();
+        headers.insert(SET_COOKIE, "session=1".parse().unwrap());
+        headers.insert("x-tracking-id", "abc".parse().unwrap());
+        headers.insert(CACHE_CONTROL, "private".parse().unwrap());
+
+        let builder = ResponseBuilder::with_policy(HeaderPolicy {
+            cache_control: Some("public, max-age=86400".to_string()),
+            ..Default::default()
+        });
+        builder.apply_header_policy(&mut headers);
+        assert!(!headers.contains_key(SET_COOKIE));
+        assert!(!headers.contains_key("x-tracking-id"));
+        assert_eq!(headers[ACCEPT_RANGES], "bytes");
+        assert_eq!(headers[CACHE_CONTROL], "public, max-age=86400");
+        assert_eq!(headers[VIA], DEFAULT_VIA);
+
+        headers.insert(VIA, "1.1 cdn".parse().unwrap());
+        builder.apply_header_policy(&mut headers);
+        builder.apply_header_policy(&mut headers);
+        assert_eq!(headers[VIA], "1.1 cdn, 1.1 proxy-server");
+        builder.apply_age(&mut headers, Some(0));
+        assert!(headers[AGE].to_str().unwrap().parse::<u64>().unwrap() > 0);
+
+        let mut upstream = HeaderMap::new();
+        upstream.insert(SET_COOKIE, "session=1".parse().unwrap());
+        upstream.insert(CONTENT_LENGTH, "1".parse().unwrap());
+        let response = builder.build_head_response(upstream, 1024);
+        assert_eq!(response.status(), hyper::StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_LENGTH], "1024");
+        assert!(!response.headers().contains_key(SET_COOKIE));
+
+        let response = builder.build_method_not_allowed_response();
+        assert_eq!(response.status(), hyper::StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.headers()[ALLOW], ALLOWED_METHODS);
+    }
+
+    fn partial(content_range: &str, content_length: &str) -> Response<Body> {
+        let mut response = Response::new(Body::empty());
+        *response.status_mut() = hyper::StatusCode::PARTIAL_CONTENT;
+        response.headers_mut().insert(hyper::header::CONTENT_RANGE, content_range.parse().unwrap());
+        response.headers_mut().insert(CONTENT_LENGTH, content_length.parse().unwrap());
+        response
+    }
+
+    #[test]
+    fn test_into_full_response() {
+        let builder = ResponseBuilder::new();
+
+        let response = builder.into_full_response(partial("bytes 0-1023/4096", "1024"));
+        assert_eq!(response.status(), hyper::StatusCode::OK);
+        assert!(!response.headers().contains_key(hyper::header::CONTENT_RANGE));
+        assert_eq!(response.headers()[CONTENT_LENGTH], "4096");
+
+        // 总大小未知时去掉 Content-Length，按分块传输
+        let response = builder.into_full_response(partial("bytes 0-1023/*", "1024"));
+        assert_eq!(response.status(), hyper::StatusCode::OK);
+        assert!(!response.headers().contains_key(CONTENT_LENGTH));
+
+        // 不是 206 的响应原样返回
+        let mut ok = Response::new(Body::empty());
+        ok.headers_mut().insert(CONTENT_LENGTH, "10".parse().unwrap());
+        let response = builder.into_full_response(ok);
+        assert_eq!(response.status(), hyper::StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_LENGTH], "10");
+    }
+}
@@ -0,0 +1,281 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use bytes::Bytes;
+use futures::Stream;
+use hyper::{Body, Response};
+use tokio::sync::Notify;
+use tokio::time::Sleep;
+use crate::log_info;
+
+/// 被暂停的低优先级下载重新检查的间隔
+const PAUSE_CHECK: Duration = Duration::from_millis(200);
+
+/// 上游下载的优先级，数值越小越优先
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    /// 客户端正在等待的数据
+    Foreground = 0,
+    /// 顺序读取的预读
+    ReadAhead = 1,
+    /// 预热、预取等后台填充
+    Background = 2,
+}
+
+tokio::task_local! {
+    static PRIORITY: Priority;
+}
+
+/// 当前任务发起上游请求使用的优先级，未指定时为前台
+pub fn current() -> Priority {
+    PRIORITY.try_with(|priority| *priority).unwrap_or(Priority::Foreground)
+}
+
+/// 以指定优先级执行，其中发起的上游请求按该优先级调度
+pub async fn with_priority<F: Future>(priority: Priority, future: F) -> F::Output {
+    PRIORITY.scope(priority, future).await
+}
+
+/// 上游下载调度配置
+#[derive(Clone)]
+pub struct SchedulerConfig {
+    /// 同时进行的上游请求数上限，0 表示不限制
+    pub max_upstream: usize,
+    /// 为前台请求保留的名额，预读和后台任务最多使用 max_upstream - reserved_foreground 个
+    pub reserved_foreground: usize,
+    /// 上游带宽（字节/秒），有前台下载且总速度接近该值时暂停低优先级下载，0 表示不检测
+    pub max_bandwidth: u64,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        Self {
+            max_upstream: 32,
+            reserved_foreground: 4,
+            max_bandwidth: 0,
+        }
+    }
+}
+
+#[derive(Default)]
+struct SchedulerState {
+    running: [usize; 3],
+    waiting: [usize; 3],
+    window_start: Option<Instant>,
+    window_bytes: u64,
+    /// 最近一个统计周期的总速度（字节/秒）
+    rate: u64,
+}
+
+impl SchedulerState {
+    fn total(&self) -> usize {
+        self.running.iter().sum()
+    }
+
+    fn higher_waiting(&self, priority: Priority) -> bool {
+        self.waiting[..priority as usize].iter().any(|&waiting| waiting > 0)
+    }
+}
+
+/// 按优先级分配上游请求名额：前台请求可以超出上限，此时低优先级下载暂停读取让出带宽
+pub struct Scheduler {
+    config: SchedulerConfig,
+    state: Mutex<SchedulerState>,
+    changed: Notify,
+}
+
+impl Scheduler {
+    pub fn new(config: SchedulerConfig) -> Arc<Self> {
+        Arc::new(Self {
+            config,
+            state: Mutex::new(SchedulerState::default()),
+            changed: Notify::new(),
+        })
+    }
+
+    /// 等待获取上游请求名额，名额在响应体读取完或被丢弃时释放
+    pub async fn acquire(self: &Arc<Self>, priority: Priority) -> UpstreamPermit {
+        let mut waiting = None;
+        loop {
+            let notified = self.changed.notified();
+            if let Some(permit) = self.try_acquire(priority) {
+                return permit;
+            }
+            if waiting.is_none() {
+                log_info!("Network", "上游请求排队: {:?}", priority);
+                waiting = Some(WaitingGuard::new(self, priority));
+            }
+            notified.await;
+        }
+    }
+
+    /// 立即获取名额，当前不允许该优先级开始时返回 None
+    pub fn try_acquire(self: &Arc<Self>, priority: Priority) -> Option<UpstreamPermit> {
+        let mut state = self.state.lock().unwrap();
+        if !self.admits(&state, priority) {
+            return None;
+        }
+        state.running[priority as usize] += 1;
+        Some(UpstreamPermit {
+            scheduler: self.clone(),
+            priority,
+        })
+    }
+
+    fn admits(&self, state: &SchedulerState, priority: Priority) -> bool {
+        if priority == Priority::Foreground {
+            return self.config.max_upstream == 0 || state.running[0] < self.config.max_upstream;
+        }
+        if state.higher_waiting(priority) || self.bandwidth_saturated(state) {
+            return false;
+        }
+        self.config.max_upstream == 0
+            || state.total() < self.config.max_upstream.saturating_sub(self.config.reserved_foreground).max(1)
+    }
+
+    fn bandwidth_saturated(&self, state: &SchedulerState) -> bool {
+        self.config.max_bandwidth > 0
+            && state.running[0] > 0
+            && state.rate.saturating_mul(10) >= self.config.max_bandwidth.saturating_mul(9)
+    }
+
+    /// 低优先级下载是否应暂停：有更高优先级在排队、前台请求占用了超出上限的名额或带宽已满
+    fn should_pause(&self, priority: Priority) -> bool {
+        if priority == Priority::Foreground {
+            return false;
+        }
+        let state = self.state.lock().unwrap();
+        state.higher_waiting(priority)
+            || (self.config.max_upstream > 0 && state.total() > self.config.max_upstream)
+            || self.bandwidth_saturated(&state)
+    }
+
+    fn record(&self, bytes: u64) {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        let window_start = *state.window_start.get_or_insert(now);
+        let elapsed = now.duration_since(window_start);
+        if elapsed >= Duration::from_secs(1) {
+            state.rate = (state.window_bytes as f64 / elapsed.as_secs_f64()) as u64;
+            state.window_start = Some(now);
+            state.window_bytes = 0;
+        }
+        state.window_bytes += bytes;
+    }
+
+    fn release(&self, priority: Priority) {
+        self.state.lock().unwrap().running[priority as usize] -= 1;
+        self.changed.notify_waiters();
+    }
+}
+
+/// 排队计数，等待被取消时同样减少
+struct WaitingGuard {
+    scheduler: Arc<Scheduler>,
+    priority: Priority,
+}
+
+impl WaitingGuard {
+    fn new(scheduler: &Arc<Scheduler>, priority: Priority) -> Self {
+        scheduler.state.lock().unwrap().waiting[priority as usize] += 1;
+        Self {
+            scheduler: scheduler.clone(),
+            priority,
+        }
+    }
+}
+
+impl Drop for WaitingGuard {
+    fn drop(&mut self) {
+        self.scheduler.state.lock().unwrap().waiting[self.priority as usize] -= 1;
+        self.scheduler.changed.notify_waiters();
+    }
+}
+
+/// 上游请求名额
+pub struct UpstreamPermit {
+    scheduler: Arc<Scheduler>,
+    priority: Priority,
+}
+
+impl UpstreamPermit {
+    /// 将名额绑定到响应体，低优先级的响应体在需要让出时暂停读取
+    pub fn attach(self, resp: Response<Body>) -> Response<Body> {
+        let (parts, body) = resp.into_parts();
+        Response::from_parts(parts, Body::wrap_stream(ScheduledStream {
+            inner: body,
+            permit: self,
+            pause: None,
+        }))
+    }
+}
+
+impl Drop for UpstreamPermit {
+    fn drop(&mut self) {
+        self.scheduler.release(self.priority);
+    }
+}
+
+struct ScheduledStream {
+    inner: Body,
+    permit: UpstreamPermit,
+    pause: Option<Pin<Box<Sleep>>>,
+}
+
+impl Stream for ScheduledStream {
+    type Item = hyper::Result<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            if let Some(pause) = self.pause.as_mut() {
+                if pause.as_mut().poll(cx).is_pending() {
+                    return Poll::Pending;
+                }
+                self.pause = None;
+            }
+            if !self.permit.scheduler.should_pause(self.permit.priority) {
+                break;
+            }
+            self.pause = Some(Box::pin(tokio::time::sleep(PAUSE_CHECK)));
+        }
+
+        let poll = Pin::new(&mut self.inner).poll_next(cx);
+        if let Poll::Ready(Some(Ok(chunk))) = &poll {
+            self.permit.scheduler.record(chunk.len() as u64);
+        }
+        poll
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_try_acquire() {
+        let scheduler = Scheduler::new(SchedulerConfig {
+            max_upstream: 3,
+            reserved_foreground: 1,
+            max_bandwidth: 0,
+        });
+
+        let background = scheduler.try_acquire(Priority::Background).unwrap();
+        let read_ahead = scheduler.try_acquire(Priority::ReadAhead).unwrap();
+        // 剩下的名额保留给前台
+        assert!(scheduler.try_acquire(Priority::Background).is_none());
+
+        let foreground: Vec<_> = (0..3).map(|_| scheduler.try_acquire(Priority::Foreground).unwrap()).collect();
+        assert!(scheduler.try_acquire(Priority::Foreground).is_none());
+        // 前台超出上限时低优先级暂停
+        assert!(scheduler.should_pause(Priority::Background));
+        assert!(!scheduler.should_pause(Priority::Foreground));
+
+        drop(foreground);
+        drop(read_ahead);
+        assert!(!scheduler.should_pause(Priority::Background));
+        assert!(scheduler.try_acquire(Priority::ReadAhead).is_some());
+        drop(background);
+    }
+}
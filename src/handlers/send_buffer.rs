@@ -0,0 +1,224 @@
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use bytes::Bytes;
+use futures::Stream;
+use serde::Serialize;
+use tokio::sync::Notify;
+use crate::utils::error::Result;
+use crate::log_info;
+
+/// 慢客户端统计，`/admin/stats/clients` 返回
+#[derive(Debug, Clone, Serialize)]
+pub struct SlowClientStats {
+    /// 当前发送缓冲达到高水位、等待客户端读取的响应数
+    pub stalled: usize,
+    /// 发送缓冲曾达到高水位的响应数
+    pub slow_responses: u64,
+    /// 达到高水位暂停上游读取的次数
+    pub pauses: u64,
+}
+
+/// 按响应统计发送缓冲达到高水位的次数
+#[derive(Default)]
+pub struct SlowClients {
+    stalled: AtomicUsize,
+    slow_responses: AtomicU64,
+    pauses: AtomicU64,
+}
+
+impl SlowClients {
+    pub fn stats(&self) -> SlowClientStats {
+        SlowClientStats {
+            stalled: self.stalled.load(Ordering::Relaxed),
+            slow_responses: self.slow_responses.load(Ordering::Relaxed),
+            pauses: self.pauses.load(Ordering::Relaxed),
+        }
+    }
+}
+
+struct BufferState {
+    chunks: VecDeque<Result<Bytes>>,
+    buffered: usize,
+    /// 发送端已结束
+    finished: bool,
+    /// 接收端已丢弃，客户端断开
+    closed: bool,
+    waker: Option<Waker>,
+}
+
+struct Shared {
+    state: Mutex<BufferState>,
+    /// 缓冲降到低水位或接收端丢弃时通知发送端
+    drained: Notify,
+    low_watermark: usize,
+}
+
+/// 创建按字节数限制的发送缓冲：缓冲数据达到 `high_watermark` 时发送端等待，客户端读取到 `low_watermark` 以下后继续，
+/// 慢客户端占用的内存不超过高水位加一个数据块
+pub fn send_buffer(high_watermark: usize, low_watermark: usize, slow_clients: Arc<SlowClients>) -> (BufferSender, BufferReceiver) {
+    let high_watermark = high_watermark.max(1);
+    let shared = Arc::new(Shared {
+        state: Mutex::new(BufferState {
+            chunks: VecDeque::new(),
+            buffered: 0,
+            finished: false,
+            closed: false,
+            waker: None,
+        }),
+        drained: Notify::new(),
+        low_watermark: low_watermark.min(high_watermark - 1),
+    });
+    let sender = BufferSender {
+        shared: shared.clone(),
+        high_watermark,
+        slow_clients,
+        slow: false,
+    };
+    (sender, BufferReceiver { shared })
+}
+
+pub struct BufferSender {
+    shared: Arc<Shared>,
+    high_watermark: usize,
+    slow_clients: Arc<SlowClients>,
+    /// 本次响应已计入慢客户端
+    slow: bool,
+}
+
+impl BufferSender {
+    /// 放入一个数据块，缓冲达到高水位时等待客户端读取，客户端已断开时返回 false
+    pub async fn send(&mut self, item: Result<Bytes>) -> bool {
+        let buffered = {
+            let mut state = self.shared.state.lock().unwrap();
+            if state.closed {
+                return false;
+            }
+            if let Ok(chunk) = &item {
+                state.buffered += chunk.len();
+            }
+            state.chunks.push_back(item);
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+            state.buffered
+        };
+        if buffered < self.high_watermark {
+            return true;
+        }
+
+        if !self.slow {
+            self.slow = true;
+            self.slow_clients.slow_responses.fetch_add(1, Ordering::Relaxed);
+            log_info!("Network", "客户端读取过慢，发送缓冲达到高水位 {} 字节，暂停转发", self.high_watermark);
+        }
+        self.slow_clients.pauses.fetch_add(1, Ordering::Relaxed);
+        let _stalled = StalledGuard::new(&self.slow_clients);
+        loop {
+            let drained = self.shared.drained.notified();
+            {
+                let state = self.shared.state.lock().unwrap();
+                if state.closed {
+                    return false;
+                }
+                if state.buffered <= self.shared.low_watermark {
+                    return true;
+                }
+            }
+            drained.await;
+        }
+    }
+}
+
+/// 等待期间计入当前等待的响应数，发送任务被取消时同样减去
+struct StalledGuard<'a>(&'a SlowClients);
+
+impl<'a> StalledGuard<'a> {
+    fn new(slow_clients: &'a SlowClients) -> Self {
+        slow_clients.stalled.fetch_add(1, Ordering::Relaxed);
+        Self(slow_clients)
+    }
+}
+
+impl Drop for StalledGuard<'_> {
+    fn drop(&mut self) {
+        self.0.stalled.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Drop for BufferSender {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.finished = true;
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+pub struct BufferReceiver {
+    shared: Arc<Shared>,
+}
+
+impl Stream for BufferReceiver {
+    type Item = Result<Bytes>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut state = self.shared.state.lock().unwrap();
+        match state.chunks.pop_front() {
+            Some(item) => {
+                if let Ok(chunk) = &item {
+                    state.buffered -= chunk.len();
+                }
+                if state.buffered <= self.shared.low_watermark {
+                    self.shared.drained.notify_one();
+                }
+                Poll::Ready(Some(item))
+            }
+            None if state.finished => Poll::Ready(None),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Drop for BufferReceiver {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.closed = true;
+        state.chunks.clear();
+        state.buffered = 0;
+        self.shared.drained.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{FutureExt, StreamExt};
+
+    #[test]
+    fn test_send_buffer_watermarks() {
+        let slow_clients = Arc::new(SlowClients::default());
+        let (mut sender, mut receiver) = send_buffer(8, 4, slow_clients.clone());
+
+        assert!(sender.send(Ok(Bytes::from_static(b"abcd"))).now_or_never().unwrap());
+        // 达到高水位后等待客户端读取
+        let mut send = Box::pin(sender.send(Ok(Bytes::from_static(b"efgh"))));
+        assert!(send.as_mut().now_or_never().is_none());
+        assert_eq!(slow_clients.stats().stalled, 1);
+
+        assert_eq!(receiver.next().now_or_never().unwrap().unwrap().unwrap(), Bytes::from_static(b"abcd"));
+        assert!(send.now_or_never().unwrap());
+        assert_eq!(slow_clients.stats().slow_responses, 1);
+        assert_eq!(slow_clients.stats().stalled, 0);
+
+        drop(sender);
+        assert_eq!(receiver.next().now_or_never().unwrap().unwrap().unwrap(), Bytes::from_static(b"efgh"));
+        assert!(receiver.next().now_or_never().unwrap().is_none());
+    }
+}
@@ -0,0 +1,331 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime};
+use bytes::Bytes;
+use futures::Stream;
+use hyper::Body;
+use serde::Serialize;
+use crate::storage::inspect::format_time;
+use crate::utils::request_id;
+
+/// 播放器指定的会话 ID，没有时按客户端地址、内容地址和请求间隔归组，响应中返回所属会话的 ID
+pub const SESSION_ID_HEADER: &str = "x-session-id";
+
+/// 没有会话 ID 时用于归组的内容地址：HLS 播放列表和分片取所在目录，使同一路流的请求归为一组，其他请求去掉查询参数
+pub fn content_group(url: &str, hls: bool) -> &str {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    if !hls {
+        return path;
+    }
+    match path.rfind('/') {
+        Some(index) if index > path.find("://").map_or(0, |scheme| scheme + 2) => &path[..index],
+        _ => path,
+    }
+}
+
+/// 播放会话统计配置
+#[derive(Clone)]
+pub struct SessionConfig {
+    pub enabled: bool,
+    /// 同一分组超过该时间没有请求时，下一个请求开始新的会话
+    pub idle_timeout: Duration,
+    /// 两次请求之间没有数据传输超过该时间视为一次卡顿，应大于播放器缓冲充足时的正常请求间隔
+    pub stall_gap: Duration,
+    /// 同时跟踪的会话数上限，超出时结束最久没有请求的会话
+    pub max_sessions: usize,
+    /// 保留的已结束会话数
+    pub history: usize,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            idle_timeout: Duration::from_secs(60),
+            stall_gap: Duration::from_secs(10),
+            max_sessions: 1000,
+            history: 1000,
+        }
+    }
+}
+
+/// 单个播放会话的统计，`/admin/sessions` 返回
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionStats {
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client: Option<String>,
+    /// 会话的第一个请求地址
+    pub url: String,
+    pub active: bool,
+    pub started_at: String,
+    pub last_active: String,
+    pub duration_secs: f64,
+    pub requests: u64,
+    pub bytes: u64,
+    /// 第一个请求到收到第一块数据的时间
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub startup_ms: Option<f64>,
+    /// 按请求间隔推断的卡顿次数
+    pub stalls: u64,
+    pub stall_secs: f64,
+}
+
+struct Session {
+    id: String,
+    client: Option<IpAddr>,
+    url: String,
+    started: Instant,
+    started_at: SystemTime,
+    /// 最近一次请求开始或响应传输结束的时间
+    last_seen: Instant,
+    /// 正在传输的响应数
+    streaming: usize,
+    requests: u64,
+    bytes: u64,
+    startup: Option<Duration>,
+    stalls: u64,
+    stall_time: Duration,
+}
+
+impl Session {
+    fn stats(&self, active: bool) -> SessionStats {
+        let idle = self.last_seen.elapsed();
+        SessionStats {
+            id: self.id.clone(),
+            client: self.client.map(|client| client.to_string()),
+            url: self.url.clone(),
+            active,
+            started_at: format_time(self.started_at),
+            last_active: format_time(SystemTime::now() - idle),
+            duration_secs: self.last_seen.duration_since(self.started).as_secs_f64(),
+            requests: self.requests,
+            bytes: self.bytes,
+            startup_ms: self.startup.map(|startup| startup.as_secs_f64() * 1000.0),
+            stalls: self.stalls,
+            stall_secs: self.stall_time.as_secs_f64(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct SessionState {
+    /// 分组键到当前会话
+    active: HashMap<String, Session>,
+    /// 已结束的会话，最新的在末尾
+    ended: VecDeque<SessionStats>,
+}
+
+impl SessionState {
+    fn end(&mut self, group: &str, history: usize) {
+        if let Some(session) = self.active.remove(group) {
+            if history == 0 {
+                return;
+            }
+            if self.ended.len() >= history {
+                self.ended.pop_front();
+            }
+            self.ended.push_back(session.stats(false));
+        }
+    }
+}
+
+/// 将请求归组为播放会话，统计起播时间、流量和卡顿
+pub struct SessionTracker {
+    config: SessionConfig,
+    state: Mutex<SessionState>,
+}
+
+impl SessionTracker {
+    pub fn new(config: SessionConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(SessionState::default()),
+        }
+    }
+
+    /// 记录一次请求并统计响应体的传输，返回所属会话的 ID；关闭统计时原样返回
+    pub fn track(
+        self: &Arc<Self>,
+        client: Option<IpAddr>,
+        session_id: Option<&str>,
+        content: &str,
+        url: &str,
+        body: Body,
+    ) -> (Body, Option<String>) {
+        if !self.config.enabled {
+            return (body, None);
+        }
+
+        // 指定了会话 ID 时不区分客户端地址
+        let group = match session_id {
+            Some(id) => format!("id:{}", id),
+            None => format!("{}|{}", client.map(|client| client.to_string()).unwrap_or_default(), content),
+        };
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        let expired = state
+            .active
+            .get(&group)
+            .is_some_and(|session| session.streaming == 0 && now.duration_since(session.last_seen) >= self.config.idle_timeout);
+        if expired {
+            state.end(&group, self.config.history);
+        }
+        if !state.active.contains_key(&group) && state.active.len() >= self.config.max_sessions.max(1) {
+            let idlest = state
+                .active
+                .iter()
+                .min_by_key(|(_, session)| session.last_seen)
+                .map(|(group, _)| group.clone());
+            if let Some(idlest) = idlest {
+                state.end(&idlest, self.config.history);
+            }
+        }
+
+        let session = state.active.entry(group.clone()).or_insert_with(|| Session {
+            id: session_id.map(str::to_string).unwrap_or_else(request_id::generate),
+            client,
+            url: url.to_string(),
+            started: now,
+            started_at: SystemTime::now(),
+            last_seen: now,
+            streaming: 0,
+            requests: 0,
+            bytes: 0,
+            startup: None,
+            stalls: 0,
+            stall_time: Duration::ZERO,
+        });
+        // 没有响应在传输时，距上次传输结束的间隔过长说明播放器的缓冲可能已经耗尽
+        let gap = now.duration_since(session.last_seen);
+        if session.requests > 0 && session.streaming == 0 && gap >= self.config.stall_gap {
+            session.stalls += 1;
+            session.stall_time += gap;
+        }
+        let first = session.requests == 0;
+        session.requests += 1;
+        session.streaming += 1;
+        session.last_seen = now;
+        let id = session.id.clone();
+        drop(state);
+
+        let stream = SessionStream {
+            inner: body,
+            tracker: self.clone(),
+            group,
+            id: id.clone(),
+            first,
+            bytes: 0,
+        };
+        (Body::wrap_stream(stream), Some(id))
+    }
+
+    /// 正在进行和最近结束的会话，按最近活动时间降序
+    pub fn sessions(&self, limit: usize) -> Vec<SessionStats> {
+        let state = self.state.lock().unwrap();
+        let mut active: Vec<_> = state.active.values().collect();
+        active.sort_by_key(|session| std::cmp::Reverse(session.last_seen));
+        active
+            .into_iter()
+            .map(|session| session.stats(true))
+            .chain(state.ended.iter().rev().cloned())
+            .take(limit)
+            .collect()
+    }
+
+    pub fn session(&self, id: &str) -> Option<SessionStats> {
+        let state = self.state.lock().unwrap();
+        state
+            .active
+            .values()
+            .find(|session| session.id == id)
+            .map(|session| session.stats(true))
+            .or_else(|| state.ended.iter().rev().find(|stats| stats.id == id).cloned())
+    }
+
+    fn update(&self, group: &str, id: &str, f: impl FnOnce(&mut Session)) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(session) = state.active.get_mut(group).filter(|session| session.id == id) {
+            f(session);
+        }
+    }
+}
+
+struct SessionStream {
+    inner: Body,
+    tracker: Arc<SessionTracker>,
+    group: String,
+    id: String,
+    /// 会话的第一个请求，收到第一块数据时记录起播时间
+    first: bool,
+    bytes: u64,
+}
+
+impl Stream for SessionStream {
+    type Item = hyper::Result<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let poll = Pin::new(&mut self.inner).poll_next(cx);
+        if let Poll::Ready(Some(Ok(chunk))) = &poll {
+            self.bytes += chunk.len() as u64;
+            if self.first {
+                self.first = false;
+                let now = Instant::now();
+                self.tracker.update(&self.group, &self.id, |session| {
+                    session.startup = Some(now.duration_since(session.started));
+                });
+            }
+        }
+        poll
+    }
+}
+
+impl Drop for SessionStream {
+    fn drop(&mut self) {
+        let bytes = self.bytes;
+        self.tracker.update(&self.group, &self.id, |session| {
+            session.bytes += bytes;
+            session.streaming = session.streaming.saturating_sub(1);
+            session.last_seen = Instant::now();
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+
+    #[test]
+    fn test_track_sessions() {
+        let tracker = Arc::new(SessionTracker::new(SessionConfig {
+            stall_gap: Duration::ZERO,
+            ..Default::default()
+        }));
+        let client = Some(IpAddr::from([127, 0, 0, 1]));
+        let url = "http://example.com/a.mp4";
+
+        let (body, id) = tracker.track(client, None, url, url, Body::from("abcd"));
+        futures::executor::block_on(hyper::body::to_bytes(body)).unwrap();
+        // 同一客户端和内容的后续请求属于同一会话，传输间隔超过 stall_gap 记为卡顿
+        let (body, same) = tracker.track(client, None, url, url, Body::from("efgh"));
+        assert_eq!(same, id);
+        futures::executor::block_on(body.collect::<Vec<_>>());
+
+        let (_, other) = tracker.track(client, Some("player-1"), url, url, Body::empty());
+        assert_eq!(other.as_deref(), Some("player-1"));
+
+        let stats = tracker.session(id.as_deref().unwrap()).unwrap();
+        assert_eq!(stats.requests, 2);
+        assert_eq!(stats.bytes, 8);
+        assert_eq!(stats.stalls, 1);
+        assert!(stats.startup_ms.is_some());
+        assert_eq!(tracker.sessions(10).len(), 2);
+
+        assert_eq!(content_group("http://example.com/live/720p/12.ts?token=1", true), "http://example.com/live/720p");
+        assert_eq!(content_group("http://example.com/a.mp4?token=1", false), "http://example.com/a.mp4");
+    }
+}
@@ -0,0 +1,341 @@
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime};
+use bytes::Bytes;
+use futures::Stream;
+use serde::Serialize;
+use crate::storage::inspect::format_time;
+use super::usage::UsageLedger;
+
+/// 最多记录的 URL 数量，超出时淘汰最久未访问的记录
+const MAX_TRACKED_URLS: usize = 10_000;
+
+/// 本次请求从源站获取的字节数
+pub const UPSTREAM_BYTES_HEADER: &str = "x-upstream-bytes";
+/// 本次请求返回给客户端的字节数
+pub const SERVED_BYTES_HEADER: &str = "x-served-bytes";
+
+/// 响应数据的来源
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CacheSource {
+    Hit,
+    /// 部分命中，前若干字节来自缓存
+    Partial(u64),
+    Miss,
+}
+
+impl CacheSource {
+    /// 返回 `served` 字节时需要从源站获取的字节数
+    pub fn upstream_bytes(&self, served: u64) -> u64 {
+        match *self {
+            CacheSource::Hit => 0,
+            CacheSource::Partial(cached) => served.saturating_sub(cached),
+            CacheSource::Miss => served,
+        }
+    }
+}
+
+/// 单个 URL 的统计
+#[derive(Debug, Clone, Serialize)]
+pub struct UrlStats {
+    pub url: String,
+    pub requests: u64,
+    pub hits: u64,
+    pub partial_hits: u64,
+    pub cache_bytes: u64,
+    pub network_bytes: u64,
+    pub last_access: String,
+    /// 响应压缩统计，没有压缩过时为空
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compression: Option<CompressionStats>,
+}
+
+/// 压缩前后的字节数和耗时
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CompressionStats {
+    pub algorithm: &'static str,
+    pub count: u64,
+    pub original_bytes: u64,
+    pub compressed_bytes: u64,
+    /// 压缩后与压缩前的字节数之比，越小压缩效果越好
+    pub ratio: f64,
+    pub time_ms: f64,
+}
+
+impl CompressionStats {
+    fn new(algorithm: &'static str) -> Self {
+        Self {
+            algorithm,
+            ..Default::default()
+        }
+    }
+
+    fn add(&mut self, original: u64, compressed: u64, elapsed: Duration) {
+        self.count += 1;
+        self.original_bytes += original;
+        self.compressed_bytes += compressed;
+        self.time_ms += elapsed.as_secs_f64() * 1000.0;
+        self.ratio = if self.original_bytes > 0 {
+            self.compressed_bytes as f64 / self.original_bytes as f64
+        } else {
+            1.0
+        };
+    }
+}
+
+#[derive(Clone)]
+struct Counters {
+    requests: u64,
+    hits: u64,
+    partial_hits: u64,
+    cache_bytes: u64,
+    network_bytes: u64,
+    last_access: SystemTime,
+    compression: Option<CompressionStats>,
+    /// 客户端按范围读取过，之后的完整响应也不再压缩
+    ranged: bool,
+}
+
+/// 排序字段
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StatsOrder {
+    Requests,
+    Hits,
+    Bytes,
+}
+
+impl StatsOrder {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "requests" => Some(Self::Requests),
+            "hits" => Some(Self::Hits),
+            "bytes" => Some(Self::Bytes),
+            _ => None,
+        }
+    }
+}
+
+/// 按 URL 统计请求数、命中数和流量
+#[derive(Default)]
+pub struct StatsRegistry {
+    entries: Mutex<HashMap<String, Counters>>,
+    /// 按压缩算法汇总，不随 URL 记录淘汰
+    compression: Mutex<HashMap<&'static str, CompressionStats>>,
+    /// 按天和源站主机累计的流量
+    usage: Arc<UsageLedger>,
+}
+
+impl StatsRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 使用可持久化的流量统计
+    pub fn with_usage(mut self, usage: Arc<UsageLedger>) -> Self {
+        self.usage = usage;
+        self
+    }
+
+    /// 按天和源站主机累计的流量
+    pub fn usage(&self) -> &Arc<UsageLedger> {
+        &self.usage
+    }
+
+    /// 记录一次请求，并统计响应数据中来自缓存和网络的字节数
+    pub fn track(
+        self: &Arc<Self>,
+        url: &str,
+        source: CacheSource,
+        stream: Pin<Box<dyn Stream<Item = hyper::Result<Bytes>> + Send>>,
+    ) -> Pin<Box<dyn Stream<Item = hyper::Result<Bytes>> + Send>> {
+        self.update(url, |counters| {
+            counters.requests += 1;
+            match source {
+                CacheSource::Hit => counters.hits += 1,
+                CacheSource::Partial(_) => counters.partial_hits += 1,
+                CacheSource::Miss => {}
+            }
+        });
+
+        let cache_remaining = match source {
+            CacheSource::Hit => u64::MAX,
+            CacheSource::Partial(bytes) => bytes,
+            CacheSource::Miss => 0,
+        };
+        Box::pin(CountingStream {
+            url: url.to_string(),
+            inner: stream,
+            registry: self.clone(),
+            cache_remaining,
+            cache_bytes: 0,
+            network_bytes: 0,
+        })
+    }
+
+    /// 按指定字段降序返回前 `limit` 个 URL
+    pub fn top(&self, limit: usize, order: StatsOrder) -> Vec<UrlStats> {
+        let entries = self.entries.lock().unwrap();
+        let mut list: Vec<_> = entries.iter().collect();
+        list.sort_by_key(|(_, counters)| {
+            std::cmp::Reverse(match order {
+                StatsOrder::Requests => counters.requests,
+                StatsOrder::Hits => counters.hits,
+                StatsOrder::Bytes => counters.cache_bytes + counters.network_bytes,
+            })
+        });
+        list.into_iter()
+            .take(limit)
+            .map(|(url, counters)| UrlStats {
+                url: url.clone(),
+                requests: counters.requests,
+                hits: counters.hits,
+                partial_hits: counters.partial_hits,
+                cache_bytes: counters.cache_bytes,
+                network_bytes: counters.network_bytes,
+                last_access: format_time(counters.last_access),
+                compression: counters.compression.clone(),
+            })
+            .collect()
+    }
+
+    /// 记录一次压缩的原始大小、压缩后大小和耗时
+    pub fn record_compression(&self, url: &str, algorithm: &'static str, original: u64, compressed: u64, elapsed: Duration) {
+        self.update(url, |counters| {
+            counters
+                .compression
+                .get_or_insert_with(|| CompressionStats::new(algorithm))
+                .add(original, compressed, elapsed);
+        });
+        self.compression
+            .lock()
+            .unwrap()
+            .entry(algorithm)
+            .or_insert_with(|| CompressionStats::new(algorithm))
+            .add(original, compressed, elapsed);
+    }
+
+    /// 记录客户端按范围读取了 URL
+    pub fn record_range_access(&self, url: &str) {
+        self.update(url, |counters| counters.ranged = true);
+    }
+
+    /// URL 是否被客户端按范围读取过
+    pub fn is_range_accessed(&self, url: &str) -> bool {
+        self.entries.lock().unwrap().get(url).is_some_and(|counters| counters.ranged)
+    }
+
+    /// 各压缩算法的汇总统计
+    pub fn compression_summary(&self) -> Vec<CompressionStats> {
+        let mut summary: Vec<_> = self.compression.lock().unwrap().values().cloned().collect();
+        summary.sort_by_key(|stats| stats.algorithm);
+        summary
+    }
+
+    fn update(&self, url: &str, f: impl FnOnce(&mut Counters)) {
+        let mut entries = self.entries.lock().unwrap();
+        if !entries.contains_key(url) && entries.len() >= MAX_TRACKED_URLS {
+            evict_oldest(&mut entries);
+        }
+        let counters = entries.entry(url.to_string()).or_insert_with(|| Counters {
+            requests: 0,
+            hits: 0,
+            partial_hits: 0,
+            cache_bytes: 0,
+            network_bytes: 0,
+            last_access: SystemTime::now(),
+            compression: None,
+            ranged: false,
+        });
+        counters.last_access = SystemTime::now();
+        f(counters);
+    }
+}
+
+/// 淘汰最久未访问的十分之一记录
+fn evict_oldest(entries: &mut HashMap<String, Counters>) {
+    let mut by_access: Vec<_> = entries
+        .iter()
+        .map(|(url, counters)| (counters.last_access, url.clone()))
+        .collect();
+    by_access.sort_unstable();
+    for (_, url) in by_access.into_iter().take((MAX_TRACKED_URLS / 10).max(1)) {
+        entries.remove(&url);
+    }
+}
+
+struct CountingStream {
+    url: String,
+    inner: Pin<Box<dyn Stream<Item = hyper::Result<Bytes>> + Send>>,
+    registry: Arc<StatsRegistry>,
+    cache_remaining: u64,
+    cache_bytes: u64,
+    network_bytes: u64,
+}
+
+impl Stream for CountingStream {
+    type Item = hyper::Result<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let poll = self.inner.as_mut().poll_next(cx);
+        if let Poll::Ready(Some(Ok(chunk))) = &poll {
+            let len = chunk.len() as u64;
+            let from_cache = len.min(self.cache_remaining);
+            self.cache_remaining -= from_cache;
+            self.cache_bytes += from_cache;
+            self.network_bytes += len - from_cache;
+        }
+        poll
+    }
+}
+
+impl Drop for CountingStream {
+    fn drop(&mut self) {
+        let (cache_bytes, network_bytes) = (self.cache_bytes, self.network_bytes);
+        self.registry.update(&self.url, |counters| {
+            counters.cache_bytes += cache_bytes;
+            counters.network_bytes += network_bytes;
+        });
+        self.registry.usage.record(&self.url, cache_bytes + network_bytes, network_bytes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+
+    #[test]
+    fn test_track_counts_bytes() {
+        let registry = Arc::new(StatsRegistry::new());
+        let chunks = vec![Ok(Bytes::from_static(b"abcd")), Ok(Bytes::from_static(b"efgh"))];
+        let stream = registry.track("http://example.com/a.mp4", CacheSource::Partial(6), Box::pin(futures::stream::iter(chunks)));
+        assert_eq!(futures::executor::block_on(stream.collect::<Vec<_>>()).len(), 2);
+
+        let top = registry.top(10, StatsOrder::Bytes);
+        assert_eq!(top.len(), 1);
+        assert_eq!(top[0].partial_hits, 1);
+        assert_eq!(top[0].cache_bytes, 6);
+        assert_eq!(top[0].network_bytes, 2);
+
+        assert_eq!(CacheSource::Hit.upstream_bytes(8), 0);
+        assert_eq!(CacheSource::Partial(6).upstream_bytes(8), 2);
+        assert_eq!(CacheSource::Miss.upstream_bytes(8), 8);
+    }
+
+    #[test]
+    fn test_record_compression() {
+        let registry = StatsRegistry::new();
+        registry.record_compression("http://example.com/a.m3u8", "gzip", 1000, 200, Duration::from_millis(1));
+        registry.record_compression("http://example.com/b.m3u8", "gzip", 1000, 400, Duration::from_millis(1));
+
+        let summary = registry.compression_summary();
+        assert_eq!(summary.len(), 1);
+        assert_eq!(summary[0].count, 2);
+        assert!((summary[0].ratio - 0.3).abs() < 1e-9);
+
+        let top = registry.top(10, StatsOrder::Requests);
+        assert_eq!(top[0].compression.as_ref().unwrap().count, 1);
+    }
+}
@@ -0,0 +1,224 @@
+use hyper::header::{ACCEPT, CONNECTION, HOST, UPGRADE};
+use hyper::{Body, Client, HeaderMap, Request, Response, StatusCode, Uri};
+use hyper_tls::HttpsConnector;
+use tokio::net::TcpStream;
+use crate::utils::error::{ProxyError, Result};
+use crate::utils::request_id;
+use crate::log_info;
+
+/// CONNECT 隧道配置，默认关闭；开启后只允许连接白名单中的主机和端口
+#[derive(Debug, Clone)]
+pub struct TunnelConfig {
+    pub enabled: bool,
+    /// 允许的目标主机，不区分大小写；以 `.` 开头时匹配该域名及其子域名，`*` 表示任意主机
+    pub allowed_hosts: Vec<String>,
+    /// 允许的目标端口
+    pub allowed_ports: Vec<u16>,
+}
+
+impl Default for TunnelConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            allowed_hosts: Vec::new(),
+            allowed_ports: vec![443],
+        }
+    }
+}
+
+impl TunnelConfig {
+    /// 目标主机和端口是否在白名单中
+    pub fn allows(&self, host: &str, port: u16) -> bool {
+        let host = host.trim_matches(|c| c == '[' || c == ']');
+        self.allowed_ports.contains(&port)
+            && self.allowed_hosts.iter().any(|allowed| {
+                if allowed == "*" {
+                    return true;
+                }
+                if let Some(domain) = allowed.strip_prefix('.') {
+                    let suffix_match = host.len() > domain.len()
+                        && host.to_ascii_lowercase().ends_with(&format!(".{}", domain.to_ascii_lowercase()));
+                    return suffix_match || host.eq_ignore_ascii_case(domain);
+                }
+                host.eq_ignore_ascii_case(allowed)
+            })
+    }
+}
+
+/// 处理 CONNECT 请求，建立到目标地址的 TCP 隧道，数据原样转发，不经过缓存
+pub async fn tunnel(req: Request<Body>) -> Result<Response<Body>> {
+    let authority = req
+        .uri()
+        .authority()
+        .map(|authority| authority.to_string())
+        .ok_or_else(|| ProxyError::request(format!("CONNECT 请求缺少目标地址: {}", req.uri())))?;
+
+    // 先连接目标，失败时直接返回 502
+    let mut target = match TcpStream::connect(&authority).await {
+        Ok(target) => target,
+        Err(e) => {
+            log_info!("Tunnel", "连接目标失败: {} - {}", authority, e);
+            let mut response = Response::new(Body::from(format!("连接目标失败: {}", e)));
+            *response.status_mut() = StatusCode::BAD_GATEWAY;
+            return Ok(response);
+        }
+    };
+
+    log_info!("Tunnel", "建立隧道: {}", authority);
+    request_id::spawn(async move {
+        let mut upgraded = match hyper::upgrade::on(req).await {
+            Ok(upgraded) => upgraded,
+            Err(e) => {
+                log_info!("Tunnel", "连接升级失败: {} - {}", authority, e);
+                return;
+            }
+        };
+
+        match tokio::io::copy_bidirectional(&mut upgraded, &mut target).await {
+            Ok((sent, received)) => {
+                log_info!("Tunnel", "隧道关闭: {} 发送 {} 字节, 接收 {} 字节", authority, sent, received);
+            }
+            Err(e) => log_info!("Tunnel", "隧道异常关闭: {} - {}", authority, e),
+        }
+    });
+
+    Ok(Response::new(Body::empty()))
+}
+
+/// 不经过缓存直接转发到上游的请求类型，默认全部关闭，按普通请求处理
+#[derive(Debug, Clone, Default)]
+pub struct PassthroughConfig {
+    /// 协议升级请求（WebSocket）
+    pub websocket: bool,
+    /// 接受 text/event-stream 的事件流请求
+    pub event_stream: bool,
+    /// 请求 ICY 元数据的电台客户端需要上游按原样插入元数据，上游的 ICY 响应同样不缓存
+    pub icy: bool,
+}
+
+impl PassthroughConfig {
+    /// 判断请求是否需要透传
+    pub fn matches(&self, headers: &HeaderMap) -> bool {
+        if self.websocket && is_upgrade_request(headers) {
+            return true;
+        }
+        if self.icy && headers.contains_key("icy-metadata") {
+            return true;
+        }
+        self.event_stream
+            && headers
+                .get(ACCEPT)
+                .and_then(|value| value.to_str().ok())
+                .is_some_and(|accept| accept.contains("text/event-stream"))
+    }
+}
+
+fn is_upgrade_request(headers: &HeaderMap) -> bool {
+    headers.contains_key(UPGRADE)
+        && headers
+            .get_all(CONNECTION)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|token| token.trim().eq_ignore_ascii_case("upgrade"))
+}
+
+/// 将请求原样转发到上游，响应体流式返回，不缓冲也不缓存；上游同意升级时双向转发升级后的连接
+pub async fn passthrough(mut req: Request<Body>, url: &str) -> Result<Response<Body>> {
+    // WebSocket 地址按对应的 HTTP 地址发起升级请求
+    let http_url = if let Some(rest) = url.strip_prefix("ws://") {
+        format!("http://{}", rest)
+    } else if let Some(rest) = url.strip_prefix("wss://") {
+        format!("https://{}", rest)
+    } else {
+        url.to_string()
+    };
+    let uri: Uri = http_url
+        .parse()
+        .map_err(|e| ProxyError::request(format!("无效的上游地址: {} - {}", url, e)))?;
+
+    let is_upgrade = is_upgrade_request(req.headers());
+    let client_upgrade = hyper::upgrade::on(&mut req);
+
+    let (mut parts, body) = req.into_parts();
+    parts.uri = uri;
+    parts.headers.remove(HOST);
+    parts.headers.remove("X-Original-Url");
+
+    log_info!("Tunnel", "透传请求: {} {}", parts.method, url);
+    let client = Client::builder().build::<_, Body>(HttpsConnector::new());
+    let mut response = client.request(Request::from_parts(parts, body)).await?;
+
+    if is_upgrade && response.status() == StatusCode::SWITCHING_PROTOCOLS {
+        let upstream_upgrade = hyper::upgrade::on(&mut response);
+        let url = url.to_string();
+        request_id::spawn(async move {
+            match tokio::try_join!(client_upgrade, upstream_upgrade) {
+                Ok((mut client_io, mut upstream_io)) => {
+                    if let Err(e) = tokio::io::copy_bidirectional(&mut client_io, &mut upstream_io).await {
+                        log_info!("Tunnel", "升级连接异常关闭: {} - {}", url, e);
+                    }
+                }
+                Err(e) => log_info!("Tunnel", "连接升级失败: {} - {}", url, e),
+            }
+        });
+    }
+
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tunnel_allows() {
+        assert!(!TunnelConfig::default().allows("example.com", 443));
+
+        let config = TunnelConfig {
+            enabled: true,
+            allowed_hosts: vec!["cdn.example.com".to_string(), ".media.test".to_string()],
+            ..TunnelConfig::default()
+        };
+        assert!(config.allows("CDN.example.com", 443));
+        assert!(config.allows("media.test", 443));
+        assert!(config.allows("a.media.test", 443));
+        assert!(!config.allows("evilmedia.test", 443));
+        assert!(!config.allows("example.com", 443));
+        assert!(!config.allows("cdn.example.com", 22));
+
+        let config = TunnelConfig { allowed_hosts: vec!["*".to_string()], ..config };
+        assert!(config.allows("[::1]", 443));
+        assert!(!config.allows("example.com", 8443));
+    }
+
+    #[test]
+    fn test_passthrough_matches() {
+        let headers = |pairs: &[(&'static str, &'static str)]| {
+            let mut headers = HeaderMap::new();
+            for (name, value) in pairs {
+                headers.insert(*name, value.parse().unwrap());
+            }
+            headers
+        };
+        let websocket = headers(&[("upgrade", "websocket"), ("connection", "keep-alive, Upgrade")]);
+        let event_stream = headers(&[("accept", "text/event-stream")]);
+        let icy = headers(&[("icy-metadata", "1")]);
+
+        let off = PassthroughConfig::default();
+        assert!(!off.matches(&websocket));
+        assert!(!off.matches(&event_stream));
+        assert!(!off.matches(&icy));
+
+        let on = PassthroughConfig { websocket: true, event_stream: true, icy: true };
+        assert!(on.matches(&websocket));
+        assert!(on.matches(&event_stream));
+        assert!(on.matches(&icy));
+        assert!(!on.matches(&headers(&[("upgrade", "websocket")])));
+        assert!(!on.matches(&headers(&[("accept", "*/*")])));
+
+        let websocket_only = PassthroughConfig { websocket: true, ..PassthroughConfig::default() };
+        assert!(websocket_only.matches(&websocket));
+        assert!(!websocket_only.matches(&event_stream));
+    }
+}
@@ -0,0 +1,199 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use crate::log_info;
+
+/// 流量统计文件名，保存在缓存根目录
+pub const USAGE_FILE: &str = "usage.json";
+
+/// 保留的天数，更早的记录在保存时丢弃
+const MAX_USAGE_DAYS: usize = 400;
+
+/// 两次写入文件的最短间隔
+const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// 某一天某个源站主机的流量
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct UsageCounters {
+    pub requests: u64,
+    /// 从源站获取的字节数
+    pub upstream_bytes: u64,
+    /// 返回给客户端的字节数
+    pub downstream_bytes: u64,
+}
+
+/// `/admin/usage` 返回的一行记录
+#[derive(Debug, Clone, Serialize)]
+pub struct UsageRecord {
+    pub date: String,
+    pub host: String,
+    pub requests: u64,
+    pub upstream_bytes: u64,
+    pub downstream_bytes: u64,
+    /// 由缓存提供、不需要从源站获取的字节数
+    pub saved_bytes: u64,
+}
+
+/// 按天和源站主机累计上下行流量，定期保存到文件，重启后继续累计
+pub struct UsageLedger {
+    path: Option<PathBuf>,
+    /// 日期 (YYYY-MM-DD) -> 主机 -> 计数
+    days: Mutex<BTreeMap<String, HashMap<String, UsageCounters>>>,
+    last_saved: Mutex<Instant>,
+    dirty: AtomicBool,
+}
+
+impl Default for UsageLedger {
+    fn default() -> Self {
+        Self::in_memory()
+    }
+}
+
+impl UsageLedger {
+    /// 不保存到文件
+    pub fn in_memory() -> Self {
+        Self {
+            path: None,
+            days: Mutex::new(BTreeMap::new()),
+            last_saved: Mutex::new(Instant::now()),
+            dirty: AtomicBool::new(false),
+        }
+    }
+
+    /// 从文件载入已有的统计，文件不存在或损坏时从零开始
+    pub fn load(path: PathBuf) -> Self {
+        let days = match std::fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data).unwrap_or_else(|e| {
+                log_info!("Stats", "流量统计文件损坏，重新开始统计: {:?} - {}", path, e);
+                BTreeMap::new()
+            }),
+            Err(_) => BTreeMap::new(),
+        };
+        Self {
+            path: Some(path),
+            days: Mutex::new(days),
+            last_saved: Mutex::new(Instant::now()),
+            dirty: AtomicBool::new(false),
+        }
+    }
+
+    /// 记录一次响应的下行字节数和其中来自源站的字节数
+    pub fn record(self: &Arc<Self>, url: &str, downstream: u64, upstream: u64) {
+        let host = host_of(url);
+        let date = chrono::Local::now().format("%Y-%m-%d").to_string();
+        {
+            let mut days = self.days.lock().unwrap();
+            let counters = days.entry(date).or_default().entry(host).or_default();
+            counters.requests += 1;
+            counters.downstream_bytes += downstream;
+            counters.upstream_bytes += upstream;
+        }
+        self.dirty.store(true, Ordering::Release);
+
+        // 距离上次保存超过间隔时在后台写入文件
+        let due = {
+            let mut last_saved = self.last_saved.lock().unwrap();
+            let due = last_saved.elapsed() >= SAVE_INTERVAL;
+            if due {
+                *last_saved = Instant::now();
+            }
+            due
+        };
+        if due && self.path.is_some() {
+            if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+                let ledger = self.clone();
+                runtime.spawn_blocking(move || ledger.save());
+            }
+        }
+    }
+
+    /// 有新的记录时写入文件，只保留最近 `MAX_USAGE_DAYS` 天
+    pub fn save(&self) {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+        if !self.dirty.swap(false, Ordering::AcqRel) {
+            return;
+        }
+        let data = {
+            let mut days = self.days.lock().unwrap();
+            while days.len() > MAX_USAGE_DAYS {
+                let oldest = days.keys().next().cloned();
+                if let Some(oldest) = oldest {
+                    days.remove(&oldest);
+                }
+            }
+            serde_json::to_vec(&*days)
+        };
+        let result = data
+            .map_err(std::io::Error::from)
+            .and_then(|data| {
+                let tmp = path.with_extension("json.tmp");
+                std::fs::write(&tmp, data)?;
+                std::fs::rename(&tmp, path)
+            });
+        if let Err(e) = result {
+            log_info!("Stats", "保存流量统计失败: {:?} - {}", path, e);
+            self.dirty.store(true, Ordering::Release);
+        }
+    }
+
+    /// 按日期和主机列出统计，`since` 为起始日期 (YYYY-MM-DD)，`host` 只返回指定主机
+    pub fn report(&self, since: Option<&str>, host: Option<&str>) -> Vec<UsageRecord> {
+        let days = self.days.lock().unwrap();
+        let mut records = Vec::new();
+        for (date, hosts) in days.iter().filter(|(date, _)| since.is_none_or(|since| date.as_str() >= since)) {
+            let mut day: Vec<_> = hosts
+                .iter()
+                .filter(|(name, _)| host.is_none_or(|host| name.eq_ignore_ascii_case(host)))
+                .map(|(name, counters)| UsageRecord {
+                    date: date.clone(),
+                    host: name.clone(),
+                    requests: counters.requests,
+                    upstream_bytes: counters.upstream_bytes,
+                    downstream_bytes: counters.downstream_bytes,
+                    saved_bytes: counters.downstream_bytes.saturating_sub(counters.upstream_bytes),
+                })
+                .collect();
+            day.sort_by_key(|b| std::cmp::Reverse(b.downstream_bytes));
+            records.extend(day);
+        }
+        records
+    }
+}
+
+/// 统计使用的主机名，无法解析的 URL 归入 `unknown`
+fn host_of(url: &str) -> String {
+    url::Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_ascii_lowercase))
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_usage_ledger() {
+        let ledger = Arc::new(UsageLedger::in_memory());
+        ledger.record("https://CDN.example.com/a.mp4", 1000, 1000);
+        ledger.record("https://cdn.example.com/a.mp4", 1000, 0);
+        ledger.record("https://other.example.com/b.ts", 10, 4);
+
+        let records = ledger.report(None, None);
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].host, "cdn.example.com");
+        assert_eq!(records[0].requests, 2);
+        assert_eq!(records[0].saved_bytes, 1000);
+
+        let records = ledger.report(None, Some("other.example.com"));
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].upstream_bytes, 4);
+        assert!(ledger.report(Some("9999-01-01"), None).is_empty());
+    }
+}
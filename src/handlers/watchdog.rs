@@ -0,0 +1,94 @@
+use std::future::Future;
+use std::time::Duration;
+use futures::StreamExt;
+use hyper::{Body, Response, StatusCode};
+use crate::utils::error::{ProxyError, Result};
+use crate::log_info;
+
+/// 上游数据停滞检测配置
+#[derive(Clone)]
+pub struct WatchdogConfig {
+    pub enabled: bool,
+    /// 传输中超过该时间没有收到数据视为连接已失效
+    pub stall_timeout: Duration,
+    /// 从当前位置重新请求的最多次数
+    pub max_retries: u32,
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            stall_timeout: Duration::from_secs(30),
+            max_retries: 3,
+        }
+    }
+}
+
+struct WatchState<F> {
+    body: Option<Body>,
+    url: String,
+    offset: u64,
+    end: Option<u64>,
+    retries: u32,
+    reissue: F,
+    config: WatchdogConfig,
+}
+
+/// 监视范围响应的数据流，停滞时放弃当前连接并从已收到的位置重新请求 `[offset, end]`
+pub fn watch<F, Fut>(url: &str, body: Body, start: u64, end: Option<u64>, config: WatchdogConfig, reissue: F) -> Body
+where
+    F: Fn(String) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<Response<Body>>> + Send + 'static,
+{
+    let state = WatchState {
+        body: Some(body),
+        url: url.to_string(),
+        offset: start,
+        end,
+        retries: 0,
+        reissue,
+        config,
+    };
+
+    Body::wrap_stream(futures::stream::unfold(state, |mut state| async move {
+        loop {
+            let body = state.body.as_mut()?;
+            let next = tokio::time::timeout(state.config.stall_timeout, body.next()).await;
+            match next {
+                Ok(Some(Ok(chunk))) => {
+                    state.offset += chunk.len() as u64;
+                    return Some((Ok(chunk), state));
+                }
+                Ok(Some(Err(e))) => {
+                    state.body = None;
+                    return Some((Err(ProxyError::from(e)), state));
+                }
+                Ok(None) => return None,
+                Err(_) => {
+                    state.body = None;
+                    if state.retries >= state.config.max_retries {
+                        let message = format!("上游 {} 秒没有数据: {}", state.config.stall_timeout.as_secs(), state.url);
+                        return Some((Err(ProxyError::network(message)), state));
+                    }
+                    state.retries += 1;
+
+                    let range = match state.end {
+                        Some(end) => format!("bytes={}-{}", state.offset, end),
+                        None => format!("bytes={}-", state.offset),
+                    };
+                    log_info!("Network", "上游数据停滞，从当前位置重新请求({}/{}): {} {}",
+                        state.retries, state.config.max_retries, state.url, range);
+                    match (state.reissue)(range).await {
+                        Ok(resp) if resp.status() == StatusCode::PARTIAL_CONTENT => state.body = Some(resp.into_body()),
+                        Ok(resp) => {
+                            let message = format!("重新请求没有返回范围数据: {}", resp.status());
+                            return Some((Err(ProxyError::network(message)), state));
+                        }
+                        Err(e) => return Some((Err(e), state)),
+                    }
+                }
+            }
+        }
+    }))
+}
@@ -1,43 +1,102 @@
 use crate::utils::error::{ProxyError, Result};
-use crate::data_request::DataRequest;
+use crate::data_request::{normalize_proxy_prefix, DataRequest, DEFAULT_PROXY_PREFIX};
 use crate::data_source_manager::DataSourceManager;
+use crate::handlers::PlaybackHint;
+use crate::handlers::scheduler::Priority;
 use crate::log_info;
-use super::{HlsHandler, HlsManager};
-use hyper::Client;
-use hyper_tls::HttpsConnector;
-use std::path::PathBuf;
-use std::sync::Arc;
+use crate::route::RouteTable;
+use crate::utils::link_codec::{LinkCodec, PercentCodec};
+use crate::utils::request_id;
+use super::{HlsHandler, HlsManager, PlaylistInfo};
+use hyper::{Body, Response};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use url::Url;
 use urlencoding;
 
+/// 重写后的播放列表缓存时间，多个客户端频繁刷新同一播放列表时只下载一次
+const PLAYLIST_CACHE_TTL: Duration = Duration::from_secs(2);
+
+/// 缓存的播放列表数量上限，超过时先清理过期项
+const PLAYLIST_CACHE_CAPACITY: usize = 1024;
+
+/// 重写播放列表缓存的键：(源站 URL, 代理前缀)
+type PlaylistKey = (String, String);
+
 pub struct DefaultHlsHandler {
     manager: Arc<HlsManager>,
     source_manager: Arc<DataSourceManager>,
-    client: Client<HttpsConnector<hyper::client::HttpConnector>>,
+    routes: Arc<RouteTable>,
+    /// 代理入口路径前缀，重写播放列表时使用
+    proxy_prefix: String,
+    /// 源站 URL 的编码方式
+    link_codec: Arc<dyn LinkCodec>,
+    /// 按 (URL, 代理前缀) 缓存重写后的播放列表
+    rewritten: Mutex<HashMap<PlaylistKey, (Instant, Arc<str>)>>,
+    /// 按播放位置正在预取的分片
+    prefetching: Arc<Mutex<HashSet<String>>>,
 }
 
 impl DefaultHlsHandler {
-    pub fn new(cache_dir: PathBuf, source_manager: Arc<DataSourceManager>) -> Self {
-        let https = HttpsConnector::new();
-        let client = Client::builder().build::<_, hyper::Body>(https);
-        
+    pub fn new(source_manager: Arc<DataSourceManager>) -> Self {
+        Self::with_routes(source_manager, Arc::new(RouteTable::default()))
+    }
+
+    pub fn with_routes(source_manager: Arc<DataSourceManager>, routes: Arc<RouteTable>) -> Self {
         Self {
-            manager: Arc::new(HlsManager::new(cache_dir)),
+            manager: Arc::new(HlsManager::new()),
             source_manager,
-            client,
+            routes,
+            proxy_prefix: DEFAULT_PROXY_PREFIX.to_string(),
+            link_codec: Arc::new(PercentCodec),
+            rewritten: Mutex::new(HashMap::new()),
+            prefetching: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+
+    /// 设置代理入口路径前缀，与 `RequestValidation::proxy_prefix` 保持一致
+    pub fn with_proxy_prefix(mut self, prefix: &str) -> Self {
+        self.proxy_prefix = normalize_proxy_prefix(prefix);
+        self
+    }
+
+    /// 设置重写链接时源站 URL 的编码方式，与 `RequestValidation::link_codec` 保持一致
+    pub fn with_link_codec(mut self, codec: Arc<dyn LinkCodec>) -> Self {
+        self.link_codec = codec;
+        self
+    }
+
+    /// 未过期的重写结果
+    fn cached_playlist(&self, url: &str, proxy_prefix: &str) -> Option<Arc<str>> {
+        let cache = self.rewritten.lock().unwrap();
+        match cache.get(&(url.to_string(), proxy_prefix.to_string())) {
+            Some((created, playlist)) if created.elapsed() < PLAYLIST_CACHE_TTL => Some(playlist.clone()),
+            _ => None,
         }
     }
 
+    fn store_playlist(&self, url: &str, proxy_prefix: &str, playlist: Arc<str>) {
+        let mut cache = self.rewritten.lock().unwrap();
+        if cache.len() >= PLAYLIST_CACHE_CAPACITY {
+            cache.retain(|_, (created, _)| created.elapsed() < PLAYLIST_CACHE_TTL);
+            if cache.len() >= PLAYLIST_CACHE_CAPACITY {
+                cache.clear();
+            }
+        }
+        cache.insert((url.to_string(), proxy_prefix.to_string()), (Instant::now(), playlist));
+    }
+
     fn get_base_url(&self, url: &str) -> Result<String> {
         let parsed = Url::parse(url)
-            .map_err(|e| ProxyError::Parse(format!("无法解析URL: {}", e)))?;
+            .map_err(|e| ProxyError::parse(format!("无法解析URL: {}", e)))?;
         
         let mut base = parsed.clone();
         if let Some(segments) = base.path_segments() {
             let segments: Vec<_> = segments.collect();
             if !segments.is_empty() {
                 base.path_segments_mut()
-                    .map_err(|_| ProxyError::Parse("无法修改URL路径".to_string()))?
+                    .map_err(|_| ProxyError::parse("无法修改URL路径".to_string()))?
                     .pop();
             }
         }
@@ -47,20 +106,119 @@ impl DefaultHlsHandler {
 
     async fn download_m3u8(&self, url: &str) -> Result<String> {
         log_info!("HLS", "下载 m3u8 文件: {}", url);
-        
-        let req = DataRequest::new_request_with_range(url, "bytes=0-");
-        let resp = self.client.request(req).await
-            .map_err(|e| ProxyError::Network(format!("请求失败: {}", e)))?;
-        
-        if !resp.status().is_success() {
-            return Err(ProxyError::Network(format!("请求失败: {}", resp.status())));
+        // 与普通请求共用上游连接和缓存，点播列表命中缓存时不请求上游
+        self.source_manager.fetch_playlist(url).await
+    }
+
+    /// 获取播放列表中所有分片的绝对地址，主播放列表选择码率最高的变体流
+    pub async fn segment_urls(&self, url: &str) -> Result<Vec<String>> {
+        let (_, segment_urls) = self.media_playlist(url).await?;
+        Ok(segment_urls)
+    }
+
+    /// 获取媒体播放列表和各分片的绝对地址，主播放列表选择码率最高的变体流。
+    /// 已结束的点播列表使用内存中的解析结果，不再重复下载
+    async fn media_playlist(&self, url: &str) -> Result<(PlaylistInfo, Vec<String>)> {
+        let mut url = url.to_string();
+        // 最多展开一层主播放列表
+        for _ in 0..2 {
+            let info = match self.manager.get_playlist(&url).await {
+                Some(info) if info.is_endlist || !info.variants.is_empty() => info,
+                _ => {
+                    let content = self.download_m3u8(&url).await?;
+                    self.manager.process_m3u8(&url, &content).await?
+                }
+            };
+            let base = Url::parse(&url)
+                .map_err(|e| ProxyError::parse(format!("无法解析URL: {}", e)))?;
+            let resolve = |uri: &str| base.join(uri).map(|u| u.to_string()).ok();
+
+            match info.variants.iter().max_by_key(|v| v.bandwidth) {
+                Some(variant) => {
+                    url = resolve(&variant.url)
+                        .ok_or_else(|| ProxyError::parse(format!("无效的变体流地址: {}", variant.url)))?;
+                }
+                None => {
+                    let segment_urls = info
+                        .segments
+                        .iter()
+                        .map(|s| resolve(&s.url).ok_or_else(|| ProxyError::parse(format!("无效的分片地址: {}", s.url))))
+                        .collect::<Result<Vec<_>>>()?;
+                    return Ok((info, segment_urls));
+                }
+            }
         }
-        
-        let body = hyper::body::to_bytes(resp.into_body()).await
-            .map_err(|e| ProxyError::Network(format!("读取响应失败: {}", e)))?;
-        
-        String::from_utf8(body.to_vec())
-            .map_err(|e| ProxyError::Parse(format!("解析响应内容失败: {}", e)))
+        Err(ProxyError::parse(format!("播放列表嵌套过深: {}", url)))
+    }
+
+    /// 只列出已缓存分片的播放列表，用于录制或导出点播内容时在另一台设备上边下边看。
+    /// 分片按顺序检查，遇到第一个未缓存的分片为止，保证播放器不会跳过缺失的分片
+    pub async fn progressive_playlist(&self, url: &str) -> Result<String> {
+        log_info!("HLS", "生成渐进式播放列表: {}", url);
+        let (info, segment_urls) = self.media_playlist(url).await?;
+
+        let mut available = 0;
+        for segment_url in &segment_urls {
+            if !self.source_manager.is_complete(segment_url).await {
+                break;
+            }
+            available += 1;
+        }
+        log_info!("HLS", "已缓存分片: {}/{} {}", available, segment_urls.len(), url);
+
+        let proxy_urls: Vec<String> = segment_urls.iter().map(|segment_url| self.proxy_url(segment_url)).collect();
+        Ok(self.manager.progressive_m3u8(&info, &proxy_urls, available))
+    }
+
+    /// 只包含音频的播放列表，用于后台收听。`url` 为主播放列表，没有可单独播放的音频流时返回 None
+    pub async fn audio_playlist(&self, url: &str) -> Result<Option<String>> {
+        log_info!("HLS", "生成纯音频播放列表: {}", url);
+        let info = match self.manager.get_playlist(url).await {
+            Some(info) if !info.variants.is_empty() => info,
+            _ => {
+                let content = self.download_m3u8(url).await?;
+                self.manager.process_m3u8(url, &content).await?
+            }
+        };
+        let base = Url::parse(url)
+            .map_err(|e| ProxyError::parse(format!("无法解析URL: {}", e)))?;
+        Ok(self.manager.audio_only_m3u8(&info, |uri| {
+            base.join(uri).ok().map(|absolute| self.proxy_url(absolute.as_str()))
+        }))
+    }
+
+    /// 播放器上报了播放位置时，在后台以预读优先级预取播放位置之后的分片，已缓存或正在预取的分片跳过
+    fn prefetch_upcoming(&self, segment_url: &str, hint: PlaybackHint) {
+        let config = self.source_manager.read_ahead_config();
+        if !config.enabled || config.hint_segments == 0 || self.source_manager.is_caching_paused() {
+            return;
+        }
+        let lookahead = config.hint_lookahead.as_secs_f64();
+        let max = config.hint_segments;
+        let segment_url = segment_url.to_string();
+        let manager = self.manager.clone();
+        let source_manager = self.source_manager.clone();
+        let prefetching = self.prefetching.clone();
+        request_id::spawn(async move {
+            let urls = manager.upcoming_segment_urls(&segment_url, hint.position, lookahead, max).await;
+            for url in urls {
+                if source_manager.is_complete(&url).await || !prefetching.lock().unwrap().insert(url.clone()) {
+                    continue;
+                }
+                match source_manager.prefetch_with_priority(&url, Priority::ReadAhead).await {
+                    Ok(bytes) => log_info!("HLS", "按播放位置预取分片: {} {} 字节", url, bytes),
+                    Err(e) => log_info!("HLS", "按播放位置预取分片失败: {} - {}", url, e),
+                }
+                prefetching.lock().unwrap().remove(&url);
+            }
+        });
+    }
+
+    /// 播放列表中 URI 的代理地址，匹配路由表时使用本地路径
+    fn proxy_url(&self, url: &str) -> String {
+        self.routes
+            .reverse(url)
+            .unwrap_or_else(|| format!("{}{}", self.proxy_prefix, self.link_codec.encode(url)))
     }
 }
 
@@ -69,22 +227,29 @@ impl HlsHandler for DefaultHlsHandler {
     async fn handle_m3u8(&self, url: &str) -> Result<String> {
         log_info!("HLS", "处理 m3u8 请求: {}", url);
         
-        // 移除可能存在的 /proxy/ 前缀
-        let clean_url = if let Some(proxy_path) = url.find("/proxy/") {
-            let url_part = &url[proxy_path + 7..];
-            // 处理可能存在的多重 /proxy/ 前缀
+        // 移除可能存在的代理入口前缀
+        let prefix = self.proxy_prefix.as_str();
+        let clean_url = if let Some(proxy_path) = url.find(prefix).filter(|_| prefix != "/") {
+            let url_part = &url[proxy_path + prefix.len()..];
+            // 处理可能存在的多重前缀
             let mut clean = url_part.to_string();
-            while let Some(idx) = clean.find("/proxy/") {
-                clean = clean[idx + 7..].to_string();
+            while let Some(idx) = clean.find(prefix) {
+                clean = clean[idx + prefix.len()..].to_string();
             }
             // 解码 URL
             urlencoding::decode(&clean)
-                .map_err(|e| ProxyError::Request(format!("URL 解码失败: {}", e)))?
+                .map_err(|e| ProxyError::request(format!("URL 解码失败: {}", e)))?
                 .into_owned()
         } else {
             url.to_string()
         };
         
+        let proxy_prefix = self.proxy_prefix.as_str();
+        if let Some(playlist) = self.cached_playlist(&clean_url, proxy_prefix) {
+            log_info!("HLS", "使用缓存的播放列表: {}", clean_url);
+            return Ok(playlist.to_string());
+        }
+        
         // 下载 m3u8 内容
         let content = self.download_m3u8(&clean_url).await?;
         
@@ -98,28 +263,22 @@ impl HlsHandler for DefaultHlsHandler {
         let rewritten = self.manager.rewrite_m3u8(
             &content,
             &base_url,
-            "/proxy"
+            proxy_prefix,
+            self.link_codec.as_ref(),
+            &self.routes,
         );
+        self.store_playlist(&clean_url, proxy_prefix, Arc::from(rewritten.as_str()));
         
         Ok(rewritten)
     }
     
-    async fn handle_segment(&self, url: &str, range: Option<String>) -> Result<Vec<u8>> {
-        log_info!("HLS", "处理分片请求: {} range={:?}", url, range);
-        
-        // 创建数据请求
-        let req = DataRequest::new_request_with_range(
-            url,
-            &range.unwrap_or_else(|| "bytes=0-".to_string())
-        );
-        
-        // 使用数据源管理器处理请求
-        let resp = self.source_manager.process_request(&DataRequest::new(&req)?).await?;
-        
-        // 读取响应体
-        let body = hyper::body::to_bytes(resp.into_body()).await
-            .map_err(|e| ProxyError::Network(format!("读取响应失败: {}", e)))?;
+    async fn handle_segment(&self, req: &DataRequest) -> Result<Response<Body>> {
+        log_info!("HLS", "处理分片请求: {} range={}", req.get_url(), req.get_range());
+        if let Some(hint) = PlaybackHint::from_headers(req.get_headers()) {
+            self.prefetch_upcoming(req.get_url(), hint);
+        }
         
-        Ok(body.to_vec())
+        // 与普通请求相同，按范围读写缓存，返回 206 和 Content-Range；没有 Range 头时返回完整分片
+        self.source_manager.process_request(req).await
     }
 } 
\ No newline at end of file
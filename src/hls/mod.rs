@@ -2,14 +2,56 @@ mod handler;
 
 pub use handler::DefaultHlsHandler;
 
-use std::path::PathBuf;
 use async_trait::async_trait;
+use hyper::{Body, Response};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::ops::Range;
 use tokio::sync::RwLock;
+use url::Url;
 use std::sync::Arc;
 use crate::utils::error::Result;
 use crate::log_info;
+use crate::data_request::DataRequest;
+use crate::route::RouteTable;
+use crate::utils::link_codec::LinkCodec;
+
+/// 渐进式播放列表的访问路径前缀，后接编码后的 m3u8 地址
+pub const PROGRESSIVE_PREFIX: &str = "/hls/progressive/";
+
+/// 纯音频播放列表的访问路径前缀，后接编码后的主播放列表地址
+pub const AUDIO_PREFIX: &str = "/hls/audio/";
+
+/// 视频编码的 CODECS 前缀，不含这些编码的变体流视为纯音频
+const VIDEO_CODECS: [&str; 6] = ["avc1", "avc3", "hvc1", "hev1", "vp09", "av01"];
+
+/// 将上游返回的播放列表转换为规范文本：去掉 BOM，支持 UTF-16，非法的 UTF-8 字节替换为 U+FFFD，
+/// 换行统一为 `\n`，去掉每行首尾的空白和空行
+pub fn normalize_playlist(data: &[u8]) -> String {
+    let text = if let Some(rest) = data.strip_prefix(&[0xEF, 0xBB, 0xBF]) {
+        String::from_utf8_lossy(rest).into_owned()
+    } else if let Some(rest) = data.strip_prefix(&[0xFF, 0xFE]) {
+        decode_utf16(rest, u16::from_le_bytes)
+    } else if let Some(rest) = data.strip_prefix(&[0xFE, 0xFF]) {
+        decode_utf16(rest, u16::from_be_bytes)
+    } else {
+        String::from_utf8_lossy(data).into_owned()
+    };
+
+    let mut result = String::with_capacity(text.len());
+    for line in text.split(['\r', '\n']).map(str::trim).filter(|line| !line.is_empty()) {
+        result.push_str(line);
+        result.push('\n');
+    }
+    result
+}
+
+fn decode_utf16(data: &[u8], from_bytes: fn([u8; 2]) -> u16) -> String {
+    let units = data.chunks_exact(2).map(|pair| from_bytes([pair[0], pair[1]]));
+    char::decode_utf16(units)
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect()
+}
 
 /// HLS 分片信息
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -26,6 +68,26 @@ pub struct Segment {
     pub cached: bool,
 }
 
+/// 需要预取的分片下标：从当前分片的下一个和覆盖播放位置的分片中较后者开始，
+/// 到开始时间达到 `position + lookahead` 的分片为止，最多 `max` 个
+pub fn upcoming_segments(segments: &[Segment], current: usize, position: f64, lookahead: f64, max: usize) -> Range<usize> {
+    let mut begin = current + 1;
+    let mut end = segments.len();
+    let mut elapsed = 0.0;
+    for (index, segment) in segments.iter().enumerate() {
+        if elapsed >= position + lookahead {
+            end = index;
+            break;
+        }
+        elapsed += segment.duration as f64;
+        if elapsed <= position {
+            begin = begin.max(index + 1);
+        }
+    }
+    let begin = begin.min(segments.len());
+    begin..end.clamp(begin, begin.saturating_add(max).min(segments.len()))
+}
+
 /// HLS 播放列表信息
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PlaylistInfo {
@@ -41,6 +103,9 @@ pub struct PlaylistInfo {
     pub segments: Vec<Segment>,
     /// 变体流信息（仅用于主播放列表）
     pub variants: Vec<VariantStream>,
+    /// `EXT-X-MEDIA` 声明的音频流（仅用于主播放列表）
+    #[serde(default)]
+    pub audio_renditions: Vec<AudioRendition>,
     /// 最后更新时间
     pub last_updated: chrono::DateTime<chrono::Utc>,
 }
@@ -54,21 +119,70 @@ pub struct VariantStream {
     pub bandwidth: u64,
     /// 分辨率（可���）
     pub resolution: Option<String>,
+    /// CODECS 属性
+    #[serde(default)]
+    pub codecs: Option<String>,
+    /// 关联的音频组 ID
+    #[serde(default)]
+    pub audio_group: Option<String>,
+}
+
+impl VariantStream {
+    /// 声明了 CODECS 且不含视频编码
+    pub fn is_audio_only(&self) -> bool {
+        match &self.codecs {
+            Some(codecs) => !codecs
+                .split(',')
+                .any(|codec| VIDEO_CODECS.iter().any(|video| codec.trim().starts_with(video))),
+            None => false,
+        }
+    }
+
+    /// CODECS 中的音频编码部分
+    fn audio_codecs(&self) -> Option<String> {
+        let codecs: Vec<&str> = self
+            .codecs
+            .as_deref()?
+            .split(',')
+            .map(str::trim)
+            .filter(|codec| !VIDEO_CODECS.iter().any(|video| codec.starts_with(video)))
+            .collect();
+        if codecs.is_empty() {
+            None
+        } else {
+            Some(codecs.join(","))
+        }
+    }
+}
+
+/// `EXT-X-MEDIA` 声明的音频流
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AudioRendition {
+    /// 媒体播放列表 URL
+    pub url: String,
+    pub group_id: String,
+    pub name: String,
+    pub language: Option<String>,
+    /// 是否为组内默认音频
+    pub default: bool,
 }
 
 /// HLS 缓存管理器
 pub struct HlsManager {
-    /// 缓存根目录
-    cache_dir: PathBuf,
     /// 播放列表缓存
     playlists: Arc<RwLock<HashMap<String, PlaylistInfo>>>,
 }
 
+impl Default for HlsManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl HlsManager {
     /// 创建新的 HLS 管理器实例
-    pub fn new(cache_dir: PathBuf) -> Self {
+    pub fn new() -> Self {
         Self {
-            cache_dir,
             playlists: Arc::new(RwLock::new(HashMap::new())),
         }
     }
@@ -79,7 +193,7 @@ impl HlsManager {
         
         // 解析 m3u8 内容
         let playlist = m3u8_rs::parse_playlist(content.as_bytes())
-            .map_err(|e| crate::utils::error::ProxyError::Parse(e.to_string()))?
+            .map_err(|e| crate::utils::error::ProxyError::parse(e.to_string()))?
             .1;  // 获取解析结果的第二个元素
 
         match playlist {
@@ -94,6 +208,24 @@ impl HlsManager {
                         url: v.uri.clone(),
                         bandwidth: v.bandwidth,
                         resolution: v.resolution.as_ref().map(|r| format!("{}x{}", r.width, r.height)),
+                        codecs: v.codecs.clone(),
+                        audio_group: v.audio.clone(),
+                    })
+                    .collect();
+
+                // 内嵌在变体流中的音频没有单独的 URI，不能单独播放
+                let audio_renditions = master
+                    .alternatives
+                    .iter()
+                    .filter(|m| m.media_type == m3u8_rs::AlternativeMediaType::Audio)
+                    .filter_map(|m| {
+                        Some(AudioRendition {
+                            url: m.uri.clone()?,
+                            group_id: m.group_id.clone(),
+                            name: m.name.clone(),
+                            language: m.language.clone(),
+                            default: m.default,
+                        })
                     })
                     .collect();
 
@@ -104,6 +236,7 @@ impl HlsManager {
                     is_endlist: false,
                     segments: vec![],
                     variants,
+                    audio_renditions,
                     last_updated: chrono::Utc::now(),
                 };
 
@@ -135,6 +268,7 @@ impl HlsManager {
                     is_endlist: media.end_list,
                     segments,
                     variants: vec![],
+                    audio_renditions: vec![],
                     last_updated: chrono::Utc::now(),
                 };
 
@@ -146,7 +280,7 @@ impl HlsManager {
     }
 
     /// 重写 m3u8 内容，将 URL 替换为代理 URL
-    pub fn rewrite_m3u8(&self, content: &str, base_url: &str, proxy_prefix: &str) -> String {
+    pub fn rewrite_m3u8(&self, content: &str, base_url: &str, proxy_prefix: &str, codec: &dyn LinkCodec, routes: &RouteTable) -> String {
         log_info!("HLS", "重写 m3u8 内容，base_url: {}", base_url);
         
         let mut result = String::new();
@@ -164,9 +298,8 @@ impl HlsManager {
                 // 处理 URL 行
                 let url = if line.starts_with("http://") || line.starts_with("https://") {
                     line.to_string()
-                } else if line.starts_with("/proxy/") {
+                } else if let Some(clean_url) = line.strip_prefix(&format!("{}/", proxy_prefix.trim_end_matches('/'))) {
                     // 如果已经是代理 URL，去掉前缀重新处理
-                    let clean_url = &line[7..];
                     if clean_url.starts_with("http://") || clean_url.starts_with("https://") {
                         clean_url.to_string()
                     } else {
@@ -178,10 +311,17 @@ impl HlsManager {
                     format!("{}/{}", base, line.trim_start_matches('/'))
                 };
 
+                // 匹配路由表时使用本地路径，不暴露源站地址
+                if let Some(path) = routes.reverse(&url) {
+                    result.push_str(&path);
+                    result.push('\n');
+                    continue;
+                }
+
                 // 添加代理前缀
                 result.push_str(&format!("{}/{}\n", 
                     proxy_prefix.trim_end_matches('/'), 
-                    urlencoding::encode(&url)
+                    codec.encode(&url)
                 ));
             }
         }
@@ -193,6 +333,33 @@ impl HlsManager {
         self.playlists.read().await.get(url).cloned()
     }
 
+    /// 分片 `segment_url` 所在的播放列表中，播放位置 `position`（秒）之后 `lookahead` 秒内需要预取的分片地址。
+    /// 直播列表的播放位置无法对应到列表内的时间，只取当前分片之后的分片
+    pub async fn upcoming_segment_urls(&self, segment_url: &str, position: f64, lookahead: f64, max: usize) -> Vec<String> {
+        let playlists = self.playlists.read().await;
+        for (url, info) in playlists.iter() {
+            let base = match Url::parse(url) {
+                Ok(base) => base,
+                Err(_) => continue,
+            };
+            let resolve = |uri: &str| base.join(uri).map(|u| u.to_string()).ok();
+            let current = match info.segments.iter().position(|s| resolve(&s.url).as_deref() == Some(segment_url)) {
+                Some(current) => current,
+                None => continue,
+            };
+            let position = if info.is_endlist {
+                position
+            } else {
+                info.segments[..current].iter().map(|s| s.duration as f64).sum()
+            };
+            return info.segments[upcoming_segments(&info.segments, current, position, lookahead, max)]
+                .iter()
+                .filter_map(|s| resolve(&s.url))
+                .collect();
+        }
+        Vec::new()
+    }
+
     /// 更新分片缓存状态
     pub async fn update_segment_cache(&self, url: &str, sequence: u64, size: u64) -> Result<()> {
         log_info!("HLS", "更新分片缓存状态: {} sequence={}", url, sequence);
@@ -206,11 +373,70 @@ impl HlsManager {
         Ok(())
     }
 
-    /// 获取分片的缓存路径
-    pub fn get_segment_cache_path(&self, url: &str, sequence: u64) -> PathBuf {
-        let hash = format!("{:x}", md5::compute(url));
-        self.cache_dir.join(format!("{}_seg_{}.ts", hash, sequence))
+    /// 生成只包含前 `available` 个分片的播放列表，`segment_urls` 为各分片的代理地址。
+    /// 全部分片可用前不带 `#EXT-X-ENDLIST`，播放器按 EVENT 类型定期刷新，只输出时长和分片地址
+    pub fn progressive_m3u8(&self, info: &PlaylistInfo, segment_urls: &[String], available: usize) -> String {
+        let available = available.min(info.segments.len()).min(segment_urls.len());
+        let target_duration = info
+            .segments
+            .iter()
+            .map(|s| s.duration)
+            .fold(info.target_duration, f32::max)
+            .ceil() as u64;
+
+        let mut result = String::from("#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-PLAYLIST-TYPE:EVENT\n");
+        result.push_str(&format!("#EXT-X-TARGETDURATION:{}\n", target_duration));
+        result.push_str(&format!("#EXT-X-MEDIA-SEQUENCE:{}\n", info.media_sequence));
+        for (segment, url) in info.segments.iter().zip(segment_urls).take(available) {
+            result.push_str(&format!("#EXTINF:{:.3},\n{}\n", segment.duration, url));
+        }
+        if available == info.segments.len() {
+            result.push_str("#EXT-X-ENDLIST\n");
+        }
+        result
     }
+
+    /// 生成只包含音频的主播放列表，`proxy_url` 将播放列表中的 URI 转换为代理地址。
+    /// 优先使用 `EXT-X-MEDIA` 中的默认音频流，其次是纯音频的变体流，都没有时返回 None
+    pub fn audio_only_m3u8(&self, info: &PlaylistInfo, proxy_url: impl Fn(&str) -> Option<String>) -> Option<String> {
+        let rendition = info
+            .audio_renditions
+            .iter()
+            .find(|r| r.default)
+            .or_else(|| info.audio_renditions.first());
+
+        let (uri, bandwidth, codecs) = match rendition {
+            Some(rendition) => {
+                // 带宽和编码取引用该音频组、码率最低的变体流
+                let variant = info
+                    .variants
+                    .iter()
+                    .filter(|v| v.audio_group.as_deref() == Some(rendition.group_id.as_str()))
+                    .min_by_key(|v| v.bandwidth);
+                log_info!("HLS", "使用音频流: {} {:?}", rendition.name, rendition.language);
+                (
+                    rendition.url.as_str(),
+                    variant.map(|v| v.bandwidth).unwrap_or(128_000),
+                    variant.and_then(|v| v.audio_codecs()),
+                )
+            }
+            None => {
+                let variant = info
+                    .variants
+                    .iter()
+                    .filter(|v| v.is_audio_only())
+                    .max_by_key(|v| v.bandwidth)?;
+                (variant.url.as_str(), variant.bandwidth, variant.codecs.clone())
+            }
+        };
+
+        let mut attributes = format!("BANDWIDTH={}", bandwidth);
+        if let Some(codecs) = codecs {
+            attributes.push_str(&format!(",CODECS=\"{}\"", codecs));
+        }
+        Some(format!("#EXTM3U\n#EXT-X-STREAM-INF:{}\n{}\n", attributes, proxy_url(uri)?))
+    }
+
 }
 
 #[async_trait]
@@ -218,6 +444,137 @@ pub trait HlsHandler {
     /// 处理 m3u8 请求
     async fn handle_m3u8(&self, url: &str) -> Result<String>;
     
-    /// 处理分片请求
-    async fn handle_segment(&self, url: &str, range: Option<String>) -> Result<Vec<u8>>;
-} 
\ No newline at end of file
+    /// 处理分片请求，分片与其他内容共用按范围缓存的存储，支持部分请求
+    async fn handle_segment(&self, req: &DataRequest) -> Result<Response<Body>>;
+} 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::link_codec::PercentCodec;
+
+    #[test]
+    fn test_normalize_playlist_fixtures() {
+        let fixtures: [&[u8]; 4] = [
+            include_bytes!("fixtures/bom_crlf.m3u8"),
+            include_bytes!("fixtures/utf16le.m3u8"),
+            include_bytes!("fixtures/whitespace.m3u8"),
+            include_bytes!("fixtures/latin1_title.m3u8"),
+        ];
+        let manager = HlsManager::new();
+        for data in fixtures {
+            let content = normalize_playlist(data);
+            assert!(content.starts_with("#EXTM3U\n"));
+            assert!(!content.contains('\r'));
+
+            let playlist = m3u8_rs::parse_playlist(content.as_bytes()).unwrap().1;
+            match playlist {
+                m3u8_rs::Playlist::MediaPlaylist(media) => {
+                    assert_eq!(media.segments.len(), 2);
+                    assert_eq!(media.segments[0].uri, "seg-0.ts");
+                    assert!(media.end_list);
+                }
+                _ => panic!("应解析为媒体播放列表"),
+            }
+
+            let rewritten = manager.rewrite_m3u8(&content, "http://example.com/vod", "/proxy", &PercentCodec, &RouteTable::default());
+            assert!(rewritten.contains("/proxy/http%3A%2F%2Fexample.com%2Fvod%2Fseg-0.ts\n"));
+        }
+    }
+
+    #[test]
+    fn test_progressive_m3u8() {
+        let manager = HlsManager::new();
+        let segment = |sequence: u64| Segment {
+            url: format!("{}.ts", sequence),
+            duration: 9.5,
+            sequence,
+            size: None,
+            cached: false,
+        };
+        let info = PlaylistInfo {
+            url: "http://example.com/a.m3u8".to_string(),
+            target_duration: 10.0,
+            media_sequence: 0,
+            is_endlist: true,
+            segments: vec![segment(0), segment(1)],
+            variants: vec![],
+            audio_renditions: vec![],
+            last_updated: chrono::Utc::now(),
+        };
+        let urls = vec!["/proxy/0".to_string(), "/proxy/1".to_string()];
+
+        let partial = manager.progressive_m3u8(&info, &urls, 1);
+        assert!(partial.contains("#EXTINF:9.500,\n/proxy/0\n"));
+        assert!(!partial.contains("/proxy/1"));
+        assert!(!partial.contains("#EXT-X-ENDLIST"));
+
+        let complete = manager.progressive_m3u8(&info, &urls, 2);
+        assert!(complete.contains("#EXT-X-TARGETDURATION:10\n"));
+        assert!(complete.ends_with("/proxy/1\n#EXT-X-ENDLIST\n"));
+    }
+
+    #[test]
+    fn test_audio_only_m3u8() {
+        let manager = HlsManager::new();
+        let variant = |url: &str, bandwidth: u64, codecs: &str| VariantStream {
+            url: url.to_string(),
+            bandwidth,
+            resolution: None,
+            codecs: Some(codecs.to_string()),
+            audio_group: Some("aac".to_string()),
+        };
+        let mut info = PlaylistInfo {
+            url: "http://example.com/master.m3u8".to_string(),
+            target_duration: 0.0,
+            media_sequence: 0,
+            is_endlist: false,
+            segments: vec![],
+            variants: vec![
+                variant("1080p.m3u8", 5_000_000, "avc1.640028,mp4a.40.2"),
+                variant("360p.m3u8", 800_000, "avc1.4d401e,mp4a.40.2"),
+            ],
+            audio_renditions: vec![],
+            last_updated: chrono::Utc::now(),
+        };
+        let proxy = |uri: &str| Some(format!("/proxy/{}", uri));
+
+        assert_eq!(manager.audio_only_m3u8(&info, proxy), None);
+
+        info.variants.push(variant("audio.m3u8", 128_000, "mp4a.40.2"));
+        assert_eq!(
+            manager.audio_only_m3u8(&info, proxy).unwrap(),
+            "#EXTM3U\n#EXT-X-STREAM-INF:BANDWIDTH=128000,CODECS=\"mp4a.40.2\"\n/proxy/audio.m3u8\n"
+        );
+
+        info.audio_renditions.push(AudioRendition {
+            url: "audio/en.m3u8".to_string(),
+            group_id: "aac".to_string(),
+            name: "English".to_string(),
+            language: Some("en".to_string()),
+            default: true,
+        });
+        assert!(manager
+            .audio_only_m3u8(&info, proxy)
+            .unwrap()
+            .ends_with("BANDWIDTH=128000,CODECS=\"mp4a.40.2\"\n/proxy/audio/en.m3u8\n"));
+    }
+
+    #[test]
+    fn test_upcoming_segments() {
+        let segments: Vec<Segment> = (0..10)
+            .map(|sequence| Segment {
+                url: format!("{}.ts", sequence),
+                duration: 10.0,
+                sequence,
+                size: None,
+                cached: false,
+            })
+            .collect();
+        assert_eq!(upcoming_segments(&segments, 0, 5.0, 30.0, 3), 1..4);
+        // 播放位置在当前请求的分片之后时从播放位置开始
+        assert_eq!(upcoming_segments(&segments, 0, 25.0, 30.0, 3), 2..5);
+        assert_eq!(upcoming_segments(&segments, 8, 85.0, 30.0, 3), 9..10);
+        assert_eq!(upcoming_segments(&segments, 9, 95.0, 30.0, 3), 10..10);
+    }
+}
@@ -0,0 +1,121 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use bytes::Bytes;
+use hyper::body::HttpBody;
+use hyper::{Body, Request, Response, Uri};
+use crate::request_handler::RequestHandler;
+use crate::server::error_response;
+use crate::utils::error::{ProxyError, Result};
+use crate::log_info;
+
+type RequestStream = h3::server::RequestStream<h3_quinn::BidiStream<Bytes>, Bytes>;
+
+/// HTTP/3 监听配置
+#[derive(Clone)]
+pub struct Http3Config {
+    pub port: u16,
+    /// PEM 格式证书链
+    pub cert_path: PathBuf,
+    /// PEM 格式 PKCS#8 私钥
+    pub key_path: PathBuf,
+}
+
+impl Http3Config {
+    pub fn new(port: u16, cert_path: impl Into<PathBuf>, key_path: impl Into<PathBuf>) -> Self {
+        Self {
+            port,
+            cert_path: cert_path.into(),
+            key_path: key_path.into(),
+        }
+    }
+}
+
+/// 启动 HTTP/3 (QUIC) 监听，与 TCP 监听共用同一个请求处理器
+pub async fn serve(config: Http3Config, handler: Arc<RequestHandler>) -> Result<()> {
+    let addr = SocketAddr::from(([127, 0, 0, 1], config.port));
+    let endpoint = quinn::Endpoint::server(server_config(&config)?, addr)?;
+    log_info!("Server", "HTTP/3 监听运行在 https://{}", addr);
+
+    while let Some(connecting) = endpoint.accept().await {
+        let handler = handler.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(connecting, handler).await {
+                log_info!("Server", "HTTP/3 连接结束: {}", e);
+            }
+        });
+    }
+
+    Ok(())
+}
+
+fn server_config(config: &Http3Config) -> Result<quinn::ServerConfig> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(&config.cert_path)?))?
+        .into_iter()
+        .map(rustls::Certificate)
+        .collect();
+    let key = rustls_pemfile::pkcs8_private_keys(&mut BufReader::new(File::open(&config.key_path)?))?
+        .into_iter()
+        .next()
+        .map(rustls::PrivateKey)
+        .ok_or_else(|| ProxyError::request(format!("未找到私钥: {:?}", config.key_path)))?;
+
+    let mut tls = rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| ProxyError::request(format!("证书配置无效: {}", e)))?;
+    tls.alpn_protocols = vec![b"h3".to_vec()];
+
+    Ok(quinn::ServerConfig::with_crypto(Arc::new(tls)))
+}
+
+async fn handle_connection(connecting: quinn::Connecting, handler: Arc<RequestHandler>) -> Result<()> {
+    let connection = connecting.await.map_err(|e| ProxyError::network(e.to_string()))?;
+    let mut h3_conn = h3::server::Connection::new(h3_quinn::Connection::new(connection))
+        .await
+        .map_err(|e| ProxyError::network(e.to_string()))?;
+
+    while let Some((req, stream)) = h3_conn.accept().await.map_err(|e| ProxyError::network(e.to_string()))? {
+        let handler = handler.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_request(req, stream, handler).await {
+                log_info!("Server", "HTTP/3 请求处理失败: {}", e);
+            }
+        });
+    }
+
+    Ok(())
+}
+
+async fn handle_request(req: Request<()>, mut stream: RequestStream, handler: Arc<RequestHandler>) -> Result<()> {
+    let (mut parts, ()) = req.into_parts();
+
+    // HTTP/3 请求携带完整地址，转为与 TCP 监听一致的路径形式
+    if let Some(authority) = parts.uri.authority().cloned() {
+        if let Ok(host) = authority.as_str().parse() {
+            parts.headers.entry(hyper::header::HOST).or_insert(host);
+        }
+    }
+    let path_and_query = parts.uri.path_and_query().map(|pq| pq.as_str()).unwrap_or("/");
+    parts.uri = path_and_query
+        .parse::<Uri>()
+        .map_err(|e| ProxyError::request(e.to_string()))?;
+
+    let response = match handler.handle_request(Request::from_parts(parts, Body::empty())).await {
+        Ok(response) => response,
+        Err(e) => error_response(&e),
+    };
+
+    let (parts, mut body) = response.into_parts();
+    let map_err = |e: h3::Error| ProxyError::network(e.to_string());
+    stream.send_response(Response::from_parts(parts, ())).await.map_err(map_err)?;
+    while let Some(chunk) = body.data().await {
+        stream.send_data(chunk?).await.map_err(map_err)?;
+    }
+    stream.finish().await.map_err(map_err)?;
+
+    Ok(())
+}
@@ -1,5 +1,9 @@
 extern crate lazy_static;
 
+pub mod admin;
+pub mod bench;
+pub mod cluster;
+pub mod config;
 pub mod data_source;
 pub mod handlers;
 pub mod storage;
@@ -7,17 +11,33 @@ pub mod utils;
 pub mod data_request;
 pub mod data_source_manager;
 pub mod server;
+pub mod simulate;
 pub mod hls;
+pub mod media;
 pub mod request_handler;
+pub mod route;
+pub mod rules;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
+#[cfg(feature = "http3")]
+pub mod http3;
 
 #[macro_export]
 macro_rules! log_info {
     ($tag:expr, $($arg:tt)*) => {
-        println!("[{} INFO {}] {}", 
-            chrono::Local::now().format("%H:%M:%S"),
-            $tag,
-            format!($($arg)*)
-        )
+        match $crate::utils::request_id::current() {
+            Some(request_id) => println!("[{} INFO {}] [{}] {}", 
+                chrono::Local::now().format("%H:%M:%S"),
+                $tag,
+                request_id,
+                format!($($arg)*)
+            ),
+            None => println!("[{} INFO {}] {}", 
+                chrono::Local::now().format("%H:%M:%S"),
+                $tag,
+                format!($($arg)*)
+            ),
+        }
     };
 }
 
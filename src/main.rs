@@ -1,11 +1,166 @@
+use proxy_server::bench::{self, BenchConfig, DiskBenchConfig};
+use proxy_server::config::ProxyConfig;
+use proxy_server::data_source_manager::DataSourceManager;
 use proxy_server::server::ProxyServer;
+use proxy_server::simulate;
+use proxy_server::storage::{migrate, CacheInspection, ShardLayout};
 use proxy_server::utils::error::ProxyError;
 use std::env;
+use std::path::PathBuf;
+use std::sync::Arc;
 
 #[tokio::main]
 async fn main() -> Result<(), ProxyError> {
     // 解析命令行参数
     let args: Vec<String> = env::args().collect();
+
+    // bench --url <url> [--target 地址] [--ranges 范围] [--concurrency N] [--requests N]：压测运行中的代理
+    if args.get(1).map(String::as_str) == Some("bench") {
+        let config = BenchConfig::from_args(&args[2..])?;
+        println!(
+            "压测 {}: {} 个请求, 并发 {}",
+            config.target, config.requests, config.concurrency
+        );
+        let report = bench::run(config).await;
+        println!("请求数:   {} (失败 {})", report.requests, report.errors);
+        println!("耗时:     {:.2} 秒", report.elapsed.as_secs_f64());
+        println!("吞吐量:   {:.2} MB/s", report.throughput() / (1024.0 * 1024.0));
+        println!(
+            "TTFB:     p50 {:?}  p90 {:?}  p99 {:?}",
+            report.ttfb_p50, report.ttfb_p90, report.ttfb_p99
+        );
+        println!(
+            "命中率:   {:.1}% (部分命中 {} 个)",
+            report.hit_ratio() * 100.0,
+            report.partial_hits
+        );
+        return Ok(());
+    }
+
+    // bench-disk [--dir 目录] [--size MB] [--chunk KB]：对比缓存写入方式和 fsync 策略的磁盘写入吞吐量
+    if args.get(1).map(String::as_str) == Some("bench-disk") {
+        let config = DiskBenchConfig::from_args(&args[2..])?;
+        println!(
+            "磁盘写入压测 {}: 每种方式 {} MB, 数据块 {} KB",
+            config.dir.display(),
+            config.size / (1024 * 1024),
+            config.chunk_size / 1024
+        );
+        for result in bench::run_disk(&config).await? {
+            println!(
+                "{}: {:.2} MB/s ({:.2} 秒)",
+                result.mode,
+                result.throughput() / (1024.0 * 1024.0),
+                result.elapsed.as_secs_f64()
+            );
+        }
+        return Ok(());
+    }
+
+    // simulate <回放文件> [缓存目录]：直接对缓存层回放访问日志，用于预热或离线评估淘汰策略
+    if args.get(1).map(String::as_str) == Some("simulate") {
+        let path = match args.get(2) {
+            Some(path) => path,
+            None => {
+                eprintln!("用法: proxy-server simulate <回放文件> [缓存目录]");
+                return Ok(());
+            }
+        };
+        let cache_dir = args.get(3).map(String::as_str).unwrap_or("cache");
+        let entries = simulate::parse_replay_log(&tokio::fs::read_to_string(path).await?);
+        let manager = DataSourceManager::new(PathBuf::from(cache_dir));
+        let report = simulate::replay(&manager, &entries).await;
+        println!("请求数:   {} (失败 {})", report.requests, report.errors);
+        println!("耗时:     {:.2} 秒", report.elapsed.as_secs_f64());
+        println!("读取:     {} 字节", report.bytes);
+        println!(
+            "命中 {} / 部分命中 {} / 未命中 {}, 命中率 {:.1}%",
+            report.hits,
+            report.partial_hits,
+            report.misses,
+            report.hit_ratio() * 100.0
+        );
+        return Ok(());
+    }
+
+    // inspect <url> [缓存目录]：查看单个 URL 的缓存情况
+    if args.get(1).map(String::as_str) == Some("inspect") {
+        let url = match args.get(2) {
+            Some(url) => url,
+            None => {
+                eprintln!("用法: proxy-server inspect <url> [缓存目录]");
+                return Ok(());
+            }
+        };
+        let cache_dir = args.get(3).map(String::as_str).unwrap_or("cache");
+        let manager = DataSourceManager::new(PathBuf::from(cache_dir));
+        match manager.inspect(url).await {
+            Some(inspection) => print_inspection(&inspection),
+            None => println!("未缓存: {}", url),
+        }
+        return Ok(());
+    }
+
+    // verify <缓存目录> [--repair]：校验缓存后退出
+    if args.get(1).map(String::as_str) == Some("verify") {
+        let cache_dir = args.get(2).map(String::as_str).unwrap_or("cache");
+        let repair = args.iter().any(|arg| arg == "--repair");
+        let manager = DataSourceManager::new(PathBuf::from(cache_dir));
+        let report = manager.verify(repair).await?;
+        println!(
+            "检查 {} 个条目, 正常 {} 个, 问题 {} 个",
+            report.checked,
+            report.healthy,
+            report.issues.len()
+        );
+        for issue in &report.issues {
+            let status = if issue.repaired { "已修复" } else { "未修复" };
+            println!("  [{}] {} - {}", status, issue.key, issue.problem);
+        }
+        return Ok(());
+    }
+    
+    // migrate <源目录> <目标目录> [--move] [--layout <层级>x<字符数>]：将旧版或其他位置的缓存迁移为指定布局，逐个校验哈希
+    if args.get(1).map(String::as_str) == Some("migrate") {
+        let (from, to) = match (args.get(2), args.get(3)) {
+            (Some(from), Some(to)) => (PathBuf::from(from), PathBuf::from(to)),
+            _ => {
+                eprintln!("用法: migrate <源目录> <目标目录> [--move] [--layout <层级>x<字符数>]");
+                return Ok(());
+            }
+        };
+        let move_files = args.iter().any(|arg| arg == "--move");
+        let layout = match args.iter().position(|arg| arg == "--layout").map(|i| args.get(i + 1)) {
+            None => ShardLayout::default(),
+            Some(value) => {
+                let parsed = value
+                    .and_then(|value| value.split_once('x'))
+                    .and_then(|(depth, width)| Some(ShardLayout::new(depth.parse().ok()?, width.parse().ok()?)))
+                    .filter(ShardLayout::is_valid);
+                match parsed {
+                    Some(layout) => layout,
+                    None => {
+                        eprintln!("无效的布局，格式为 <层级>x<字符数>，例如 2x2");
+                        return Ok(());
+                    }
+                }
+            }
+        };
+        let report = tokio::task::spawn_blocking(move || migrate::migrate(&from, &to, layout, move_files))
+            .await
+            .map_err(|e| ProxyError::storage(format!("迁移任务失败: {}", e)))??;
+        println!(
+            "迁移 {} 个条目 ({} 字节), 跳过 {} 个已存在的条目, 失败 {} 个",
+            report.migrated,
+            report.bytes,
+            report.skipped,
+            report.issues.len()
+        );
+        for issue in &report.issues {
+            println!("  {} - {}", issue.source.display(), issue.problem);
+        }
+        return Ok(());
+    }
     
     // 获取端口号，默认为 8080
     let port = if args.len() > 1 {
@@ -21,9 +176,100 @@ async fn main() -> Result<(), ProxyError> {
         "cache"
     };
 
+    // 获取监听地址，默认为 127.0.0.1，`::` 以双栈方式同时接受 IPv4 和 IPv6 连接
+    let mut config = ProxyConfig::default();
+    if let Some(addr) = args.get(3) {
+        match addr.trim_start_matches('[').trim_end_matches(']').parse() {
+            Ok(addr) => config.listen.addr = addr,
+            Err(_) => {
+                eprintln!("无效的监听地址: {}", addr);
+                return Ok(());
+            }
+        }
+    }
+
     // 启动服务器
-    let server = ProxyServer::new(port, cache_dir);
-    let _ = server.start().await;
+    let server = Arc::new(ProxyServer::with_config(port, cache_dir, config));
+    #[cfg(unix)]
+    spawn_signal_handlers(server.clone())?;
+    if let Err(e) = server.start().await {
+        eprintln!("启动失败: {}", e);
+    }
     
     Ok(())
 }
+
+/// SIGHUP 重新载入配置，SIGUSR1 将缓存统计和正在进行的下载输出到日志
+#[cfg(unix)]
+fn spawn_signal_handlers(server: Arc<ProxyServer>) -> Result<(), ProxyError> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = signal(SignalKind::hangup())?;
+    let mut user1 = signal(SignalKind::user_defined1())?;
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                Some(()) = hangup.recv() => server.reload().await,
+                Some(()) = user1.recv() => server.dump_stats().await,
+                else => break,
+            }
+        }
+    });
+    Ok(())
+}
+
+fn print_inspection(inspection: &CacheInspection) {
+    let format_ranges = |ranges: &[(u64, u64)]| {
+        if ranges.is_empty() {
+            return "无".to_string();
+        }
+        ranges
+            .iter()
+            .map(|(start, end)| format!("{}-{}", start, end))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    println!("URL:      {}", inspection.url);
+    println!("缓存键:   {}", inspection.key);
+    match inspection.total_size {
+        Some(total) => println!("总大小:   {} 字节", total),
+        None => println!("总大小:   未知"),
+    }
+    println!("已缓存:   {}", format_ranges(&inspection.ranges));
+    println!("缺失:     {}", format_ranges(&inspection.gaps));
+
+    for (label, info) in [("数据", &inspection.entry), ("MP4 尾部", &inspection.tail)] {
+        let info = match info {
+            Some(info) => info,
+            None => continue,
+        };
+        println!();
+        println!("[{}]", label);
+        if let Some(path) = &info.path {
+            println!("  路径:     {}", path.display());
+        }
+        println!("  字节数:   {}", info.cached_bytes);
+        println!("  磁盘占用: {}", info.allocated_bytes);
+        println!("  存储层:   {}", info.tier);
+        println!("  命中次数: {}", info.hits);
+        println!("  最后访问: {}", info.last_access);
+        println!("  最后写入: {}", info.last_write);
+        match info.ttl_secs {
+            Some(ttl) if ttl > 0 => println!("  剩余 TTL: {} 秒", ttl),
+            Some(_) => println!("  剩余 TTL: 已过期"),
+            None => println!("  剩余 TTL: 未指定"),
+        }
+        for (name, value) in &info.meta.headers {
+            if matches!(name.as_str(), "etag" | "last-modified" | "cache-control" | "expires") {
+                println!("  {}: {}", name, value);
+            }
+        }
+        if let Some(hash) = &info.meta.content_hash {
+            println!("  内容哈希: {}", hash);
+        }
+        if let Some(user) = &info.meta.user {
+            println!("  用户元数据: {}", user);
+        }
+    }
+}
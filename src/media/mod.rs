@@ -0,0 +1,120 @@
+pub mod mp4;
+pub mod segment;
+pub mod sniff;
+pub mod subtitle;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::StreamExt;
+use crate::handlers::{CacheHandler, NetworkHandler};
+use crate::utils::error::{ProxyError, Result};
+use crate::log_info;
+use mp4::RangeReader;
+
+pub use sniff::SniffConfig;
+pub use subtitle::SubtitleConfig;
+
+/// MP4 快速起播配置
+#[derive(Clone)]
+pub struct FastStartConfig {
+    /// 是否启用
+    pub enabled: bool,
+    /// 预取的文件头部大小
+    pub head_size: u64,
+    /// 尾部 moov 超过该大小时不预取
+    pub max_tail_size: u64,
+}
+
+impl Default for FastStartConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            head_size: 1024 * 1024,           // 1MB
+            max_tail_size: 64 * 1024 * 1024,  // 64MB
+        }
+    }
+}
+
+/// 通过上游范围请求读取数据
+struct UpstreamReader<'a> {
+    network_handler: &'a NetworkHandler,
+    url: &'a str,
+}
+
+#[async_trait]
+impl RangeReader for UpstreamReader<'_> {
+    async fn read_range(&self, start: u64, end: u64) -> Result<Bytes> {
+        self.network_handler.fetch_bytes(self.url, start, end).await
+    }
+}
+
+/// 检测 MP4 文件布局，预取头部和位于文件尾部的 moov，使后续起播和拖动无需等待上游
+///
+/// 尾部数据保存在单独的缓存键下，元数据中的 content_length 记录完整文件大小，
+/// 尾部起始位置为文件大小减去尾部缓存大小。
+///
+/// `fetch_head` 由触发预取的请求在开始写入前确定：请求不是从头开始且此前没有任何缓存数据。
+/// 请求本身正在写入同一个键，写入后的大小不能说明头部是否已缓存
+pub async fn prefetch_mp4(
+    cache_handler: &CacheHandler,
+    network_handler: &NetworkHandler,
+    url: &str,
+    key: &str,
+    fetch_head: bool,
+    total_size: u64,
+    config: &FastStartConfig,
+) -> Result<()> {
+    let reader = UpstreamReader { network_handler, url };
+    let layout = mp4::scan_layout(&reader, total_size).await?;
+    log_info!("Media", "MP4 布局: {} moov={:?} mdat={:?}", url, layout.moov, layout.mdat);
+
+    // 预留头部范围后再写入，其他下载正在写入重叠的范围时由它负责
+    if fetch_head {
+        let head_end = match (&layout.moov, layout.moov_at_end()) {
+            (Some(moov), false) => moov.end().max(config.head_size.saturating_sub(1)),
+            _ => config.head_size.saturating_sub(1),
+        };
+        let head_end = head_end.min(total_size - 1);
+        match cache_handler.try_begin_write(key, (0, head_end)) {
+            Some(_writing) => {
+                log_info!("Media", "预取 MP4 头部: {} 0-{}", url, head_end);
+                let data = reader.read_range(0, head_end).await?;
+                let stream = Box::pin(futures::stream::once(async move { Ok(data) }));
+                cache_handler.write_stream(key, (0, head_end), stream).await?;
+            }
+            None => log_info!("Media", "头部正在由其他下载写入，跳过预取: {}", url),
+        }
+    }
+
+    let moov = match (&layout.moov, layout.moov_at_end()) {
+        (Some(moov), true) => moov,
+        _ => return Ok(()),
+    };
+
+    let tail_key = mp4::tail_key(key);
+    let tail_size = total_size - moov.offset;
+    if tail_size > config.max_tail_size {
+        log_info!("Media", "尾部 moov 过大，跳过预取: {} {} 字节", url, tail_size);
+        return Ok(());
+    }
+    if matches!(cache_handler.get_meta(&tail_key).await, Some(meta) if meta.content_length.is_some()) {
+        return Ok(());
+    }
+
+    log_info!("Media", "moov 位于文件尾部，预取: {} {}-{}", url, moov.offset, total_size - 1);
+    let (resp, _, _) = network_handler.fetch(url, &format!("bytes={}-", moov.offset)).await?;
+    if resp.status() != hyper::StatusCode::PARTIAL_CONTENT {
+        return Err(ProxyError::network(format!("上游不支持范围请求: {}", resp.status())));
+    }
+    let headers = resp.headers().clone();
+    let stream = Box::pin(resp.into_body().map(|result| {
+        result.map_err(|e| ProxyError::network(e.to_string()))
+    }));
+    cache_handler.write_stream(&tail_key, (0, tail_size - 1), stream).await?;
+
+    // 尾部写入完整后才记录文件大小，之后才会用于响应
+    if cache_handler.get_size(&tail_key).await? == Some(tail_size) {
+        cache_handler.save_headers(&tail_key, &headers, Some(total_size)).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,155 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use crate::utils::error::{ProxyError, Result};
+
+/// 盒子头最大长度：4 字节大小 + 4 字节类型 + 8 字节扩展大小
+const BOX_HEADER_SIZE: u64 = 16;
+
+/// 最多扫描的顶层盒子数量，防止异常文件导致大量请求
+const MAX_TOP_LEVEL_BOXES: usize = 64;
+
+/// 按范围读取数据，`end` 为闭区间
+#[async_trait]
+pub trait RangeReader {
+    async fn read_range(&self, start: u64, end: u64) -> Result<Bytes>;
+}
+
+/// MP4 顶层盒子头
+#[derive(Debug, Clone, PartialEq)]
+pub struct BoxHeader {
+    pub box_type: [u8; 4],
+    pub offset: u64,
+    pub size: u64,
+}
+
+impl BoxHeader {
+    /// 盒子最后一个字节的位置
+    pub fn end(&self) -> u64 {
+        self.offset + self.size - 1
+    }
+}
+
+/// 顶层 moov 和 mdat 的位置
+#[derive(Debug, Clone, Default)]
+pub struct Mp4Layout {
+    pub moov: Option<BoxHeader>,
+    pub mdat: Option<BoxHeader>,
+}
+
+impl Mp4Layout {
+    /// moov 是否位于 mdat 之后，此时播放器需要先读取文件尾部才能开始播放
+    pub fn moov_at_end(&self) -> bool {
+        matches!((&self.moov, &self.mdat), (Some(moov), Some(mdat)) if moov.offset > mdat.offset)
+    }
+}
+
+/// 解析位于 `offset` 的盒子头，`file_size` 用于处理大小为 0（延伸到文件末尾）的盒子
+pub fn parse_box_header(data: &[u8], offset: u64, file_size: u64) -> Option<BoxHeader> {
+    if data.len() < 8 {
+        return None;
+    }
+
+    let size = u32::from_be_bytes(data[0..4].try_into().ok()?) as u64;
+    let box_type: [u8; 4] = data[4..8].try_into().ok()?;
+
+    let size = match size {
+        0 => file_size.checked_sub(offset)?,
+        1 => u64::from_be_bytes(data.get(8..16)?.try_into().ok()?),
+        size => size,
+    };
+    if size < 8 {
+        return None;
+    }
+
+    Some(BoxHeader { box_type, offset, size })
+}
+
+/// 通过范围请求逐个读取顶层盒子头，定位 moov 和 mdat
+pub async fn scan_layout<R: RangeReader + Sync>(reader: &R, file_size: u64) -> Result<Mp4Layout> {
+    let mut layout = Mp4Layout::default();
+    let mut offset = 0u64;
+
+    for _ in 0..MAX_TOP_LEVEL_BOXES {
+        if offset + 8 > file_size {
+            break;
+        }
+
+        let end = (offset + BOX_HEADER_SIZE).min(file_size) - 1;
+        let data = reader.read_range(offset, end).await?;
+        let header = parse_box_header(&data, offset, file_size)
+            .ok_or_else(|| ProxyError::parse(format!("无效的 MP4 盒子头，位置: {}", offset)))?;
+
+        offset = offset.saturating_add(header.size);
+        match &header.box_type {
+            b"moov" => layout.moov = Some(header),
+            b"mdat" => layout.mdat = Some(header),
+            _ => {}
+        }
+
+        if layout.moov.is_some() && layout.mdat.is_some() {
+            break;
+        }
+    }
+
+    Ok(layout)
+}
+
+/// 保存文件尾部数据的缓存键
+pub fn tail_key(key: &str) -> String {
+    format!("{}#mp4-tail", key)
+}
+
+/// 判断 URL 是否为 MP4 文件
+pub fn is_mp4(url: &str) -> bool {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    path.to_ascii_lowercase().ends_with(".mp4")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn box_bytes(size: u32, box_type: &[u8; 4]) -> Vec<u8> {
+        let mut data = size.to_be_bytes().to_vec();
+        data.extend_from_slice(box_type);
+        data
+    }
+
+    #[test]
+    fn test_parse_box_header() {
+        let header = parse_box_header(&box_bytes(32, b"ftyp"), 0, 1000).unwrap();
+        assert_eq!(&header.box_type, b"ftyp");
+        assert_eq!(header.end(), 31);
+
+        // 大小为 0 延伸到文件末尾
+        let header = parse_box_header(&box_bytes(0, b"mdat"), 100, 1000).unwrap();
+        assert_eq!(header.size, 900);
+
+        // 64 位扩展大小
+        let mut data = box_bytes(1, b"mdat");
+        data.extend_from_slice(&(5_000_000_000u64).to_be_bytes());
+        let header = parse_box_header(&data, 0, 6_000_000_000).unwrap();
+        assert_eq!(header.size, 5_000_000_000);
+
+        assert!(parse_box_header(&box_bytes(4, b"free"), 0, 1000).is_none());
+    }
+
+    #[test]
+    fn test_moov_at_end() {
+        let mut layout = Mp4Layout {
+            moov: Some(BoxHeader { box_type: *b"moov", offset: 900, size: 100 }),
+            mdat: Some(BoxHeader { box_type: *b"mdat", offset: 32, size: 868 }),
+        };
+        assert!(layout.moov_at_end());
+
+        layout.moov.as_mut().unwrap().offset = 32;
+        layout.mdat.as_mut().unwrap().offset = 132;
+        assert!(!layout.moov_at_end());
+    }
+
+    #[test]
+    fn test_is_mp4() {
+        assert!(is_mp4("http://example.com/video.MP4?token=1"));
+        assert!(!is_mp4("http://example.com/index.m3u8"));
+    }
+}
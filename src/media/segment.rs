@@ -0,0 +1,99 @@
+use std::fmt;
+
+/// MPEG-TS 包长度
+const TS_PACKET_LEN: usize = 188;
+
+/// 校验需要读取的分片头部长度，覆盖若干个 TS 包
+pub const CHECK_LEN: u64 = (TS_PACKET_LEN * 8) as u64;
+
+/// fMP4 分片可以出现在开头的 box
+const FMP4_LEADING_BOXES: [&[u8; 4]; 7] = [b"styp", b"ftyp", b"moof", b"sidx", b"emsg", b"prft", b"free"];
+
+/// 需要校验的 HLS 分片扩展名
+pub fn is_segment(url: &str) -> bool {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    path.ends_with(".ts") || path.ends_with(".m4s")
+}
+
+/// 分片损坏的原因
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SegmentError {
+    Empty,
+    /// 指定偏移处的 TS 包缺少同步字节
+    TsSync(usize),
+    /// TS 分片长度不是包长度的整数倍，通常是下载被截断
+    TsTruncated(u64),
+    /// fMP4 box 头无效
+    BadBox,
+    /// 既不是 TS 也不是 fMP4
+    UnknownFormat,
+}
+
+impl fmt::Display for SegmentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SegmentError::Empty => write!(f, "分片为空"),
+            SegmentError::TsSync(offset) => write!(f, "偏移 {} 处缺少 TS 同步字节", offset),
+            SegmentError::TsTruncated(size) => write!(f, "TS 分片长度 {} 不是 188 的整数倍", size),
+            SegmentError::BadBox => write!(f, "无效的 fMP4 box 头"),
+            SegmentError::UnknownFormat => write!(f, "无法识别的分片格式"),
+        }
+    }
+}
+
+/// 按分片头部和总大小检查分片是否完整：TS 每个包以 0x47 开头，fMP4 以合法的 box 头开头。
+/// 以 ID3 标签开头的打包音频不做检查
+pub fn check_segment(head: &[u8], size: u64) -> Result<(), SegmentError> {
+    if size == 0 || head.is_empty() {
+        return Err(SegmentError::Empty);
+    }
+    if head.starts_with(b"ID3") {
+        return Ok(());
+    }
+    if head[0] == 0x47 {
+        if let Some(offset) = (0..head.len()).step_by(TS_PACKET_LEN).find(|&offset| head[offset] != 0x47) {
+            return Err(SegmentError::TsSync(offset));
+        }
+        if !size.is_multiple_of(TS_PACKET_LEN as u64) {
+            return Err(SegmentError::TsTruncated(size));
+        }
+        return Ok(());
+    }
+    if head.len() >= 8 && FMP4_LEADING_BOXES.iter().any(|name| &head[4..8] == *name) {
+        // 1 表示使用 64 位长度，0 表示延伸到文件末尾
+        let box_size = u32::from_be_bytes([head[0], head[1], head[2], head[3]]) as u64;
+        return match box_size {
+            0 | 1 => Ok(()),
+            box_size if box_size >= 8 && box_size <= size => Ok(()),
+            _ => Err(SegmentError::BadBox),
+        };
+    }
+    Err(SegmentError::UnknownFormat)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_segment() {
+        let mut ts = vec![0u8; TS_PACKET_LEN * 3];
+        for offset in (0..ts.len()).step_by(TS_PACKET_LEN) {
+            ts[offset] = 0x47;
+        }
+        assert_eq!(check_segment(&ts, ts.len() as u64), Ok(()));
+        assert_eq!(check_segment(&ts, ts.len() as u64 - 10), Err(SegmentError::TsTruncated(ts.len() as u64 - 10)));
+        ts[TS_PACKET_LEN * 2] = 0;
+        assert_eq!(check_segment(&ts, ts.len() as u64), Err(SegmentError::TsSync(TS_PACKET_LEN * 2)));
+
+        let mut fmp4 = vec![0, 0, 0, 24];
+        fmp4.extend_from_slice(b"styp");
+        fmp4.resize(64, 0);
+        assert_eq!(check_segment(&fmp4, 64), Ok(()));
+        assert_eq!(check_segment(&fmp4, 16), Err(SegmentError::BadBox));
+
+        assert_eq!(check_segment(b"<html>", 6), Err(SegmentError::UnknownFormat));
+        assert_eq!(check_segment(&[], 0), Err(SegmentError::Empty));
+        assert!(is_segment("https://example.com/seg-1.ts?token=1"));
+    }
+}
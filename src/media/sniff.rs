@@ -0,0 +1,103 @@
+use hyper::header::HeaderValue;
+
+/// 检测内容类型需要读取的字节数，MPEG-TS 需要至少两个 188 字节的包
+pub const SNIFF_LEN: u64 = 512;
+
+/// 按文件头识别内容类型的配置
+#[derive(Clone)]
+pub struct SniffConfig {
+    pub enabled: bool,
+    /// 上游声明为这些类型（或没有声明）时按文件头修正
+    pub untrusted_types: Vec<String>,
+}
+
+impl Default for SniffConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            untrusted_types: [
+                "application/octet-stream",
+                "binary/octet-stream",
+                "application/x-download",
+                "application/force-download",
+                "text/plain",
+                "text/html",
+            ]
+            .iter()
+            .map(|content_type| content_type.to_string())
+            .collect(),
+        }
+    }
+}
+
+impl SniffConfig {
+    /// 上游声明的类型是否需要检测
+    pub fn should_sniff(&self, declared: Option<&HeaderValue>) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        let declared = match declared.and_then(|value| value.to_str().ok()) {
+            Some(declared) => declared,
+            None => return true,
+        };
+        let mime = declared.split(';').next().unwrap_or("").trim();
+        mime.is_empty() || self.untrusted_types.iter().any(|untrusted| untrusted.eq_ignore_ascii_case(mime))
+    }
+}
+
+/// 按文件头识别 MP4、MPEG-TS、Matroska/WebM、WebVTT 和 m3u8
+pub fn sniff(data: &[u8]) -> Option<&'static str> {
+    if data.len() >= 12 && &data[4..8] == b"ftyp" {
+        return Some(match &data[8..12] {
+            b"qt  " => "video/quicktime",
+            b"M4A " | b"M4B " => "audio/mp4",
+            _ => "video/mp4",
+        });
+    }
+    if data.starts_with(&[0x1a, 0x45, 0xdf, 0xa3]) {
+        let header = &data[..data.len().min(64)];
+        return Some(if header.windows(4).any(|window| window == b"webm") {
+            "video/webm"
+        } else {
+            "video/x-matroska"
+        });
+    }
+    if data.len() >= 189 && data[0] == 0x47 && data[188] == 0x47 {
+        return Some("video/mp2t");
+    }
+
+    let text = data.strip_prefix(b"\xef\xbb\xbf").unwrap_or(data);
+    if text.starts_with(b"WEBVTT") {
+        return Some("text/vtt");
+    }
+    if text.starts_with(b"#EXTM3U") {
+        return Some("application/vnd.apple.mpegurl");
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sniff() {
+        assert_eq!(sniff(b"\x00\x00\x00\x20ftypisom\x00\x00\x02\x00"), Some("video/mp4"));
+        assert_eq!(sniff(b"\x1a\x45\xdf\xa3\x9f\x42\x82\x84webm"), Some("video/webm"));
+        assert_eq!(sniff(b"\xef\xbb\xbfWEBVTT\n\n00:00.000 --> 00:01.000"), Some("text/vtt"));
+
+        let mut ts = vec![0u8; 376];
+        ts[0] = 0x47;
+        ts[188] = 0x47;
+        assert_eq!(sniff(&ts), Some("video/mp2t"));
+        assert_eq!(sniff(b"<html></html>"), None);
+    }
+
+    #[test]
+    fn test_should_sniff() {
+        let config = SniffConfig::default();
+        assert!(config.should_sniff(None));
+        assert!(config.should_sniff(Some(&HeaderValue::from_static("application/octet-stream; charset=binary"))));
+        assert!(!config.should_sniff(Some(&HeaderValue::from_static("video/mp4"))));
+    }
+}
@@ -0,0 +1,163 @@
+use bytes::BytesMut;
+use futures::StreamExt;
+use crate::handlers::{CacheHandler, NetworkHandler};
+use crate::utils::error::{ProxyError, Result};
+use crate::log_info;
+
+/// 外挂字幕的访问路径前缀：`/subtitles/<扩展名>/<编码后的视频 URL>`
+pub const SUBTITLE_PREFIX: &str = "/subtitles/";
+
+/// 外挂字幕配置
+#[derive(Clone)]
+pub struct SubtitleConfig {
+    /// 请求视频时是否在后台探测并缓存同名字幕文件
+    pub enabled: bool,
+    /// 探测的字幕扩展名
+    pub extensions: Vec<String>,
+    /// 触发探测的视频扩展名
+    pub video_extensions: Vec<String>,
+    /// 字幕文件大小上限，超过时不缓存
+    pub max_size: u64,
+}
+
+impl Default for SubtitleConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            extensions: vec!["vtt".to_string(), "srt".to_string()],
+            video_extensions: vec!["mp4".to_string(), "mkv".to_string()],
+            max_size: 4 * 1024 * 1024,  // 4MB
+        }
+    }
+}
+
+impl SubtitleConfig {
+    pub fn is_video(&self, url: &str) -> bool {
+        extension(url).is_some_and(|ext| self.video_extensions.iter().any(|v| v.eq_ignore_ascii_case(ext)))
+    }
+
+    pub fn is_subtitle_extension(&self, ext: &str) -> bool {
+        self.extensions.iter().any(|e| e.eq_ignore_ascii_case(ext))
+    }
+}
+
+/// URL 路径最后一段的扩展名
+fn extension(url: &str) -> Option<&str> {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    let name = path.rsplit('/').next()?;
+    name.rsplit_once('.').map(|(_, ext)| ext).filter(|ext| !ext.is_empty())
+}
+
+/// 替换视频 URL 的扩展名得到同名字幕地址，查询参数保持不变
+pub fn sidecar_url(video_url: &str, ext: &str) -> Option<String> {
+    let split = video_url.find(['?', '#']).unwrap_or(video_url.len());
+    let (path, rest) = video_url.split_at(split);
+    let dot = path.rfind('.')?;
+    if path.rfind('/').is_some_and(|slash| slash > dot) {
+        return None;
+    }
+    Some(format!("{}.{}{}", &path[..dot], ext, rest))
+}
+
+pub fn content_type(ext: &str) -> &'static str {
+    match ext.to_ascii_lowercase().as_str() {
+        "vtt" => "text/vtt; charset=utf-8",
+        "srt" => "application/x-subrip; charset=utf-8",
+        _ => "text/plain; charset=utf-8",
+    }
+}
+
+/// 解析 `/subtitles/<扩展名>/<编码后的视频 URL>`，返回扩展名和视频 URL
+pub fn parse_subtitle_path(path: &str) -> Option<(String, String)> {
+    let (ext, encoded) = path.strip_prefix(SUBTITLE_PREFIX)?.split_once('/')?;
+    if ext.is_empty() || !ext.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return None;
+    }
+    let url = urlencoding::decode(encoded).ok()?.into_owned();
+    Some((ext.to_string(), url))
+}
+
+/// 依次探测字幕地址并写入缓存，`candidates` 为字幕 URL 和对应的缓存键。
+/// 已缓存的跳过，上游不存在或超过大小上限的忽略
+pub async fn probe_subtitles(
+    cache_handler: &CacheHandler,
+    network_handler: &NetworkHandler,
+    candidates: Vec<(String, String)>,
+    config: &SubtitleConfig,
+) {
+    for (url, key) in candidates {
+        if matches!(cache_handler.get_meta(&key).await, Some(meta) if meta.content_length.is_some()) {
+            continue;
+        }
+        match fetch_subtitle(cache_handler, network_handler, &url, &key, config.max_size).await {
+            Ok(Some(size)) => log_info!("Media", "已缓存字幕: {} {} 字节", url, size),
+            Ok(None) => {}
+            Err(e) => log_info!("Media", "字幕探测失败: {} - {}", url, e),
+        }
+    }
+}
+
+async fn fetch_subtitle(
+    cache_handler: &CacheHandler,
+    network_handler: &NetworkHandler,
+    url: &str,
+    key: &str,
+    max_size: u64,
+) -> Result<Option<u64>> {
+    let (resp, _, _) = match network_handler.fetch(url, "bytes=0-").await {
+        Ok(fetched) => fetched,
+        // 大部分视频没有字幕文件，上游返回错误状态码时不记录日志
+        Err(e) if e.upstream_status().is_some() => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    if !resp.status().is_success() {
+        return Ok(None);
+    }
+
+    let headers = resp.headers().clone();
+    let mut body = resp.into_body();
+    let mut data = BytesMut::new();
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk.map_err(|e| ProxyError::network(e.to_string()))?;
+        if (data.len() + chunk.len()) as u64 > max_size {
+            log_info!("Media", "字幕文件超过 {} 字节，不缓存: {}", max_size, url);
+            return Ok(None);
+        }
+        data.extend_from_slice(&chunk);
+    }
+    if data.is_empty() {
+        return Ok(None);
+    }
+
+    let size = data.len() as u64;
+    let data = data.freeze();
+    let stream = Box::pin(futures::stream::once(async move { Ok(data) }));
+    cache_handler.write_stream(key, (0, size - 1), stream).await?;
+    cache_handler.save_headers(key, &headers, Some(size)).await?;
+    cache_handler.complete(key, size).await?;
+    Ok(Some(size))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sidecar_url() {
+        assert_eq!(
+            sidecar_url("https://example.com/v/movie.mp4?token=1", "vtt").as_deref(),
+            Some("https://example.com/v/movie.vtt?token=1")
+        );
+        assert_eq!(sidecar_url("https://example.com/v.d/movie", "srt"), None);
+
+        let config = SubtitleConfig::default();
+        assert!(config.is_video("https://example.com/movie.MKV"));
+        assert!(!config.is_video("https://example.com/movie.m3u8"));
+
+        assert_eq!(
+            parse_subtitle_path("/subtitles/vtt/https%3A%2F%2Fexample.com%2Fmovie.mp4"),
+            Some(("vtt".to_string(), "https://example.com/movie.mp4".to_string()))
+        );
+        assert_eq!(parse_subtitle_path("/subtitles/../x"), None);
+    }
+}
@@ -100,7 +100,7 @@ impl MixedSourceHandler {
                         Some(Err(e)) => {
                             log_info!("Cache", "网络数据读取错误: {}", e);
                             state.network_stream = None;
-                            return Some((Err(ProxyError::Cache(e.to_string())), state));
+                            return Some((Err(ProxyError::cache(e.to_string())), state));
                         }
                         None => {
                             log_info!("Cache", "网络数据发送完毕");
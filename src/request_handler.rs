@@ -1,43 +1,479 @@
-use crate::data_request::DataRequest;
+use crate::data_request::{is_listen_addr, DataRequest, RequestType, RequestValidation};
 use crate::data_source_manager::DataSourceManager;
-use crate::hls::{DefaultHlsHandler, HlsHandler};
-use crate::utils::error::Result;
-use hyper::{Body, Request, Response};
+use crate::admin::{AdminHandler, Prefetcher};
+use crate::config::ProxyConfig;
+use crate::handlers::{CorsConfig, ResponseBuilder};
+use crate::handlers::checksum::{accepts_trailers, with_checksum_trailers};
+use crate::handlers::compression::compress_response;
+use crate::handlers::hooks::{parse_meta_path, META_PREFIX};
+use crate::handlers::session::{content_group, SessionTracker, SESSION_ID_HEADER};
+use crate::handlers::tunnel::{passthrough, tunnel, PassthroughConfig, TunnelConfig};
+use crate::hls::{DefaultHlsHandler, HlsHandler, AUDIO_PREFIX, PROGRESSIVE_PREFIX};
+use crate::media::subtitle::{parse_subtitle_path, SUBTITLE_PREFIX};
+use crate::route::RouteTable;
+use crate::rules::{RuleOutcome, RuleSet};
+use crate::utils::canonical::canonicalize;
+use crate::utils::error::{ErrorKind, ProxyError, Result};
+use crate::utils::request_id::{self, RequestContext, REQUEST_ID_HEADER};
+use crate::log_info;
+use hyper::header::{HeaderValue, CACHE_CONTROL, CONTENT_TYPE};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
 
 pub struct RequestHandler {
     source_manager: Arc<DataSourceManager>,
     hls_handler: Arc<DefaultHlsHandler>,
+    routes: Arc<RouteTable>,
+    rules: Arc<RuleSet>,
+    validation: RequestValidation,
+    method_passthrough: bool,
+    passthrough: PassthroughConfig,
+    tunnel: TunnelConfig,
+    checksum_trailers: bool,
+    cors: CorsConfig,
+    response_builder: ResponseBuilder,
+    sessions: Arc<SessionTracker>,
+    admin: AdminHandler,
 }
 
 impl RequestHandler {
     pub fn new(source_manager: Arc<DataSourceManager>, hls_handler: Arc<DefaultHlsHandler>) -> Self {
+        Self::with_config(source_manager, hls_handler, &ProxyConfig::default())
+    }
+
+    pub fn with_config(
+        source_manager: Arc<DataSourceManager>,
+        hls_handler: Arc<DefaultHlsHandler>,
+        config: &ProxyConfig,
+    ) -> Self {
+        let prefetcher = Arc::new(Prefetcher::new(source_manager.clone(), hls_handler.clone()));
+        let sessions = Arc::new(SessionTracker::new(config.sessions.clone()));
+        
         Self {
+            admin: AdminHandler::new(source_manager.clone(), prefetcher)
+                .with_sessions(sessions.clone())
+                .with_config(config.admin.clone())
+                .with_peer_secret(config.cluster.peer_secret.clone()),
             source_manager,
             hls_handler,
+            routes: Arc::new(config.routes.clone()),
+            rules: Arc::new(config.rules.clone()),
+            validation: config.validation.clone(),
+            method_passthrough: config.method_passthrough,
+            passthrough: config.passthrough.clone(),
+            tunnel: config.tunnel.clone(),
+            checksum_trailers: config.headers.checksum_trailers,
+            cors: config.cors.clone(),
+            response_builder: ResponseBuilder::new(),
+            sessions,
         }
     }
     
     pub async fn handle_request(&self, req: Request<Body>) -> Result<Response<Body>> {
-        let data_request = DataRequest::new(&req)?;
+        // 沿用客户端传入的请求 ID，没有时生成新的
+        let id = req
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| !value.is_empty() && value.len() <= 64)
+            .map(|value| value.to_string())
+            .unwrap_or_else(request_id::generate);
+
+        let context = RequestContext {
+            id: id.clone(),
+            client: req.extensions().get::<SocketAddr>().map(|addr| addr.to_string()),
+        };
+        let result = request_id::scope(context, async {
+            let result = self.handle(req).await;
+            if let Err(e) = &result {
+                log_info!("Request", "请求处理失败: {}", e);
+            }
+            result
+        })
+        .await;
+
+        let mut response = result?;
+        if let Ok(value) = HeaderValue::from_str(&id) {
+            response.headers_mut().insert(REQUEST_ID_HEADER, value);
+        }
+        Ok(response)
+    }
+    
+    async fn handle(&self, req: Request<Body>) -> Result<Response<Body>> {
+        // 跨域预检请求直接应答
+        if self.cors.is_preflight(req.method(), req.headers()) {
+            let headers = self.cors.response_headers(req.headers(), true).unwrap_or_default();
+            return Ok(self.response_builder.build_preflight_response(headers));
+        }
+        
+        let cors_headers = self.cors.response_headers(req.headers(), false);
+        let mut response = self.dispatch(req).await?;
+        if let Some(cors_headers) = cors_headers {
+            response.headers_mut().extend(cors_headers);
+        }
+        Ok(response)
+    }
+    
+    pub fn admin(&self) -> &AdminHandler {
+        &self.admin
+    }
+    
+    pub fn source_manager(&self) -> &Arc<DataSourceManager> {
+        &self.source_manager
+    }
+    
+    /// 未开启隧道时返回 405；目标地址依次经过入口校验、自身地址检测、白名单和规则，全部通过后才建立连接
+    async fn connect(&self, req: Request<Body>) -> Result<Response<Body>> {
+        if !self.tunnel.enabled {
+            log_info!("Tunnel", "未开启 CONNECT 隧道: {}", req.uri());
+            return Ok(self.response_builder.build_method_not_allowed_response());
+        }
+        let (host, port) = match req.uri().authority() {
+            Some(authority) => (authority.host().to_string(), authority.port_u16().unwrap_or(443)),
+            None => return Ok(bad_request(&ProxyError::request(format!("CONNECT 请求缺少目标地址: {}", req.uri())))),
+        };
+
+        let url = format!("https://{}:{}/", host, port);
+        if let Err(e) = self.validation.validate(&url) {
+            log_info!("Tunnel", "隧道目标校验失败: {} {}", req.uri(), e.message());
+            return Ok(bad_request(&e));
+        }
+        if is_listen_addr(&req, &host, port) {
+            log_info!("Tunnel", "隧道目标指向代理自身: {}", req.uri());
+            return Ok(bad_request(&ProxyError::request(format!("目标地址指向代理自身: {}", req.uri()))));
+        }
+        if !self.tunnel.allows(&host, port) {
+            log_info!("Tunnel", "隧道目标不在白名单中: {}", req.uri());
+            return Ok(forbidden());
+        }
+        if self.rules.evaluate(&url).deny {
+            log_info!("Rules", "隧道被规则拒绝: {}", req.uri());
+            return Ok(RuleOutcome::deny_response());
+        }
+
+        tunnel(req).await
+    }
+    
+    /// 附属入口解码出的目标 URL 与代理入口一样经过校验、自身地址检测和规则，返回规范化后的 URL；
+    /// 不通过时返回应答给客户端的响应
+    fn check_target(&self, req: &Request<Body>, url: String) -> std::result::Result<String, Box<Response<Body>>> {
+        let url = match self.validation.check(req, url) {
+            Ok(url) => canonicalize(&url),
+            Err(e) => {
+                log_info!("Request", "请求校验失败: {} {}", req.uri(), e.message());
+                return Err(Box::new(bad_request(&e)));
+            }
+        };
+        let outcome = self.rules.evaluate(&url);
+        if outcome.deny {
+            log_info!("Rules", "请求被规则拒绝: {}", url);
+            return Err(Box::new(RuleOutcome::deny_response()));
+        }
+        match outcome.rewritten {
+            Some(rewritten) => match self.validation.check(req, rewritten) {
+                Ok(rewritten) => Ok(canonicalize(&rewritten)),
+                Err(e) => {
+                    log_info!("Rules", "改写后的 URL 校验失败: {} {}", url, e.message());
+                    Err(Box::new(bad_request(&e)))
+                }
+            },
+            None => Ok(url),
+        }
+    }
+    
+    async fn dispatch(&self, req: Request<Body>) -> Result<Response<Body>> {
+        // CONNECT 请求作为普通正向代理隧道转发
+        if req.method() == Method::CONNECT {
+            return self.connect(req).await;
+        }
+        
+        if AdminHandler::is_admin_request(&req) {
+            return self.admin.handle(req).await;
+        }
+        
+        let client = req.extensions().get::<SocketAddr>().map(|addr| addr.ip());
+        
+        // 代理入口只提供读取，其他方法不再当作 GET 处理；开启透传时转发到目标地址
+        let forward_method = !matches!(*req.method(), Method::GET | Method::HEAD);
+        match *req.method() {
+            Method::GET | Method::HEAD => {}
+            _ if self.method_passthrough => {}
+            Method::OPTIONS => return Ok(self.response_builder.build_options_response()),
+            _ => {
+                log_info!("Request", "不支持的请求方法: {} {}", req.method(), req.uri());
+                return Ok(self.response_builder.build_method_not_allowed_response());
+            }
+        }
+        
+        // 完成回调生成的附属条目
+        if req.uri().host().is_none() && req.uri().path().starts_with(META_PREFIX) {
+            let response = match parse_meta_path(req.uri().path()) {
+                Some((name, url)) => {
+                    let url = match self.check_target(&req, url) {
+                        Ok(url) => url,
+                        Err(response) => return Ok(*response),
+                    };
+                    self.source_manager.serve_aux(&name, &url, req.headers()).await?
+                }
+                None => None,
+            };
+            return Ok(response.unwrap_or_else(not_found));
+        }
+        
+        // 视频的同名外挂字幕
+        if req.uri().host().is_none() && req.uri().path().starts_with(SUBTITLE_PREFIX) {
+            let response = match parse_subtitle_path(req.uri().path()) {
+                Some((ext, url)) => {
+                    let url = match self.check_target(&req, url) {
+                        Ok(url) => url,
+                        Err(response) => return Ok(*response),
+                    };
+                    self.source_manager.serve_subtitle(&url, &ext).await?
+                }
+                None => None,
+            };
+            return Ok(response.unwrap_or_else(not_found));
+        }
+        
+        // 只包含已缓存分片的播放列表，录制过程中可在其他设备上播放
+        if req.uri().host().is_none() && req.uri().path().starts_with(PROGRESSIVE_PREFIX) {
+            let encoded = &req.uri().path()[PROGRESSIVE_PREFIX.len()..];
+            let url = urlencoding::decode(encoded)
+                .map_err(|e| ProxyError::request(format!("URL 解码失败: {}", e)))?
+                .into_owned();
+            let url = match self.check_target(&req, url) {
+                Ok(url) => url,
+                Err(response) => return Ok(*response),
+            };
+            let playlist = self.hls_handler.progressive_playlist(&url).await?;
+            return Ok(playlist_response(playlist));
+        }
+        
+        // 主播放列表中只保留音频流
+        if req.uri().host().is_none() && req.uri().path().starts_with(AUDIO_PREFIX) {
+            let encoded = &req.uri().path()[AUDIO_PREFIX.len()..];
+            let url = urlencoding::decode(encoded)
+                .map_err(|e| ProxyError::request(format!("URL 解码失败: {}", e)))?
+                .into_owned();
+            let url = match self.check_target(&req, url) {
+                Ok(url) => url,
+                Err(response) => return Ok(*response),
+            };
+            return Ok(match self.hls_handler.audio_playlist(&url).await? {
+                Some(playlist) => playlist_response(playlist),
+                None => not_found(),
+            });
+        }
+        
+        let mut data_request = match DataRequest::with_validation(&req, &self.routes, &self.validation) {
+            Ok(data_request) => data_request,
+            Err(e) if e.kind() == ErrorKind::Request => {
+                log_info!("Request", "请求校验失败: {} {}", req.uri(), e.message());
+                return Ok(bad_request(&e));
+            }
+            Err(e) => return Err(e),
+        };
         
-        match data_request.get_type() {
+        let outcome = self.rules.evaluate(data_request.get_url());
+        if outcome.deny {
+            log_info!("Rules", "请求被规则拒绝: {}", data_request.get_url());
+            return Ok(RuleOutcome::deny_response());
+        }
+        // 改写后的地址重新校验，规则不能把请求改写到代理自身或不允许的协议
+        if let Some(url) = outcome.rewritten.clone() {
+            match self.validation.check(&req, url) {
+                Ok(url) => data_request = data_request.with_url(canonicalize(&url)),
+                Err(e) => {
+                    log_info!("Rules", "改写后的 URL 校验失败: {} {}", data_request.get_url(), e.message());
+                    return Ok(bad_request(&e));
+                }
+            }
+        }
+        
+        // 配置开启透传的 WebSocket、事件流以及 POST 等其他方法直接透传，不进入缓存流程；规则指定跳过缓存的请求同样直接转发
+        if outcome.bypass || forward_method || self.passthrough.matches(req.headers()) {
+            let url = data_request.get_url().to_string();
+            let mut response = passthrough(req, &url).await?;
+            outcome.apply(&mut response);
+            return Ok(response);
+        }
+        
+        // HEAD 请求不下载内容；播放列表需要重写后才知道长度，仍按 GET 生成，响应体由 hyper 丢弃
+        if req.method() == Method::HEAD && !matches!(data_request.get_type(), RequestType::M3u8) {
+            let mut response = self.source_manager.head(&data_request).await?;
+            outcome.apply(&mut response);
+            return Ok(response);
+        }
+        
+        // 下载入口返回原始内容，不处理播放列表也不压缩，保证范围请求和 ETag 对应原始字节
+        if data_request.is_download() {
+            let mut response = self.source_manager.download(&data_request).await?;
+            outcome.apply(&mut response);
+            let response = self.track_session(response, client, &data_request);
+            return Ok(self.with_trailers(response, &data_request));
+        }
+        
+        let mut response = match data_request.get_type() {
             crate::data_request::RequestType::M3u8 => {
                 // 处理 m3u8 请求
                 let content = self.hls_handler.handle_m3u8(data_request.get_url()).await?;
-                Ok(Response::new(Body::from(content)))
+                let response = Response::new(Body::from(content));
+                compress_response(response, data_request.get_headers(), data_request.get_url(), self.source_manager.stats()).await?
             }
             crate::data_request::RequestType::Segment => {
-                // 处理分片请求
-                let data = self.hls_handler
-                    .handle_segment(data_request.get_url(), Some(data_request.get_range().to_string()))
-                    .await?;
-                Ok(Response::new(Body::from(data)))
+                // 处理分片请求，分片是已压缩的媒体数据，不再压缩
+                self.hls_handler.handle_segment(&data_request).await?
             }
             _ => {
                 // 处理普通请求
-                self.source_manager.process_request(&data_request).await
+                let response = self.source_manager.process_request(&data_request).await?;
+                compress_response(response, data_request.get_headers(), data_request.get_url(), self.source_manager.stats()).await?
             }
+        };
+        outcome.apply(&mut response);
+        let response = self.track_session(response, client, &data_request);
+        Ok(self.with_trailers(response, &data_request))
+    }
+    
+    /// 将请求计入所属的播放会话，响应中返回会话 ID
+    fn track_session(&self, response: Response<Body>, client: Option<IpAddr>, data_request: &DataRequest) -> Response<Body> {
+        let session_id = data_request
+            .get_headers()
+            .get(SESSION_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| !value.is_empty() && value.len() <= 64);
+        let url = data_request.get_url();
+        let hls = matches!(data_request.get_type(), RequestType::M3u8 | RequestType::Segment);
+        let (mut parts, body) = response.into_parts();
+        let (body, id) = self.sessions.track(client, session_id, content_group(url, hls), url, body);
+        if let Some(value) = id.and_then(|id| HeaderValue::from_str(&id).ok()) {
+            parts.headers.insert(SESSION_ID_HEADER, value);
         }
+        Response::from_parts(parts, body)
+    }
+    
+    /// 按配置和客户端的 TE 头附加校验和尾部字段
+    fn with_trailers(&self, response: Response<Body>, data_request: &DataRequest) -> Response<Body> {
+        if self.checksum_trailers && accepts_trailers(data_request.get_headers()) {
+            with_checksum_trailers(response)
+        } else {
+            response
+        }
+    }
+}
+
+/// 动态生成的播放列表，播放器每次都需要重新获取
+fn playlist_response(playlist: String) -> Response<Body> {
+    let mut response = Response::new(Body::from(playlist));
+    response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("application/vnd.apple.mpegurl"));
+    response.headers_mut().insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+    response
+}
+
+fn not_found() -> Response<Body> {
+    let mut response = Response::new(Body::from("Not Found"));
+    *response.status_mut() = StatusCode::NOT_FOUND;
+    response
+}
+
+fn forbidden() -> Response<Body> {
+    let mut response = Response::new(Body::from("Forbidden"));
+    *response.status_mut() = StatusCode::FORBIDDEN;
+    response
+}
+
+/// 校验失败的请求返回 400 和 JSON 格式的错误信息
+fn bad_request(err: &ProxyError) -> Response<Body> {
+    let body = serde_json::json!({ "error": "invalid_request", "message": err.message() });
+    let mut response = Response::new(Body::from(body.to_string()));
+    *response.status_mut() = StatusCode::BAD_REQUEST;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::{Rule, RuleAction};
+
+    fn handler(name: &str, config: ProxyConfig) -> (RequestHandler, std::path::PathBuf) {
+        let dir = std::env::temp_dir().join(format!("request-handler-{}-{}", name, std::process::id()));
+        let source_manager = Arc::new(DataSourceManager::with_config(dir.clone(), config.clone()));
+        let hls_handler = Arc::new(DefaultHlsHandler::new(source_manager.clone()));
+        (RequestHandler::with_config(source_manager, hls_handler, &config), dir)
+    }
+
+    fn get(uri: &str) -> Request<Body> {
+        Request::builder().uri(uri).body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_rewrite_revalidated() {
+        let rules = RuleSet::new(vec![Rule::new()
+            .with_host("example.com")
+            .then(RuleAction::Rewrite { from: "https://".to_string(), to: "ftp://".to_string() })]);
+        let (handler, dir) = handler("rewrite", ProxyConfig { rules, ..ProxyConfig::default() });
+
+        let response = handler.dispatch(get("/proxy/https%3A%2F%2Fexample.com%2Fa.mp4")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_meta_target_checked() {
+        let rules = RuleSet::new(vec![Rule::new().with_path_prefix("/private/").then(RuleAction::Deny)]);
+        let (handler, dir) = handler("meta", ProxyConfig { rules, ..ProxyConfig::default() });
+
+        let response = handler.dispatch(get("/meta/thumb/https%3A%2F%2Fexample.com%2Fprivate%2Fa.mp4")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = handler.dispatch(get("/meta/thumb/ftp%3A%2F%2Fexample.com%2Fa.mp4")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = handler.dispatch(get("/meta/thumb/https%3A%2F%2Fexample.com%2Fa.mp4")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_progressive_target_checked() {
+        let (handler, dir) = handler("progressive", ProxyConfig::default());
+        let mut req = get(&format!("{}http%3A%2F%2F127.0.0.1%3A8080%2Fadmin%2Fcleanup", PROGRESSIVE_PREFIX));
+        req.extensions_mut().insert(crate::data_request::LocalAddr("127.0.0.1:8080".parse().unwrap()));
+
+        let response = handler.dispatch(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_subtitle_target_checked() {
+        let rules = RuleSet::new(vec![Rule::new().with_host("blocked.example.com").then(RuleAction::Deny)]);
+        let (handler, dir) = handler("subtitle", ProxyConfig { rules, ..ProxyConfig::default() });
+
+        let subtitle = |url: &str| get(&format!("{}srt/{}", SUBTITLE_PREFIX, urlencoding::encode(url)));
+        let response = handler.dispatch(subtitle("https://blocked.example.com/a.mp4")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = handler.dispatch(subtitle("file:///etc/passwd")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_audio_target_checked() {
+        let rules = RuleSet::new(vec![Rule::new().with_extension("m3u8").then(RuleAction::Deny)]);
+        let (handler, dir) = handler("audio", ProxyConfig { rules, ..ProxyConfig::default() });
+
+        let response = handler.dispatch(get(&format!("{}https%3A%2F%2Fexample.com%2Fmaster.m3u8", AUDIO_PREFIX))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = handler.dispatch(get(&format!("{}http%3A%2F%2Fexample.com%2Fproxy%2Fhttp%3A%2F%2Fa.com", AUDIO_PREFIX))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let _ = std::fs::remove_dir_all(&dir);
     }
 }
@@ -0,0 +1,80 @@
+/// 反向代理路由映射，例如 `/media/` -> `https://cdn.example.com/`
+#[derive(Debug, Clone)]
+pub struct RouteMapping {
+    /// 本地路径前缀
+    pub path_prefix: String,
+    /// 上游 URL 前缀
+    pub upstream_prefix: String,
+}
+
+impl RouteMapping {
+    pub fn new(path_prefix: impl Into<String>, upstream_prefix: impl Into<String>) -> Self {
+        Self {
+            path_prefix: path_prefix.into(),
+            upstream_prefix: upstream_prefix.into(),
+        }
+    }
+}
+
+/// 路由表，客户端使用稳定的本地路径访问，不暴露源站地址
+#[derive(Debug, Clone, Default)]
+pub struct RouteTable {
+    pub routes: Vec<RouteMapping>,
+}
+
+impl RouteTable {
+    pub fn new(routes: Vec<RouteMapping>) -> Self {
+        Self { routes }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+
+    /// 将本地路径（含查询参数）映射为上游 URL，匹配最长前缀
+    pub fn resolve(&self, path_and_query: &str) -> Option<String> {
+        self.routes
+            .iter()
+            .filter(|route| path_and_query.starts_with(&route.path_prefix))
+            .max_by_key(|route| route.path_prefix.len())
+            .map(|route| format!("{}{}", route.upstream_prefix, &path_and_query[route.path_prefix.len()..]))
+    }
+
+    /// 将上游 URL 映射回本地路径，用于重写播放列表
+    pub fn reverse(&self, url: &str) -> Option<String> {
+        self.routes
+            .iter()
+            .filter(|route| url.starts_with(&route.upstream_prefix))
+            .max_by_key(|route| route.upstream_prefix.len())
+            .map(|route| format!("{}{}", route.path_prefix, &url[route.upstream_prefix.len()..]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_and_reverse() {
+        let routes = RouteTable::new(vec![
+            RouteMapping::new("/media/", "https://cdn.example.com/"),
+            RouteMapping::new("/media/live/", "https://live.example.com/hls/"),
+        ]);
+
+        assert_eq!(
+            routes.resolve("/media/movies/a.mp4?t=1").as_deref(),
+            Some("https://cdn.example.com/movies/a.mp4?t=1")
+        );
+        assert_eq!(
+            routes.resolve("/media/live/index.m3u8").as_deref(),
+            Some("https://live.example.com/hls/index.m3u8")
+        );
+        assert_eq!(routes.resolve("/proxy/abc"), None);
+
+        assert_eq!(
+            routes.reverse("https://live.example.com/hls/seg-1.ts").as_deref(),
+            Some("/media/live/seg-1.ts")
+        );
+        assert_eq!(routes.reverse("https://other.com/a.ts"), None);
+    }
+}
@@ -0,0 +1,193 @@
+use std::time::Duration;
+use hyper::header::{HeaderName, HeaderValue, CACHE_CONTROL};
+use hyper::{Body, Response, StatusCode};
+use url::Url;
+
+/// 规则命中后执行的动作
+#[derive(Debug, Clone)]
+pub enum RuleAction {
+    /// 将 URL 中第一次出现的 `from` 替换为 `to`
+    Rewrite { from: String, to: String },
+    /// 设置响应头，同名时覆盖
+    SetHeader(String, String),
+    /// 删除响应头
+    RemoveHeader(String),
+    /// 不经过缓存，直接转发到上游
+    Bypass,
+    /// 强制响应的缓存有效期（`Cache-Control: max-age`）
+    Ttl(Duration),
+    /// 拒绝请求
+    Deny,
+}
+
+/// 单条规则，所有已设置的条件都满足时命中。`host` 以 `*.` 开头时匹配所有子域名，扩展名不区分大小写
+#[derive(Debug, Clone, Default)]
+pub struct Rule {
+    pub host: Option<String>,
+    pub path_prefix: Option<String>,
+    pub extensions: Vec<String>,
+    pub actions: Vec<RuleAction>,
+}
+
+impl Rule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_host(mut self, host: impl Into<String>) -> Self {
+        self.host = Some(host.into());
+        self
+    }
+
+    pub fn with_path_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.path_prefix = Some(prefix.into());
+        self
+    }
+
+    pub fn with_extension(mut self, extension: impl Into<String>) -> Self {
+        self.extensions.push(extension.into().trim_start_matches('.').to_ascii_lowercase());
+        self
+    }
+
+    pub fn then(mut self, action: RuleAction) -> Self {
+        self.actions.push(action);
+        self
+    }
+
+    fn matches(&self, url: &Url) -> bool {
+        let host = url.host_str().unwrap_or("");
+        let host_matches = match self.host.as_deref() {
+            None => true,
+            Some(pattern) => match pattern.strip_prefix("*.") {
+                Some(domain) => {
+                    host.eq_ignore_ascii_case(domain)
+                        || host.to_ascii_lowercase().ends_with(&format!(".{}", domain.to_ascii_lowercase()))
+                }
+                None => host.eq_ignore_ascii_case(pattern),
+            },
+        };
+        let path = url.path();
+        let path_matches = self.path_prefix.as_deref().is_none_or(|prefix| path.starts_with(prefix));
+        let extension_matches = self.extensions.is_empty() || {
+            let extension = path.rsplit('/').next().and_then(|name| name.rsplit_once('.')).map(|(_, ext)| ext.to_ascii_lowercase());
+            extension.is_some_and(|extension| self.extensions.contains(&extension))
+        };
+        host_matches && path_matches && extension_matches
+    }
+}
+
+/// 规则求值结果
+#[derive(Debug, Clone, Default)]
+pub struct RuleOutcome {
+    /// 改写后的 URL，没有改写时为空
+    pub rewritten: Option<String>,
+    pub bypass: bool,
+    pub deny: bool,
+    pub ttl: Option<Duration>,
+    pub set_headers: Vec<(String, String)>,
+    pub remove_headers: Vec<String>,
+}
+
+impl RuleOutcome {
+    /// 将响应头相关的动作应用到响应
+    pub fn apply(&self, response: &mut Response<Body>) {
+        let headers = response.headers_mut();
+        for name in &self.remove_headers {
+            headers.remove(name.as_str());
+        }
+        for (name, value) in &self.set_headers {
+            if let (Ok(name), Ok(value)) = (HeaderName::from_bytes(name.as_bytes()), HeaderValue::from_str(value)) {
+                headers.insert(name, value);
+            }
+        }
+        if let Some(ttl) = self.ttl {
+            if let Ok(value) = HeaderValue::from_str(&format!("public, max-age={}", ttl.as_secs())) {
+                headers.insert(CACHE_CONTROL, value);
+            }
+        }
+    }
+
+    /// 被拒绝的请求返回 403
+    pub fn deny_response() -> Response<Body> {
+        let mut response = Response::new(Body::from("Forbidden by rule"));
+        *response.status_mut() = StatusCode::FORBIDDEN;
+        response
+    }
+}
+
+/// 按顺序求值的规则列表，在请求分发前执行，所有命中的规则依次生效，改写后的 URL 参与后续规则的匹配
+#[derive(Debug, Clone, Default)]
+pub struct RuleSet {
+    pub rules: Vec<Rule>,
+}
+
+impl RuleSet {
+    pub fn new(rules: Vec<Rule>) -> Self {
+        Self { rules }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn evaluate(&self, url: &str) -> RuleOutcome {
+        let mut outcome = RuleOutcome::default();
+        let mut current = url.to_string();
+        for rule in &self.rules {
+            let parsed = match Url::parse(&current) {
+                Ok(parsed) => parsed,
+                Err(_) => break,
+            };
+            if !rule.matches(&parsed) {
+                continue;
+            }
+            for action in &rule.actions {
+                match action {
+                    RuleAction::Rewrite { from, to } => {
+                        if current.contains(from.as_str()) {
+                            current = current.replacen(from.as_str(), to, 1);
+                        }
+                    }
+                    RuleAction::SetHeader(name, value) => outcome.set_headers.push((name.clone(), value.clone())),
+                    RuleAction::RemoveHeader(name) => outcome.remove_headers.push(name.clone()),
+                    RuleAction::Bypass => outcome.bypass = true,
+                    RuleAction::Ttl(ttl) => outcome.ttl = Some(*ttl),
+                    RuleAction::Deny => {
+                        outcome.deny = true;
+                        return outcome;
+                    }
+                }
+            }
+        }
+        if current != url {
+            outcome.rewritten = Some(current);
+        }
+        outcome
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evaluate() {
+        let rules = RuleSet::new(vec![
+            Rule::new()
+                .with_host("*.example.com")
+                .then(RuleAction::Rewrite { from: "http://".to_string(), to: "https://".to_string() }),
+            Rule::new().with_extension("m3u8").then(RuleAction::Bypass).then(RuleAction::Ttl(Duration::from_secs(2))),
+            Rule::new().with_path_prefix("/private/").then(RuleAction::Deny),
+        ]);
+
+        let outcome = rules.evaluate("http://cdn.example.com/live/index.M3U8");
+        assert_eq!(outcome.rewritten.as_deref(), Some("https://cdn.example.com/live/index.M3U8"));
+        assert!(outcome.bypass);
+        assert_eq!(outcome.ttl, Some(Duration::from_secs(2)));
+
+        let outcome = rules.evaluate("http://other.com/video.mp4");
+        assert!(outcome.rewritten.is_none() && !outcome.bypass && !outcome.deny);
+
+        assert!(rules.evaluate("http://other.com/private/a.mp4").deny);
+    }
+}
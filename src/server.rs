@@ -1,76 +1,493 @@
+use crate::config::ProxyConfig;
+use crate::data_request::LocalAddr;
 use crate::data_source_manager::DataSourceManager;
 use crate::hls::DefaultHlsHandler;
 use crate::request_handler::RequestHandler;
-use crate::utils::error::Result;
-use hyper::service::{make_service_fn, service_fn};
-use hyper::Server;
+use crate::utils::error::{ErrorKind, ProxyError, Result};
+use futures::StreamExt;
+use hyper::body::HttpBody;
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
 use std::convert::Infallible;
-use std::net::SocketAddr;
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::path::PathBuf;
+use std::pin::Pin;
 use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpSocket};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::{timeout, Sleep};
 use crate::log_info;
 
+/// 服务器并发限制
+#[derive(Clone)]
+pub struct LimitConfig {
+    /// 同时保持的客户端连接数上限
+    pub max_connections: usize,
+    /// 同时处理的请求数上限
+    pub max_concurrent_requests: usize,
+    /// 请求排队等待处理的最长时间
+    pub queue_timeout: Duration,
+    /// 单个范围请求最多返回的字节数，超出时截断为该长度的 206 响应，客户端按 Content-Range 继续请求。
+    /// 只限制带 Range 头的请求，暂停缓存直接转发时同样限制，None 表示不限制
+    pub max_range_span: Option<u64>,
+}
+
+impl Default for LimitConfig {
+    fn default() -> Self {
+        Self {
+            max_connections: 1024,
+            max_concurrent_requests: 256,
+            queue_timeout: Duration::from_secs(10),
+            max_range_span: None,
+        }
+    }
+}
+
+/// 监听地址配置
+#[derive(Clone)]
+pub struct ListenConfig {
+    /// 监听的地址，`::` 监听所有 IPv6 地址
+    pub addr: IpAddr,
+    /// 监听 IPv6 地址时是否同时接受 IPv4 连接（双栈）
+    pub dual_stack: bool,
+    /// 等待接受的连接队列长度
+    pub backlog: u32,
+    /// 优先使用 systemd 套接字激活传入的监听套接字（`LISTEN_FDS`）
+    pub socket_activation: bool,
+}
+
+impl Default for ListenConfig {
+    fn default() -> Self {
+        Self {
+            addr: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            dual_stack: true,
+            backlog: 1024,
+            socket_activation: true,
+        }
+    }
+}
+
+impl ListenConfig {
+    /// 绑定监听端口
+    pub fn bind(&self, port: u16) -> io::Result<TcpListener> {
+        let addr = SocketAddr::new(self.addr, port);
+        let socket = if addr.is_ipv6() {
+            let socket = TcpSocket::new_v6()?;
+            set_only_v6(&socket, !self.dual_stack)?;
+            socket
+        } else {
+            TcpSocket::new_v4()?
+        };
+        #[cfg(unix)]
+        socket.set_reuseaddr(true)?;
+        socket.bind(addr)?;
+        socket.listen(self.backlog)
+    }
+}
+
+/// systemd 传入的第一个文件描述符
+#[cfg(unix)]
+const SD_LISTEN_FDS_START: i32 = 3;
+
+/// 取出 systemd 套接字激活传入的监听套接字，`LISTEN_PID` 不是当前进程或没有传入时返回 None。
+/// 取出后清除相关环境变量，避免子进程重复使用
+#[cfg(unix)]
+pub fn systemd_listener() -> Option<std::net::TcpListener> {
+    use std::os::unix::io::FromRawFd;
+
+    let pid: u32 = std::env::var("LISTEN_PID").ok()?.parse().ok()?;
+    if pid != std::process::id() {
+        return None;
+    }
+    let fds: i32 = std::env::var("LISTEN_FDS").ok()?.parse().ok()?;
+    std::env::remove_var("LISTEN_PID");
+    std::env::remove_var("LISTEN_FDS");
+    std::env::remove_var("LISTEN_FDNAMES");
+    if fds < 1 {
+        return None;
+    }
+    if fds > 1 {
+        log_info!("Server", "systemd 传入 {} 个套接字，只使用第一个", fds);
+    }
+    // 子进程不应继承监听套接字
+    unsafe {
+        libc::fcntl(SD_LISTEN_FDS_START, libc::F_SETFD, libc::FD_CLOEXEC);
+    }
+    Some(unsafe { std::net::TcpListener::from_raw_fd(SD_LISTEN_FDS_START) })
+}
+
+#[cfg(not(unix))]
+pub fn systemd_listener() -> Option<std::net::TcpListener> {
+    None
+}
+
+/// 设置 IPV6_V6ONLY，系统默认值不一定允许双栈，需要显式设置
+#[cfg(unix)]
+fn set_only_v6(socket: &TcpSocket, only_v6: bool) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let value: libc::c_int = only_v6 as libc::c_int;
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_IPV6,
+            libc::IPV6_V6ONLY,
+            &value as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_only_v6(_socket: &TcpSocket, _only_v6: bool) -> io::Result<()> {
+    Ok(())
+}
+
+/// 客户端连接的超时和保活配置
+#[derive(Clone)]
+pub struct ConnectionConfig {
+    /// 读取请求头的超时，也限制保活连接等待下一个请求的时间，None 表示不限制
+    pub header_read_timeout: Option<Duration>,
+    /// 响应写入停滞的超时：客户端停止读取超过该时间时断开连接，
+    /// 只在写入阻塞时计时，上游数据慢的直播流不受影响
+    pub write_idle_timeout: Option<Duration>,
+    /// 是否启用 HTTP/1 保活
+    pub keep_alive: bool,
+    /// HTTP/2 保活 PING 间隔，None 表示不发送
+    pub http2_keep_alive_interval: Option<Duration>,
+    /// HTTP/2 保活 PING 的响应超时
+    pub http2_keep_alive_timeout: Duration,
+    /// 单个响应发送缓冲的高水位（字节）：边下载边转发时客户端读取跟不上，缓冲达到该值后暂停读取上游并计为慢客户端
+    pub send_buffer_high: usize,
+    /// 发送缓冲的低水位（字节），客户端读取到该值以下后继续转发
+    pub send_buffer_low: usize,
+}
+
+impl Default for ConnectionConfig {
+    fn default() -> Self {
+        Self {
+            header_read_timeout: Some(Duration::from_secs(30)),
+            write_idle_timeout: Some(Duration::from_secs(60)),
+            keep_alive: true,
+            http2_keep_alive_interval: None,
+            http2_keep_alive_timeout: Duration::from_secs(20),
+            send_buffer_high: 4 * 1024 * 1024,  // 4MB
+            send_buffer_low: 1024 * 1024,       // 1MB
+        }
+    }
+}
+
+impl ConnectionConfig {
+    fn http(&self) -> Http {
+        let mut http = Http::new();
+        http.http1_keep_alive(self.keep_alive)
+            .http2_keep_alive_interval(self.http2_keep_alive_interval)
+            .http2_keep_alive_timeout(self.http2_keep_alive_timeout);
+        if let Some(header_read_timeout) = self.header_read_timeout {
+            http.http1_header_read_timeout(header_read_timeout);
+        }
+        http
+    }
+}
+
 pub struct ProxyServer {
     port: u16,
     handler: Arc<RequestHandler>,
+    listen: ListenConfig,
+    limits: LimitConfig,
+    connection: ConnectionConfig,
+    warmup_file: Option<PathBuf>,
+    /// 外部传入的已绑定监听套接字，启动时优先使用
+    listener: std::sync::Mutex<Option<std::net::TcpListener>>,
 }
 
 impl ProxyServer {
     pub fn new(port: u16, cache_dir: &str) -> Self {
+        Self::with_config(port, cache_dir, ProxyConfig::default())
+    }
+
+    pub fn with_config(port: u16, cache_dir: &str, config: ProxyConfig) -> Self {
         let cache_dir = PathBuf::from(cache_dir);
         
         // 创建数据源管理器
-        let source_manager = Arc::new(DataSourceManager::new(cache_dir.clone()));
+        let source_manager = Arc::new(DataSourceManager::with_config(cache_dir.clone(), config.clone()));
         
         // 创建 HLS 处理器
-        let hls_handler = Arc::new(DefaultHlsHandler::new(cache_dir.clone(), source_manager.clone()));
+        let routes = Arc::new(config.routes.clone());
+        let hls_handler = Arc::new(
+            DefaultHlsHandler::with_routes(source_manager.clone(), routes)
+                .with_proxy_prefix(&config.validation.proxy_prefix)
+                .with_link_codec(config.validation.link_codec.clone()),
+        );
         
         // 创建请求处理器
-        let handler = Arc::new(RequestHandler::new(source_manager, hls_handler));
+        let handler = Arc::new(RequestHandler::with_config(source_manager, hls_handler, &config));
         
         Self {
             port,
             handler,
+            listen: config.listen,
+            limits: config.limits,
+            connection: config.connection,
+            warmup_file: config.warmup_file,
+            listener: std::sync::Mutex::new(None),
         }
     }
     
+    /// 使用已绑定的监听套接字，例如由上一个进程传递过来的套接字，重启时不会断开监听
+    pub fn with_listener(mut self, listener: std::net::TcpListener) -> Self {
+        self.listener = std::sync::Mutex::new(Some(listener));
+        self
+    }
+    
+    /// 启动服务。监听套接字依次取传入的套接字、systemd 套接字激活传入的套接字，都没有时按配置绑定端口
     pub async fn start(&self) -> Result<()> {
-        let addr = SocketAddr::from(([127, 0, 0, 1], self.port));
+        let inherited = self.listener.lock().unwrap().take().or_else(|| {
+            if self.listen.socket_activation {
+                systemd_listener()
+            } else {
+                None
+            }
+        });
+        let listener = match inherited {
+            Some(listener) => {
+                log_info!("Server", "使用已绑定的监听套接字");
+                listener.set_nonblocking(true)?;
+                TcpListener::from_std(listener)?
+            }
+            None => self.listen.bind(self.port)?,
+        };
+        self.serve(listener).await
+    }
+    
+    /// 重新载入可在运行时更新的配置：重新读取预热文件并预取其中的 URL。
+    /// 其他配置在创建服务器时传入，日志直接写到标准输出，没有需要重新打开的日志文件
+    pub async fn reload(&self) {
+        log_info!("Server", "重新载入配置");
+        if let Some(path) = &self.warmup_file {
+            if let Err(e) = self.handler.admin().prefetcher().warmup_from_file(path).await {
+                log_info!("Server", "读取预热文件失败: {:?} - {}", path, e);
+            }
+        }
+    }
+    
+    /// 将缓存统计和正在进行的下载输出到日志
+    pub async fn dump_stats(&self) {
+        self.handler.source_manager().log_snapshot().await;
+    }
+    
+    /// 在已绑定的监听器上提供服务，可用于监听临时端口
+    pub async fn serve(&self, listener: TcpListener) -> Result<()> {
+        let local = listener.local_addr()?;
+        log_info!("Server", "代理服务器正在运行在 http://{}", local);
+        
+        if let Some(path) = &self.warmup_file {
+            if let Err(e) = self.handler.admin().prefetcher().warmup_from_file(path).await {
+                log_info!("Server", "读取预热文件失败: {:?} - {}", path, e);
+            }
+        }
+        
+        // 连接数达到上限时暂停 accept，新连接在内核队列中等待
+        let connections = Arc::new(Semaphore::new(self.limits.max_connections.max(1)));
+        let requests = Arc::new(Semaphore::new(self.limits.max_concurrent_requests.max(1)));
+        let queue_timeout = self.limits.queue_timeout;
+        let http = self.connection.http();
+        let write_idle_timeout = self.connection.write_idle_timeout;
         
-        let handler = self.handler.clone();
-        let make_svc = make_service_fn(move |_conn| {
-            let handler = handler.clone();
-            async move {
-                Ok::<_, Infallible>(service_fn(move |req| {
+        loop {
+            let permit = connections.clone().acquire_owned().await?;
+            let (stream, remote) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    // 文件描述符耗尽等错误，稍后重试
+                    log_info!("Server", "接受连接失败: {}", e);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            };
+            
+            let handler = self.handler.clone();
+            let requests = requests.clone();
+            let http = http.clone();
+            let stream = WriteIdleTimeout::new(stream, write_idle_timeout);
+            tokio::spawn(async move {
+                let service = service_fn(move |mut req: hyper::Request<hyper::Body>| {
+                    // 记录客户端地址，供日志和下载追踪使用
+                    req.extensions_mut().insert(remote);
+                    // 记录监听地址，用于识别指回代理自身的 URL
+                    req.extensions_mut().insert(LocalAddr(local));
                     let handler = handler.clone();
+                    let requests = requests.clone();
                     async move {
-                        match handler.handle_request(req).await {
-                            Ok(response) => Ok::<_, Infallible>(response),
-                            Err(e) => {
-                                let error_message = format!("Error: {}", e);
-                                Ok(hyper::Response::builder()
-                                    .status(500)
-                                    .body(hyper::Body::from(error_message))
-                                    .unwrap())
+                        // 排队超时返回 503，避免请求无限堆积
+                        let permit = match timeout(queue_timeout, requests.acquire_owned()).await {
+                            Ok(Ok(permit)) => permit,
+                            _ => {
+                                log_info!("Server", "请求排队超时，返回 503: {}", req.uri());
+                                return Ok::<_, Infallible>(hyper::Response::builder()
+                                    .status(503)
+                                    .header(hyper::header::RETRY_AFTER, queue_timeout.as_secs().max(1))
+                                    .body(hyper::Body::from("Server busy"))
+                                    .unwrap());
                             }
-                        }
+                        };
+                        
+                        let response = match handler.handle_request(req).await {
+                            Ok(response) => response,
+                            Err(e) => error_response(&e),
+                        };
+                        Ok::<_, Infallible>(hold_permit(response, permit))
                     }
-                }))
+                });
+                
+                if let Err(e) = http.serve_connection(stream, service).with_upgrades().await {
+                    log_info!("Server", "连接处理错误: {}", e);
+                }
+                drop(permit);
+            });
+        }
+    }
+}
+
+/// 请求名额随响应体释放，响应体发送完或客户端断开前一直计入并发请求数。
+/// 空响应体和协议升级的响应在返回响应头时释放
+fn hold_permit(response: hyper::Response<hyper::Body>, permit: OwnedSemaphorePermit) -> hyper::Response<hyper::Body> {
+    if response.body().is_end_stream() || response.status() == hyper::StatusCode::SWITCHING_PROTOCOLS {
+        return response;
+    }
+    response.map(|body| {
+        hyper::Body::wrap_stream(body.map(move |chunk| {
+            let _ = &permit;
+            chunk
+        }))
+    })
+}
+
+/// 写入阻塞超过指定时间时返回超时错误，释放不再读取数据的客户端连接
+struct WriteIdleTimeout<S> {
+    inner: S,
+    timeout: Option<Duration>,
+    deadline: Option<Pin<Box<Sleep>>>,
+}
+
+impl<S> WriteIdleTimeout<S> {
+    fn new(inner: S, timeout: Option<Duration>) -> Self {
+        Self {
+            inner,
+            timeout,
+            deadline: None,
+        }
+    }
+
+    /// 写入仍未就绪时检查是否超时，写入有进展时重新计时
+    fn poll_stalled<T>(&mut self, cx: &mut Context<'_>, poll: Poll<io::Result<T>>) -> Poll<io::Result<T>> {
+        if poll.is_ready() {
+            self.deadline = None;
+            return poll;
+        }
+        if let Some(timeout) = self.timeout {
+            let deadline = self.deadline.get_or_insert_with(|| Box::pin(tokio::time::sleep(timeout)));
+            if deadline.as_mut().poll(cx).is_ready() {
+                log_info!("Server", "客户端 {} 秒未读取数据，断开连接", timeout.as_secs());
+                return Poll::Ready(Err(io::Error::new(io::ErrorKind::TimedOut, "客户端写入超时")));
             }
-        });
-        
-        let server = Server::bind(&addr).serve(make_svc);
-        log_info!("Server", "代理服务器正在运行在 http://{}", addr);
-        
-        if let Err(e) = server.await {
-            eprintln!("server error: {}", e);
         }
-        
-        Ok(())
+        Poll::Pending
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for WriteIdleTimeout<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for WriteIdleTimeout<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+        self.poll_stalled(cx, poll)
+    }
+
+    fn poll_write_vectored(mut self: Pin<&mut Self>, cx: &mut Context<'_>, bufs: &[io::IoSlice<'_>]) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write_vectored(cx, bufs);
+        self.poll_stalled(cx, poll)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let poll = Pin::new(&mut self.inner).poll_flush(cx);
+        self.poll_stalled(cx, poll)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(feature = "http3")]
+impl ProxyServer {
+    /// 启动 HTTP/3 监听，可与 `start` 同时运行
+    pub async fn start_http3(&self, config: crate::http3::Http3Config) -> Result<()> {
+        crate::http3::serve(config, self.handler.clone()).await
     }
 }
 
 pub async fn run_server(port: u16, cache_dir: &str) -> Result<()> {
     let server = ProxyServer::new(port, cache_dir);
     server.start().await
-}
\ No newline at end of file
+}
+
+/// 按错误类别生成错误响应，繁忙类错误带上 Retry-After 让客户端稍后重试
+pub(crate) fn error_response(err: &ProxyError) -> hyper::Response<hyper::Body> {
+    let status = err.status_code();
+    let mut builder = hyper::Response::builder().status(status);
+    if err.kind() == ErrorKind::Busy {
+        builder = builder.header(hyper::header::RETRY_AFTER, 1);
+    }
+    builder
+        .body(hyper::Body::from(format!("Error: {}", err)))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{proxy_url, spawn_proxy, MockOrigin, MockResource};
+
+    #[tokio::test]
+    async fn test_request_permit_held_while_streaming() {
+        let origin = MockOrigin::start().await.unwrap();
+        origin.add("/slow.mp4", MockResource::sized(8 * 1024).with_drip(1024, Duration::from_millis(100)));
+        origin.add("/fast.mp4", MockResource::sized(1024));
+
+        let dir = std::env::temp_dir().join(format!("server-permit-{}", std::process::id()));
+        let limits = LimitConfig { max_concurrent_requests: 1, queue_timeout: Duration::from_millis(200), ..LimitConfig::default() };
+        let proxy = spawn_proxy(&dir, ProxyConfig { limits, ..ProxyConfig::default() }).await.unwrap();
+        let client = hyper::Client::new();
+
+        // 第一个请求的响应体仍在发送时，第二个请求排队直到超时
+        let slow = client.get(proxy_url(proxy, &origin.url("/slow.mp4")).parse().unwrap()).await.unwrap();
+        assert_eq!(slow.status(), hyper::StatusCode::OK);
+        let queued = client.get(proxy_url(proxy, &origin.url("/fast.mp4")).parse().unwrap()).await.unwrap();
+        assert_eq!(queued.status(), hyper::StatusCode::SERVICE_UNAVAILABLE);
+
+        // 响应体发送完后释放名额
+        assert_eq!(hyper::body::to_bytes(slow.into_body()).await.unwrap().len(), 8 * 1024);
+        let fast = client.get(proxy_url(proxy, &origin.url("/fast.mp4")).parse().unwrap()).await.unwrap();
+        assert_eq!(fast.status(), hyper::StatusCode::OK);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
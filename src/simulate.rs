@@ -0,0 +1,140 @@
+use std::time::{Duration, Instant};
+use futures::StreamExt;
+use crate::data_request::{DataRequest, DEFAULT_PROXY_PREFIX};
+use crate::data_source_manager::{DataSourceManager, CACHE_STATUS_HEADER};
+use crate::utils::error::Result;
+use crate::log_info;
+
+/// 回放的一条请求
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplayEntry {
+    pub url: String,
+    /// Range 请求头，如 `bytes=0-1023`
+    pub range: String,
+}
+
+/// 回放结果
+#[derive(Debug, Clone, Default)]
+pub struct SimulateReport {
+    pub requests: usize,
+    pub errors: usize,
+    pub hits: usize,
+    pub partial_hits: usize,
+    pub misses: usize,
+    pub bytes: u64,
+    pub elapsed: Duration,
+}
+
+impl SimulateReport {
+    /// 完全命中缓存的比例
+    pub fn hit_ratio(&self) -> f64 {
+        let succeeded = self.requests - self.errors;
+        if succeeded > 0 { self.hits as f64 / succeeded as f64 } else { 0.0 }
+    }
+}
+
+/// 按顺序将请求直接交给缓存层处理，不经过 HTTP 服务
+pub async fn replay(manager: &DataSourceManager, entries: &[ReplayEntry]) -> SimulateReport {
+    let started = Instant::now();
+    let mut report = SimulateReport::default();
+    for entry in entries {
+        report.requests += 1;
+        match replay_one(manager, entry).await {
+            Ok((status, bytes)) => {
+                report.bytes += bytes;
+                match status.as_deref() {
+                    Some("HIT") => report.hits += 1,
+                    Some("PARTIAL") => report.partial_hits += 1,
+                    _ => report.misses += 1,
+                }
+            }
+            Err(e) => {
+                log_info!("Simulate", "回放失败: {} {} - {}", entry.url, entry.range, e);
+                report.errors += 1;
+            }
+        }
+    }
+    report.elapsed = started.elapsed();
+    report
+}
+
+async fn replay_one(manager: &DataSourceManager, entry: &ReplayEntry) -> Result<(Option<String>, u64)> {
+    let req = DataRequest::new(&DataRequest::new_request_with_range(&entry.url, &entry.range))?;
+    let response = manager.process_request(&req).await?;
+    let status = response
+        .headers()
+        .get(CACHE_STATUS_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string());
+
+    let mut body = response.into_body();
+    let mut bytes = 0u64;
+    while let Some(chunk) = body.next().await {
+        bytes += chunk?.len() as u64;
+    }
+    Ok((status, bytes))
+}
+
+/// 解析回放文件，每行一条请求，支持两种格式：
+///
+/// - CSV：`url[,range]`，range 形如 `0-1023` 或 `bytes=0-1023`
+/// - 访问日志：取引号中的请求行 `"GET <url> HTTP/1.1"`，`/proxy/` 路径会被解码
+///
+/// 空行、`#` 开头的注释和非 GET 请求会被忽略
+pub fn parse_replay_log(content: &str) -> Vec<ReplayEntry> {
+    content
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| parse_access_line(line).or_else(|| parse_csv_line(line)))
+        .collect()
+}
+
+fn parse_csv_line(line: &str) -> Option<ReplayEntry> {
+    let mut fields = line.split(',').map(|field| field.trim());
+    let url = fields.next().filter(|url| url.starts_with("http://") || url.starts_with("https://"))?;
+    let range = match fields.next().filter(|range| !range.is_empty()) {
+        Some(range) if range.starts_with("bytes=") => range.to_string(),
+        Some(range) => format!("bytes={}", range),
+        None => "bytes=0-".to_string(),
+    };
+    Some(ReplayEntry { url: url.to_string(), range })
+}
+
+fn parse_access_line(line: &str) -> Option<ReplayEntry> {
+    let request_line = line.split('"').nth(1)?;
+    let mut parts = request_line.split_whitespace();
+    if parts.next()? != "GET" {
+        return None;
+    }
+    let target = parts.next()?;
+    let url = match target.strip_prefix(DEFAULT_PROXY_PREFIX) {
+        Some(encoded) => urlencoding::decode(encoded).ok()?.into_owned(),
+        None if target.starts_with("http://") || target.starts_with("https://") => target.to_string(),
+        None => return None,
+    };
+    Some(ReplayEntry { url, range: "bytes=0-".to_string() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_replay_log() {
+        let content = "\
+# 回放样例
+http://example.com/a.mp4,0-1023
+http://example.com/a.mp4, bytes=1024-
+http://example.com/b.mp4
+127.0.0.1 - - [10/Oct/2026:13:55:36 +0800] \"GET /proxy/http%3A%2F%2Fexample.com%2Fc.ts HTTP/1.1\" 200 2326
+127.0.0.1 - - [10/Oct/2026:13:55:37 +0800] \"POST /admin/cleanup HTTP/1.1\" 200 41
+";
+        let entries = parse_replay_log(content);
+        assert_eq!(entries.len(), 4);
+        assert_eq!(entries[0].range, "bytes=0-1023");
+        assert_eq!(entries[1].range, "bytes=1024-");
+        assert_eq!(entries[2].range, "bytes=0-");
+        assert_eq!(entries[3].url, "http://example.com/c.ts");
+    }
+}
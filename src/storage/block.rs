@@ -29,6 +29,12 @@ pub struct BlockManager {
     blocks: RwLock<BTreeMap<u64, BlockInfo>>, // 使用 BTreeMap 按偏移量排序存储区块
 }
 
+impl Default for BlockManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl BlockManager {
     pub fn new() -> Self {
         Self {
@@ -70,7 +76,7 @@ impl BlockManager {
         // 检查是否与现有区块重叠
         if let Some((_, existing)) = blocks.range(..=offset).next_back() {
             if offset < existing.offset + existing.length {
-                return Err(ProxyError::Cache("区块重叠".to_string()));
+                return Err(ProxyError::cache("区块重叠".to_string()));
             }
         }
 
@@ -95,7 +101,7 @@ impl BlockManager {
             block.last_access = SystemTime::now();
             Ok(())
         } else {
-            Err(ProxyError::Cache("区块不存在".to_string()))
+            Err(ProxyError::cache("区块不存在".to_string()))
         }
     }
 
@@ -1,39 +1,94 @@
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 use std::fs::File;
+use std::sync::{Arc, Mutex, Weak};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::io::{self, Read, Seek, SeekFrom};
 use tokio::fs as tokio_fs;
-use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
 use futures::Stream;
 use async_trait::async_trait;
 use bytes::Bytes;
-use md5;
+use sha2::{Digest, Sha256};
 
 use crate::utils::error::{Result, ProxyError};
 use crate::log_info;
-use super::{StorageEngine, StorageConfig};
+use super::{StorageEngine, StorageConfig, StorageTier, EntryMeta, IndexEntry};
+use super::inline::{InlineStore, INLINE_FILE};
+use super::writer::{DiskWriter, EntryWriter};
+use super::layout::{ShardLayout, MANIFEST_FILE};
+
+const BLOB_DIR: &str = "blobs";
+const BLOB_INDEX_FILE: &str = "index.json";
+/// 内存映射读取时每个数据块的大小
+#[cfg(target_pointer_width = "64")]
+const MMAP_CHUNK_SIZE: usize = 1024 * 1024;
 
 pub struct DiskStorage {
     config: StorageConfig,
+    layout: ShardLayout,
+    blob_index: tokio::sync::Mutex<HashMap<String, String>>, // 缓存键 -> 内容哈希
+    mapped: Mutex<HashMap<String, Weak<()>>>,   // 缓存键 -> 内存映射租约，有映射时截断改为复制后替换
+    inline: InlineStore,                        // 直接保存在索引中的小对象
+    fast_keys: Mutex<HashSet<String>>,          // 数据位于高速层的缓存键
+    unindexed: AtomicBool,                      // 扫描索引时发现没有元数据的数据文件
+}
+
+/// 内存映射的文件区间，释放前持有租约
+#[cfg(target_pointer_width = "64")]
+struct MappedRegion {
+    mmap: memmap2::Mmap,
+    _lease: Arc<()>,
+}
+
+#[cfg(target_pointer_width = "64")]
+impl AsRef<[u8]> for MappedRegion {
+    fn as_ref(&self) -> &[u8] {
+        &self.mmap
+    }
 }
 
 impl DiskStorage {
     pub fn new(config: StorageConfig) -> Self {
-        Self { config }
+        let index_path = config.root_path.join(BLOB_DIR).join(BLOB_INDEX_FILE);
+        let blob_index = std::fs::read(&index_path)
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+        let layout = ShardLayout::resolve(&config.root_path, config.layout);
+        let inline = InlineStore::open(config.root_path.join(INLINE_FILE));
+
+        Self {
+            config,
+            layout,
+            blob_index: tokio::sync::Mutex::new(blob_index),
+            mapped: Mutex::new(HashMap::new()),
+            inline,
+            fast_keys: Mutex::new(HashSet::new()),
+            unindexed: AtomicBool::new(false),
+        }
+    }
+
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    pub(crate) fn chunk_size(&self) -> usize {
+        self.config.chunk_size
     }
 
     fn get_file_path(&self, key: &str) -> PathBuf {
-        // 使用MD5生成URL的哈希值
-        let hash = format!("{:x}", md5::compute(key.as_bytes()));
-        
-        // 创建二级目录结构，使用哈希的前两个字符
-        let dir1 = &hash[0..2];
-        let dir2 = &hash[2..4];
-        
-        // 构建完整的文件路径
-        self.config.root_path
-            .join(dir1)
-            .join(dir2)
-            .join(hash)
+        // 已迁移到高速层的数据从高速层读写
+        self.file_path_in(self.tier_root(key), key)
+    }
+
+    /// 缓存键当前所在存储层的根目录
+    fn tier_root(&self, key: &str) -> &Path {
+        match &self.config.fast_root_path {
+            Some(fast_root) if self.fast_keys.lock().unwrap().contains(key) => fast_root,
+            _ => &self.config.root_path,
+        }
+    }
+
+    fn file_path_in(&self, root: &Path, key: &str) -> PathBuf {
+        self.layout.file_path(root, key)
     }
 
     async fn ensure_dir_exists(&self, path: &Path) -> io::Result<()> {
@@ -44,55 +99,74 @@ impl DiskStorage {
         }
         Ok(())
     }
-}
 
-#[async_trait]
-impl StorageEngine for DiskStorage {
-    async fn write<S>(&self, key: &str, mut stream: S, range: (u64, u64)) -> Result<u64>
-    where
-        S: Stream<Item = Result<Bytes>> + Send + Unpin + 'static,
-    {
+    /// 准备写入：创建目录、断开共享数据块，从文件末尾之后写入时先扩展文件长度
+    pub(crate) async fn open_for_write(&self, key: &str, range: (u64, u64)) -> Result<(PathBuf, tokio_fs::File)> {
         let file_path = self.get_file_path(key);
-        self.ensure_dir_exists(&file_path).await?;
+        self.ensure_dir_exists(&file_path).await.map_err(write_error)?;
+        self.detach_shared(key, &file_path).await?;
+        self.spill_inline(key, &file_path).await?;
 
         log_info!("Storage", "写入文件: {:?}, 范围: {}-{}", file_path, range.0, range.1);
         
-        let mut file = if file_path.exists() {
+        let file = if file_path.exists() {
             tokio_fs::OpenOptions::new()
                 .read(true)
                 .write(true)
                 .create(true)
+                .truncate(false)
                 .open(&file_path)
-                .await?
+                .await
+                .map_err(write_error)?
         } else {
             tokio_fs::OpenOptions::new()
                 .write(true)
                 .create(true)
+                .truncate(false)
                 .open(&file_path)
-                .await?
+                .await
+                .map_err(write_error)?
         };
 
-        // 设置文件写入位置
-        file.seek(SeekFrom::Start(range.0)).await?;
-
-        let mut written = 0u64;
-        while let Some(chunk) = futures::StreamExt::next(&mut stream).await {
-            let chunk = chunk?;
-            file.write_all(&chunk).await?;
-            written += chunk.len() as u64;
+        // 从文件末尾之后开始写入时只扩展长度不填充数据，中间的空洞不占用磁盘
+        let len = file.metadata().await?.len();
+        if range.0 > len {
+            file.set_len(range.0).await.map_err(write_error)?;
         }
 
-        file.flush().await?;
-        log_info!("Storage", "写入完成: {:?}, 写入字节数: {}", file_path, written);
-        
-        Ok(written)
+        Ok((file_path, file))
     }
 
-    async fn read(&self, key: &str, range: (u64, u64)) -> Result<Box<dyn Stream<Item = Result<Bytes>> + Send + Unpin>> {
+    /// 内联条目再次写入时先转为普通的数据文件和元数据文件
+    async fn spill_inline(&self, key: &str, file_path: &Path) -> Result<()> {
+        let (meta, data) = match self.inline.get(key) {
+            Some(entry) => entry,
+            None => return Ok(()),
+        };
+        tokio_fs::write(file_path, &data).await.map_err(write_error)?;
+        tokio_fs::write(self.meta_path(key), meta.encode()?).await.map_err(write_error)?;
+        self.inline.remove(key).map_err(write_error)?;
+        log_info!("Storage", "内联条目转为数据文件: {} ({} 字节)", key, data.len());
+        Ok(())
+    }
+
+    /// 读取内联条目的数据，不是内联条目时返回 None
+    pub(crate) fn read_inline(&self, key: &str, range: (u64, u64)) -> Option<Result<Box<dyn Stream<Item = Result<Bytes>> + Send + Unpin>>> {
+        let (_, data) = self.inline.get(key)?;
+        let len = data.len() as u64;
+        if range.0 >= len {
+            return Some(Err(ProxyError::storage(format!("读取范围超出内联数据大小: {} {}-{}", key, range.0, range.1))));
+        }
+        let chunk = data.slice(range.0 as usize..=range.1.min(len - 1) as usize);
+        Some(Ok(Box::new(futures::stream::iter(std::iter::once(Ok(chunk))))))
+    }
+
+    /// 准备读取：打开数据文件并计算实际的结束位置，返回文件路径、文件、文件大小和结束位置
+    pub(crate) fn open_for_read(&self, key: &str, range: (u64, u64)) -> Result<(PathBuf, File, u64, u64)> {
         let file_path = self.get_file_path(key);
         
         if !file_path.exists() {
-            return Err(ProxyError::Storage(format!("文件不存在: {:?}", file_path)));
+            return Err(ProxyError::storage(format!("文件不存在: {:?}", file_path)));
         }
 
         log_info!("Storage", "读取文件: {:?}, 范围: {}-{}", file_path, range.0, range.1);
@@ -102,7 +176,7 @@ impl StorageEngine for DiskStorage {
         let file_size = metadata.len();
         
         if range.0 >= file_size {
-            return Err(ProxyError::Storage("请求范围超出文件大小".to_string()));
+            return Err(ProxyError::storage("请求范围超出文件大小".to_string()));
         }
 
         // 计算实际的结束位置
@@ -112,10 +186,335 @@ impl StorageEngine for DiskStorage {
             std::cmp::min(range.1, file_size - 1)
         };
 
+        Ok((file_path, file, file_size, end))
+    }
+
+    /// 启用内存映射且文件已完整缓存时通过映射读取，不满足条件或映射失败时返回 None
+    pub(crate) fn try_read_mapped(&self, key: &str, file: &File, file_size: u64, start: u64, end: u64) -> Option<Box<dyn Stream<Item = Result<Bytes>> + Send + Unpin>> {
+        #[cfg(target_pointer_width = "64")]
+        if self.config.mmap && file_size >= self.config.mmap_threshold && self.is_complete(key, file_size) {
+            match self.read_mapped(key, file, file_size, start, end) {
+                Ok(stream) => return Some(stream),
+                Err(e) => log_info!("Storage", "内存映射读取失败，改用普通读取: {} - {}", key, e),
+            }
+        }
+        #[cfg(not(target_pointer_width = "64"))]
+        let _ = (key, file, file_size, start, end);
+        None
+    }
+
+    /// 元数据记录的完整大小与文件大小一致时视为完整缓存
+    #[cfg(target_pointer_width = "64")]
+    fn is_complete(&self, key: &str, file_size: u64) -> bool {
+        std::fs::read(self.meta_path(key))
+            .ok()
+            .and_then(|data| EntryMeta::decode(&data).ok())
+            .and_then(|meta| meta.content_length)
+            == Some(file_size)
+    }
+
+    /// 获取缓存键的内存映射租约，同一个键的映射共享租约
+    #[cfg(target_pointer_width = "64")]
+    fn lease(&self, key: &str) -> Arc<()> {
+        let mut mapped = self.mapped.lock().unwrap();
+        mapped.retain(|_, lease| lease.strong_count() > 0);
+        if let Some(lease) = mapped.get(key).and_then(Weak::upgrade) {
+            return lease;
+        }
+        let lease = Arc::new(());
+        mapped.insert(key.to_string(), Arc::downgrade(&lease));
+        lease
+    }
+
+    /// 通过内存映射读取完整缓存的文件，数据块是映射上的 `Bytes` 视图，不逐块复制
+    #[cfg(target_pointer_width = "64")]
+    fn read_mapped(&self, key: &str, file: &File, file_size: u64, start: u64, end: u64) -> Result<Box<dyn Stream<Item = Result<Bytes>> + Send + Unpin>> {
+        // 先取得租约再确认文件大小，之后的截断不会修改映射中的文件
+        let lease = self.lease(key);
+        if file.metadata()?.len() != file_size {
+            return Err(ProxyError::storage("文件大小已变化".to_string()));
+        }
+
+        let len = (end - start + 1) as usize;
+        // SAFETY: 完整缓存的文件不再追加或截断，持有租约期间的截断通过复制后替换完成，映射的内容始终有效
+        let mmap = unsafe { memmap2::MmapOptions::new().offset(start).len(len).map(file)? };
+        let data = Bytes::from_owner(MappedRegion { mmap, _lease: lease });
+        let chunks = (0..len)
+            .step_by(MMAP_CHUNK_SIZE)
+            .map(move |offset| Ok(data.slice(offset..(offset + MMAP_CHUNK_SIZE).min(len))));
+        Ok(Box::new(futures::stream::iter(chunks)))
+    }
+
+    /// 元数据始终保存在大容量层，不随分层迁移
+    fn meta_path(&self, key: &str) -> PathBuf {
+        self.file_path_in(&self.config.root_path, key).with_extension("meta")
+    }
+
+    fn blob_path(&self, content_hash: &str) -> PathBuf {
+        self.config.root_path
+            .join(BLOB_DIR)
+            .join(&content_hash[0..2])
+            .join(content_hash)
+    }
+
+    /// 在持有数据块索引锁时调用，写入顺序与修改顺序一致
+    async fn save_blob_index(&self, index: &HashMap<String, String>) -> Result<()> {
+        let index_path = self.config.root_path.join(BLOB_DIR).join(BLOB_INDEX_FILE);
+        self.ensure_dir_exists(&index_path).await.map_err(write_error)?;
+        tokio_fs::write(&index_path, serde_json::to_vec(index)?).await.map_err(write_error)?;
+        Ok(())
+    }
+
+    /// 写入前断开与共享数据块的硬链接，避免修改其他 URL 的数据
+    #[cfg(unix)]
+    async fn detach_shared(&self, key: &str, file_path: &Path) -> Result<()> {
+        use std::os::unix::fs::MetadataExt;
+
+        let metadata = match tokio_fs::metadata(file_path).await {
+            Ok(metadata) => metadata,
+            Err(_) => return Ok(()),
+        };
+        if metadata.nlink() <= 1 {
+            return Ok(());
+        }
+
+        log_info!("Storage", "断开共享数据块: {:?}", file_path);
+        let tmp_path = file_path.with_extension("tmp");
+        tokio_fs::copy(file_path, &tmp_path).await.map_err(write_error)?;
+        tokio_fs::rename(&tmp_path, file_path).await?;
+        self.release_blob(key).await
+    }
+
+    #[cfg(not(unix))]
+    async fn detach_shared(&self, _key: &str, _file_path: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    /// 移除缓存键与数据块的关联，没有其他引用的数据块一并删除
+    async fn release_blob(&self, key: &str) -> Result<()> {
+        // 删除数据块期间持有索引锁，避免其他条目同时链接到将被删除的数据块
+        let mut index = self.blob_index.lock().await;
+        let content_hash = match index.remove(key) {
+            Some(content_hash) => content_hash,
+            None => return Ok(()),
+        };
+        self.save_blob_index(&index).await?;
+
+        let still_referenced = index.values().any(|h| *h == content_hash);
+        if !still_referenced {
+            let blob_path = self.blob_path(&content_hash);
+            match tokio_fs::remove_file(&blob_path).await {
+                Ok(()) => log_info!("Storage", "删除数据块: {:?}", blob_path),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    async fn link_blob(&self, key: &str) -> Result<Option<String>> {
+        use std::os::unix::fs::MetadataExt;
+
+        let file_path = self.get_file_path(key);
+        if !file_path.exists() {
+            return Ok(None);
+        }
+
+        let hash_path = file_path.clone();
+        let content_hash = tokio::task::spawn_blocking(move || hash_file(&hash_path))
+            .await
+            .map_err(|e| ProxyError::storage(format!("计算内容哈希失败: {}", e)))??;
+
+        let blob_path = self.blob_path(&content_hash);
+        self.ensure_dir_exists(&blob_path).await?;
+
+        let mut index = self.blob_index.lock().await;
+        let file_meta = tokio_fs::metadata(&file_path).await?;
+        let linked = match tokio_fs::metadata(&blob_path).await {
+            Ok(blob_meta) => {
+                if blob_meta.len() != file_meta.len() {
+                    log_info!("Storage", "内容哈希相同但大小不同，跳过去重: {}", key);
+                    return Ok(None);
+                }
+                if blob_meta.ino() != file_meta.ino() || blob_meta.dev() != file_meta.dev() {
+                    // 已有相同内容的数据块，用硬链接替换当前文件
+                    let tmp_path = file_path.with_extension("tmp");
+                    let linked = tokio_fs::hard_link(&blob_path, &tmp_path).await;
+                    if linked.is_ok() {
+                        tokio_fs::rename(&tmp_path, &file_path).await?;
+                        log_info!("Storage", "去重命中: {} -> {}，节省 {} 字节", key, content_hash, file_meta.len());
+                    }
+                    linked
+                } else {
+                    Ok(())
+                }
+            }
+            Err(_) => {
+                let linked = tokio_fs::hard_link(&file_path, &blob_path).await;
+                if linked.is_ok() {
+                    log_info!("Storage", "新建数据块: {} -> {}", key, content_hash);
+                }
+                linked
+            }
+        };
+        match linked {
+            Ok(()) => {}
+            // 高速层与数据块目录不在同一文件系统时无法建立硬链接，保留独立的数据文件
+            Err(e) if e.raw_os_error() == Some(libc::EXDEV) => {
+                log_info!("Storage", "数据文件与数据块不在同一文件系统，跳过去重: {}", key);
+                return Ok(None);
+            }
+            Err(e) => return Err(e.into()),
+        }
+
+        index.insert(key.to_string(), content_hash.clone());
+        self.save_blob_index(&index).await?;
+        Ok(Some(content_hash))
+    }
+
+    #[cfg(not(unix))]
+    async fn link_blob(&self, _key: &str) -> Result<Option<String>> {
+        Ok(None)
+    }
+}
+
+/// 扫描第 `shard` 份分片目录中的元数据，分片目录按序号对 `shards` 取余分配。
+/// 同时返回没有元数据的数据文件数量，这些文件无法得知缓存键，不进入索引
+fn scan_index(root: &Path, fast_root: Option<&Path>, layout: ShardLayout, shard: usize, shards: usize) -> io::Result<(Vec<IndexEntry>, usize)> {
+    let mut index = Vec::new();
+    let mut unindexed = 0;
+    if !root.exists() {
+        return Ok((index, unindexed));
+    }
+
+    let dirs = layout.leaf_dirs(root)?;
+    for dir in dirs.into_iter().skip(shard).step_by(shards.max(1)) {
+        for file in std::fs::read_dir(&dir)? {
+            let meta_path = file?.path();
+            if meta_path.extension().is_none() {
+                if is_data_file(&meta_path) && !meta_path.with_extension("meta").exists() {
+                    unindexed += 1;
+                }
+                continue;
+            }
+            if meta_path.extension().and_then(|ext| ext.to_str()) != Some("meta") {
+                continue;
+            }
+
+            let decoded = std::fs::read(&meta_path)
+                .map_err(ProxyError::from)
+                .and_then(|data| EntryMeta::decode(&data));
+            let meta = match decoded {
+                Ok(meta) => meta,
+                Err(e) => {
+                    log_info!("Storage", "跳过无法解析的元数据: {:?} - {}", meta_path, e);
+                    continue;
+                }
+            };
+
+            let data_path = meta_path.with_extension("");
+            let fast_path = fast_root.map(|fast_root| layout.file_path(fast_root, &meta.key));
+            let metadata = fast_path
+                .iter()
+                .map(|path| (StorageTier::Fast, path))
+                .chain(std::iter::once((StorageTier::Slow, &data_path)))
+                .find_map(|(tier, path)| std::fs::metadata(path).ok().map(|metadata| (tier, metadata)));
+
+            match metadata {
+                Some((tier, metadata)) => index.push(IndexEntry {
+                    meta,
+                    size: metadata.len(),
+                    allocated: allocated_bytes(&metadata),
+                    tier,
+                }),
+                // 数据文件可能还未写入或位于暂时不可用的高速层，保留元数据，条目按未缓存处理
+                None => log_info!("Storage", "元数据没有对应的数据文件，跳过: {:?}", meta_path),
+            }
+        }
+    }
+
+    Ok((index, unindexed))
+}
+
+/// 数据文件以缓存键的 MD5 命名
+fn is_data_file(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.len() == 32 && name.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+/// 文件实际占用的磁盘空间
+#[cfg(unix)]
+fn allocated_bytes(metadata: &std::fs::Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    // st_blocks 固定以 512 字节为单位
+    metadata.blocks() * 512
+}
+
+#[cfg(not(unix))]
+fn allocated_bytes(metadata: &std::fs::Metadata) -> u64 {
+    metadata.len()
+}
+
+/// 计算文件内容的 SHA-256
+pub(crate) fn hash_file(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let n = file.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// 旧版本记录的是 MD5，无法与 `hash_file` 的结果比较
+pub(crate) fn is_legacy_hash(content_hash: &str) -> bool {
+    content_hash.len() != 64
+}
+
+#[async_trait]
+impl StorageEngine for DiskStorage {
+    async fn write<S>(&self, key: &str, mut stream: S, range: (u64, u64)) -> Result<u64>
+    where
+        S: Stream<Item = Result<Bytes>> + Send + Unpin + 'static,
+    {
+        let (file_path, mut file) = self.open_for_write(key, range).await?;
+
+        // 设置文件写入位置
+        file.seek(SeekFrom::Start(range.0)).await?;
+
+        let mut written = 0u64;
+        while let Some(chunk) = futures::StreamExt::next(&mut stream).await {
+            let chunk = chunk?;
+            file.write_all(&chunk).await.map_err(write_error)?;
+            written += chunk.len() as u64;
+        }
+
+        file.flush().await.map_err(write_error)?;
+        log_info!("Storage", "写入完成: {:?}, 写入字节数: {}", file_path, written);
+        
+        Ok(written)
+    }
+
+    async fn read(&self, key: &str, range: (u64, u64)) -> Result<Box<dyn Stream<Item = Result<Bytes>> + Send + Unpin>> {
+        if let Some(stream) = self.read_inline(key, range) {
+            return stream;
+        }
+        let (_, file, file_size, end) = self.open_for_read(key, range)?;
+
         // 计算需要读取的总字节数
         let total_bytes = end - range.0 + 1;
         log_info!("Storage", "需要读取的总字节数: {} (范围: {}-{})", total_bytes, range.0, end);
 
+        if let Some(stream) = self.try_read_mapped(key, &file, file_size, range.0, end) {
+            return Ok(stream);
+        }
+
         let chunk_size = self.config.chunk_size;
         
         // 创建异步读取流
@@ -150,6 +549,9 @@ impl StorageEngine for DiskStorage {
     }
 
     async fn get_size(&self, key: &str) -> Result<Option<u64>> {
+        if let Some((_, data)) = self.inline.get(key) {
+            return Ok(Some(data.len() as u64));
+        }
         let file_path = self.get_file_path(key);
         if !file_path.exists() {
             return Ok(None);
@@ -159,7 +561,22 @@ impl StorageEngine for DiskStorage {
         Ok(Some(metadata.len()))
     }
 
+    async fn allocated_size(&self, key: &str) -> Result<Option<u64>> {
+        if let Some((_, data)) = self.inline.get(key) {
+            return Ok(Some(data.len() as u64));
+        }
+        match tokio_fs::metadata(self.get_file_path(key)).await {
+            Ok(metadata) => Ok(Some(allocated_bytes(&metadata))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
     async fn check_range(&self, key: &str, range: (u64, u64)) -> Result<bool> {
+        if let Some((_, data)) = self.inline.get(key) {
+            let len = data.len() as u64;
+            return Ok(range.0 < len && range.1 < len);
+        }
         let file_path = self.get_file_path(key);
         if !file_path.exists() {
             return Ok(false);
@@ -171,4 +588,360 @@ impl StorageEngine for DiskStorage {
         // 检查范围是否完全在文件内
         Ok(range.0 < file_size && range.1 <= file_size)
     }
-} 
\ No newline at end of file
+
+    async fn remove(&self, key: &str) -> Result<()> {
+        if self.inline.remove(key)?.is_some() {
+            log_info!("Storage", "删除内联条目: {}", key);
+            return Ok(());
+        }
+        let mut paths = vec![self.file_path_in(&self.config.root_path, key)];
+        if let Some(fast_root) = &self.config.fast_root_path {
+            paths.push(self.file_path_in(fast_root, key));
+        }
+
+        for file_path in paths {
+            match tokio_fs::remove_file(&file_path).await {
+                Ok(()) => log_info!("Storage", "删除文件: {:?}", file_path),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        match tokio_fs::remove_file(self.meta_path(key)).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        self.fast_keys.lock().unwrap().remove(key);
+        self.release_blob(key).await
+    }
+
+    fn available_space(&self, key: &str) -> Option<u64> {
+        available_space(self.tier_root(key))
+    }
+
+    fn has_unindexed_data(&self) -> bool {
+        self.unindexed.load(Ordering::Acquire)
+    }
+
+    /// 以启动时写入的缓存清单为标记，卸载后挂载点下只剩空目录
+    async fn is_available(&self) -> bool {
+        tokio_fs::metadata(self.config.root_path.join(MANIFEST_FILE)).await.is_ok()
+    }
+
+    async fn move_to_tier(&self, key: &str, tier: StorageTier) -> Result<()> {
+        // 内联条目常驻内存，不需要迁移
+        if self.inline.contains(key) {
+            return Ok(());
+        }
+        let fast_root = match &self.config.fast_root_path {
+            Some(fast_root) => fast_root,
+            None => return Ok(()),
+        };
+
+        let fast_path = self.file_path_in(fast_root, key);
+        let slow_path = self.file_path_in(&self.config.root_path, key);
+        let (from, to, to_root) = match tier {
+            StorageTier::Fast => (slow_path, fast_path, fast_root.as_path()),
+            StorageTier::Slow => (fast_path, slow_path, self.config.root_path.as_path()),
+        };
+
+        let size = match tokio_fs::metadata(&from).await {
+            Ok(metadata) => metadata.len(),
+            Err(_) => return Ok(()),
+        };
+        // 目标层剩余空间不足时放弃迁移，数据留在原层
+        if available_space(to_root).is_some_and(|available| available < size) {
+            return Err(ProxyError::no_space(format!("{:?} 剩余空间不足，无法迁移 {}", to_root, key)));
+        }
+
+        // 先复制到临时文件再重命名，迁移过程中读取仍能命中源文件
+        self.ensure_dir_exists(&to).await.map_err(write_error)?;
+        let tmp_path = to.with_extension("tmp");
+        tokio_fs::copy(&from, &tmp_path).await.map_err(write_error)?;
+        tokio_fs::rename(&tmp_path, &to).await?;
+        // 切换读写路径后再删除源文件
+        {
+            let mut fast_keys = self.fast_keys.lock().unwrap();
+            match tier {
+                StorageTier::Fast => fast_keys.insert(key.to_string()),
+                StorageTier::Slow => fast_keys.remove(key),
+            };
+        }
+        tokio_fs::remove_file(&from).await?;
+
+        log_info!("Storage", "迁移文件: {:?} -> {:?}", from, to);
+        Ok(())
+    }
+
+    async fn deduplicate(&self, key: &str) -> Result<Option<String>> {
+        if self.inline.contains(key) {
+            return Ok(None);
+        }
+        self.link_blob(key).await
+    }
+
+    async fn save_inline(&self, meta: &EntryMeta, data: &Bytes) -> Result<bool> {
+        self.inline.put(meta, data.clone()).map_err(write_error)?;
+        Ok(true)
+    }
+
+    async fn save_meta(&self, meta: &EntryMeta) -> Result<()> {
+        if let Some((_, data)) = self.inline.get(&meta.key) {
+            return self.inline.put(meta, data).map_err(write_error);
+        }
+        let meta_path = self.meta_path(&meta.key);
+        self.ensure_dir_exists(&meta_path).await.map_err(write_error)?;
+        tokio_fs::write(&meta_path, meta.encode()?).await.map_err(write_error)?;
+        Ok(())
+    }
+
+    async fn load_index(&self) -> Result<Vec<IndexEntry>> {
+        let index = self.load_index_shard(0, 1).await?;
+        log_info!("Storage", "加载索引完成: {} 个条目", index.len());
+        Ok(index)
+    }
+
+    async fn load_index_shard(&self, shard: usize, shards: usize) -> Result<Vec<IndexEntry>> {
+        let root = self.config.root_path.clone();
+        let fast_root = self.config.fast_root_path.clone();
+        let layout = self.layout;
+        let (mut index, unindexed) = tokio::task::spawn_blocking(move || scan_index(&root, fast_root.as_deref(), layout, shard, shards))
+            .await
+            .map_err(|e| ProxyError::storage(format!("加载索引失败: {}", e)))??;
+        if unindexed > 0 {
+            log_info!("Storage", "发现 {} 个没有元数据的数据文件，未命中索引的请求仍检查磁盘", unindexed);
+            self.unindexed.store(true, Ordering::Release);
+        }
+        self.fast_keys.lock().unwrap().extend(
+            index
+                .iter()
+                .filter(|entry| entry.tier == StorageTier::Fast)
+                .map(|entry| entry.meta.key.clone()),
+        );
+        // 内联条目随第 0 份返回
+        if shard == 0 {
+            index.extend(self.inline.index());
+        }
+        Ok(index)
+    }
+
+    async fn open_writer(&self, key: &str, range: (u64, u64)) -> Result<Option<Box<dyn EntryWriter>>> {
+        let (_, file) = self.open_for_write(key, range).await?;
+        Ok(Some(Box::new(DiskWriter::new(file, range.0).await?)))
+    }
+
+    async fn truncate(&self, key: &str, len: u64) -> Result<()> {
+        if let Some((meta, data)) = self.inline.get(key) {
+            let data = data.slice(..(len as usize).min(data.len()));
+            return self.inline.put(&meta, data).map_err(write_error);
+        }
+        let file_path = self.get_file_path(key);
+        self.detach_shared(key, &file_path).await?;
+
+        // 没有内存映射时直接截断，检查和截断在同一个锁内完成，避免与新建映射交错
+        let mapped = {
+            let mapped = self.mapped.lock().unwrap();
+            let live = mapped.get(key).is_some_and(|lease| lease.strong_count() > 0);
+            if !live {
+                std::fs::OpenOptions::new().write(true).open(&file_path)?.set_len(len)?;
+            }
+            live
+        };
+
+        // 仍有映射时复制开头部分后替换，映射继续引用原文件
+        if mapped {
+            let tmp_path = file_path.with_extension("tmp");
+            let mut source = tokio_fs::File::open(&file_path).await?.take(len);
+            let mut target = tokio_fs::File::create(&tmp_path).await.map_err(write_error)?;
+            tokio::io::copy(&mut source, &mut target).await.map_err(write_error)?;
+            tokio_fs::rename(&tmp_path, &file_path).await?;
+        }
+        log_info!("Storage", "截断文件: {:?} -> {} 字节", file_path, len);
+        Ok(())
+    }
+
+    fn data_path(&self, key: &str) -> Option<PathBuf> {
+        if self.inline.contains(key) {
+            return None;
+        }
+        Some(self.get_file_path(key))
+    }
+
+    async fn content_hash(&self, key: &str) -> Result<Option<String>> {
+        let file_path = self.get_file_path(key);
+        if !file_path.exists() {
+            return Ok(None);
+        }
+        let content_hash = tokio::task::spawn_blocking(move || hash_file(&file_path))
+            .await
+            .map_err(|e| ProxyError::storage(format!("计算内容哈希失败: {}", e)))??;
+        Ok(Some(content_hash))
+    }
+}
+
+/// 转换写入错误，磁盘已满时返回 `ErrorKind::NoSpace` 以便上层触发紧急清理
+pub(crate) fn write_error(err: io::Error) -> ProxyError {
+    if is_no_space(&err) {
+        ProxyError::no_space(err.to_string())
+    } else {
+        err.into()
+    }
+}
+
+#[cfg(unix)]
+fn is_no_space(err: &io::Error) -> bool {
+    err.raw_os_error() == Some(libc::ENOSPC)
+}
+
+#[cfg(windows)]
+fn is_no_space(err: &io::Error) -> bool {
+    // ERROR_HANDLE_DISK_FULL / ERROR_DISK_FULL
+    matches!(err.raw_os_error(), Some(39) | Some(112))
+}
+
+#[cfg(not(any(unix, windows)))]
+fn is_no_space(_err: &io::Error) -> bool {
+    false
+}
+
+#[cfg(unix)]
+fn available_space(path: &Path) -> Option<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+fn available_space(_path: &Path) -> Option<u64> {
+    None
+} 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn storage(name: &str) -> (DiskStorage, PathBuf) {
+        let root = std::env::temp_dir().join(format!("disk-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let storage = DiskStorage::new(StorageConfig {
+            root_path: root.clone(),
+            chunk_size: 8192,
+            fast_root_path: None,
+            layout: ShardLayout::default(),
+            mmap: false,
+            mmap_threshold: 0,
+        });
+        (storage, root)
+    }
+
+    async fn put(storage: &DiskStorage, key: &str, data: &'static [u8]) {
+        let stream = futures::stream::iter(vec![Ok(Bytes::from_static(data))]);
+        storage.write(key, stream, (0, data.len() as u64 - 1)).await.unwrap();
+    }
+
+    #[test]
+    fn test_hash_file_sha256() {
+        let path = std::env::temp_dir().join(format!("disk-hash-{}", std::process::id()));
+        std::fs::write(&path, b"abc").unwrap();
+        assert_eq!(
+            hash_file(&path).unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert!(!is_legacy_hash(&hash_file(&path).unwrap()));
+        assert!(is_legacy_hash("900150983cd24fb0d6963f7d28e17f72"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_dedup_shares_blob_until_last_link() {
+        use std::os::unix::fs::MetadataExt;
+
+        let (storage, root) = storage("dedup");
+        put(&storage, "a", b"same content").await;
+        put(&storage, "b", b"same content").await;
+
+        let hash_a = storage.deduplicate("a").await.unwrap().unwrap();
+        let hash_b = storage.deduplicate("b").await.unwrap().unwrap();
+        assert_eq!(hash_a, hash_b);
+        let inode = |key: &str| std::fs::metadata(storage.get_file_path(key)).unwrap().ino();
+        assert_eq!(inode("a"), inode("b"));
+
+        // 索引持久化后重新打开仍能找到引用
+        let reopened = serde_json::from_slice::<HashMap<String, String>>(
+            &std::fs::read(root.join(BLOB_DIR).join(BLOB_INDEX_FILE)).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(reopened.len(), 2);
+
+        let blob_path = storage.blob_path(&hash_a);
+        storage.remove("a").await.unwrap();
+        assert!(blob_path.exists());
+        storage.remove("b").await.unwrap();
+        assert!(!blob_path.exists());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_write_detaches_shared_blob() {
+        let (storage, root) = storage("detach");
+        put(&storage, "a", b"shared").await;
+        put(&storage, "b", b"shared").await;
+        storage.deduplicate("a").await.unwrap();
+        storage.deduplicate("b").await.unwrap();
+
+        put(&storage, "a", b"SHARED").await;
+        assert_eq!(std::fs::read(storage.get_file_path("a")).unwrap(), b"SHARED");
+        assert_eq!(std::fs::read(storage.get_file_path("b")).unwrap(), b"shared");
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_scan_keeps_orphans() {
+        let (storage, root) = storage("scan");
+        put(&storage, "indexed", b"data").await;
+        storage.save_meta(&EntryMeta::new("indexed")).await.unwrap();
+        // 旧版本写入的数据文件没有元数据
+        put(&storage, "legacy", b"data").await;
+        // 元数据已保存但数据文件还没写入
+        storage.save_meta(&EntryMeta::new("pending")).await.unwrap();
+
+        let storage = DiskStorage::new(storage.config.clone());
+        let index = storage.load_index().await.unwrap();
+        let keys: Vec<_> = index.iter().map(|entry| entry.meta.key.as_str()).collect();
+        assert_eq!(keys, vec!["indexed"]);
+        assert!(storage.has_unindexed_data());
+        assert!(storage.meta_path("pending").exists());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_sparse_write_counts_allocated_blocks() {
+        let (storage, root) = storage("sparse");
+        let offset = 16 * 1024 * 1024;
+        let stream = futures::stream::iter(vec![Ok(Bytes::from(vec![1u8; 4096]))]);
+        storage.write("tail", stream, (offset, offset + 4095)).await.unwrap();
+        storage.save_meta(&EntryMeta::new("tail")).await.unwrap();
+
+        // 文件长度包含开头的空洞，实际占用只有写入的数据块
+        assert_eq!(storage.get_size("tail").await.unwrap(), Some(offset + 4096));
+        let allocated = storage.allocated_size("tail").await.unwrap().unwrap();
+        assert!(allocated < 1024 * 1024, "allocated {} bytes", allocated);
+
+        let index = storage.load_index().await.unwrap();
+        assert_eq!(index[0].size, offset + 4096);
+        assert_eq!(index[0].allocated, allocated);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use crate::log_info;
+use super::meta::{EntryMeta, IndexEntry};
+use super::tier::StorageTier;
+
+/// 内联小对象的日志文件名，保存在缓存根目录
+pub const INLINE_FILE: &str = "inline.log";
+
+/// 日志中的失效记录超过该数量且多于有效条目时重写日志
+const COMPACT_MIN_STALE: usize = 1024;
+
+/// 日志中的一行，`data` 为空表示删除
+#[derive(Serialize, Deserialize)]
+struct InlineRecord {
+    key: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    meta: Option<EntryMeta>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    data: Option<String>,
+}
+
+#[derive(Default)]
+struct InlineState {
+    entries: HashMap<String, (EntryMeta, Bytes)>,
+    /// 日志中的记录数，包括已被覆盖或删除的记录
+    records: usize,
+}
+
+/// 直接保存在索引中的小对象，所有条目追加写入同一个日志文件，数据常驻内存，
+/// 避免直播 HLS 中大量不足 1KB 的播放列表、密钥各占一个数据文件和元数据文件
+pub struct InlineStore {
+    path: PathBuf,
+    state: Mutex<InlineState>,
+}
+
+impl InlineStore {
+    /// 重放日志恢复条目，无法解析的行跳过
+    pub fn open(path: PathBuf) -> Self {
+        let mut state = InlineState::default();
+        if let Ok(file) = fs::File::open(&path) {
+            for line in BufReader::new(file).lines() {
+                let record = match line.map_err(|e| e.to_string()).and_then(|line| {
+                    serde_json::from_str::<InlineRecord>(&line).map_err(|e| e.to_string())
+                }) {
+                    Ok(record) => record,
+                    Err(e) => {
+                        log_info!("Storage", "跳过无法解析的内联记录: {:?} - {}", path, e);
+                        continue;
+                    }
+                };
+                state.records += 1;
+                match (record.meta, record.data.map(|data| STANDARD.decode(data))) {
+                    (Some(meta), Some(Ok(data))) => {
+                        state.entries.insert(record.key, (meta, Bytes::from(data)));
+                    }
+                    _ => {
+                        state.entries.remove(&record.key);
+                    }
+                }
+            }
+        }
+        Self {
+            path,
+            state: Mutex::new(state),
+        }
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.state.lock().unwrap().entries.contains_key(key)
+    }
+
+    pub fn get(&self, key: &str) -> Option<(EntryMeta, Bytes)> {
+        self.state.lock().unwrap().entries.get(key).cloned()
+    }
+
+    /// 保存或替换条目
+    pub fn put(&self, meta: &EntryMeta, data: Bytes) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let record = InlineRecord {
+            key: meta.key.clone(),
+            meta: Some(meta.clone()),
+            data: Some(STANDARD.encode(&data)),
+        };
+        self.append(&mut state, &record)?;
+        state.entries.insert(meta.key.clone(), (meta.clone(), data));
+        Ok(())
+    }
+
+    /// 删除条目，返回条目是否存在
+    pub fn remove(&self, key: &str) -> io::Result<Option<(EntryMeta, Bytes)>> {
+        let mut state = self.state.lock().unwrap();
+        if !state.entries.contains_key(key) {
+            return Ok(None);
+        }
+        let record = InlineRecord {
+            key: key.to_string(),
+            meta: None,
+            data: None,
+        };
+        self.append(&mut state, &record)?;
+        Ok(state.entries.remove(key))
+    }
+
+    /// 所有条目的索引
+    pub fn index(&self) -> Vec<IndexEntry> {
+        self.state
+            .lock()
+            .unwrap()
+            .entries
+            .values()
+            .map(|(meta, data)| IndexEntry {
+                meta: meta.clone(),
+                size: data.len() as u64,
+                allocated: data.len() as u64,
+                tier: StorageTier::Slow,
+            })
+            .collect()
+    }
+
+    fn append(&self, state: &mut InlineState, record: &InlineRecord) -> io::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        OpenOptions::new().create(true).append(true).open(&self.path)?.write_all(&line)?;
+        state.records += 1;
+
+        let stale = state.records - state.entries.len().min(state.records);
+        if stale >= COMPACT_MIN_STALE && stale > state.entries.len() {
+            if let Err(e) = self.compact(state) {
+                log_info!("Storage", "重写内联日志失败: {:?} - {}", self.path, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// 只保留有效条目重写日志
+    fn compact(&self, state: &mut InlineState) -> io::Result<()> {
+        let mut data = Vec::new();
+        for (key, (meta, bytes)) in &state.entries {
+            let record = InlineRecord {
+                key: key.clone(),
+                meta: Some(meta.clone()),
+                data: Some(STANDARD.encode(bytes)),
+            };
+            data.extend(serde_json::to_vec(&record)?);
+            data.push(b'\n');
+        }
+        let tmp = self.path.with_extension("log.tmp");
+        fs::write(&tmp, data)?;
+        fs::rename(&tmp, &self.path)?;
+        state.records = state.entries.len();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inline_store() {
+        let dir = std::env::temp_dir().join(format!("inline-store-{}", std::process::id()));
+        let path = dir.join(INLINE_FILE);
+        let _ = fs::remove_file(&path);
+
+        let store = InlineStore::open(path.clone());
+        let mut meta = EntryMeta::new("https://example.com/live.m3u8");
+        store.put(&meta, Bytes::from_static(b"#EXTM3U\n")).unwrap();
+        meta.content_length = Some(8);
+        store.put(&meta, Bytes::from_static(b"#EXTM3U\n")).unwrap();
+        store.put(&EntryMeta::new("https://example.com/key"), Bytes::from_static(b"0123")).unwrap();
+        assert!(store.remove("https://example.com/key").unwrap().is_some());
+        assert!(store.remove("https://example.com/key").unwrap().is_none());
+
+        let reopened = InlineStore::open(path.clone());
+        let index = reopened.index();
+        assert_eq!(index.len(), 1);
+        assert_eq!(index[0].meta.content_length, Some(8));
+        assert_eq!(reopened.get("https://example.com/live.m3u8").unwrap().1, Bytes::from_static(b"#EXTM3U\n"));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,213 @@
+use std::path::PathBuf;
+use std::time::SystemTime;
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use super::EntryMeta;
+
+/// 单个缓存条目的状态，用于排查播放缺段问题
+#[derive(Debug, Clone, Serialize)]
+pub struct EntryInfo {
+    pub key: String,
+    /// 数据文件路径
+    pub path: Option<PathBuf>,
+    /// 已缓存的连续字节数（从 0 开始）
+    pub cached_bytes: u64,
+    /// 实际占用的磁盘空间
+    pub allocated_bytes: u64,
+    pub tier: String,
+    pub hits: u32,
+    pub last_access: String,
+    pub last_write: String,
+    /// 按 Cache-Control / Expires 计算的剩余新鲜时间（秒），为负表示已过期
+    pub ttl_secs: Option<i64>,
+    pub meta: EntryMeta,
+}
+
+/// 单个 URL 的缓存情况
+#[derive(Debug, Clone, Serialize)]
+pub struct CacheInspection {
+    pub url: String,
+    pub key: String,
+    /// 完整对象大小，未知时为 None
+    pub total_size: Option<u64>,
+    /// 已缓存的区间（闭区间）
+    pub ranges: Vec<(u64, u64)>,
+    /// 未缓存的区间（闭区间），总大小未知时为空
+    pub gaps: Vec<(u64, u64)>,
+    pub entry: Option<EntryInfo>,
+    /// MP4 尾部预取缓存
+    pub tail: Option<EntryInfo>,
+}
+
+/// 区间的数据来源
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlanSource {
+    Cache,
+    Network,
+}
+
+/// 请求范围中的一段（闭区间）
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PlanSegment {
+    pub start: u64,
+    pub end: u64,
+    pub source: PlanSource,
+}
+
+/// 请求范围中各段分别从缓存还是网络读取
+#[derive(Debug, Clone, Serialize)]
+pub struct RangePlan {
+    pub url: String,
+    pub start: u64,
+    pub end: u64,
+    /// 完整对象大小，未知时为 None
+    pub total_size: Option<u64>,
+    pub cache_bytes: u64,
+    pub network_bytes: u64,
+    pub segments: Vec<PlanSegment>,
+}
+
+impl RangePlan {
+    pub fn new(url: &str, start: u64, end: u64, total_size: Option<u64>, cached: &[(u64, u64)]) -> Self {
+        let segments = plan_segments(cached, start, end);
+        let bytes = |source: PlanSource| {
+            segments
+                .iter()
+                .filter(|segment| segment.source == source)
+                .map(|segment| segment.end - segment.start + 1)
+                .sum()
+        };
+        Self {
+            url: url.to_string(),
+            start,
+            end,
+            total_size,
+            cache_bytes: bytes(PlanSource::Cache),
+            network_bytes: bytes(PlanSource::Network),
+            segments,
+        }
+    }
+}
+
+/// 按已缓存的区间将 `[start, end]` 划分为缓存段和网络段
+pub fn plan_segments(cached: &[(u64, u64)], start: u64, end: u64) -> Vec<PlanSegment> {
+    let mut segments: Vec<PlanSegment> = Vec::new();
+    if start > end {
+        return segments;
+    }
+    let mut sorted: Vec<_> = cached
+        .iter()
+        .filter(|(s, e)| *e >= start && *s <= end)
+        .map(|&(s, e)| (s.max(start), e.min(end)))
+        .collect();
+    sorted.sort_unstable();
+
+    let mut next = start;
+    for (s, e) in sorted {
+        if e < next {
+            continue;
+        }
+        let s = s.max(next);
+        if s > next {
+            segments.push(PlanSegment { start: next, end: s - 1, source: PlanSource::Network });
+        }
+        match segments.last_mut() {
+            Some(last) if last.source == PlanSource::Cache && last.end + 1 == s => last.end = e,
+            _ => segments.push(PlanSegment { start: s, end: e, source: PlanSource::Cache }),
+        }
+        if e == end {
+            return segments;
+        }
+        next = e + 1;
+    }
+    segments.push(PlanSegment { start: next, end, source: PlanSource::Network });
+    segments
+}
+
+pub fn format_time(time: SystemTime) -> String {
+    DateTime::<Local>::from(time).to_rfc3339()
+}
+
+/// 根据保存的响应头计算剩余新鲜时间，没有相关响应头时返回 None
+pub fn freshness_ttl(meta: &EntryMeta, stored_at: SystemTime) -> Option<i64> {
+    let header = |name: &str| {
+        meta.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    };
+    let age = SystemTime::now()
+        .duration_since(stored_at)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+
+    if let Some(cache_control) = header("cache-control") {
+        for directive in cache_control.split(',').map(|d| d.trim()) {
+            if directive.eq_ignore_ascii_case("no-cache") || directive.eq_ignore_ascii_case("no-store") {
+                return Some(0);
+            }
+            if let Some(max_age) = directive.strip_prefix("max-age=") {
+                if let Ok(max_age) = max_age.trim_matches('"').parse::<i64>() {
+                    return Some(max_age - age);
+                }
+            }
+        }
+    }
+
+    let expires = DateTime::parse_from_rfc2822(header("expires")?).ok()?;
+    Some(expires.timestamp() - Local::now().timestamp())
+}
+
+/// 计算 `[0, total)` 中未被 `ranges` 覆盖的区间，区间均为闭区间
+pub fn missing_ranges(ranges: &[(u64, u64)], total: u64) -> Vec<(u64, u64)> {
+    let mut sorted = ranges.to_vec();
+    sorted.sort_unstable();
+
+    let mut gaps = Vec::new();
+    let mut next = 0;
+    for (start, end) in sorted {
+        if start > next {
+            gaps.push((next, start - 1));
+        }
+        next = next.max(end.saturating_add(1));
+    }
+    if next < total {
+        gaps.push((next, total - 1));
+    }
+    gaps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_ranges() {
+        assert_eq!(missing_ranges(&[], 100), vec![(0, 99)]);
+        assert_eq!(missing_ranges(&[(0, 99)], 100), vec![]);
+        assert_eq!(missing_ranges(&[(90, 99), (0, 9)], 100), vec![(10, 89)]);
+        assert_eq!(missing_ranges(&[(0, 49), (20, 59)], 100), vec![(60, 99)]);
+    }
+
+    #[test]
+    fn test_plan_segments() {
+        let plan = plan_segments(&[(0, 49), (50, 59), (90, 99)], 10, 94);
+        assert_eq!(plan, vec![
+            PlanSegment { start: 10, end: 59, source: PlanSource::Cache },
+            PlanSegment { start: 60, end: 89, source: PlanSource::Network },
+            PlanSegment { start: 90, end: 94, source: PlanSource::Cache },
+        ]);
+        assert_eq!(plan_segments(&[], 0, 9), vec![PlanSegment { start: 0, end: 9, source: PlanSource::Network }]);
+    }
+
+    #[test]
+    fn test_freshness_ttl() {
+        let mut meta = EntryMeta::new("http://example.com/a.mp4");
+        assert_eq!(freshness_ttl(&meta, SystemTime::now()), None);
+
+        meta.headers.push(("cache-control".to_string(), "public, max-age=3600".to_string()));
+        let ttl = freshness_ttl(&meta, SystemTime::now()).unwrap();
+        assert!(ttl > 3590 && ttl <= 3600);
+    }
+}
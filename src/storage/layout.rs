@@ -0,0 +1,132 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use crate::log_info;
+
+/// 缓存清单文件，记录缓存目录使用的布局
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// 分片目录布局：数据文件保存在 `<h[0..w]>/<h[w..2w]>/.../<h>`，共 `depth` 级目录，每级 `width` 个哈希字符。
+/// 层级少、字符多时单个目录下的文件更多，层级多时目录数量更多
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShardLayout {
+    /// 目录层级数，0 表示所有文件直接放在根目录
+    pub depth: usize,
+    /// 每级目录名使用的哈希字符数
+    pub width: usize,
+}
+
+impl Default for ShardLayout {
+    fn default() -> Self {
+        Self { depth: 2, width: 2 }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheManifest {
+    layout: ShardLayout,
+}
+
+impl ShardLayout {
+    pub fn new(depth: usize, width: usize) -> Self {
+        Self { depth, width }
+    }
+
+    /// MD5 哈希为 32 个字符，目录名最多使用其中一半
+    pub fn is_valid(&self) -> bool {
+        (1..=4).contains(&self.width) && self.depth <= 4 && self.depth * self.width <= 16
+    }
+
+    /// 缓存键对应的数据文件路径
+    pub fn file_path(&self, root: &Path, key: &str) -> PathBuf {
+        // 使用MD5生成URL的哈希值
+        let hash = format!("{:x}", md5::compute(key.as_bytes()));
+
+        let mut path = root.to_path_buf();
+        for level in 0..self.depth {
+            path.push(&hash[level * self.width..(level + 1) * self.width]);
+        }
+        path.join(hash)
+    }
+
+    /// 遍历分片目录，返回最后一级目录
+    pub fn leaf_dirs(&self, root: &Path) -> io::Result<Vec<PathBuf>> {
+        let mut dirs = vec![root.to_path_buf()];
+        for _ in 0..self.depth {
+            let mut next = Vec::new();
+            for dir in dirs {
+                for entry in fs::read_dir(&dir)? {
+                    let path = entry?.path();
+                    let is_shard = path.file_name().and_then(|name| name.to_str()).is_some_and(|name| {
+                        name.len() == self.width && name.chars().all(|c| c.is_ascii_hexdigit())
+                    });
+                    if is_shard && path.is_dir() {
+                        next.push(path);
+                    }
+                }
+            }
+            dirs = next;
+        }
+        Ok(dirs)
+    }
+
+    /// 读取缓存目录清单中记录的布局
+    pub fn recorded(root: &Path) -> Option<Self> {
+        let data = fs::read(root.join(MANIFEST_FILE)).ok()?;
+        let manifest: CacheManifest = serde_json::from_slice(&data).ok()?;
+        Some(manifest.layout).filter(ShardLayout::is_valid)
+    }
+
+    /// 确定缓存目录实际使用的布局并写入清单。
+    /// 已有清单时以清单为准，已有数据但没有清单时按默认布局处理，更改布局需要用 migrate 命令迁移
+    pub fn resolve(root: &Path, configured: ShardLayout) -> Self {
+        if let Some(recorded) = Self::recorded(root) {
+            if recorded != configured {
+                log_info!("Storage", "缓存目录已使用 {:?} 布局，忽略配置的 {:?}，如需更改请使用 migrate 命令", recorded, configured);
+            }
+            return recorded;
+        }
+
+        let layout = if !configured.is_valid() {
+            log_info!("Storage", "无效的目录布局 {:?}，使用默认布局", configured);
+            Self::default()
+        } else if configured != Self::default() && Self::default().leaf_dirs(root).is_ok_and(|dirs| !dirs.is_empty()) {
+            log_info!("Storage", "缓存目录已有默认布局的数据，忽略配置的 {:?}", configured);
+            Self::default()
+        } else {
+            configured
+        };
+
+        let manifest = CacheManifest { layout };
+        let written = fs::create_dir_all(root)
+            .and_then(|_| fs::write(root.join(MANIFEST_FILE), serde_json::to_vec(&manifest)?));
+        if let Err(e) = written {
+            log_info!("Storage", "写入缓存清单失败: {}", e);
+        }
+        layout
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_path() {
+        let root = Path::new("/cache");
+        let hash = format!("{:x}", md5::compute(b"http://example.com/a.mp4"));
+
+        let path = ShardLayout::default().file_path(root, "http://example.com/a.mp4");
+        assert_eq!(path, root.join(&hash[0..2]).join(&hash[2..4]).join(&hash));
+
+        let path = ShardLayout::new(1, 3).file_path(root, "http://example.com/a.mp4");
+        assert_eq!(path, root.join(&hash[0..3]).join(&hash));
+
+        let path = ShardLayout::new(0, 2).file_path(root, "http://example.com/a.mp4");
+        assert_eq!(path, root.join(&hash));
+
+        assert!(!ShardLayout::new(2, 0).is_valid());
+        assert!(!ShardLayout::new(5, 2).is_valid());
+    }
+}
@@ -1,18 +1,80 @@
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::collections::HashMap;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::sync::RwLock;
-use futures::Stream;
+use futures::{Stream, StreamExt};
 use bytes::Bytes;
+use hyper::HeaderMap;
+use serde_json::Value;
 
-use crate::utils::error::Result;
-use super::StorageEngine;
+use crate::utils::error::{ErrorKind, ProxyError, Result};
+use crate::utils::bloom::BloomFilter;
+use crate::log_info;
+use super::{EntryMeta, StorageEngine};
+use super::meta::{IndexEntry, DEFAULT_STORED_HEADERS, MAX_USER_META_SIZE};
+use super::schedule::CleanupSchedule;
+use super::tier::{plan_tier_moves, StorageTier, TierCandidate, TieringConfig};
+use super::inspect::{format_time, freshness_ttl, EntryInfo};
+use super::layout::ShardLayout;
+use super::disk::is_legacy_hash;
+use super::permits::{IoPermitConfig, IoPermitStats, IoPermits};
+use super::verify::VerifyReport;
+use super::warmup::{IndexProgress, IndexStatus};
+use super::writer::{EntryWriter, FsyncPolicy};
+
+/// 校验、查看条目前等待索引重建的最长时间（秒）
+const INDEX_WAIT_SECS: u32 = 30;
+
+/// 校验发现的问题及修复方式
+enum VerifyProblem {
+    /// 删除整个条目
+    Remove(String),
+    /// 将记录的大小截断到实际文件大小
+    Truncate(u64),
+}
 
 #[derive(Clone)]
 pub struct StorageManagerConfig {
     pub max_cache_size: u64,
     pub max_file_count: usize,
-    pub cleanup_interval: Duration,
+    /// 定期清理计划
+    pub cleanup: CleanupSchedule,
+    /// 磁盘保留的最小剩余空间，大块写入前检查，不足时先淘汰冷数据
+    pub reserved_free_space: u64,
+    /// 达到该大小的写入视为大块写入，需要检查剩余空间
+    pub large_write_threshold: u64,
+    /// 磁盘写满时紧急清理的最少字节数
+    pub emergency_evict_size: u64,
+    /// 冷热分层配置，未设置时只使用单层存储
+    pub tiering: Option<TieringConfig>,
+    /// 对下载完成的数据按内容哈希去重，相同内容的 URL 共享同一份数据
+    pub dedup: bool,
+    /// 已知缓存键过滤器的初始容量，加载索引后条目更多时按条目数扩大
+    pub index_capacity: usize,
+    /// 随缓存保存的上游响应头，支持以 `*` 结尾的前缀匹配
+    pub stored_headers: Vec<String>,
+    /// 达到该大小的条目淘汰时只截断尾部，保留开头部分用于快速起播，0 表示总是删除整个条目
+    pub trim_threshold: u64,
+    /// 截断后保留的大小
+    pub trim_keep: u64,
+    /// 数据文件的分片目录布局，只对新建的缓存目录生效
+    pub layout: ShardLayout,
+    /// 完整缓存的大文件通过内存映射读取，减少逐块读取的系统调用
+    pub mmap_reads: bool,
+    /// 使用内存映射读取的最小文件大小
+    pub mmap_threshold: u64,
+    /// 读写并发许可
+    pub io_permits: IoPermitConfig,
+    /// 不超过该大小的完整对象（播放列表、密钥、缩略图）直接保存在索引中，不单独建文件，0 表示不启用
+    pub inline_max_size: u64,
+    /// 检查缓存目录是否可用的间隔，目录所在的移动硬盘被卸载时转为直接转发，重新挂载后重建索引。
+    /// 为 None 时不检查
+    pub mount_check_interval: Option<Duration>,
+    /// 启动时并行加载索引的分片数，加载期间未加载到的键直接转发到源站
+    pub index_load_shards: usize,
+    /// 边下载边缓存时数据同步到磁盘的时机，默认只在写入结束时同步
+    pub fsync: FsyncPolicy,
 }
 
 impl Default for StorageManagerConfig {
@@ -20,16 +82,144 @@ impl Default for StorageManagerConfig {
         Self {
             max_cache_size: 1024 * 1024 * 1024, // 1GB
             max_file_count: 1000,
-            cleanup_interval: Duration::from_secs(60),
+            cleanup: CleanupSchedule::default(),
+            reserved_free_space: 256 * 1024 * 1024, // 256MB
+            large_write_threshold: 4 * 1024 * 1024, // 4MB
+            emergency_evict_size: 64 * 1024 * 1024, // 64MB
+            tiering: None,
+            dedup: false,
+            index_capacity: 100_000,
+            stored_headers: DEFAULT_STORED_HEADERS.iter().map(|name| name.to_string()).collect(),
+            trim_threshold: 1024 * 1024 * 1024, // 1GB
+            trim_keep: 32 * 1024 * 1024,        // 32MB
+            layout: ShardLayout::default(),
+            mmap_reads: false,
+            mmap_threshold: 64 * 1024 * 1024, // 64MB
+            io_permits: IoPermitConfig::default(),
+            inline_max_size: 16 * 1024, // 16KB
+            mount_check_interval: Some(Duration::from_secs(5)),
+            index_load_shards: 8,
+            fsync: FsyncPolicy::default(),
         }
     }
 }
 
+/// 重建索引需要的共享状态，启动时和缓存目录恢复时使用
+struct IndexHandle<E> {
+    engine: Arc<E>,
+    cache_entries: Arc<RwLock<HashMap<String, CacheEntry>>>,
+    total_size: Arc<RwLock<u64>>,
+    known_keys: Arc<RwLock<BloomFilter>>,
+    index_ready: Arc<AtomicBool>,
+    progress: Arc<IndexProgress>,
+    shards: usize,
+}
+
+impl<E: StorageEngine> IndexHandle<E> {
+    /// 从持久化的元数据重建索引，各分片并行扫描，扫描完一份就加入索引
+    async fn load(&self) {
+        let shards = self.shards.max(1);
+        self.progress.start(shards);
+        let mut pending: futures::stream::FuturesUnordered<_> = (0..shards)
+            .map(|shard| self.engine.load_index_shard(shard, shards))
+            .collect();
+
+        let mut failed = false;
+        while let Some(result) = pending.next().await {
+            match result {
+                Ok(index) => {
+                    let count = index.len();
+                    self.insert(index).await;
+                    self.progress.shard_loaded(count);
+                }
+                Err(e) => {
+                    log_info!("Storage", "加载索引分片失败: {}", e);
+                    failed = true;
+                }
+            }
+        }
+        self.progress.finish();
+
+        if failed {
+            // 索引不完整时保持回退到磁盘检查
+            log_info!("Storage", "加载索引失败，缓存查询将直接检查磁盘");
+            return;
+        }
+        self.resize_filter().await;
+        self.index_ready.store(true, Ordering::Release);
+        let status = self.progress.status(true);
+        log_info!("Storage", "索引重建完成: {} 个条目, {} 字节, 用时 {:.1} 秒", status.entries, *self.total_size.read().await, status.elapsed_secs);
+    }
+
+    async fn insert(&self, index: Vec<IndexEntry>) {
+        let mut entries = self.cache_entries.write().await;
+        let mut total = self.total_size.write().await;
+        let mut filter = self.known_keys.write().await;
+        let now = SystemTime::now();
+        for item in index {
+            filter.insert(&item.meta.key);
+            if entries.contains_key(&item.meta.key) {
+                continue;
+            }
+            *total += item.allocated;
+            entries.insert(item.meta.key.clone(), CacheEntry {
+                key: item.meta.key.clone(),
+                total_size: item.size,
+                allocated: item.allocated,
+                last_access: now,
+                last_write: UNIX_EPOCH,
+                hits: 0,
+                tier: item.tier,
+                meta: item.meta,
+            });
+        }
+    }
+
+    /// 条目数超过过滤器容量时按条目数重建过滤器，避免误判率随缓存增长上升。
+    /// 先取索引锁再取过滤器锁，与写入时的加锁顺序一致，重建期间新增的键不会丢失
+    async fn resize_filter(&self) {
+        let entries = self.cache_entries.read().await;
+        let mut filter = self.known_keys.write().await;
+        if entries.len() <= filter.capacity() {
+            return;
+        }
+        let mut resized = BloomFilter::new(entries.len() * 2, 0.01);
+        for key in entries.keys() {
+            resized.insert(key);
+        }
+        log_info!("Storage", "已知键过滤器扩容: {} -> {}", filter.capacity(), resized.capacity());
+        *filter = resized;
+    }
+
+    /// 清空内存中的索引，之后的查询不再访问磁盘
+    async fn clear(&self) {
+        self.index_ready.store(false, Ordering::Release);
+        self.cache_entries.write().await.clear();
+        *self.total_size.write().await = 0;
+        self.known_keys.write().await.clear();
+    }
+}
+
+/// 连续写入一个条目，由 `StorageManager::open_writer` 创建
+pub struct ManagedWriter {
+    key: String,
+    start: u64,
+    written: u64,
+    /// 上次同步后写入的字节数
+    unsynced: u64,
+    writer: Box<dyn EntryWriter>,
+}
+
 #[derive(Clone)]
 struct CacheEntry {
     key: String,
     total_size: u64,     // 文件的总大小
+    allocated: u64,      // 实际占用的磁盘空间，用于容量统计
     last_access: SystemTime,
+    last_write: SystemTime,
+    hits: u32,           // 当前分层周期内的命中次数
+    tier: StorageTier,
+    meta: EntryMeta,
 }
 
 pub struct StorageManager<E> {
@@ -37,22 +227,124 @@ pub struct StorageManager<E> {
     config: StorageManagerConfig,
     cache_entries: Arc<RwLock<HashMap<String, CacheEntry>>>,
     total_size: Arc<RwLock<u64>>,
+    known_keys: Arc<RwLock<BloomFilter>>, // 出现过的缓存键，不在其中的键一定未缓存
+    index_ready: Arc<AtomicBool>,
+    index_progress: Arc<IndexProgress>,
+    /// 缓存目录是否可用
+    online: Arc<AtomicBool>,
+    permits: IoPermits,
 }
 
 impl<E: StorageEngine + 'static> StorageManager<E> {
     pub fn new(engine: E, config: StorageManagerConfig) -> Self {
+        let known_keys = BloomFilter::new(config.index_capacity, 0.01);
+        let permits = IoPermits::new(&config.io_permits);
         let manager = Self {
             engine: Arc::new(engine),
             config,
             cache_entries: Arc::new(RwLock::new(HashMap::new())),
             total_size: Arc::new(RwLock::new(0)),
+            known_keys: Arc::new(RwLock::new(known_keys)),
+            index_ready: Arc::new(AtomicBool::new(false)),
+            index_progress: Arc::new(IndexProgress::default()),
+            online: Arc::new(AtomicBool::new(true)),
+            permits,
         };
         
+        // 从持久化的元数据重建索引
+        manager.start_index_load();
+        
         // 启动清理任务
         manager.start_cleanup();
+        if let Some(tiering) = manager.config.tiering.clone() {
+            manager.start_tiering(tiering);
+        }
+        if let Some(interval) = manager.config.mount_check_interval {
+            manager.start_mount_watch(interval);
+        }
         manager
     }
     
+    fn start_index_load(&self) {
+        // 任务开始前就进入加载状态，启动后的第一批请求不会逐个检查磁盘
+        self.index_progress.start(self.config.index_load_shards.max(1));
+        let index = self.index_handle();
+        tokio::spawn(async move {
+            index.load().await;
+        });
+    }
+
+    fn index_handle(&self) -> IndexHandle<E> {
+        IndexHandle {
+            engine: self.engine.clone(),
+            cache_entries: self.cache_entries.clone(),
+            total_size: self.total_size.clone(),
+            known_keys: self.known_keys.clone(),
+            index_ready: self.index_ready.clone(),
+            progress: self.index_progress.clone(),
+            shards: self.config.index_load_shards,
+        }
+    }
+
+    /// 定期检查缓存目录，不可用时清空索引并转为直接转发，恢复后重新扫描索引
+    fn start_mount_watch(&self, interval: Duration) {
+        let index = self.index_handle();
+        let online = self.online.clone();
+
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                let available = index.engine.is_available().await;
+                if available == online.load(Ordering::Acquire) {
+                    continue;
+                }
+                if available {
+                    log_info!("Storage", "缓存目录已恢复，重新扫描索引");
+                    index.load().await;
+                    online.store(true, Ordering::Release);
+                } else {
+                    log_info!("Storage", "缓存目录不可用，请求将直接转发到源站");
+                    online.store(false, Ordering::Release);
+                    index.clear().await;
+                }
+            }
+        });
+    }
+
+    /// 缓存目录是否可用，不可用时所有键都视为未缓存
+    pub fn is_online(&self) -> bool {
+        self.online.load(Ordering::Acquire)
+    }
+
+    /// 索引加载进度
+    pub fn index_status(&self) -> IndexStatus {
+        self.index_progress.status(self.index_ready.load(Ordering::Acquire))
+    }
+
+    /// 索引是否正在加载，加载期间不写入缓存
+    pub fn is_index_loading(&self) -> bool {
+        self.index_progress.is_loading()
+    }
+
+    /// 索引加载完成后，不在过滤器中的键一定没有缓存，无需访问磁盘。
+    /// 加载期间尚未加载到的键也按未缓存处理，直接转发到源站，避免冷启动时逐个请求扫描磁盘。
+    /// 存储中有没有元数据的旧数据文件时过滤器不完整，仍检查磁盘
+    async fn is_definite_miss(&self, key: &str) -> bool {
+        !self.is_online()
+            || self.index_progress.is_loading()
+            || (self.index_ready.load(Ordering::Acquire)
+                && !self.engine.has_unindexed_data()
+                && !self.known_keys.read().await.contains(key))
+    }
+
+    fn ensure_online(&self, key: &str) -> Result<()> {
+        if self.is_online() {
+            Ok(())
+        } else {
+            Err(ProxyError::storage(format!("缓存目录不可用: {}", key)))
+        }
+    }
+    
     fn start_cleanup(&self) {
         let cache_entries = self.cache_entries.clone();
         let total_size = self.total_size.clone();
@@ -61,46 +353,238 @@ impl<E: StorageEngine + 'static> StorageManager<E> {
         
         tokio::spawn(async move {
             loop {
-                tokio::time::sleep(config.cleanup_interval).await;
+                tokio::time::sleep(config.cleanup.interval).await;
+                
+                // 不在低峰时段时跳过，磁盘写满时仍会紧急清理
+                if !config.cleanup.is_due_now() {
+                    continue;
+                }
                 
-                // 检查缓存大小
                 let mut entries = cache_entries.write().await;
                 let mut total = total_size.write().await;
-                
-                if *total <= config.max_cache_size && entries.len() <= config.max_file_count {
+                enforce_limits(engine.as_ref(), &mut entries, &mut total, &config).await;
+            }
+        });
+    }
+    
+    /// 立即执行一次清理，返回释放的字节数和删除的条目数
+    pub async fn cleanup(&self) -> (u64, usize) {
+        let mut entries = self.cache_entries.write().await;
+        let mut total = self.total_size.write().await;
+        let count = entries.len();
+        let freed = enforce_limits(self.engine.as_ref(), &mut entries, &mut total, &self.config).await;
+        let removed = count - entries.len();
+        log_info!("Storage", "手动清理完成: 释放 {} 字节, 删除 {} 个条目", freed, removed);
+        (freed, removed)
+    }
+
+    /// 校验全部缓存条目，`repair` 为 true 时修复或删除损坏的条目
+    pub async fn verify(&self, repair: bool) -> VerifyReport {
+        let started = Instant::now();
+        let mut report = VerifyReport {
+            started_at: chrono::Local::now().to_rfc3339(),
+            ..Default::default()
+        };
+
+        self.wait_for_index().await;
+        let snapshot: Vec<CacheEntry> = self.cache_entries.read().await.values().cloned().collect();
+        for entry in snapshot {
+            report.checked += 1;
+            let key = entry.key.as_str();
+            let actual = match self.engine.get_size(key).await {
+                Ok(size) => size,
+                Err(e) => {
+                    report.add_issue(key, format!("读取文件大小失败: {}", e), false);
                     continue;
                 }
-                
-                // 收集需要删除的条目
-                let mut to_remove = Vec::new();
-                {
-                    // 按最后访问时间排序
-                    let mut entry_list: Vec<_> = entries.values().cloned().collect();
-                    entry_list.sort_by(|a, b| a.last_access.cmp(&b.last_access));
-                    
-                    // 收集要删除的键，直到满足大小限制
-                    let mut current_total = *total;
-                    let mut current_count = entries.len();
-                    
-                    for entry in entry_list {
-                        if current_total <= config.max_cache_size && current_count <= config.max_file_count {
-                            break;
+            };
+
+            let problem = match actual {
+                None => Some(VerifyProblem::Remove("数据文件缺失".to_string())),
+                Some(size) if entry.meta.content_length.is_some_and(|length| size > length) => {
+                    Some(VerifyProblem::Remove(format!(
+                        "数据大小 {} 超过文件总大小 {}",
+                        size,
+                        entry.meta.content_length.unwrap_or(0)
+                    )))
+                }
+                Some(size) if size < entry.total_size => Some(VerifyProblem::Truncate(size)),
+                Some(size) => self.check_hash(&entry, size, &mut report).await,
+            };
+
+            match problem {
+                None => report.healthy += 1,
+                Some(VerifyProblem::Remove(reason)) => {
+                    if repair {
+                        self.remove(key).await;
+                    }
+                    report.add_issue(key, reason, repair);
+                }
+                Some(VerifyProblem::Truncate(size)) => {
+                    if repair {
+                        let mut entries = self.cache_entries.write().await;
+                        if let Some(cached) = entries.get_mut(key) {
+                            let mut total = self.total_size.write().await;
+                            let allocated = cached.allocated.min(size);
+                            *total = total.saturating_sub(cached.allocated - allocated);
+                            cached.total_size = size;
+                            cached.allocated = allocated;
                         }
-                        current_total -= entry.total_size;
-                        current_count -= 1;
-                        to_remove.push(entry);
                     }
+                    report.add_issue(
+                        key,
+                        format!("记录的范围 {} 字节超过实际文件大小 {} 字节", entry.total_size, size),
+                        repair,
+                    );
                 }
-                
-                // 删除收集到的条目
-                for entry in to_remove {
-                    // 使用空流写入来清除文件
-                    if let Ok(_) = engine.write(&entry.key, futures::stream::empty(), (0, 0)).await {
-                        if let Some(removed) = entries.remove(&entry.key) {
-                            *total -= removed.total_size;
+            }
+        }
+
+        report.duration_secs = started.elapsed().as_secs_f64();
+        log_info!(
+            "Storage",
+            "缓存校验完成: 检查 {} 个条目, 正常 {} 个, 问题 {} 个",
+            report.checked,
+            report.healthy,
+            report.issues.len()
+        );
+        report
+    }
+
+    /// 等待启动时的索引重建完成，最多等待 `INDEX_WAIT_SECS` 秒
+    async fn wait_for_index(&self) {
+        for _ in 0..INDEX_WAIT_SECS {
+            if self.index_ready.load(Ordering::Acquire) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+    }
+
+    /// 内容完整时重新计算哈希并与记录的哈希比较
+    async fn check_hash(&self, entry: &CacheEntry, size: u64, report: &mut VerifyReport) -> Option<VerifyProblem> {
+        let expected = entry.meta.content_hash.as_ref().filter(|hash| !is_legacy_hash(hash))?;
+        if entry.meta.content_length != Some(size) {
+            return None;
+        }
+        report.hashed += 1;
+        match self.engine.content_hash(&entry.key).await {
+            Ok(Some(actual)) if &actual != expected => {
+                Some(VerifyProblem::Remove("内容校验失败".to_string()))
+            }
+            Ok(_) => None,
+            Err(e) => Some(VerifyProblem::Remove(format!("计算内容哈希失败: {}", e))),
+        }
+    }
+
+    /// 统计键前缀下的缓存大小和条目数
+    pub async fn prefix_usage(&self, prefix: &str) -> (u64, usize) {
+        let entries = self.cache_entries.read().await;
+        entries
+            .values()
+            .filter(|entry| entry.key.starts_with(prefix))
+            .fold((0, 0), |(bytes, count), entry| (bytes + entry.allocated, count + 1))
+    }
+
+    /// 按最后访问时间淘汰键前缀下的条目，直到该前缀的总大小不超过 `quota`，返回释放的字节数
+    pub async fn enforce_prefix_quota(&self, prefix: &str, quota: u64) -> u64 {
+        let mut candidates: Vec<(SystemTime, String, u64)> = {
+            let entries = self.cache_entries.read().await;
+            entries
+                .values()
+                .filter(|entry| entry.key.starts_with(prefix))
+                .map(|entry| (entry.last_access, entry.key.clone(), entry.allocated))
+                .collect()
+        };
+        let mut usage: u64 = candidates.iter().map(|(_, _, size)| size).sum();
+        if usage <= quota {
+            return 0;
+        }
+
+        candidates.sort();
+        let mut freed = 0;
+        for (_, key, size) in candidates {
+            if usage <= quota {
+                break;
+            }
+            self.remove(&key).await;
+            usage = usage.saturating_sub(size);
+            freed += size;
+        }
+        log_info!("Storage", "命名空间超出配额: {} 释放 {} 字节", prefix, freed);
+        freed
+    }
+
+    /// 删除键前缀下的全部条目，返回释放的字节数和删除的条目数
+    pub async fn purge_prefix(&self, prefix: &str) -> (u64, usize) {
+        let keys: Vec<(String, u64)> = {
+            let entries = self.cache_entries.read().await;
+            entries
+                .values()
+                .filter(|entry| entry.key.starts_with(prefix))
+                .map(|entry| (entry.key.clone(), entry.allocated))
+                .collect()
+        };
+        let freed = keys.iter().map(|(_, size)| size).sum();
+        for (key, _) in &keys {
+            self.remove(key).await;
+        }
+        log_info!("Storage", "清除前缀: {} 释放 {} 字节, 删除 {} 个条目", prefix, freed, keys.len());
+        (freed, keys.len())
+    }
+
+    /// 删除条目及其数据
+    pub async fn remove(&self, key: &str) {
+        if let Some(entry) = self.cache_entries.write().await.remove(key) {
+            let mut total = self.total_size.write().await;
+            *total = total.saturating_sub(entry.allocated);
+        }
+        if let Err(e) = self.engine.remove(key).await {
+            log_info!("Storage", "删除损坏条目失败: {} - {}", key, e);
+        }
+    }
+    
+    fn start_tiering(&self, tiering: TieringConfig) {
+        let cache_entries = self.cache_entries.clone();
+        let engine = self.engine.clone();
+
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(tiering.interval).await;
+
+                let moves = {
+                    let entries = cache_entries.read().await;
+                    let candidates: Vec<_> = entries
+                        .values()
+                        .map(|entry| TierCandidate {
+                            key: &entry.key,
+                            size: entry.total_size,
+                            tier: entry.tier,
+                            hits: entry.hits,
+                            last_access: entry.last_access,
+                            last_write: entry.last_write,
+                        })
+                        .collect();
+                    plan_tier_moves(&candidates, &tiering)
+                };
+
+                for (key, tier) in moves {
+                    match engine.move_to_tier(&key, tier).await {
+                        Ok(()) => {
+                            if let Some(entry) = cache_entries.write().await.get_mut(&key) {
+                                entry.tier = tier;
+                            }
+                        }
+                        Err(e) => {
+                            log_info!("Storage", "分层迁移失败: {} -> {:?} - {}", key, tier, e);
                         }
                     }
                 }
+
+                // 命中次数减半，只保留近期热度
+                for entry in cache_entries.write().await.values_mut() {
+                    entry.hits /= 2;
+                }
             }
         });
     }
@@ -109,41 +593,366 @@ impl<E: StorageEngine + 'static> StorageManager<E> {
     where
         S: Stream<Item = Result<Bytes>> + Send + Unpin + 'static,
     {
+        self.ensure_online(key)?;
+        let permit = self.permits.acquire_write().await?;
         let bytes_written = self.engine.write(key, stream, range).await?;
+        drop(permit);
+        self.record_write(key, range.0 + bytes_written).await;
+        
+        Ok(bytes_written)
+    }
+
+    /// 数据写到 `end_pos` 后更新索引中的大小和容量统计
+    async fn record_write(&self, key: &str, end_pos: u64) {
+        // 容量按实际占用统计，中间有空洞的稀疏文件只计算已写入的数据
+        let allocated = match self.engine.allocated_size(key).await {
+            Ok(Some(allocated)) => allocated,
+            _ => end_pos,
+        };
         
         // 更新缓存信息
         let mut entries = self.cache_entries.write().await;
         let mut total = self.total_size.write().await;
         
-        let end_pos = range.0 + bytes_written;
+        let mut is_new = false;
         
         if let Some(entry) = entries.get_mut(key) {
             // 更新文件的总大小（如果新写入的范围扩展了文件）
             if end_pos > entry.total_size {
-                *total = *total - entry.total_size + end_pos;
                 entry.total_size = end_pos;
             }
+            *total = total.saturating_sub(entry.allocated) + allocated;
+            entry.allocated = allocated;
             entry.last_access = SystemTime::now();
+            entry.last_write = entry.last_access;
         } else {
+            let now = SystemTime::now();
             entries.insert(key.to_string(), CacheEntry {
                 key: key.to_string(),
                 total_size: end_pos,
-                last_access: SystemTime::now(),
+                allocated,
+                last_access: now,
+                last_write: now,
+                hits: 0,
+                tier: StorageTier::Slow,
+                meta: EntryMeta::new(key),
             });
-            *total += end_pos;
+            *total += allocated;
+            is_new = true;
         }
+        drop(total);
+        drop(entries);
         
-        Ok(bytes_written)
+        if is_new {
+            self.known_keys.write().await.insert(key);
+            if let Err(e) = self.engine.save_meta(&EntryMeta::new(key)).await {
+                log_info!("Storage", "保存元数据失败: {} - {}", key, e);
+            }
+        }
+    }
+    
+    /// 写入一段内存数据，磁盘写满时紧急淘汰冷数据后重试一次
+    pub async fn write_bytes(&self, key: &str, data: Bytes, range: (u64, u64)) -> Result<u64> {
+        let len = data.len() as u64;
+        self.ensure_free_space(key, len).await;
+
+        let stream = Box::pin(futures::stream::once(futures::future::ready(Ok(data.clone()))));
+        match self.write(key, stream, range).await {
+            Err(e) if e.kind() == ErrorKind::NoSpace => {
+                log_info!("Storage", "磁盘空间不足，紧急清理后重试: {} - {}", key, e);
+                let freed = self.emergency_evict(key, len.max(self.config.emergency_evict_size)).await;
+                if freed == 0 {
+                    return Err(e);
+                }
+                let stream = Box::pin(futures::stream::once(futures::future::ready(Ok(data))));
+                self.write(key, stream, range).await
+            }
+            result => result,
+        }
+    }
+
+    /// 打开条目用于连续写入 `range`，文件只打开和定位一次；引擎不支持时返回 None，由调用方改用 `write_bytes`
+    pub async fn open_writer(&self, key: &str, range: (u64, u64)) -> Result<Option<ManagedWriter>> {
+        self.ensure_online(key)?;
+        let writer = self.engine.open_writer(key, range).await?;
+        Ok(writer.map(|writer| ManagedWriter {
+            key: key.to_string(),
+            start: range.0,
+            written: 0,
+            unsynced: 0,
+            writer,
+        }))
+    }
+
+    /// 追加一批数据并更新索引，按 fsync 策略同步；磁盘写满时紧急淘汰冷数据后重试一次
+    pub async fn write_to(&self, writer: &mut ManagedWriter, data: Bytes) -> Result<u64> {
+        let key = writer.key.clone();
+        self.ensure_online(&key)?;
+        // 写入过程中条目被删除时停止，避免向已删除的文件追加数据后又记入索引
+        if writer.written > 0 && !self.cache_entries.read().await.contains_key(&key) {
+            return Err(ProxyError::storage(format!("写入过程中条目已被删除: {}", key)));
+        }
+        let len = data.len() as u64;
+        self.ensure_free_space(&key, len).await;
+
+        let mut permit = self.permits.acquire_write().await?;
+        if let Err(e) = writer.writer.write(&data).await {
+            if e.kind() != ErrorKind::NoSpace {
+                return Err(e);
+            }
+            drop(permit);
+            log_info!("Storage", "磁盘空间不足，紧急清理后重试: {} - {}", key, e);
+            if self.emergency_evict(&key, len.max(self.config.emergency_evict_size)).await == 0 {
+                return Err(e);
+            }
+            permit = self.permits.acquire_write().await?;
+            writer.writer.write(&data).await?;
+        }
+        writer.written += len;
+        writer.unsynced += len;
+        if self.config.fsync.should_sync(writer.unsynced) {
+            writer.writer.sync().await?;
+            writer.unsynced = 0;
+        }
+        drop(permit);
+
+        self.record_write(&key, writer.start + writer.written).await;
+        Ok(len)
+    }
+
+    /// 结束连续写入，同步尚未同步的数据
+    pub async fn finish_writer(&self, mut writer: ManagedWriter) -> Result<()> {
+        if writer.unsynced == 0 {
+            return Ok(());
+        }
+        let _permit = self.permits.acquire_write().await?;
+        writer.writer.sync().await
+    }
+
+    /// 完整对象是否可以直接保存在索引中
+    pub fn fits_inline(&self, len: u64) -> bool {
+        len > 0 && len <= self.config.inline_max_size
+    }
+
+    /// 将完整的小对象保存在索引中，引擎不支持时按普通数据写入
+    pub async fn write_inline(&self, key: &str, data: Bytes) -> Result<u64> {
+        self.ensure_online(key)?;
+        let len = data.len() as u64;
+        let meta = match self.cache_entries.read().await.get(key) {
+            // 已有数据文件的条目按普通数据写入
+            Some(_) => return self.write_bytes(key, data, (0, len - 1)).await,
+            None => EntryMeta::new(key),
+        };
+        if !self.engine.save_inline(&meta, &data).await? {
+            return self.write_bytes(key, data, (0, len - 1)).await;
+        }
+
+        let now = SystemTime::now();
+        let previous = self.cache_entries.write().await.insert(key.to_string(), CacheEntry {
+            key: key.to_string(),
+            total_size: len,
+            allocated: len,
+            last_access: now,
+            last_write: now,
+            hits: 0,
+            tier: StorageTier::Slow,
+            meta,
+        });
+        let mut total = self.total_size.write().await;
+        *total = total.saturating_sub(previous.map_or(0, |entry| entry.allocated)) + len;
+        drop(total);
+        self.known_keys.write().await.insert(key);
+        log_info!("Storage", "内联保存: {} ({} 字节)", key, len);
+        Ok(len)
+    }
+
+    /// 大块写入前检查剩余空间是否满足写入 `needed` 字节后仍高于保留下限，不足时淘汰冷数据
+    pub async fn ensure_free_space(&self, key: &str, needed: u64) {
+        if needed < self.config.large_write_threshold {
+            return;
+        }
+
+        let available = match self.engine.available_space(key) {
+            Some(available) => available,
+            None => return,
+        };
+
+        let required = needed.saturating_add(self.config.reserved_free_space);
+        if available >= required {
+            return;
+        }
+
+        log_info!("Storage", "剩余空间低于保留下限: 可用 {} 字节, 需要 {} 字节", available, required);
+        self.emergency_evict(key, required - available).await;
+    }
+
+    /// 立即淘汰最冷的条目直到释放 `bytes` 字节，`exclude` 为正在写入的条目，不会被淘汰。
+    /// 持有索引锁时只选出淘汰对象并移出索引，删除和截断文件时不持锁，磁盘 I/O 期间不阻塞其他请求
+    async fn emergency_evict(&self, exclude: &str, bytes: u64) -> u64 {
+        let trim = (self.config.trim_threshold > 0).then_some((self.config.trim_threshold, self.config.trim_keep));
+        let victims = {
+            let mut entries = self.cache_entries.write().await;
+            let mut total = self.total_size.write().await;
+            select_cold_entries(&mut entries, &mut total, exclude, trim, bytes)
+        };
+
+        let mut freed = 0u64;
+        for victim in victims {
+            match victim {
+                EvictVictim::Remove { entry, reclaimed } => match self.engine.remove(&entry.key).await {
+                    Ok(()) => freed += reclaimed,
+                    Err(e) => {
+                        log_info!("Storage", "淘汰缓存失败: {} - {}", entry.key, e);
+                        // 文件仍在，放回索引继续计入容量
+                        let mut entries = self.cache_entries.write().await;
+                        if !entries.contains_key(&entry.key) {
+                            *self.total_size.write().await += entry.allocated;
+                            entries.insert(entry.key.clone(), entry);
+                        }
+                    }
+                },
+                EvictVictim::Trim { key, keep } => {
+                    if let Err(e) = self.engine.truncate(&key, keep).await {
+                        log_info!("Storage", "截断缓存失败，删除整个条目: {} - {}", key, e);
+                        if let Some(entry) = self.cache_entries.write().await.remove(&key) {
+                            let mut total = self.total_size.write().await;
+                            *total = total.saturating_sub(entry.allocated);
+                            if self.engine.remove(&key).await.is_ok() {
+                                freed += entry.allocated;
+                            }
+                        }
+                        continue;
+                    }
+                    let allocated = self.engine.allocated_size(&key).await.ok().flatten();
+                    let meta = {
+                        let mut entries = self.cache_entries.write().await;
+                        let entry = match entries.get_mut(&key) {
+                            Some(entry) => entry,
+                            None => continue,
+                        };
+                        let allocated = allocated.unwrap_or(entry.allocated.min(keep));
+                        let trimmed = entry.allocated.saturating_sub(allocated);
+                        entry.allocated = allocated;
+                        let mut total = self.total_size.write().await;
+                        *total = total.saturating_sub(trimmed);
+                        freed += trimmed;
+                        log_info!("Storage", "截断冷数据尾部: {} 释放 {} 字节", key, trimmed);
+                        entry.meta.clone()
+                    };
+                    if let Err(e) = self.engine.save_meta(&meta).await {
+                        log_info!("Storage", "保存元数据失败: {} - {}", key, e);
+                    }
+                }
+            }
+        }
+
+        log_info!("Storage", "紧急清理完成: 释放 {} 字节, 目标 {} 字节", freed, bytes);
+        freed
+    }
+    
+    /// 保存上游响应头和完整对象大小，命中缓存时使用
+    pub async fn save_headers(&self, key: &str, headers: &HeaderMap, content_length: Option<u64>) -> Result<()> {
+        let meta = {
+            let mut entries = self.cache_entries.write().await;
+            let entry = match entries.get_mut(key) {
+                Some(entry) => entry,
+                None => return Ok(()),
+            };
+            entry.meta.capture_headers(headers, &self.config.stored_headers);
+            entry.meta.fetched_at = SystemTime::now().duration_since(UNIX_EPOCH).ok().map(|now| now.as_secs());
+            if content_length.is_some() {
+                entry.meta.content_length = content_length;
+            }
+            entry.meta.clone()
+        };
+        self.engine.save_meta(&meta).await
+    }
+
+    /// 设置或清除条目的用户元数据，条目不存在时返回 false
+    pub async fn set_user_meta(&self, key: &str, user: Option<Value>) -> Result<bool> {
+        if let Some(user) = &user {
+            let size = serde_json::to_vec(user)?.len();
+            if size > MAX_USER_META_SIZE {
+                return Err(ProxyError::request(format!("用户元数据过大: {} 字节，上限 {} 字节", size, MAX_USER_META_SIZE)));
+            }
+        }
+        let meta = {
+            let mut entries = self.cache_entries.write().await;
+            let entry = match entries.get_mut(key) {
+                Some(entry) => entry,
+                None => return Ok(false),
+            };
+            entry.meta.user = user;
+            entry.meta.clone()
+        };
+        self.engine.save_meta(&meta).await?;
+        Ok(true)
+    }
+
+    /// 获取条目的完整状态
+    pub async fn inspect(&self, key: &str) -> Option<EntryInfo> {
+        self.wait_for_index().await;
+        let entries = self.cache_entries.read().await;
+        let entry = entries.get(key)?;
+        Some(EntryInfo {
+            key: entry.key.clone(),
+            path: self.engine.data_path(key),
+            cached_bytes: entry.total_size,
+            allocated_bytes: entry.allocated,
+            tier: format!("{:?}", entry.tier),
+            hits: entry.hits,
+            last_access: format_time(entry.last_access),
+            last_write: format_time(entry.last_write),
+            ttl_secs: freshness_ttl(&entry.meta, entry.last_write),
+            meta: entry.meta.clone(),
+        })
+    }
+
+    /// 获取条目元数据
+    pub async fn get_meta(&self, key: &str) -> Option<EntryMeta> {
+        self.cache_entries.read().await.get(key).map(|entry| entry.meta.clone())
+    }
+
+    /// 标记数据已完整下载，开启去重时与相同内容的数据共享存储
+    pub async fn mark_complete(&self, key: &str) -> Result<()> {
+        if !self.config.dedup {
+            return Ok(());
+        }
+
+        if let Some(content_hash) = self.engine.deduplicate(key).await? {
+            log_info!("Storage", "数据已索引: {} -> {}", key, content_hash);
+            let meta = match self.cache_entries.write().await.get_mut(key) {
+                Some(entry) => {
+                    entry.meta.content_hash = Some(content_hash);
+                    entry.meta.clone()
+                }
+                None => return Ok(()),
+            };
+            self.engine.save_meta(&meta).await?;
+        }
+        Ok(())
     }
     
     pub async fn read(&self, key: &str, range: (u64, u64)) -> Result<Box<dyn Stream<Item = Result<Bytes>> + Send + Unpin>> {
         // 更新访问时间
         if let Some(entry) = self.cache_entries.write().await.get_mut(key) {
             entry.last_access = SystemTime::now();
+            entry.hits = entry.hits.saturating_add(1);
         }
         
-        // 读取数据
-        self.engine.read(key, range).await
+        self.ensure_online(key)?;
+        // 读取许可随数据流释放
+        let permit = self.permits.acquire_read().await?;
+        let stream = self.engine.read(key, range).await?;
+        Ok(Box::new(stream.map(move |chunk| {
+            let _ = &permit;
+            chunk
+        })))
+    }
+
+    /// 存储读写许可的饱和度
+    pub fn permit_stats(&self) -> IoPermitStats {
+        self.permits.stats()
     }
 
     pub async fn get_size(&self, key: &str) -> Result<Option<u64>> {
@@ -152,6 +961,10 @@ impl<E: StorageEngine + 'static> StorageManager<E> {
             return Ok(Some(entry.total_size));
         }
         
+        if self.is_definite_miss(key).await {
+            return Ok(None);
+        }
+        
         // 如果缓存中没有，从存储引擎获取
         self.engine.get_size(key).await
     }
@@ -173,7 +986,452 @@ impl<E: StorageEngine + 'static> StorageManager<E> {
             return Ok(end < entry.total_size);
         }
         
+        if self.is_definite_miss(key).await {
+            return Ok(false);
+        }
+        
         // 如果缓存中没有，从存储引擎检查
         self.engine.check_range(key, range).await
     }
-} 
\ No newline at end of file
+} 
+
+/// 将条目截断为前 `keep` 字节，返回释放的字节数
+async fn trim_entry<E: StorageEngine>(
+    engine: &E,
+    entries: &mut HashMap<String, CacheEntry>,
+    key: &str,
+    keep: u64,
+) -> Result<u64> {
+    engine.truncate(key, keep).await?;
+    let allocated = engine.allocated_size(key).await.ok().flatten();
+    let entry = match entries.get_mut(key) {
+        Some(entry) => entry,
+        None => return Ok(0),
+    };
+    let allocated = allocated.unwrap_or(entry.allocated.min(keep));
+    let trimmed = entry.allocated.saturating_sub(allocated);
+    entry.total_size = keep;
+    entry.allocated = allocated;
+    // 数据已不完整，不再与其他条目共享内容
+    if entry.meta.content_hash.take().is_some() {
+        engine.save_meta(&entry.meta).await?;
+    }
+    log_info!("Storage", "截断冷数据尾部: {} 释放 {} 字节", key, trimmed);
+    Ok(trimmed)
+}
+
+/// 紧急清理选出的条目
+enum EvictVictim {
+    /// 已移出索引，需要删除数据
+    /// `reclaimed` 为删除后实际释放的空间，仍与其他条目共享数据块时为 0
+    Remove { entry: CacheEntry, reclaimed: u64 },
+    /// 索引中的大小已截断到 `keep`，需要截断数据文件
+    Trim { key: String, keep: u64 },
+}
+
+/// 在索引锁内按最后访问时间从旧到新选出预计能释放 `bytes` 字节的条目。
+/// 删除的条目直接移出索引；达到截断阈值的条目只把记录的大小改为保留大小，实际释放的空间在截断文件后计入
+fn select_cold_entries(
+    entries: &mut HashMap<String, CacheEntry>,
+    total: &mut u64,
+    exclude: &str,
+    trim: Option<(u64, u64)>,
+    bytes: u64,
+) -> Vec<EvictVictim> {
+    let mut candidates: Vec<_> = entries
+        .values()
+        .filter(|entry| entry.key != exclude)
+        .map(|entry| (entry.last_access, entry.key.clone()))
+        .collect();
+    candidates.sort_unstable();
+
+    let mut planned = 0u64;
+    let mut victims = Vec::new();
+    for (_, key) in candidates {
+        if planned >= bytes {
+            break;
+        }
+        if let Some((threshold, keep)) = trim {
+            if let Some(entry) = entries.get_mut(&key).filter(|entry| entry.total_size >= threshold && entry.total_size > keep) {
+                planned += entry.allocated.saturating_sub(keep);
+                entry.total_size = keep;
+                // 数据已不完整，不再与其他条目共享内容
+                entry.meta.content_hash = None;
+                victims.push(EvictVictim::Trim { key, keep });
+                continue;
+            }
+        }
+        if let Some(entry) = entries.remove(&key) {
+            *total = total.saturating_sub(entry.allocated);
+            let reclaimed = reclaimed_bytes(entries, &entry);
+            planned += reclaimed;
+            victims.push(EvictVictim::Remove { entry, reclaimed });
+        }
+    }
+    victims
+}
+
+/// 已移出索引的条目删除后实际释放的空间，索引中仍有条目共享同一数据块时硬链接不会释放空间
+fn reclaimed_bytes(entries: &HashMap<String, CacheEntry>, removed: &CacheEntry) -> u64 {
+    let shared = removed.meta.content_hash.as_ref().is_some_and(|content_hash| {
+        entries.values().any(|entry| entry.meta.content_hash.as_ref() == Some(content_hash))
+    });
+    if shared { 0 } else { removed.allocated }
+}
+
+/// 按最后访问时间淘汰，直到满足大小和数量限制
+async fn enforce_limits<E: StorageEngine>(
+    engine: &E,
+    entries: &mut HashMap<String, CacheEntry>,
+    total: &mut u64,
+    config: &StorageManagerConfig,
+) -> u64 {
+    if *total <= config.max_cache_size && entries.len() <= config.max_file_count {
+        return 0;
+    }
+
+    let trim = (config.trim_threshold > 0).then_some((config.trim_threshold, config.trim_keep));
+    evict_cold_entries(engine, entries, total, None, trim, |_, current_total, current_count| {
+        current_total <= config.max_cache_size && current_count <= config.max_file_count
+    }).await
+}
+
+/// 按最后访问时间从旧到新淘汰条目，直到 `satisfied(已释放字节, 剩余总大小, 剩余条目数)` 返回 true。
+/// `trim` 为 (阈值, 保留大小)，达到阈值的条目只截断尾部
+async fn evict_cold_entries<E, F>(
+    engine: &E,
+    entries: &mut HashMap<String, CacheEntry>,
+    total: &mut u64,
+    exclude: Option<&str>,
+    trim: Option<(u64, u64)>,
+    satisfied: F,
+) -> u64
+where
+    E: StorageEngine,
+    F: Fn(u64, u64, usize) -> bool,
+{
+    let mut candidates: Vec<_> = entries
+        .values()
+        .filter(|entry| Some(entry.key.as_str()) != exclude)
+        .cloned()
+        .collect();
+    candidates.sort_by_key(|a| a.last_access);
+
+    let mut freed = 0u64;
+    for entry in candidates {
+        if satisfied(freed, *total, entries.len()) {
+            break;
+        }
+
+        if let Some((threshold, keep)) = trim {
+            if entry.total_size >= threshold && entry.total_size > keep {
+                match trim_entry(engine, entries, &entry.key, keep).await {
+                    Ok(trimmed) => {
+                        *total = total.saturating_sub(trimmed);
+                        freed += trimmed;
+                        continue;
+                    }
+                    Err(e) => log_info!("Storage", "截断缓存失败，删除整个条目: {} - {}", entry.key, e),
+                }
+            }
+        }
+
+        match engine.remove(&entry.key).await {
+            Ok(()) => {
+                if let Some(removed) = entries.remove(&entry.key) {
+                    *total = total.saturating_sub(removed.allocated);
+                    freed += reclaimed_bytes(entries, &removed);
+                }
+            }
+            Err(e) => {
+                log_info!("Storage", "淘汰缓存失败: {} - {}", entry.key, e);
+            }
+        }
+    }
+
+    freed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// 容量固定的内存存储，写满时返回 `ErrorKind::NoSpace`
+    struct MemoryEngine {
+        files: Mutex<HashMap<String, Vec<u8>>>,
+        capacity: usize,
+        /// 置为 false 模拟缓存目录被卸载
+        available: AtomicBool,
+    }
+
+    impl MemoryEngine {
+        fn new(capacity: usize) -> Self {
+            Self { files: Mutex::new(HashMap::new()), capacity, available: AtomicBool::new(true) }
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl StorageEngine for MemoryEngine {
+        async fn write<S>(&self, key: &str, mut stream: S, range: (u64, u64)) -> Result<u64>
+        where
+            S: Stream<Item = Result<Bytes>> + Send + Unpin + 'static,
+        {
+            let mut data = Vec::new();
+            while let Some(chunk) = stream.next().await {
+                data.extend_from_slice(&chunk?);
+            }
+            let mut files = self.files.lock().unwrap();
+            let used: usize = files.iter().filter(|(name, _)| *name != key).map(|(_, file)| file.len()).sum();
+            let end = range.0 as usize + data.len();
+            if used + end > self.capacity {
+                return Err(ProxyError::no_space(format!("空间不足: {}", key)));
+            }
+            let file = files.entry(key.to_string()).or_default();
+            file.resize(file.len().max(end), 0);
+            file[range.0 as usize..end].copy_from_slice(&data);
+            Ok(data.len() as u64)
+        }
+
+        async fn read(&self, key: &str, range: (u64, u64)) -> Result<Box<dyn Stream<Item = Result<Bytes>> + Send + Unpin>> {
+            let data = self.files.lock().unwrap().get(key).cloned().unwrap_or_default();
+            let end = (range.1 as usize + 1).min(data.len());
+            let chunk = Bytes::copy_from_slice(&data[range.0 as usize..end]);
+            Ok(Box::new(futures::stream::iter(vec![Ok(chunk)])))
+        }
+
+        async fn get_size(&self, key: &str) -> Result<Option<u64>> {
+            Ok(self.files.lock().unwrap().get(key).map(|file| file.len() as u64))
+        }
+
+        async fn check_range(&self, key: &str, range: (u64, u64)) -> Result<bool> {
+            Ok(self.get_size(key).await?.is_some_and(|size| size > range.1))
+        }
+
+        async fn remove(&self, key: &str) -> Result<()> {
+            self.files.lock().unwrap().remove(key);
+            Ok(())
+        }
+
+        async fn is_available(&self) -> bool {
+            self.available.load(Ordering::Acquire)
+        }
+
+        async fn load_index_shard(&self, shard: usize, shards: usize) -> Result<Vec<IndexEntry>> {
+            let files = self.files.lock().unwrap();
+            Ok(files
+                .iter()
+                .filter(|(key, _)| key.bytes().map(usize::from).sum::<usize>() % shards == shard)
+                .map(|(key, file)| IndexEntry {
+                    meta: EntryMeta::new(key),
+                    size: file.len() as u64,
+                    allocated: file.len() as u64,
+                    tier: StorageTier::Slow,
+                })
+                .collect())
+        }
+
+        async fn truncate(&self, key: &str, len: u64) -> Result<()> {
+            if let Some(file) = self.files.lock().unwrap().get_mut(key) {
+                file.truncate(len as usize);
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_no_space_evicts_and_retries() {
+        let engine = MemoryEngine::new(100);
+        let config = StorageManagerConfig {
+            emergency_evict_size: 1,
+            trim_threshold: 0,
+            mount_check_interval: None,
+            ..StorageManagerConfig::default()
+        };
+        let manager = StorageManager::new(engine, config);
+
+        manager.write_bytes("old", Bytes::from(vec![1u8; 80]), (0, 79)).await.unwrap();
+        manager.write_bytes("new", Bytes::from(vec![2u8; 50]), (0, 49)).await.unwrap();
+
+        assert_eq!(manager.engine.get_size("new").await.unwrap(), Some(50));
+        assert_eq!(manager.engine.get_size("old").await.unwrap(), None);
+        assert!(!manager.cache_entries.read().await.contains_key("old"));
+        assert_eq!(*manager.total_size.read().await, 50);
+
+        // 没有可淘汰的条目时返回原来的错误
+        let err = manager.write_bytes("new", Bytes::from(vec![3u8; 120]), (0, 119)).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NoSpace);
+    }
+
+    #[tokio::test]
+    async fn test_cold_large_entry_trimmed() {
+        let engine = MemoryEngine::new(1024);
+        let config = StorageManagerConfig {
+            max_cache_size: 100,
+            trim_threshold: 150,
+            trim_keep: 20,
+            mount_check_interval: None,
+            ..StorageManagerConfig::default()
+        };
+        let manager = StorageManager::new(engine, config);
+        manager.write_bytes("big", Bytes::from(vec![1u8; 200]), (0, 199)).await.unwrap();
+
+        // 超过截断阈值的冷条目只截断尾部，开头的数据仍可命中
+        assert_eq!(manager.cleanup().await, (180, 0));
+        assert_eq!(manager.engine.get_size("big").await.unwrap(), Some(20));
+        assert_eq!(manager.get_size("big").await.unwrap(), Some(20));
+        assert!(manager.check_range("big", (0, 19)).await.unwrap());
+        assert!(!manager.check_range("big", (0, 20)).await.unwrap());
+        assert_eq!(*manager.total_size.read().await, 20);
+    }
+
+    #[tokio::test]
+    async fn test_read_permit_held_by_stream() {
+        let engine = MemoryEngine::new(1024);
+        let config = StorageManagerConfig {
+            io_permits: IoPermitConfig { max_reads: 1, max_writes: 1, acquire_timeout: Duration::from_millis(50) },
+            mount_check_interval: None,
+            ..StorageManagerConfig::default()
+        };
+        let manager = StorageManager::new(engine, config);
+        manager.write_bytes("a", Bytes::from_static(b"data"), (0, 3)).await.unwrap();
+
+        // 读取许可随数据流释放，数据流未结束时其他读取等待超时
+        let stream = manager.read("a", (0, 3)).await.unwrap();
+        let err = manager.read("a", (0, 3)).await.err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::Busy);
+        let stats = manager.permit_stats();
+        assert_eq!((stats.reads.in_use, stats.reads.timeouts), (1, 1));
+        assert_eq!(stats.writes.in_use, 0);
+
+        drop(stream);
+        assert!(manager.read("a", (0, 3)).await.is_ok());
+    }
+
+    async fn wait_online(manager: &StorageManager<MemoryEngine>, online: bool) {
+        for _ in 0..200 {
+            if manager.is_online() == online {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("缓存目录状态没有变为 {}", online);
+    }
+
+    #[tokio::test]
+    async fn test_unmounted_cache_passes_through() {
+        let config = StorageManagerConfig {
+            mount_check_interval: Some(Duration::from_millis(10)),
+            ..StorageManagerConfig::default()
+        };
+        let manager = StorageManager::new(MemoryEngine::new(1024), config);
+        manager.write_bytes("a", Bytes::from_static(b"data"), (0, 3)).await.unwrap();
+
+        // 目录被卸载后清空索引，所有键按未缓存处理，写入直接失败
+        manager.engine.available.store(false, Ordering::Release);
+        wait_online(&manager, false).await;
+        assert!(!manager.check_range("a", (0, 3)).await.unwrap());
+        assert!(manager.write_bytes("b", Bytes::from_static(b"data"), (0, 3)).await.is_err());
+        assert_eq!(*manager.total_size.read().await, 0);
+
+        // 重新挂载后重新扫描索引
+        manager.engine.available.store(true, Ordering::Release);
+        wait_online(&manager, true).await;
+        assert!(manager.check_range("a", (0, 3)).await.unwrap());
+        assert_eq!(*manager.total_size.read().await, 4);
+    }
+
+    #[tokio::test]
+    async fn test_index_loaded_in_shards() {
+        let engine = MemoryEngine::new(1024);
+        let keys: Vec<String> = (0..10).map(|i| format!("k{}", i)).collect();
+        for key in &keys {
+            engine.files.lock().unwrap().insert(key.clone(), b"data".to_vec());
+        }
+        let config = StorageManagerConfig {
+            index_load_shards: 4,
+            mount_check_interval: None,
+            ..StorageManagerConfig::default()
+        };
+        let manager = StorageManager::new(engine, config);
+
+        // 加载期间尚未加载到的键直接按未缓存处理，不访问磁盘
+        assert!(manager.is_index_loading());
+        assert!(manager.is_definite_miss("k0").await);
+
+        for _ in 0..200 {
+            if !manager.is_index_loading() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let status = manager.index_status();
+        assert!(status.ready && !status.loading);
+        assert_eq!((status.loaded_shards, status.total_shards, status.entries), (4, 4, 10));
+        for key in &keys {
+            assert_eq!(manager.get_size(key).await.unwrap(), Some(4));
+        }
+        assert_eq!(*manager.total_size.read().await, 40);
+    }
+
+    fn cached(key: &str, allocated: u64, idle_secs: u64, content_hash: Option<&str>) -> CacheEntry {
+        let mut meta = EntryMeta::new(key);
+        meta.content_hash = content_hash.map(str::to_string);
+        CacheEntry {
+            key: key.to_string(),
+            total_size: allocated,
+            allocated,
+            last_access: SystemTime::now() - Duration::from_secs(idle_secs),
+            last_write: UNIX_EPOCH,
+            hits: 0,
+            tier: StorageTier::Slow,
+            meta,
+        }
+    }
+
+    #[test]
+    fn test_shared_blob_freed_on_last_link() {
+        let mut entries: HashMap<_, _> = [
+            cached("a", 100, 30, Some("h")),
+            cached("b", 100, 20, Some("h")),
+            cached("c", 40, 10, None),
+        ]
+        .into_iter()
+        .map(|entry| (entry.key.clone(), entry))
+        .collect();
+        let mut total = 240;
+
+        // 删除第一个链接不释放空间，继续淘汰直到最后一个链接
+        let victims = select_cold_entries(&mut entries, &mut total, "", None, 100);
+        let reclaimed: Vec<_> = victims
+            .iter()
+            .map(|victim| match victim {
+                EvictVictim::Remove { entry, reclaimed } => (entry.key.as_str(), *reclaimed),
+                EvictVictim::Trim { .. } => panic!("未开启截断"),
+            })
+            .collect();
+        assert_eq!(reclaimed, vec![("a", 0), ("b", 100)]);
+        assert_eq!(total, 40);
+        assert!(entries.contains_key("c"));
+    }
+
+    #[tokio::test]
+    async fn test_filter_resized_to_entry_count() {
+        let engine = MemoryEngine::new(1024);
+        let config = StorageManagerConfig {
+            index_capacity: 2,
+            inline_max_size: 0,
+            mount_check_interval: None,
+            ..StorageManagerConfig::default()
+        };
+        let manager = StorageManager::new(engine, config);
+        for key in ["a", "b", "c", "d"] {
+            manager.write_bytes(key, Bytes::from_static(b"data"), (0, 3)).await.unwrap();
+        }
+
+        manager.index_handle().resize_filter().await;
+        let filter = manager.known_keys.read().await;
+        assert_eq!(filter.capacity(), 8);
+        assert!(["a", "b", "c", "d"].iter().all(|key| filter.contains(key)));
+    }
+}
@@ -0,0 +1,181 @@
+use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::utils::error::{ProxyError, Result};
+use super::tier::StorageTier;
+
+/// 当前元数据格式版本，没有 version 字段的旧文件视为版本 1
+pub const META_VERSION: u32 = 2;
+
+/// 用户元数据序列化后的最大字节数
+pub const MAX_USER_META_SIZE: usize = 64 * 1024;
+
+/// 默认随缓存保存的上游响应头
+pub const DEFAULT_STORED_HEADERS: &[&str] = &[
+    "content-type",
+    "etag",
+    "last-modified",
+    "content-disposition",
+    "content-language",
+    "cache-control",
+    "expires",
+];
+
+/// 缓存条目元数据，与数据文件一起持久化，启动时用于重建索引
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EntryMeta {
+    /// 格式版本，写入时总是当前版本
+    #[serde(default)]
+    pub version: u32,
+    /// 缓存键（原始 URL）
+    pub key: String,
+    /// 上游响应头，命中缓存时原样返回
+    #[serde(default)]
+    pub headers: Vec<(String, String)>,
+    /// 完整对象大小
+    #[serde(default)]
+    pub content_length: Option<u64>,
+    /// 完整内容的哈希，下载完成并去重后可用
+    #[serde(default)]
+    pub content_hash: Option<String>,
+    /// 通过库接口或 `/admin/meta` 保存的用户元数据（JSON），例如播放进度、编码信息
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<Value>,
+    /// 最近一次从上游获取响应头的时间（Unix 秒），用于返回 `Age`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fetched_at: Option<u64>,
+}
+
+impl EntryMeta {
+    pub fn new(key: &str) -> Self {
+        Self {
+            version: META_VERSION,
+            key: key.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// 解析持久化的元数据，旧版本升级为当前格式，比当前更新的版本拒绝解析，避免误删或覆盖
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        let value: Value = serde_json::from_slice(data)?;
+        let version = value.get("version").and_then(Value::as_u64).unwrap_or(1);
+        match version {
+            1 => Ok(serde_json::from_value::<EntryMetaV1>(value)?.into()),
+            2 => Ok(serde_json::from_value(value)?),
+            _ => Err(ProxyError::parse(format!("不支持的元数据版本 {}，当前版本 {}", version, META_VERSION))),
+        }
+    }
+
+    /// 按当前版本序列化
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let meta = Self {
+            version: META_VERSION,
+            ..self.clone()
+        };
+        Ok(serde_json::to_vec(&meta)?)
+    }
+
+    /// 从上游响应头中挑选需要保存的字段，`names` 支持以 `*` 结尾的前缀匹配
+    pub fn capture_headers(&mut self, headers: &HeaderMap, names: &[String]) {
+        self.headers = headers
+            .iter()
+            .filter(|(name, _)| header_matches(name.as_str(), names))
+            .filter_map(|(name, value)| {
+                value.to_str().ok().map(|value| (name.as_str().to_string(), value.to_string()))
+            })
+            .collect();
+    }
+
+    /// 转换为响应头
+    pub fn header_map(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in &self.headers {
+            if let (Ok(name), Ok(value)) = (
+                HeaderName::from_bytes(name.as_bytes()),
+                HeaderValue::from_str(value),
+            ) {
+                headers.append(name, value);
+            }
+        }
+        headers
+    }
+
+    /// 获取保存的响应头
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// 版本 1 的元数据，与版本 2 相比没有 version 字段
+#[derive(Deserialize)]
+struct EntryMetaV1 {
+    key: String,
+    #[serde(default)]
+    headers: Vec<(String, String)>,
+    #[serde(default)]
+    content_length: Option<u64>,
+    #[serde(default)]
+    content_hash: Option<String>,
+}
+
+impl From<EntryMetaV1> for EntryMeta {
+    fn from(meta: EntryMetaV1) -> Self {
+        Self {
+            version: META_VERSION,
+            key: meta.key,
+            headers: meta.headers,
+            content_length: meta.content_length,
+            content_hash: meta.content_hash,
+            user: None,
+            fetched_at: None,
+        }
+    }
+}
+
+/// 判断响应头名称是否匹配，`patterns` 支持以 `*` 结尾的前缀匹配
+pub fn header_matches(name: &str, patterns: &[String]) -> bool {
+    patterns.iter().any(|pattern| match pattern.strip_suffix('*') {
+        Some(prefix) => name.len() >= prefix.len() && name[..prefix.len()].eq_ignore_ascii_case(prefix),
+        None => name.eq_ignore_ascii_case(pattern),
+    })
+}
+
+/// 启动时从存储加载的索引条目
+#[derive(Debug, Clone)]
+pub struct IndexEntry {
+    pub meta: EntryMeta,
+    /// 数据文件当前大小
+    pub size: u64,
+    /// 数据文件实际占用的磁盘空间，稀疏文件小于 `size`
+    pub allocated: u64,
+    /// 数据文件所在的存储层
+    pub tier: StorageTier,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_versions() {
+        let v1 = br#"{"key":"http://example.com/a.mp4","content_length":1024}"#;
+        let meta = EntryMeta::decode(v1).unwrap();
+        assert_eq!(meta.version, META_VERSION);
+        assert_eq!(meta.content_length, Some(1024));
+
+        let encoded = meta.encode().unwrap();
+        assert_eq!(EntryMeta::decode(&encoded).unwrap().key, "http://example.com/a.mp4");
+
+        let meta = EntryMeta {
+            user: Some(serde_json::json!({ "position": 42.5 })),
+            ..meta
+        };
+        let decoded = EntryMeta::decode(&meta.encode().unwrap()).unwrap();
+        assert_eq!(decoded.user, meta.user);
+
+        assert!(EntryMeta::decode(br#"{"version":99,"key":"a"}"#).is_err());
+    }
+}
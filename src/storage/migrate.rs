@@ -0,0 +1,187 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use serde::Serialize;
+use serde_json::Value;
+use crate::log_info;
+use super::disk::{hash_file, is_legacy_hash};
+use super::layout::ShardLayout;
+use super::EntryMeta;
+
+/// 旧版布局的数据文件和状态文件名：`<md5>/cache.data`、`<md5>/state.json`
+const LEGACY_DATA_FILE: &str = "cache.data";
+const LEGACY_STATE_FILE: &str = "state.json";
+
+/// 迁移单个条目的结果
+#[derive(Debug, Clone, Serialize)]
+pub struct MigrateIssue {
+    pub source: PathBuf,
+    pub problem: String,
+}
+
+/// 迁移报告
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MigrateReport {
+    pub migrated: usize,
+    pub skipped: usize,
+    pub bytes: u64,
+    pub issues: Vec<MigrateIssue>,
+}
+
+impl MigrateReport {
+    fn add_issue(&mut self, source: &Path, problem: String) {
+        log_info!("Storage", "迁移失败: {:?} - {}", source, problem);
+        self.issues.push(MigrateIssue {
+            source: source.to_path_buf(),
+            problem,
+        });
+    }
+}
+
+/// 待迁移的条目
+struct SourceEntry {
+    data: PathBuf,
+    meta: EntryMeta,
+    /// 旧布局的目录，迁移完成后随 `move_files` 一并删除
+    legacy_dir: Option<PathBuf>,
+}
+
+/// 将 `from` 中的缓存迁移为 `layout` 分片布局写入 `to`，自动识别旧版 `<md5>/cache.data` 布局和分片布局。
+/// `to` 已记录布局时沿用原布局。每个条目复制后比较源文件和目标文件的哈希，`move_files` 为 true 时校验通过后删除源文件
+pub fn migrate(from: &Path, to: &Path, layout: ShardLayout, move_files: bool) -> io::Result<MigrateReport> {
+    let mut report = MigrateReport::default();
+    let mut sources = Vec::new();
+    scan_legacy(from, &mut sources, &mut report)?;
+    scan_sharded(from, ShardLayout::recorded(from).unwrap_or_default(), &mut sources)?;
+    let layout = ShardLayout::resolve(to, layout);
+    log_info!("Storage", "开始迁移: {:?} -> {:?}, {} 个条目, 目标布局 {:?}", from, to, sources.len(), layout);
+
+    for source in sources {
+        let target = layout.file_path(to, &source.meta.key);
+        if target.exists() {
+            report.skipped += 1;
+            continue;
+        }
+        match migrate_entry(&source, &target, move_files) {
+            Ok(bytes) => {
+                report.migrated += 1;
+                report.bytes += bytes;
+            }
+            Err(e) => {
+                let _ = fs::remove_file(&target);
+                report.add_issue(&source.data, e.to_string());
+            }
+        }
+    }
+
+    log_info!("Storage", "迁移完成: {} 个条目, {} 字节, 跳过 {} 个, 失败 {} 个",
+        report.migrated, report.bytes, report.skipped, report.issues.len());
+    Ok(report)
+}
+
+fn migrate_entry(source: &SourceEntry, target: &Path, move_files: bool) -> io::Result<u64> {
+    let source_hash = hash_file(&source.data)?;
+    if let Some(expected) = source.meta.content_hash.as_ref().filter(|hash| !is_legacy_hash(hash)) {
+        if *expected != source_hash {
+            return Err(io::Error::other(format!("源数据哈希 {} 与记录的 {} 不一致", source_hash, expected)));
+        }
+    }
+
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let bytes = fs::copy(&source.data, target)?;
+    let target_hash = hash_file(target)?;
+    if target_hash != source_hash {
+        return Err(io::Error::other(format!("复制后哈希不一致: {} != {}", target_hash, source_hash)));
+    }
+    let meta = source.meta.encode().map_err(|e| io::Error::other(e.to_string()))?;
+    fs::write(target.with_extension("meta"), meta)?;
+
+    if move_files {
+        match &source.legacy_dir {
+            Some(dir) => fs::remove_dir_all(dir)?,
+            None => {
+                fs::remove_file(&source.data)?;
+                let _ = fs::remove_file(source.data.with_extension("meta"));
+            }
+        }
+    }
+    Ok(bytes)
+}
+
+/// 旧版布局：根目录下以 URL 的 MD5 命名的目录，其中的 state.json 记录 URL
+fn scan_legacy(root: &Path, sources: &mut Vec<SourceEntry>, report: &mut MigrateReport) -> io::Result<()> {
+    for dir in fs::read_dir(root)? {
+        let dir = dir?.path();
+        let is_legacy = dir.file_name().and_then(|name| name.to_str()).is_some_and(|name| {
+            name.len() == 32 && name.chars().all(|c| c.is_ascii_hexdigit())
+        });
+        let data = dir.join(LEGACY_DATA_FILE);
+        if !is_legacy || !data.is_file() {
+            continue;
+        }
+
+        let state = fs::read(dir.join(LEGACY_STATE_FILE))
+            .ok()
+            .and_then(|data| serde_json::from_slice::<Value>(&data).ok());
+        match state.as_ref().and_then(legacy_meta) {
+            Some(meta) => sources.push(SourceEntry {
+                data,
+                meta,
+                legacy_dir: Some(dir),
+            }),
+            None => report.add_issue(&data, "状态文件中没有 URL，无法确定缓存键".to_string()),
+        }
+    }
+    Ok(())
+}
+
+/// 分片布局：数据文件和同名 .meta 元数据，按源目录清单记录的布局遍历
+fn scan_sharded(root: &Path, layout: ShardLayout, sources: &mut Vec<SourceEntry>) -> io::Result<()> {
+    for dir in layout.leaf_dirs(root)? {
+        for file in fs::read_dir(&dir)? {
+            let meta_path = file?.path();
+            if meta_path.extension().and_then(|ext| ext.to_str()) != Some("meta") {
+                continue;
+            }
+            let data = meta_path.with_extension("");
+            let meta = fs::read(&meta_path)
+                .ok()
+                .and_then(|bytes| EntryMeta::decode(&bytes).ok());
+            if let (Some(meta), true) = (meta, data.is_file()) {
+                sources.push(SourceEntry {
+                    data,
+                    meta,
+                    legacy_dir: None,
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 从旧版状态文件中读取 URL 和文件总大小
+fn legacy_meta(state: &Value) -> Option<EntryMeta> {
+    let url = ["url", "key"].iter().find_map(|field| state.get(*field)?.as_str())?;
+    let mut meta = EntryMeta::new(url);
+    meta.content_length = ["total_size", "content_length", "size"]
+        .iter()
+        .find_map(|field| state.get(*field)?.as_u64())
+        .filter(|size| *size > 0);
+    Some(meta)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_legacy_meta() {
+        let state = serde_json::json!({ "url": "http://example.com/a.mp4", "total_size": 1024, "ranges": [[0, 511]] });
+        let meta = legacy_meta(&state).unwrap();
+        assert_eq!(meta.key, "http://example.com/a.mp4");
+        assert_eq!(meta.content_length, Some(1024));
+        assert!(legacy_meta(&serde_json::json!({ "total_size": 1024 })).is_none());
+    }
+}
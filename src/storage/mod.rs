@@ -1,19 +1,57 @@
 use std::path::PathBuf;
 use futures::Stream;
 use bytes::Bytes;
-use crate::utils::error::Result;
+use crate::utils::error::{ProxyError, Result};
 
 pub mod block;
 pub mod disk;
+pub mod inline;
+pub mod inspect;
+pub mod layout;
 pub mod manager;
+pub mod meta;
+pub mod migrate;
+pub mod permits;
+pub mod schedule;
+pub mod tier;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+pub mod uring;
+pub mod verify;
+pub mod warmup;
+pub mod writer;
 
 pub use disk::DiskStorage;
-pub use manager::{StorageManager, StorageManagerConfig};
+pub use inspect::{CacheInspection, EntryInfo, PlanSegment, PlanSource, RangePlan};
+pub use layout::ShardLayout;
+pub use manager::{ManagedWriter, StorageManager, StorageManagerConfig};
+pub use meta::{EntryMeta, IndexEntry};
+pub use migrate::{MigrateIssue, MigrateReport};
+pub use permits::{IoPermitConfig, IoPermitStats, PermitStats};
+pub use schedule::CleanupSchedule;
+pub use tier::{StorageTier, TieringConfig};
+pub use verify::{VerifyIssue, VerifyReport};
+pub use warmup::IndexStatus;
+pub use writer::{EntryWriter, FsyncPolicy};
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+pub use uring::UringStorage;
+
+/// 缓存使用的存储引擎，在 Linux 上启用 `io-uring` 特性时读写走 io_uring
+#[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+pub type CacheEngine = DiskStorage;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+pub type CacheEngine = UringStorage;
 
 #[derive(Clone)]
 pub struct StorageConfig {
     pub root_path: PathBuf,
     pub chunk_size: usize,
+    /// 高速层目录，未设置时只使用 `root_path`
+    pub fast_root_path: Option<PathBuf>,
+    /// 分片目录布局，缓存目录已记录布局时以记录为准
+    pub layout: ShardLayout,
+    /// 完整缓存且达到 `mmap_threshold` 的文件通过内存映射读取，32 位平台忽略
+    pub mmap: bool,
+    pub mmap_threshold: u64,
 }
 
 #[async_trait::async_trait]
@@ -26,5 +64,82 @@ pub trait StorageEngine: Send + Sync {
 
     async fn get_size(&self, key: &str) -> Result<Option<u64>>;
 
+    /// 数据实际占用的存储空间，稀疏存储时小于数据大小
+    async fn allocated_size(&self, key: &str) -> Result<Option<u64>> {
+        self.get_size(key).await
+    }
+
     async fn check_range(&self, key: &str, range: (u64, u64)) -> Result<bool>;
+
+    /// 删除缓存数据
+    async fn remove(&self, key: &str) -> Result<()>;
+
+    /// 缓存键所在存储层磁盘的剩余可用空间，无法获取时返回 None
+    fn available_space(&self, _key: &str) -> Option<u64> {
+        None
+    }
+
+    /// 存储中是否有加载索引时无法识别缓存键的数据，例如没有元数据的旧版本数据文件
+    fn has_unindexed_data(&self) -> bool {
+        false
+    }
+
+    /// 缓存目录当前是否可用，目录所在的移动硬盘被卸载时返回 false
+    async fn is_available(&self) -> bool {
+        true
+    }
+
+    /// 将数据迁移到指定存储层，不支持分层的引擎忽略
+    async fn move_to_tier(&self, _key: &str, _tier: StorageTier) -> Result<()> {
+        Ok(())
+    }
+
+    /// 按内容哈希对已完成的数据去重，返回共享数据块的哈希，不支持去重的引擎返回 None
+    async fn deduplicate(&self, _key: &str) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    /// 将完整的小对象连同元数据直接保存在索引中，不支持的引擎返回 false，由调用方按普通数据写入
+    async fn save_inline(&self, _meta: &EntryMeta, _data: &Bytes) -> Result<bool> {
+        Ok(false)
+    }
+
+    /// 保存条目元数据
+    async fn save_meta(&self, _meta: &EntryMeta) -> Result<()> {
+        Ok(())
+    }
+
+    /// 加载全部条目元数据，用于启动时重建索引
+    async fn load_index(&self) -> Result<Vec<IndexEntry>> {
+        Ok(Vec::new())
+    }
+
+    /// 将索引分为 `shards` 份并行加载，加载第 `shard` 份。不支持分片的引擎在第 0 份返回全部条目
+    async fn load_index_shard(&self, shard: usize, _shards: usize) -> Result<Vec<IndexEntry>> {
+        if shard == 0 {
+            self.load_index().await
+        } else {
+            Ok(Vec::new())
+        }
+    }
+
+    /// 重新计算数据内容哈希，数据不存在或引擎不支持时返回 None
+    async fn content_hash(&self, _key: &str) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    /// 数据文件路径，不落盘的引擎返回 None
+    fn data_path(&self, _key: &str) -> Option<PathBuf> {
+        None
+    }
+
+    /// 打开条目用于连续写入 `range`，不支持的引擎返回 None，由调用方逐批调用 `write`
+    async fn open_writer(&self, _key: &str, _range: (u64, u64)) -> Result<Option<Box<dyn EntryWriter>>> {
+        Ok(None)
+    }
+
+    /// 将数据截断为前 `len` 字节，释放尾部占用的空间
+    async fn truncate(&self, key: &str, _len: u64) -> Result<()> {
+        Err(ProxyError::storage(format!("存储引擎不支持截断: {}", key)))
+    }
 } 
\ No newline at end of file
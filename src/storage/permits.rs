@@ -0,0 +1,126 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use serde::Serialize;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::timeout;
+use crate::utils::error::{ProxyError, Result};
+
+/// 存储读写并发限制。读和写使用独立的许可，慢写入不会占满读取的并发
+#[derive(Clone)]
+pub struct IoPermitConfig {
+    pub max_reads: usize,
+    pub max_writes: usize,
+    /// 等待许可的最长时间，超时返回 `ErrorKind::Busy`，对客户端表现为 503
+    pub acquire_timeout: Duration,
+}
+
+impl Default for IoPermitConfig {
+    fn default() -> Self {
+        Self {
+            max_reads: 256,
+            max_writes: 32,
+            acquire_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+/// 一类许可的饱和度统计
+#[derive(Debug, Clone, Serialize)]
+pub struct PermitStats {
+    pub limit: usize,
+    pub in_use: usize,
+    /// 正在等待许可的操作数
+    pub waiting: usize,
+    pub acquired: u64,
+    pub timeouts: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IoPermitStats {
+    pub reads: PermitStats,
+    pub writes: PermitStats,
+}
+
+struct PermitPool {
+    name: &'static str,
+    limit: usize,
+    semaphore: Arc<Semaphore>,
+    waiting: AtomicUsize,
+    acquired: AtomicU64,
+    timeouts: AtomicU64,
+}
+
+impl PermitPool {
+    fn new(name: &'static str, limit: usize) -> Self {
+        let limit = limit.max(1);
+        Self {
+            name,
+            limit,
+            semaphore: Arc::new(Semaphore::new(limit)),
+            waiting: AtomicUsize::new(0),
+            acquired: AtomicU64::new(0),
+            timeouts: AtomicU64::new(0),
+        }
+    }
+
+    async fn acquire(&self, wait: Duration) -> Result<OwnedSemaphorePermit> {
+        self.waiting.fetch_add(1, Ordering::Relaxed);
+        let result = timeout(wait, self.semaphore.clone().acquire_owned()).await;
+        self.waiting.fetch_sub(1, Ordering::Relaxed);
+
+        match result {
+            Ok(permit) => {
+                self.acquired.fetch_add(1, Ordering::Relaxed);
+                Ok(permit?)
+            }
+            Err(_) => {
+                self.timeouts.fetch_add(1, Ordering::Relaxed);
+                Err(ProxyError::busy(format!("等待存储{}许可超时 ({}ms)", self.name, wait.as_millis())))
+            }
+        }
+    }
+
+    fn stats(&self) -> PermitStats {
+        PermitStats {
+            limit: self.limit,
+            in_use: self.limit - self.semaphore.available_permits(),
+            waiting: self.waiting.load(Ordering::Relaxed),
+            acquired: self.acquired.load(Ordering::Relaxed),
+            timeouts: self.timeouts.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// 存储读写许可
+pub struct IoPermits {
+    reads: PermitPool,
+    writes: PermitPool,
+    acquire_timeout: Duration,
+}
+
+impl IoPermits {
+    pub fn new(config: &IoPermitConfig) -> Self {
+        Self {
+            reads: PermitPool::new("读取", config.max_reads),
+            writes: PermitPool::new("写入", config.max_writes),
+            acquire_timeout: config.acquire_timeout,
+        }
+    }
+
+    pub async fn acquire_read(&self) -> Result<OwnedSemaphorePermit> {
+        self.reads.acquire(self.acquire_timeout).await
+    }
+
+    pub async fn acquire_write(&self) -> Result<OwnedSemaphorePermit> {
+        self.writes.acquire(self.acquire_timeout).await
+    }
+
+    pub fn stats(&self) -> IoPermitStats {
+        IoPermitStats {
+            reads: self.reads.stats(),
+            writes: self.writes.stats(),
+        }
+    }
+}
+
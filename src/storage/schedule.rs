@@ -0,0 +1,70 @@
+use std::time::Duration;
+use chrono::Timelike;
+
+/// 定期清理计划
+#[derive(Debug, Clone)]
+pub struct CleanupSchedule {
+    /// 检查间隔
+    pub interval: Duration,
+    /// 只在该时段（本地时间，起止小时，左闭右开）内执行定期清理，可跨越午夜，如 (23, 6)
+    pub off_peak: Option<(u32, u32)>,
+}
+
+impl Default for CleanupSchedule {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(60),
+            off_peak: None,
+        }
+    }
+}
+
+impl CleanupSchedule {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            off_peak: None,
+        }
+    }
+
+    /// 限制在低峰时段执行
+    pub fn with_off_peak(mut self, start_hour: u32, end_hour: u32) -> Self {
+        self.off_peak = Some((start_hour % 24, end_hour % 24));
+        self
+    }
+
+    /// 当前是否允许执行定期清理
+    pub fn is_due_now(&self) -> bool {
+        self.allows_hour(chrono::Local::now().hour())
+    }
+
+    fn allows_hour(&self, hour: u32) -> bool {
+        match self.off_peak {
+            None => true,
+            Some((start, end)) if start == end => true,
+            Some((start, end)) if start < end => hour >= start && hour < end,
+            Some((start, end)) => hour >= start || hour < end,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allows_hour() {
+        let schedule = CleanupSchedule::default();
+        assert!(schedule.allows_hour(12));
+
+        let schedule = CleanupSchedule::default().with_off_peak(2, 6);
+        assert!(schedule.allows_hour(2));
+        assert!(!schedule.allows_hour(6));
+        assert!(!schedule.allows_hour(12));
+
+        let schedule = CleanupSchedule::default().with_off_peak(23, 5);
+        assert!(schedule.allows_hour(23));
+        assert!(schedule.allows_hour(0));
+        assert!(!schedule.allows_hour(5));
+    }
+}
@@ -0,0 +1,165 @@
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+/// 存储层级
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageTier {
+    Fast, // 高速层（NVMe / 内存盘）
+    Slow, // 大容量层（HDD）
+}
+
+/// 冷热分层配置
+#[derive(Clone)]
+pub struct TieringConfig {
+    pub fast_path: PathBuf,      // 高速层目录
+    pub slow_path: Option<PathBuf>, // 大容量层目录，未设置时使用缓存目录
+    pub fast_capacity: u64,      // 高速层容量上限
+    pub promote_hits: u32,       // 一个周期内命中多少次后升级到高速层
+    pub demote_after: Duration,  // 高速层条目空闲多久后降级
+    pub interval: Duration,      // 后台迁移周期
+}
+
+impl TieringConfig {
+    pub fn new(fast_path: impl Into<PathBuf>) -> Self {
+        Self {
+            fast_path: fast_path.into(),
+            slow_path: None,
+            fast_capacity: 8 * 1024 * 1024 * 1024, // 8GB
+            promote_hits: 3,
+            demote_after: Duration::from_secs(30 * 60),
+            interval: Duration::from_secs(60),
+        }
+    }
+
+    /// 大容量层使用缓存目录以外的目录
+    pub fn with_slow_path(mut self, slow_path: impl Into<PathBuf>) -> Self {
+        self.slow_path = Some(slow_path.into());
+        self
+    }
+}
+
+/// 参与分层决策的条目信息
+pub struct TierCandidate<'a> {
+    pub key: &'a str,
+    pub size: u64,
+    pub tier: StorageTier,
+    pub hits: u32,
+    pub last_access: SystemTime,
+    pub last_write: SystemTime,
+}
+
+/// 计算本周期需要迁移的条目，先降级冷数据腾出空间，再按热度升级
+pub fn plan_tier_moves(candidates: &[TierCandidate<'_>], config: &TieringConfig) -> Vec<(String, StorageTier)> {
+    let now = SystemTime::now();
+    let elapsed = |time: SystemTime| now.duration_since(time).unwrap_or_default();
+
+    let mut moves = Vec::new();
+    let mut fast_used: u64 = candidates
+        .iter()
+        .filter(|c| c.tier == StorageTier::Fast)
+        .map(|c| c.size)
+        .sum();
+
+    // 正在写入的条目不迁移，避免迁移过程中丢失新写入的数据
+    let settled = |c: &TierCandidate<'_>| elapsed(c.last_write) >= config.interval;
+
+    // 降级：空闲过久或高速层超出容量的条目，从最久未访问的开始
+    let mut fast: Vec<_> = candidates
+        .iter()
+        .filter(|c| c.tier == StorageTier::Fast && settled(c))
+        .collect();
+    fast.sort_by_key(|c| c.last_access);
+    for candidate in fast {
+        if elapsed(candidate.last_access) >= config.demote_after || fast_used > config.fast_capacity {
+            fast_used = fast_used.saturating_sub(candidate.size);
+            moves.push((candidate.key.to_string(), StorageTier::Slow));
+        }
+    }
+
+    // 升级：近期命中次数达到阈值的条目，按命中次数从高到低
+    let mut hot: Vec<_> = candidates
+        .iter()
+        .filter(|c| c.tier == StorageTier::Slow && c.hits >= config.promote_hits && settled(c))
+        .collect();
+    hot.sort_by_key(|b| std::cmp::Reverse(b.hits));
+    for candidate in hot {
+        if fast_used + candidate.size > config.fast_capacity {
+            continue;
+        }
+        fast_used += candidate.size;
+        moves.push((candidate.key.to_string(), StorageTier::Fast));
+    }
+
+    moves
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    fn config() -> TieringConfig {
+        TieringConfig {
+            fast_capacity: 100,
+            promote_hits: 3,
+            demote_after: 30 * MINUTE,
+            interval: MINUTE,
+            ..TieringConfig::new("/fast")
+        }
+    }
+
+    fn candidate(key: &str, size: u64, tier: StorageTier, hits: u32, idle: Duration) -> TierCandidate<'_> {
+        let now = SystemTime::now();
+        TierCandidate {
+            key,
+            size,
+            tier,
+            hits,
+            last_access: now - idle,
+            last_write: now - 10 * MINUTE,
+        }
+    }
+
+    #[test]
+    fn test_demote_idle_fast_entries() {
+        let candidates = [
+            candidate("idle", 10, StorageTier::Fast, 0, 40 * MINUTE),
+            candidate("active", 10, StorageTier::Fast, 0, MINUTE),
+        ];
+        assert_eq!(plan_tier_moves(&candidates, &config()), vec![("idle".to_string(), StorageTier::Slow)]);
+    }
+
+    #[test]
+    fn test_demote_coldest_when_over_capacity() {
+        let candidates = [
+            candidate("warm", 60, StorageTier::Fast, 0, MINUTE),
+            candidate("cold", 60, StorageTier::Fast, 0, 5 * MINUTE),
+        ];
+        assert_eq!(plan_tier_moves(&candidates, &config()), vec![("cold".to_string(), StorageTier::Slow)]);
+    }
+
+    #[test]
+    fn test_promote_hottest_within_capacity() {
+        let candidates = [
+            candidate("hot", 60, StorageTier::Slow, 9, MINUTE),
+            candidate("warm", 60, StorageTier::Slow, 5, MINUTE),
+            candidate("small", 30, StorageTier::Slow, 4, MINUTE),
+            candidate("cool", 10, StorageTier::Slow, 2, MINUTE),
+        ];
+        assert_eq!(
+            plan_tier_moves(&candidates, &config()),
+            vec![("hot".to_string(), StorageTier::Fast), ("small".to_string(), StorageTier::Fast)]
+        );
+    }
+
+    #[test]
+    fn test_skip_recently_written_entries() {
+        let mut writing = candidate("writing", 10, StorageTier::Slow, 9, Duration::ZERO);
+        writing.last_write = SystemTime::now();
+        let mut idle = candidate("idle", 10, StorageTier::Fast, 0, 40 * MINUTE);
+        idle.last_write = SystemTime::now();
+        assert!(plan_tier_moves(&[writing, idle], &config()).is_empty());
+    }
+}
@@ -0,0 +1,239 @@
+use std::path::{Path, PathBuf};
+use std::thread;
+use tokio::sync::{mpsc, oneshot};
+use tokio_stream::wrappers::ReceiverStream;
+use futures::{Stream, StreamExt};
+use async_trait::async_trait;
+use bytes::Bytes;
+
+use crate::utils::error::{Result, ProxyError};
+use crate::log_info;
+use super::disk::write_error;
+use super::{DiskStorage, EntryWriter, StorageEngine, StorageConfig, StorageTier, EntryMeta, IndexEntry};
+
+/// 读取时在 io_uring 线程和调用方之间缓冲的数据块数量
+const READ_AHEAD_CHUNKS: usize = 4;
+
+type DataStream = Box<dyn Stream<Item = Result<Bytes>> + Send + Unpin>;
+
+/// 提交给 io_uring 线程的操作
+enum UringOp {
+    Read {
+        path: PathBuf,
+        start: u64,
+        end: u64,
+        chunk_size: usize,
+        tx: mpsc::Sender<Result<Bytes>>,
+    },
+    Write {
+        path: PathBuf,
+        offset: u64,
+        stream: DataStream,
+        reply: oneshot::Sender<Result<u64>>,
+    },
+}
+
+/// 基于 io_uring 的存储引擎，只接管读写热路径，目录布局、元数据、去重和分层仍由 `DiskStorage` 处理。
+/// tokio-uring 的运行时和文件句柄不能跨线程，读写操作提交到专用线程上执行
+pub struct UringStorage {
+    inner: DiskStorage,
+    ops: mpsc::UnboundedSender<UringOp>,
+}
+
+impl UringStorage {
+    pub fn new(config: StorageConfig) -> Self {
+        let (ops, mut rx) = mpsc::unbounded_channel::<UringOp>();
+        thread::Builder::new()
+            .name("storage-uring".to_string())
+            .spawn(move || {
+                tokio_uring::start(async move {
+                    while let Some(op) = rx.recv().await {
+                        tokio_uring::spawn(run(op));
+                    }
+                });
+            })
+            .expect("启动 io_uring 线程失败");
+        log_info!("Storage", "使用 io_uring 存储引擎");
+
+        Self {
+            inner: DiskStorage::new(config),
+            ops,
+        }
+    }
+
+    fn submit(&self, op: UringOp) -> Result<()> {
+        self.ops
+            .send(op)
+            .map_err(|_| ProxyError::storage("io_uring 线程已退出".to_string()))
+    }
+}
+
+async fn run(op: UringOp) {
+    match op {
+        UringOp::Read { path, start, end, chunk_size, tx } => {
+            if let Err(e) = read_file(&path, start, end, chunk_size, &tx).await {
+                let _ = tx.send(Err(e.into())).await;
+            }
+        }
+        UringOp::Write { path, offset, stream, reply } => {
+            let _ = reply.send(write_file(&path, offset, stream).await);
+        }
+    }
+}
+
+async fn read_file(path: &Path, start: u64, end: u64, chunk_size: usize, tx: &mpsc::Sender<Result<Bytes>>) -> std::io::Result<()> {
+    let file = tokio_uring::fs::File::open(path).await?;
+    let mut position = start;
+    while position <= end {
+        let to_read = chunk_size.min((end - position + 1) as usize);
+        let (res, buffer) = file.read_at(Vec::with_capacity(to_read), position).await;
+        let n = res?;
+        if n == 0 {
+            break;
+        }
+        position += n as u64;
+        // 调用方已丢弃读取流时停止读取
+        if tx.send(Ok(Bytes::from(buffer))).await.is_err() {
+            break;
+        }
+    }
+    file.close().await
+}
+
+async fn write_file(path: &Path, offset: u64, mut stream: DataStream) -> Result<u64> {
+    let file = tokio_uring::fs::OpenOptions::new()
+        .write(true)
+        .open(path)
+        .await
+        .map_err(write_error)?;
+
+    let mut written = 0u64;
+    while let Some(chunk) = stream.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                let _ = file.close().await;
+                return Err(e);
+            }
+        };
+        let len = chunk.len() as u64;
+        let (res, _) = file.write_all_at(chunk, offset + written).await;
+        res.map_err(write_error)?;
+        written += len;
+    }
+
+    file.close().await?;
+    Ok(written)
+}
+
+#[async_trait]
+impl StorageEngine for UringStorage {
+    async fn write<S>(&self, key: &str, stream: S, range: (u64, u64)) -> Result<u64>
+    where
+        S: Stream<Item = Result<Bytes>> + Send + Unpin + 'static,
+    {
+        // 目录和文件长度的准备沿用普通引擎，数据块写入提交到 io_uring
+        let (path, _) = self.inner.open_for_write(key, range).await?;
+        let (reply, rx) = oneshot::channel();
+        self.submit(UringOp::Write {
+            path: path.clone(),
+            offset: range.0,
+            stream: Box::new(stream),
+            reply,
+        })?;
+        let written = rx
+            .await
+            .map_err(|_| ProxyError::storage("io_uring 写入任务已取消".to_string()))??;
+        log_info!("Storage", "写入完成: {:?}, 写入字节数: {}", path, written);
+        Ok(written)
+    }
+
+    async fn read(&self, key: &str, range: (u64, u64)) -> Result<DataStream> {
+        if let Some(stream) = self.inner.read_inline(key, range) {
+            return stream;
+        }
+        let (path, file, file_size, end) = self.inner.open_for_read(key, range)?;
+        if let Some(stream) = self.inner.try_read_mapped(key, &file, file_size, range.0, end) {
+            return Ok(stream);
+        }
+        drop(file);
+
+        let (tx, rx) = mpsc::channel(READ_AHEAD_CHUNKS);
+        self.submit(UringOp::Read {
+            path,
+            start: range.0,
+            end,
+            chunk_size: self.inner.chunk_size(),
+            tx,
+        })?;
+        Ok(Box::new(ReceiverStream::new(rx)))
+    }
+
+    async fn get_size(&self, key: &str) -> Result<Option<u64>> {
+        self.inner.get_size(key).await
+    }
+
+    async fn allocated_size(&self, key: &str) -> Result<Option<u64>> {
+        self.inner.allocated_size(key).await
+    }
+
+    async fn check_range(&self, key: &str, range: (u64, u64)) -> Result<bool> {
+        self.inner.check_range(key, range).await
+    }
+
+    async fn remove(&self, key: &str) -> Result<()> {
+        self.inner.remove(key).await
+    }
+
+    fn available_space(&self, key: &str) -> Option<u64> {
+        self.inner.available_space(key)
+    }
+
+    fn has_unindexed_data(&self) -> bool {
+        self.inner.has_unindexed_data()
+    }
+
+    async fn is_available(&self) -> bool {
+        self.inner.is_available().await
+    }
+
+    async fn move_to_tier(&self, key: &str, tier: StorageTier) -> Result<()> {
+        self.inner.move_to_tier(key, tier).await
+    }
+
+    async fn deduplicate(&self, key: &str) -> Result<Option<String>> {
+        self.inner.deduplicate(key).await
+    }
+
+    async fn save_inline(&self, meta: &EntryMeta, data: &Bytes) -> Result<bool> {
+        self.inner.save_inline(meta, data).await
+    }
+
+    async fn save_meta(&self, meta: &EntryMeta) -> Result<()> {
+        self.inner.save_meta(meta).await
+    }
+
+    async fn load_index(&self) -> Result<Vec<IndexEntry>> {
+        self.inner.load_index().await
+    }
+
+    async fn load_index_shard(&self, shard: usize, shards: usize) -> Result<Vec<IndexEntry>> {
+        self.inner.load_index_shard(shard, shards).await
+    }
+
+    async fn content_hash(&self, key: &str) -> Result<Option<String>> {
+        self.inner.content_hash(key).await
+    }
+
+    fn data_path(&self, key: &str) -> Option<PathBuf> {
+        self.inner.data_path(key)
+    }
+
+    async fn open_writer(&self, key: &str, range: (u64, u64)) -> Result<Option<Box<dyn EntryWriter>>> {
+        self.inner.open_writer(key, range).await
+    }
+
+    async fn truncate(&self, key: &str, len: u64) -> Result<()> {
+        self.inner.truncate(key, len).await
+    }
+}
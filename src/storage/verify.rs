@@ -0,0 +1,34 @@
+use serde::Serialize;
+
+/// 校验发现的问题
+#[derive(Debug, Clone, Serialize)]
+pub struct VerifyIssue {
+    pub key: String,
+    pub problem: String,
+    /// 是否已修复
+    pub repaired: bool,
+}
+
+/// 缓存校验报告
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct VerifyReport {
+    /// 检查的条目数
+    pub checked: usize,
+    /// 没有问题的条目数
+    pub healthy: usize,
+    /// 重新计算过哈希的条目数
+    pub hashed: usize,
+    pub issues: Vec<VerifyIssue>,
+    pub started_at: String,
+    pub duration_secs: f64,
+}
+
+impl VerifyReport {
+    pub fn add_issue(&mut self, key: &str, problem: String, repaired: bool) {
+        self.issues.push(VerifyIssue {
+            key: key.to_string(),
+            problem,
+            repaired,
+        });
+    }
+}
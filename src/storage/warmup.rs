@@ -0,0 +1,71 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use serde::Serialize;
+
+/// 索引加载进度，`/readyz` 返回
+#[derive(Debug, Clone, Serialize)]
+pub struct IndexStatus {
+    /// 索引已加载完成，缓存查询不再回退到磁盘
+    pub ready: bool,
+    /// 正在加载，未加载到的键直接转发到源站
+    pub loading: bool,
+    pub loaded_shards: usize,
+    pub total_shards: usize,
+    /// 已加载的条目数
+    pub entries: usize,
+    /// 加载用时，加载中为已用的时间
+    pub elapsed_secs: f64,
+}
+
+/// 分片并行加载索引时记录进度
+#[derive(Default)]
+pub struct IndexProgress {
+    loading: AtomicBool,
+    loaded_shards: AtomicUsize,
+    total_shards: AtomicUsize,
+    entries: AtomicUsize,
+    started: Mutex<Option<Instant>>,
+    took: Mutex<Option<Duration>>,
+}
+
+impl IndexProgress {
+    pub fn start(&self, shards: usize) {
+        self.loaded_shards.store(0, Ordering::Relaxed);
+        self.total_shards.store(shards, Ordering::Relaxed);
+        self.entries.store(0, Ordering::Relaxed);
+        *self.started.lock().unwrap() = Some(Instant::now());
+        *self.took.lock().unwrap() = None;
+        self.loading.store(true, Ordering::Release);
+    }
+
+    pub fn shard_loaded(&self, entries: usize) {
+        self.loaded_shards.fetch_add(1, Ordering::Relaxed);
+        self.entries.fetch_add(entries, Ordering::Relaxed);
+    }
+
+    pub fn finish(&self) {
+        *self.took.lock().unwrap() = self.started.lock().unwrap().map(|started| started.elapsed());
+        self.loading.store(false, Ordering::Release);
+    }
+
+    pub fn is_loading(&self) -> bool {
+        self.loading.load(Ordering::Acquire)
+    }
+
+    pub fn status(&self, ready: bool) -> IndexStatus {
+        IndexStatus {
+            ready,
+            loading: self.is_loading(),
+            loaded_shards: self.loaded_shards.load(Ordering::Relaxed),
+            total_shards: self.total_shards.load(Ordering::Relaxed),
+            entries: self.entries.load(Ordering::Relaxed),
+            elapsed_secs: self
+                .took
+                .lock()
+                .unwrap()
+                .or_else(|| self.started.lock().unwrap().map(|started| started.elapsed()))
+                .map_or(0.0, |elapsed| elapsed.as_secs_f64()),
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use std::io::SeekFrom;
+use async_trait::async_trait;
+use tokio::fs as tokio_fs;
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use crate::utils::error::Result;
+use super::disk::write_error;
+
+/// 连续写入的数据同步到磁盘的时机
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FsyncPolicy {
+    /// 每写入一批缓冲数据同步一次
+    EveryChunk,
+    /// 累计写入指定字节数后同步一次，写入结束时再同步剩余部分
+    EveryBytes(u64),
+    /// 只在写入结束时同步一次
+    #[default]
+    OnCompletion,
+}
+
+impl FsyncPolicy {
+    /// 自上次同步后又写入了 `unsynced` 字节，是否需要立即同步
+    pub fn should_sync(&self, unsynced: u64) -> bool {
+        match *self {
+            Self::EveryChunk => unsynced > 0,
+            Self::EveryBytes(bytes) => unsynced >= bytes.max(1),
+            Self::OnCompletion => false,
+        }
+    }
+}
+
+/// 连续写入同一条目的句柄，文件只在打开时定位一次，之后顺序追加
+#[async_trait]
+pub trait EntryWriter: Send {
+    /// 追加数据，返回时数据已交给操作系统，其他读取方可以读到
+    async fn write(&mut self, data: &[u8]) -> Result<()>;
+
+    /// 将已写入的数据同步到磁盘
+    async fn sync(&mut self) -> Result<()>;
+}
+
+/// 磁盘文件的连续写入
+pub struct DiskWriter {
+    file: tokio_fs::File,
+    offset: u64,
+    /// 上次写入失败，文件位置不确定，下次写入前重新定位
+    reposition: bool,
+}
+
+impl DiskWriter {
+    pub async fn new(mut file: tokio_fs::File, offset: u64) -> Result<Self> {
+        file.seek(SeekFrom::Start(offset)).await.map_err(write_error)?;
+        Ok(Self {
+            file,
+            offset,
+            reposition: false,
+        })
+    }
+}
+
+#[async_trait]
+impl EntryWriter for DiskWriter {
+    async fn write(&mut self, data: &[u8]) -> Result<()> {
+        if self.reposition {
+            self.file.seek(SeekFrom::Start(self.offset)).await.map_err(write_error)?;
+            self.reposition = false;
+        }
+        // tokio 的文件写入在后台线程完成，flush 等待写入结束，不同步到磁盘
+        let result = match self.file.write_all(data).await {
+            Ok(()) => self.file.flush().await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            self.reposition = true;
+            return Err(write_error(e));
+        }
+        self.offset += data.len() as u64;
+        Ok(())
+    }
+
+    async fn sync(&mut self) -> Result<()> {
+        self.file.sync_data().await.map_err(write_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fsync_policy() {
+        assert!(FsyncPolicy::EveryChunk.should_sync(1));
+        assert!(!FsyncPolicy::EveryChunk.should_sync(0));
+        assert!(!FsyncPolicy::EveryBytes(1024).should_sync(1023));
+        assert!(FsyncPolicy::EveryBytes(1024).should_sync(1024));
+        assert!(!FsyncPolicy::OnCompletion.should_sync(u64::MAX));
+    }
+}
@@ -0,0 +1,265 @@
+//! 集成测试辅助工具：本地模拟源站和临时端口上的代理服务器
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use bytes::Bytes;
+use hyper::header::{ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG, IF_NONE_MATCH, IF_RANGE, RANGE};
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use hyper::{Body, Request, Response, StatusCode};
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+use crate::config::ProxyConfig;
+use crate::server::ProxyServer;
+use crate::utils::error::Result;
+use crate::utils::range::parse_range;
+
+/// 模拟源站上的一个资源
+#[derive(Clone)]
+pub struct MockResource {
+    pub body: Bytes,
+    pub content_type: String,
+    pub etag: Option<String>,
+    /// 是否支持 Range 请求
+    pub accept_ranges: bool,
+    /// 注入错误：设置后总是返回该状态码
+    pub status: Option<StatusCode>,
+    /// 每次发送的字节数
+    pub chunk_size: usize,
+    /// 慢速发送：每块数据之间的间隔
+    pub chunk_delay: Option<Duration>,
+    /// 发送指定字节数后断开连接
+    pub disconnect_after: Option<u64>,
+}
+
+impl MockResource {
+    pub fn new(body: impl Into<Bytes>) -> Self {
+        Self {
+            body: body.into(),
+            content_type: "application/octet-stream".to_string(),
+            etag: None,
+            accept_ranges: true,
+            status: None,
+            chunk_size: 16 * 1024,
+            chunk_delay: None,
+            disconnect_after: None,
+        }
+    }
+
+    /// 生成指定大小的测试数据，内容为按位置循环的字节
+    pub fn sized(size: usize) -> Self {
+        Self::new((0..size).map(|i| (i % 251) as u8).collect::<Vec<u8>>())
+    }
+
+    pub fn with_etag(mut self, etag: &str) -> Self {
+        self.etag = Some(etag.to_string());
+        self
+    }
+
+    pub fn with_status(mut self, status: StatusCode) -> Self {
+        self.status = Some(status);
+        self
+    }
+
+    pub fn with_drip(mut self, chunk_size: usize, delay: Duration) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self.chunk_delay = Some(delay);
+        self
+    }
+
+    pub fn with_disconnect_after(mut self, bytes: u64) -> Self {
+        self.disconnect_after = Some(bytes);
+        self
+    }
+}
+
+#[derive(Default)]
+struct OriginState {
+    resources: HashMap<String, MockResource>,
+    requests: Vec<(String, Option<String>)>,
+}
+
+/// 本地模拟源站，支持 Range、ETag、If-Range、慢速发送和错误注入，丢弃时停止
+pub struct MockOrigin {
+    addr: SocketAddr,
+    state: Arc<Mutex<OriginState>>,
+    task: JoinHandle<()>,
+}
+
+impl MockOrigin {
+    /// 在临时端口上启动
+    pub async fn start() -> Result<Self> {
+        let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0))).await?;
+        let addr = listener.local_addr()?;
+        let state = Arc::new(Mutex::new(OriginState::default()));
+
+        let server_state = state.clone();
+        let task = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let state = server_state.clone();
+                tokio::spawn(async move {
+                    let service = service_fn(move |req| {
+                        let response = respond(&state, req);
+                        async move { Ok::<_, Infallible>(response) }
+                    });
+                    let _ = Http::new().serve_connection(stream, service).await;
+                });
+            }
+        });
+
+        Ok(Self { addr, state, task })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// 资源的完整 URL
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.addr, path)
+    }
+
+    /// 注册资源，已存在时替换
+    pub fn add(&self, path: &str, resource: MockResource) {
+        self.state.lock().unwrap().resources.insert(path.to_string(), resource);
+    }
+
+    /// 按到达顺序记录的请求路径和 Range 头
+    pub fn requests(&self) -> Vec<(String, Option<String>)> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    /// 指定路径收到的请求数
+    pub fn request_count(&self, path: &str) -> usize {
+        self.state.lock().unwrap().requests.iter().filter(|(p, _)| p == path).count()
+    }
+}
+
+impl Drop for MockOrigin {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+fn respond(state: &Mutex<OriginState>, req: Request<Body>) -> Response<Body> {
+    let path = req.uri().path().to_string();
+    let range = req.headers().get(RANGE).and_then(|v| v.to_str().ok()).map(|v| v.to_string());
+    let resource = {
+        let mut state = state.lock().unwrap();
+        state.requests.push((path.clone(), range.clone()));
+        state.resources.get(&path).cloned()
+    };
+
+    let resource = match resource {
+        Some(resource) => resource,
+        None => return status_response(StatusCode::NOT_FOUND),
+    };
+    if let Some(status) = resource.status {
+        return status_response(status);
+    }
+
+    let mut builder = Response::builder().header(CONTENT_TYPE, resource.content_type.as_str());
+    if let Some(etag) = &resource.etag {
+        let if_none_match = req.headers().get(IF_NONE_MATCH).and_then(|v| v.to_str().ok());
+        if if_none_match == Some(etag.as_str()) {
+            return Response::builder()
+                .status(StatusCode::NOT_MODIFIED)
+                .header(ETAG, etag.as_str())
+                .body(Body::empty())
+                .unwrap();
+        }
+        builder = builder.header(ETAG, etag.as_str());
+    }
+
+    // If-Range 与当前 ETag 不一致时忽略 Range，返回完整内容
+    let if_range = req.headers().get(IF_RANGE).and_then(|v| v.to_str().ok());
+    let range = range.filter(|_| if_range.is_none_or(|validator| resource.etag.as_deref() == Some(validator)));
+
+    let total = resource.body.len() as u64;
+    let (status, start, end) = match range.filter(|_| resource.accept_ranges) {
+        Some(range) => match parse_range(&range) {
+            Ok((start, end)) if start < total => (StatusCode::PARTIAL_CONTENT, start, end.min(total - 1)),
+            _ => {
+                return Response::builder()
+                    .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                    .header(CONTENT_RANGE, format!("bytes */{}", total))
+                    .body(Body::empty())
+                    .unwrap();
+            }
+        },
+        None if total == 0 => (StatusCode::OK, 0, 0),
+        None => (StatusCode::OK, 0, total - 1),
+    };
+
+    if resource.accept_ranges {
+        builder = builder.header(ACCEPT_RANGES, "bytes");
+    }
+    if status == StatusCode::PARTIAL_CONTENT {
+        builder = builder.header(CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, total));
+    }
+    let data = if total == 0 {
+        Bytes::new()
+    } else {
+        resource.body.slice(start as usize..=end as usize)
+    };
+
+    builder
+        .status(status)
+        .header(CONTENT_LENGTH, data.len())
+        .body(body_stream(data, &resource))
+        .unwrap()
+}
+
+/// 按块发送数据，支持慢速发送和中途断开
+fn body_stream(data: Bytes, resource: &MockResource) -> Body {
+    let chunk_size = resource.chunk_size.max(1);
+    let delay = resource.chunk_delay;
+    let limit = resource.disconnect_after;
+
+    let stream = futures::stream::unfold(0usize, move |offset| {
+        let data = data.clone();
+        async move {
+            if offset >= data.len() {
+                return None;
+            }
+            if limit.is_some_and(|limit| offset as u64 >= limit) {
+                let error = io::Error::new(io::ErrorKind::ConnectionReset, "模拟源站断开连接");
+                return Some((Err(error), data.len()));
+            }
+            if let Some(delay) = delay {
+                tokio::time::sleep(delay).await;
+            }
+            let mut end = (offset + chunk_size).min(data.len());
+            if let Some(limit) = limit {
+                end = end.min((limit as usize).max(offset + 1));
+            }
+            Some((Ok(data.slice(offset..end)), end))
+        }
+    });
+    Body::wrap_stream(stream)
+}
+
+fn status_response(status: StatusCode) -> Response<Body> {
+    Response::builder().status(status).body(Body::empty()).unwrap()
+}
+
+/// 在临时端口上启动代理服务器，返回监听地址
+pub async fn spawn_proxy(cache_dir: &Path, config: ProxyConfig) -> Result<SocketAddr> {
+    let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0))).await?;
+    let addr = listener.local_addr()?;
+    let server = ProxyServer::with_config(addr.port(), &cache_dir.to_string_lossy(), config);
+    tokio::spawn(async move {
+        let _ = server.serve(listener).await;
+    });
+    Ok(addr)
+}
+
+/// 通过代理访问上游 URL 的地址
+pub fn proxy_url(proxy: SocketAddr, url: &str) -> String {
+    format!("http://{}/proxy/{}", proxy, urlencoding::encode(url))
+}
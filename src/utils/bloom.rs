@@ -0,0 +1,87 @@
+/// 布隆过滤器
+///
+/// 判断为不存在的元素一定不存在，判断为存在的元素有一定误判率
+#[derive(Debug, Clone)]
+pub struct BloomFilter {
+    capacity: usize,
+    bits: Vec<u64>,
+    num_bits: u64,
+    num_hashes: u32,
+}
+
+impl BloomFilter {
+    /// 按预期元素数量和误判率创建过滤器
+    pub fn new(expected_items: usize, false_positive_rate: f64) -> Self {
+        let n = expected_items.max(1) as f64;
+        let p = false_positive_rate.clamp(1e-6, 0.5);
+        let ln2 = std::f64::consts::LN_2;
+
+        let num_bits = ((-n * p.ln()) / (ln2 * ln2)).ceil().max(64.0) as u64;
+        let num_hashes = ((num_bits as f64 / n) * ln2).round().clamp(1.0, 16.0) as u32;
+
+        Self {
+            capacity: expected_items,
+            bits: vec![0; num_bits.div_ceil(64) as usize],
+            num_bits,
+            num_hashes,
+        }
+    }
+
+    /// 创建时预期的元素数量，超过后误判率上升
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn insert(&mut self, item: &str) {
+        for index in self.indexes(item) {
+            self.bits[(index / 64) as usize] |= 1u64 << (index % 64);
+        }
+    }
+
+    pub fn contains(&self, item: &str) -> bool {
+        self.indexes(item)
+            .all(|index| self.bits[(index / 64) as usize] & (1u64 << (index % 64)) != 0)
+    }
+
+    pub fn clear(&mut self) {
+        self.bits.iter_mut().for_each(|word| *word = 0);
+    }
+
+    /// 双重哈希生成 k 个位置
+    fn indexes(&self, item: &str) -> impl Iterator<Item = u64> {
+        let digest = md5::compute(item.as_bytes()).0;
+        let h1 = u64::from_le_bytes(digest[0..8].try_into().unwrap());
+        let h2 = u64::from_le_bytes(digest[8..16].try_into().unwrap()) | 1;
+        let num_bits = self.num_bits;
+        (0..self.num_hashes as u64).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % num_bits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_and_contains() {
+        let mut filter = BloomFilter::new(1000, 0.01);
+        for i in 0..1000 {
+            filter.insert(&format!("http://example.com/{}", i));
+        }
+        for i in 0..1000 {
+            assert!(filter.contains(&format!("http://example.com/{}", i)));
+        }
+
+        let false_positives = (1000..11000)
+            .filter(|i| filter.contains(&format!("http://example.com/{}", i)))
+            .count();
+        assert!(false_positives < 500);
+    }
+
+    #[test]
+    fn test_clear() {
+        let mut filter = BloomFilter::new(10, 0.01);
+        filter.insert("http://example.com/video.mp4");
+        filter.clear();
+        assert!(!filter.contains("http://example.com/video.mp4"));
+    }
+}
@@ -0,0 +1,81 @@
+use url::Url;
+
+/// 规范化 URL，等价的地址得到相同的缓存键：协议和主机名转为小写、去掉默认端口和片段、
+/// 解码非保留字符的百分号转义，其余转义统一为大写十六进制。无法解析的 URL 原样返回
+pub fn canonicalize(url: &str) -> String {
+    // 解析时会处理协议、主机名大小写和默认端口
+    let mut parsed = match Url::parse(url) {
+        Ok(parsed) => parsed,
+        Err(_) => return url.to_string(),
+    };
+    parsed.set_fragment(None);
+    if !parsed.cannot_be_a_base() {
+        let path = normalize_escapes(parsed.path());
+        parsed.set_path(&path);
+    }
+    if let Some(query) = parsed.query().map(normalize_escapes) {
+        parsed.set_query(Some(&query));
+    }
+    parsed.to_string()
+}
+
+/// 按参数名排序查询参数，参数顺序不影响内容的源站可以开启，同名参数保持原有顺序
+pub fn sort_query(url: &str) -> String {
+    let (base, query) = match url.split_once('?') {
+        Some(parts) => parts,
+        None => return url.to_string(),
+    };
+    let mut params: Vec<&str> = query.split('&').filter(|param| !param.is_empty()).collect();
+    params.sort_by_key(|param| param.split('=').next().unwrap_or(""));
+    if params.is_empty() {
+        return base.to_string();
+    }
+    format!("{}?{}", base, params.join("&"))
+}
+
+fn is_unreserved(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~')
+}
+
+/// 输入来自已解析的 URL，只包含 ASCII 字符
+fn normalize_escapes(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut output = String::with_capacity(input.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() && bytes[i + 1].is_ascii_hexdigit() && bytes[i + 2].is_ascii_hexdigit() {
+            let hex = &input[i + 1..i + 3];
+            let byte = u8::from_str_radix(hex, 16).unwrap_or(b'%');
+            if is_unreserved(byte) {
+                output.push(byte as char);
+            } else {
+                output.push('%');
+                output.push_str(&hex.to_ascii_uppercase());
+            }
+            i += 3;
+            continue;
+        }
+        output.push(bytes[i] as char);
+        i += 1;
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_canonicalize() {
+        assert_eq!(
+            canonicalize("HTTP://Example.COM:80/%7Euser/a%2fb%41.mp4?x=%6a#frag"),
+            "http://example.com/~user/a%2FbA.mp4?x=j"
+        );
+        assert_eq!(canonicalize("https://example.com:443/a.mp4"), "https://example.com/a.mp4");
+        assert_eq!(canonicalize("https://example.com:8443/a.mp4"), "https://example.com:8443/a.mp4");
+        assert_eq!(canonicalize("not a url"), "not a url");
+
+        assert_eq!(sort_query("http://example.com/a.mp4?b=2&a=1&b=1"), "http://example.com/a.mp4?a=1&b=2&b=1");
+        assert_eq!(sort_query("http://example.com/a.mp4"), "http://example.com/a.mp4");
+    }
+}
@@ -1,90 +1,291 @@
 use std::error::Error;
 use std::fmt;
 use std::io;
+use std::sync::Arc;
+use hyper::StatusCode;
 use tokio::sync::AcquireError;
 use std::str::Utf8Error;
 
 /// 全局结果集类型
 pub type Result<T> = std::result::Result<T, ProxyError>;
 
+/// 错误类别，调用方据此区分源站错误、磁盘错误和客户端断开等情况
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    Cache,
+    /// 连接上游失败或上游数据流中断
+    Network,
+    /// 上游返回了错误状态码，状态码见 `ProxyError::upstream_status`
+    Upstream,
+    InvalidRange,
+    Request,
+    Storage,
+    /// 磁盘已满
+    NoSpace,
+    /// 资源繁忙，等待超时，对客户端返回 503
+    Busy,
+    Parse,
+    Io,
+    /// 客户端在响应完成前断开
+    ClientAborted,
+    /// 下载被取消
+    Cancelled,
+}
+
+impl ErrorKind {
+    fn label(&self) -> &'static str {
+        match self {
+            ErrorKind::Cache => "Cache",
+            ErrorKind::Network => "Network",
+            ErrorKind::Upstream => "Upstream",
+            ErrorKind::InvalidRange => "Invalid range",
+            ErrorKind::Request => "Request",
+            ErrorKind::Storage => "Storage",
+            ErrorKind::NoSpace => "No space",
+            ErrorKind::Busy => "Busy",
+            ErrorKind::Parse => "Parse",
+            ErrorKind::Io => "IO",
+            ErrorKind::ClientAborted => "Client aborted",
+            ErrorKind::Cancelled => "Cancelled",
+        }
+    }
+}
+
+/// 代理错误：类别、描述、可选的底层错误以及出错的 URL 和字节范围
 #[derive(Debug, Clone)]
-pub enum ProxyError {
-    Cache(String),
-    Network(String),
-    InvalidRange(String),
-    Range(String),
-    Request(String),
-    Storage(String),
-    Parse(String),
-    IO(String),
+#[non_exhaustive]
+pub struct ProxyError {
+    kind: ErrorKind,
+    message: String,
+    source: Option<Arc<dyn Error + Send + Sync>>,
+    upstream_status: Option<StatusCode>,
+    url: Option<String>,
+    range: Option<(u64, u64)>,
+}
+
+impl ProxyError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+            source: None,
+            upstream_status: None,
+            url: None,
+            range: None,
+        }
+    }
+
+    pub fn cache(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Cache, message)
+    }
+
+    pub fn network(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Network, message)
+    }
+
+    /// 上游返回错误状态码
+    pub fn upstream(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            upstream_status: Some(status),
+            ..Self::new(ErrorKind::Upstream, message)
+        }
+    }
+
+    pub fn invalid_range(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::InvalidRange, message)
+    }
+
+    pub fn request(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Request, message)
+    }
+
+    pub fn storage(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Storage, message)
+    }
+
+    pub fn no_space(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::NoSpace, message)
+    }
+
+    pub fn busy(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Busy, message)
+    }
+
+    pub fn parse(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Parse, message)
+    }
+
+    pub fn io(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Io, message)
+    }
+
+    pub fn client_aborted(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::ClientAborted, message)
+    }
+
+    pub fn cancelled(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Cancelled, message)
+    }
+
+    /// 附加底层错误，通过 `Error::source` 返回
+    pub fn with_source(mut self, source: impl Error + Send + Sync + 'static) -> Self {
+        self.source = Some(Arc::new(source));
+        self
+    }
+
+    /// 记录出错的 URL，已有 URL 时保留原值
+    pub fn with_url(mut self, url: impl Into<String>) -> Self {
+        if self.url.is_none() {
+            self.url = Some(url.into());
+        }
+        self
+    }
+
+    /// 记录出错的字节范围，已有范围时保留原值
+    pub fn with_range(mut self, start: u64, end: u64) -> Self {
+        if self.range.is_none() {
+            self.range = Some((start, end));
+        }
+        self
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn upstream_status(&self) -> Option<StatusCode> {
+        self.upstream_status
+    }
+
+    pub fn url(&self) -> Option<&str> {
+        self.url.as_deref()
+    }
+
+    pub fn range(&self) -> Option<(u64, u64)> {
+        self.range
+    }
+
+    /// 稍后重试可能成功的错误：网络中断、资源繁忙、上游 5xx 和 429
+    pub fn is_retryable(&self) -> bool {
+        match self.kind {
+            ErrorKind::Network | ErrorKind::Busy => true,
+            ErrorKind::Upstream => self.upstream_status.is_some_and(|status| {
+                status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+            }),
+            _ => false,
+        }
+    }
+
+    /// 返回给客户端的状态码，上游的 4xx 原样透传
+    pub fn status_code(&self) -> StatusCode {
+        match self.kind {
+            ErrorKind::Upstream => match self.upstream_status {
+                Some(status) if status.is_client_error() => status,
+                _ => StatusCode::BAD_GATEWAY,
+            },
+            ErrorKind::Network => StatusCode::BAD_GATEWAY,
+            ErrorKind::InvalidRange => StatusCode::RANGE_NOT_SATISFIABLE,
+            ErrorKind::Request | ErrorKind::Parse => StatusCode::BAD_REQUEST,
+            ErrorKind::NoSpace | ErrorKind::Busy | ErrorKind::Cancelled => StatusCode::SERVICE_UNAVAILABLE,
+            // 客户端已断开，状态码只用于日志
+            ErrorKind::ClientAborted => StatusCode::from_u16(499).unwrap_or(StatusCode::BAD_REQUEST),
+            ErrorKind::Cache | ErrorKind::Storage | ErrorKind::Io => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
 }
 
 impl fmt::Display for ProxyError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            ProxyError::Cache(msg) => write!(f, "Cache error: {}", msg),
-            ProxyError::Network(msg) => write!(f, "Network error: {}", msg),
-            ProxyError::InvalidRange(msg) => write!(f, "Invalid range error: {}", msg),
-            ProxyError::Range(msg) => write!(f, "Range error: {}", msg),
-            ProxyError::Request(msg) => write!(f, "Request error: {}", msg),
-            ProxyError::Storage(msg) => write!(f, "Storage error: {}", msg),
-            ProxyError::Parse(msg) => write!(f, "Parse error: {}", msg),
-            ProxyError::IO(msg) => write!(f, "IO error: {}", msg),
+        write!(f, "{} error: {}", self.kind.label(), self.message)?;
+        if let Some(url) = &self.url {
+            write!(f, " (url: {}", url)?;
+            if let Some((start, end)) = self.range {
+                write!(f, ", range: {}-{}", start, end)?;
+            }
+            write!(f, ")")?;
         }
+        Ok(())
     }
 }
 
 impl Error for ProxyError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
-        None
+        self.source.as_deref().map(|source| source as &(dyn Error + 'static))
     }
 }
 
 impl From<hyper::Error> for ProxyError {
     fn from(err: hyper::Error) -> Self {
-        ProxyError::Network(err.to_string())
+        ProxyError::network(err.to_string()).with_source(err)
     }
 }
 
 impl From<io::Error> for ProxyError {
     fn from(err: io::Error) -> Self {
-        ProxyError::IO(err.to_string())
+        ProxyError::io(err.to_string()).with_source(err)
     }
 }
 
 impl From<std::num::ParseIntError> for ProxyError {
     fn from(err: std::num::ParseIntError) -> Self {
-        ProxyError::Parse(err.to_string())
+        ProxyError::parse(err.to_string()).with_source(err)
     }
 }
 
 impl From<serde_json::Error> for ProxyError {
     fn from(err: serde_json::Error) -> Self {
-        ProxyError::Parse(err.to_string())
+        ProxyError::parse(err.to_string()).with_source(err)
     }
 }
 
 impl From<hyper::http::Error> for ProxyError {
     fn from(err: hyper::http::Error) -> Self {
-        ProxyError::Request(err.to_string())
+        ProxyError::request(err.to_string()).with_source(err)
     }
 }
 
 impl From<hyper::header::ToStrError> for ProxyError {
     fn from(err: hyper::header::ToStrError) -> Self {
-        ProxyError::Request(err.to_string())
+        ProxyError::request(err.to_string()).with_source(err)
     }
 }
 
 impl From<Utf8Error> for ProxyError {
     fn from(err: Utf8Error) -> Self {
-        ProxyError::Parse(err.to_string())
+        ProxyError::parse(err.to_string()).with_source(err)
     }
 }
 
 impl From<AcquireError> for ProxyError {
-    fn from(_: AcquireError) -> Self {
-        ProxyError::Storage("无法获取信号量".to_string())
+    fn from(err: AcquireError) -> Self {
+        ProxyError::storage("无法获取信号量").with_source(err)
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kind_and_status() {
+        let err = ProxyError::upstream(StatusCode::NOT_FOUND, "源站返回 404").with_url("http://example.com/a.mp4");
+        assert_eq!(err.kind(), ErrorKind::Upstream);
+        assert_eq!(err.status_code(), StatusCode::NOT_FOUND);
+        assert!(!err.is_retryable());
+        assert_eq!(err.url(), Some("http://example.com/a.mp4"));
+
+        let err = ProxyError::upstream(StatusCode::SERVICE_UNAVAILABLE, "源站返回 503");
+        assert_eq!(err.status_code(), StatusCode::BAD_GATEWAY);
+        assert!(err.is_retryable());
+
+        let err = ProxyError::from(io::Error::other("磁盘错误"));
+        assert_eq!(err.kind(), ErrorKind::Io);
+        assert!(err.source().is_some());
+        assert_eq!(ProxyError::no_space("磁盘已满").status_code(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
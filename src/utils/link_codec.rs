@@ -0,0 +1,183 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Nonce};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use crate::utils::error::{ProxyError, Result};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// 签名截取的字节数
+const SIGNATURE_LEN: usize = 16;
+
+/// ChaCha20-Poly1305 的 nonce 长度
+const NONCE_LEN: usize = 12;
+
+/// 由主密钥派生加密子密钥和 nonce 子密钥时使用的标签
+const CIPHER_KEY_LABEL: &[u8] = b"proxy-link-codec cipher";
+const NONCE_KEY_LABEL: &[u8] = b"proxy-link-codec nonce";
+
+/// 代理链接中源站 URL 的编码方式，重写播放列表时编码，解析入口请求时还原
+pub trait LinkCodec: Send + Sync {
+    /// 编码源站 URL，结果直接拼接在代理入口前缀之后，只能包含路径中安全的字符
+    fn encode(&self, url: &str) -> String;
+    /// 还原源站 URL，链接被篡改或无法识别时返回错误
+    fn decode(&self, token: &str) -> Result<String>;
+    /// 客户端是否可以自行构造链接，是时入口接受百分号编码的明文地址
+    fn is_plain(&self) -> bool {
+        false
+    }
+}
+
+/// 百分号编码，默认方式
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PercentCodec;
+
+impl LinkCodec for PercentCodec {
+    fn encode(&self, url: &str) -> String {
+        urlencoding::encode(url).into_owned()
+    }
+
+    fn decode(&self, token: &str) -> Result<String> {
+        urlencoding::decode(token)
+            .map(|url| url.into_owned())
+            .map_err(|e| ProxyError::request(format!("URL 解码失败: {}", e)))
+    }
+
+    fn is_plain(&self) -> bool {
+        true
+    }
+}
+
+/// HMAC-SHA256 签名：`<Base64 URL>.<签名>`，客户端无法伪造或修改链接，但源站 URL 仍可解码得到
+pub struct SignedCodec {
+    key: Vec<u8>,
+}
+
+impl SignedCodec {
+    pub fn new(key: impl Into<Vec<u8>>) -> Self {
+        Self { key: key.into() }
+    }
+
+    fn sign(&self, data: &[u8]) -> HmacSha256 {
+        let mut mac = <HmacSha256 as Mac>::new_from_slice(&self.key).expect("HMAC 支持任意长度的密钥");
+        mac.update(data);
+        mac
+    }
+}
+
+impl LinkCodec for SignedCodec {
+    fn encode(&self, url: &str) -> String {
+        let signature = self.sign(url.as_bytes()).finalize().into_bytes();
+        format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(url),
+            URL_SAFE_NO_PAD.encode(&signature[..SIGNATURE_LEN])
+        )
+    }
+
+    fn decode(&self, token: &str) -> Result<String> {
+        let (payload, signature) = token
+            .split_once('.')
+            .ok_or_else(|| ProxyError::request("代理链接缺少签名"))?;
+        let url = URL_SAFE_NO_PAD
+            .decode(payload)
+            .map_err(|_| ProxyError::request("代理链接格式无效"))?;
+        let signature = URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(|_| ProxyError::request("代理链接签名格式无效"))?;
+        if signature.len() != SIGNATURE_LEN {
+            return Err(ProxyError::request("代理链接签名无效"));
+        }
+        self.sign(&url)
+            .verify_truncated_left(&signature)
+            .map_err(|_| ProxyError::request("代理链接签名无效"))?;
+        String::from_utf8(url).map_err(|_| ProxyError::request("代理链接格式无效"))
+    }
+}
+
+/// ChaCha20-Poly1305 加密：客户端看不到源站 URL 和其中的 CDN 令牌。
+/// nonce 由 URL 的 HMAC 得到，同一 URL 总是得到相同的链接，不影响播放列表和客户端缓存
+pub struct EncryptedCodec {
+    cipher: ChaCha20Poly1305,
+    nonce_key: SignedCodec,
+}
+
+impl EncryptedCodec {
+    /// `key` 为 32 字节密钥，加密和生成 nonce 分别使用由它派生的子密钥
+    pub fn new(key: [u8; 32]) -> Self {
+        let master = SignedCodec::new(key.to_vec());
+        let cipher_key = master.sign(CIPHER_KEY_LABEL).finalize().into_bytes();
+        let nonce_key = master.sign(NONCE_KEY_LABEL).finalize().into_bytes();
+        Self {
+            cipher: ChaCha20Poly1305::new(&cipher_key),
+            nonce_key: SignedCodec::new(nonce_key.to_vec()),
+        }
+    }
+}
+
+impl LinkCodec for EncryptedCodec {
+    fn encode(&self, url: &str) -> String {
+        let digest = self.nonce_key.sign(url.as_bytes()).finalize().into_bytes();
+        let nonce = Nonce::from_slice(&digest[..NONCE_LEN]);
+        let ciphertext = self
+            .cipher
+            .encrypt(nonce, url.as_bytes())
+            .expect("ChaCha20-Poly1305 加密不会失败");
+        let mut token = nonce.to_vec();
+        token.extend_from_slice(&ciphertext);
+        URL_SAFE_NO_PAD.encode(token)
+    }
+
+    fn decode(&self, token: &str) -> Result<String> {
+        let data = URL_SAFE_NO_PAD
+            .decode(token)
+            .map_err(|_| ProxyError::request("代理链接格式无效"))?;
+        if data.len() < NONCE_LEN {
+            return Err(ProxyError::request("代理链接格式无效"));
+        }
+        let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+        let url = self
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| ProxyError::request("代理链接无法解密"))?;
+        String::from_utf8(url).map_err(|_| ProxyError::request("代理链接格式无效"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_link_codecs() {
+        let url = "https://cdn.example.com/vod/seg-1.ts?token=abc&expires=1";
+
+        let signed = SignedCodec::new("secret");
+        let token = signed.encode(url);
+        assert!(!token.contains('/') && !token.contains('?'));
+        assert_eq!(signed.decode(&token).unwrap(), url);
+        let (payload, signature) = token.split_once('.').unwrap();
+        let forged = format!("{}.{}", URL_SAFE_NO_PAD.encode("https://evil.example.com/"), signature);
+        assert!(signed.decode(&forged).is_err());
+        assert!(SignedCodec::new("other").decode(&token).is_err());
+        assert!(signed.decode(payload).is_err());
+
+        let encrypted = EncryptedCodec::new([7u8; 32]);
+        let token = encrypted.encode(url);
+        assert_eq!(token, encrypted.encode(url));
+        assert!(!token.contains("example"));
+        assert_eq!(encrypted.decode(&token).unwrap(), url);
+        assert!(EncryptedCodec::new([8u8; 32]).decode(&token).is_err());
+        assert!(encrypted.decode("AAAA").is_err());
+
+        // 加密子密钥与主密钥不同，直接用主密钥无法解密
+        let data = URL_SAFE_NO_PAD.decode(&token).unwrap();
+        let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+        let master = ChaCha20Poly1305::new(chacha20poly1305::Key::from_slice(&[7u8; 32]));
+        assert!(master.decrypt(Nonce::from_slice(nonce), ciphertext).is_err());
+
+        assert_eq!(PercentCodec.decode(&PercentCodec.encode(url)).unwrap(), url);
+    }
+}
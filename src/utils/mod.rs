@@ -1,6 +1,10 @@
 pub mod error;
 pub mod range;
 pub mod logger;
+pub mod bloom;
+pub mod request_id;
+pub mod canonical;
+pub mod link_codec;
 
 pub use range::parse_range;
 pub use logger::Logger;
@@ -1,38 +1,220 @@
+use std::fmt;
 use crate::utils::error::{Result, ProxyError};
 
-pub fn parse_range(range: &str) -> Result<(u64, u64)> {
-    // 检查前缀
-    if !range.starts_with("bytes=") {
-        return Err(ProxyError::Request("Invalid range format".to_string()));
+/// Range 头中的单个字节范围（RFC 7233 2.1）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteRange {
+    /// `first-last`
+    Closed(u64, u64),
+    /// `first-`，到文件末尾
+    From(u64),
+    /// `-length`，最后 length 个字节
+    Suffix(u64),
+}
+
+impl ByteRange {
+    /// 按文件总大小计算闭区间，结束位置超出文件时截断到最后一个字节
+    pub fn resolve(&self, total: u64) -> std::result::Result<(u64, u64), RangeError> {
+        if total == 0 {
+            return Err(RangeError::Unsatisfiable);
+        }
+        match *self {
+            ByteRange::Closed(start, end) if start < total => Ok((start, end.min(total - 1))),
+            ByteRange::From(start) if start < total => Ok((start, total - 1)),
+            ByteRange::Suffix(length) if length > 0 => Ok((total - length.min(total), total - 1)),
+            _ => Err(RangeError::Unsatisfiable),
+        }
     }
 
-    // 移除前缀
-    let range = &range[6..];
+    /// 不知道文件大小时的闭区间，结束位置未知时为 `u64::MAX`，后缀范围无法确定起点
+    fn bounds(&self) -> Option<(u64, u64)> {
+        match *self {
+            ByteRange::Closed(start, end) => Some((start, end)),
+            ByteRange::From(start) => Some((start, u64::MAX)),
+            ByteRange::Suffix(_) => None,
+        }
+    }
+}
 
-    // 分割范围
-    let parts: Vec<&str> = range.split('-').collect();
-    if parts.len() != 2 {
-        return Err(ProxyError::Request("Invalid range format".to_string()));
+/// Range 头解析错误
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RangeError {
+    /// 单位不是 bytes
+    UnsupportedUnit(String),
+    /// 语法错误
+    Malformed(String),
+    /// 起始位置大于结束位置
+    StartAfterEnd(u64, u64),
+    /// 后缀范围需要知道文件大小
+    UnknownLength,
+    /// 范围与文件没有交集
+    Unsatisfiable,
+}
+
+impl fmt::Display for RangeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RangeError::UnsupportedUnit(unit) => write!(f, "不支持的范围单位: {}", unit),
+            RangeError::Malformed(spec) => write!(f, "无效的范围格式: {}", spec),
+            RangeError::StartAfterEnd(start, end) => write!(f, "无效的范围: 起始位置 {} 大于结束位置 {}", start, end),
+            RangeError::UnknownLength => write!(f, "文件大小未知，无法处理后缀范围"),
+            RangeError::Unsatisfiable => write!(f, "请求范围超出文件大小"),
+        }
     }
+}
 
-    // 解析开始位置
-    let start = parts[0]
-        .parse::<u64>()
-        .map_err(|_| ProxyError::Request("Invalid start position".to_string()))?;
+impl std::error::Error for RangeError {}
 
-    // 解析结束位置
-    let end = if parts[1].is_empty() {
-        u64::MAX
-    } else {
-        parts[1]
-            .parse::<u64>()
-            .map_err(|_| ProxyError::Request("Invalid end position".to_string()))?
-    };
+impl From<RangeError> for ProxyError {
+    fn from(err: RangeError) -> Self {
+        match err {
+            RangeError::Unsatisfiable | RangeError::UnknownLength | RangeError::StartAfterEnd(..) => {
+                ProxyError::invalid_range(err.to_string())
+            }
+            _ => ProxyError::request(err.to_string()),
+        }
+    }
+}
 
-    // 验证范围
-    if start > end {
-        return Err(ProxyError::Request("Invalid range: start > end".to_string()));
+/// 解析完整的 Range 头：单位不区分大小写，允许空白和多个范围，空的列表项按 RFC 7230 7 忽略
+pub fn parse_ranges(header: &str) -> std::result::Result<Vec<ByteRange>, RangeError> {
+    let (unit, set) = header
+        .split_once('=')
+        .ok_or_else(|| RangeError::Malformed(header.to_string()))?;
+    let unit = unit.trim();
+    if !unit.eq_ignore_ascii_case("bytes") {
+        return Err(RangeError::UnsupportedUnit(unit.to_string()));
     }
 
+    let mut ranges = Vec::new();
+    for spec in set.split(',').map(str::trim).filter(|spec| !spec.is_empty()) {
+        let (first, last) = spec
+            .split_once('-')
+            .ok_or_else(|| RangeError::Malformed(spec.to_string()))?;
+        let (first, last) = (first.trim(), last.trim());
+        let number = |value: &str| {
+            if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(RangeError::Malformed(spec.to_string()));
+            }
+            value.parse::<u64>().map_err(|_| RangeError::Malformed(spec.to_string()))
+        };
+        let range = match (first.is_empty(), last.is_empty()) {
+            (true, true) => return Err(RangeError::Malformed(spec.to_string())),
+            (true, false) => ByteRange::Suffix(number(last)?),
+            (false, true) => ByteRange::From(number(first)?),
+            (false, false) => {
+                let (start, end) = (number(first)?, number(last)?);
+                if start > end {
+                    return Err(RangeError::StartAfterEnd(start, end));
+                }
+                ByteRange::Closed(start, end)
+            }
+        };
+        ranges.push(range);
+    }
+
+    if ranges.is_empty() {
+        return Err(RangeError::Malformed(header.to_string()));
+    }
+    Ok(ranges)
+}
+
+/// 将 Range 头解析为单个闭区间。多个范围合并为覆盖全部范围的一个区间；
+/// 结束位置未知时为 `u64::MAX`。`total` 为文件大小，已知时用于处理后缀范围并校验范围
+pub fn resolve_range(header: &str, total: Option<u64>) -> std::result::Result<(u64, u64), RangeError> {
+    let ranges = parse_ranges(header)?;
+    let bounds: Vec<(u64, u64)> = match total {
+        Some(total) => {
+            // 只要有一个范围可以满足就不返回 416，不可满足的范围被忽略
+            let satisfiable: Vec<_> = ranges.iter().filter_map(|range| range.resolve(total).ok()).collect();
+            if satisfiable.is_empty() {
+                return Err(RangeError::Unsatisfiable);
+            }
+            satisfiable
+        }
+        None => ranges
+            .iter()
+            .map(|range| range.bounds().ok_or(RangeError::UnknownLength))
+            .collect::<std::result::Result<_, _>>()?,
+    };
+
+    let start = bounds.iter().map(|(start, _)| *start).min().unwrap_or(0);
+    let end = bounds.iter().map(|(_, end)| *end).max().unwrap_or(u64::MAX);
     Ok((start, end))
 }
+
+/// 将范围长度限制在 `max_span` 字节以内，返回截断后的结束位置
+pub fn cap_span(start: u64, end: u64, max_span: u64) -> u64 {
+    end.min(start.saturating_add(max_span.max(1) - 1))
+}
+
+/// 解析 Range 头，不知道文件大小，后缀范围返回错误
+pub fn parse_range(range: &str) -> Result<(u64, u64)> {
+    Ok(resolve_range(range, None)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_parse_ranges() {
+        assert_eq!(parse_range("bytes=0-").unwrap(), (0, u64::MAX));
+        assert_eq!(parse_range("bytes=100-199").unwrap(), (100, 199));
+        assert_eq!(parse_range(" Bytes = 100 - 199 ").unwrap(), (100, 199));
+        assert_eq!(parse_range("bytes=0-99, 200-299,").unwrap(), (0, 299));
+        assert_eq!(
+            parse_ranges("bytes=-500").unwrap(),
+            vec![ByteRange::Suffix(500)]
+        );
+
+        assert_eq!(resolve_range("bytes=-500", Some(1000)).unwrap(), (500, 999));
+        assert_eq!(resolve_range("bytes=-5000", Some(1000)).unwrap(), (0, 999));
+        assert_eq!(resolve_range("bytes=900-2000", Some(1000)).unwrap(), (900, 999));
+        assert_eq!(resolve_range("bytes=2000-, 0-9", Some(1000)).unwrap(), (0, 9));
+
+        assert_eq!(resolve_range("bytes=-500", None), Err(RangeError::UnknownLength));
+        assert_eq!(resolve_range("bytes=1000-", Some(1000)), Err(RangeError::Unsatisfiable));
+        assert_eq!(resolve_range("bytes=-0", Some(1000)), Err(RangeError::Unsatisfiable));
+        assert_eq!(parse_ranges("bytes=9-1"), Err(RangeError::StartAfterEnd(9, 1)));
+        assert!(matches!(parse_ranges("items=0-1"), Err(RangeError::UnsupportedUnit(_))));
+        assert!(matches!(parse_ranges("bytes=-"), Err(RangeError::Malformed(_))));
+        assert!(matches!(parse_ranges("bytes=+1-2"), Err(RangeError::Malformed(_))));
+        assert!(matches!(parse_ranges("bytes=,"), Err(RangeError::Malformed(_))));
+    }
+
+    #[test]
+    fn test_cap_span() {
+        assert_eq!(cap_span(0, u64::MAX, 1024), 1023);
+        assert_eq!(cap_span(100, 199, 1024), 199);
+        assert_eq!(cap_span(100, 2000, 1), 100);
+        assert_eq!(cap_span(u64::MAX - 1, u64::MAX, 1024), u64::MAX);
+        assert_eq!(
+            ProxyError::from(RangeError::StartAfterEnd(9, 1)).kind(),
+            crate::utils::error::ErrorKind::InvalidRange
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn prop_closed_range_roundtrip(start in 0u64..u64::MAX / 2, len in 0u64..1 << 40) {
+            let end = start + len;
+            prop_assert_eq!(parse_range(&format!("bytes={}-{}", start, end)).unwrap(), (start, end));
+        }
+
+        #[test]
+        fn prop_resolved_range_within_total(header in "bytes=[0-9]{0,4}-[0-9]{0,4}", total in 1u64..20_000) {
+            if let Ok((start, end)) = resolve_range(&header, Some(total)) {
+                prop_assert!(start <= end);
+                prop_assert!(end < total);
+            }
+        }
+
+        #[test]
+        fn prop_never_panics(header in "\\PC*") {
+            let _ = parse_ranges(&header);
+            let _ = resolve_range(&header, Some(1024));
+        }
+    }
+}
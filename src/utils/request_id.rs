@@ -0,0 +1,58 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::task::JoinHandle;
+
+/// 请求 ID 响应头
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// 请求上下文，在处理请求的任务及其后台任务中可见
+#[derive(Debug, Clone)]
+pub struct RequestContext {
+    pub id: String,
+    /// 客户端地址
+    pub client: Option<String>,
+}
+
+tokio::task_local! {
+    static CONTEXT: RequestContext;
+}
+
+/// 生成请求 ID：秒级时间戳加自增序号
+pub fn generate() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let seq = COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!("{:x}-{:x}", secs, seq)
+}
+
+/// 当前任务所属请求的 ID
+pub fn current() -> Option<String> {
+    CONTEXT.try_with(|context| context.id.clone()).ok()
+}
+
+/// 当前任务所属请求的客户端地址
+pub fn client() -> Option<String> {
+    CONTEXT.try_with(|context| context.client.clone()).ok().flatten()
+}
+
+/// 在请求上下文作用域内执行
+pub async fn scope<F: Future>(context: RequestContext, future: F) -> F::Output {
+    CONTEXT.scope(context, future).await
+}
+
+/// 启动后台任务并继承当前请求上下文
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    match CONTEXT.try_with(|context| context.clone()) {
+        Ok(context) => tokio::spawn(CONTEXT.scope(context, future)),
+        Err(_) => tokio::spawn(future),
+    }
+}
@@ -27,7 +27,7 @@ impl UrlUtils {
             
             urlencoding::decode(&clean)
                 .map(|s| s.into_owned())
-                .map_err(|e| ProxyError::Request(format!("URL 解码失败: {}", e)))
+                .map_err(|e| ProxyError::request(format!("URL 解码失败: {}", e)))
         } else {
             Ok(url.to_string())
         }
@@ -45,14 +45,14 @@ impl UrlUtils {
     /// ```
     pub fn get_base_url(url: &str) -> Result<String> {
         let parsed = Url::parse(url)
-            .map_err(|e| ProxyError::Parse(format!("无法解析URL: {}", e)))?;
+            .map_err(|e| ProxyError::parse(format!("无法解析URL: {}", e)))?;
         
         let mut base = parsed.clone();
         let segments: Vec<_> = base.path_segments()
             .map(|s| s.collect())
             .unwrap_or_default();
         let mut segments_mut = base.path_segments_mut()
-            .map_err(|_| ProxyError::Parse("无法修改URL路径".to_string()))?;
+            .map_err(|_| ProxyError::parse("无法修改URL路径".to_string()))?;
         segments_mut.clear();
         
         if !segments.is_empty() {